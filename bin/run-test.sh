@@ -98,6 +98,26 @@ for x in "$@"; do
       fi
       passed=$((passed + 1))
     fi
+  elif [ -f warn.sml ]; then
+    if ! "$MILLET" warn.sml >out.tmp; then
+      echo "$x: expected success, got failure"
+      failed=$((failed + 1))
+    elif "$generate"; then
+      mv out.tmp out.txt
+      if ! "$quiet"; then
+        echo "$x: generated"
+      fi
+      generated=$((generated + 1))
+    elif diff out.txt out.tmp; then
+      rm out.tmp
+      if ! "$quiet"; then
+        echo "$x: ok"
+      fi
+      passed=$((passed + 1))
+    else
+      echo "$x: expected lhs, got rhs"
+      failed=$((failed + 1))
+    fi
   elif [ -f err.sml ]; then
     set +e
     "$MILLET" err.sml >out.tmp
@@ -1,5 +1,7 @@
 //! Command-line arguments.
 
+use millet_core::statics::BasisLevel;
+
 pub fn get() -> Result<Option<Args>, pico_args::Error> {
   let mut args = pico_args::Arguments::from_env();
   if args.contains(["-h", "--help"]) {
@@ -13,6 +15,9 @@ pub fn get() -> Result<Option<Args>, pico_args::Error> {
   Ok(Some(Args {
     quiet: args.contains(["-q", "--quiet"]),
     just_ast: args.contains("--just-ast"),
+    full_types: args.contains("--full-types"),
+    successor_ml: args.contains("--successor-ml"),
+    basis: args.opt_value_from_str("--basis")?.unwrap_or_default(),
     files: args.free()?,
   }))
 }
@@ -20,5 +25,8 @@ pub fn get() -> Result<Option<Args>, pico_args::Error> {
 pub struct Args {
   pub quiet: bool,
   pub just_ast: bool,
+  pub full_types: bool,
+  pub successor_ml: bool,
+  pub basis: BasisLevel,
   pub files: Vec<String>,
 }
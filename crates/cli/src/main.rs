@@ -3,7 +3,7 @@
 mod args;
 mod source;
 
-use codespan_reporting::diagnostic::{Diagnostic, Label};
+use codespan_reporting::diagnostic::{Diagnostic, Label, Severity};
 use codespan_reporting::term;
 use codespan_reporting::term::termcolor::{ColorChoice, StandardStream};
 use millet_core::{intern, lex, parse, statics};
@@ -57,41 +57,70 @@ fn run() -> bool {
     }
   }
   let store = store.finish();
+  let features = if args.successor_ml {
+    parse::LanguageFeatures::successor_ml()
+  } else {
+    parse::LanguageFeatures::default()
+  };
   let mut top_decs = Vec::with_capacity(src.len());
+  let mut parse_ok = true;
   for ((id, file), lexer) in src.iter().zip(lexers) {
-    match parse::get(lexer) {
-      Ok(xs) => {
-        if args.just_ast {
-          writeln!(w, "{}: {:#?}", file.name(), xs).unwrap();
-        } else {
-          top_decs.push((id, xs));
-        }
-      }
-      Err(e) => {
-        let diag = simple(e.val.message(&store), id, e.loc);
-        term::emit(&mut w, &config, &src, &diag).unwrap();
-        writeln!(&mut w, "parsing failed").unwrap();
-        return false;
+    // report every error the parser recovered from, and check the declarations it did parse.
+    let (xs, errors) = parse::get_partial(lexer, features);
+    for e in errors {
+      let mut diag = simple(e.val.message(&store), id, e.loc);
+      for related in e.val.related() {
+        diag
+          .labels
+          .push(Label::secondary(id, related.loc).with_message(related.val));
       }
+      term::emit(&mut w, &config, &src, &diag).unwrap();
+      parse_ok = false;
+    }
+    if args.just_ast {
+      writeln!(w, "{}: {:#?}", file.name(), xs).unwrap();
+    } else {
+      top_decs.push((id, xs));
     }
   }
   if args.just_ast {
-    return true;
+    if !parse_ok {
+      writeln!(&mut w, "parsing failed").unwrap();
+    }
+    return parse_ok;
   }
-  let mut s = statics::Statics::new();
+  let limit = if args.full_types {
+    statics::TyLimit::NONE
+  } else {
+    statics::TyLimit::default()
+  };
+  let mut s = statics::Statics::with_basis(args.basis);
+  let mut ok = true;
   for (id, xs) in top_decs {
     for x in xs {
-      match s.get(&x) {
-        Ok(()) => {}
-        Err(e) => {
-          let diag = simple(e.val.message(&store), id, e.loc);
-          term::emit(&mut w, &config, &src, &diag).unwrap();
-          writeln!(&mut w, "typechecking failed").unwrap();
-          return false;
+      for e in s.get(&x) {
+        let mut diag = simple(e.val.message_with(&store, limit), id, e.loc);
+        match e.val.severity() {
+          statics::Severity::Error => ok = false,
+          statics::Severity::Warning => diag.severity = Severity::Warning,
+        }
+        for related in e.val.related() {
+          diag
+            .labels
+            .push(Label::secondary(id, related.loc).with_message(related.val));
         }
+        term::emit(&mut w, &config, &src, &diag).unwrap();
       }
     }
   }
+  if !parse_ok {
+    writeln!(&mut w, "parsing failed").unwrap();
+    return false;
+  }
+  if !ok {
+    writeln!(&mut w, "typechecking failed").unwrap();
+    return false;
+  }
   s.finish();
   if !args.quiet {
     writeln!(&mut w, "no errors").unwrap();
@@ -102,7 +131,7 @@ fn run() -> bool {
 fn main() {
   let ec = match std::thread::Builder::new()
     .name("run".to_owned())
-    .stack_size(10 * 1024 * 1024)
+    .stack_size(parse::STACK_SIZE)
     .spawn(run)
     .unwrap()
     .join()
@@ -1,6 +1,7 @@
 //! Utilities for dealing with (collections of) source files.
 
 use codespan_reporting::files::Files;
+use millet_core::line_index::LineIndex;
 
 /// An opaque identifier for a source file.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -10,21 +11,16 @@ pub struct SourceId(usize);
 pub struct Source {
   name: String,
   contents: String,
-  new_lines: Vec<usize>,
+  lines: LineIndex,
 }
 
 impl Source {
   fn new(name: String, contents: String) -> Self {
-    let new_lines = contents
-      .as_bytes()
-      .iter()
-      .enumerate()
-      .filter_map(|(idx, &b)| if b == b'\n' { Some(idx) } else { None })
-      .collect();
+    let lines = LineIndex::new(contents.as_bytes());
     Self {
       name,
       contents,
-      new_lines,
+      lines,
     }
   }
 
@@ -80,30 +76,12 @@ impl<'a> Files<'a> for SourceMap {
 
   fn line_index(&'a self, id: Self::FileId, byte_index: usize) -> Option<usize> {
     let file = self.files.get(id.0)?;
-    let ret = file
-      .new_lines
-      .iter()
-      .position(|&x| byte_index <= x)
-      .unwrap_or_else(|| file.new_lines.len());
-    Some(ret)
+    Some(file.lines.line(byte_index))
   }
 
   fn line_range(&'a self, id: Self::FileId, line_index: usize) -> Option<std::ops::Range<usize>> {
     let file = self.files.get(id.0)?;
-    if line_index > file.new_lines.len() {
-      return None;
-    }
-    let begin = if line_index == 0 {
-      0
-    } else {
-      file.new_lines[line_index - 1] + 1
-    };
-    let end = if line_index == file.new_lines.len() {
-      file.as_bytes().len()
-    } else {
-      file.new_lines[line_index]
-    };
-    Some(begin..end)
+    file.lines.line_range(line_index)
   }
 }
 
@@ -12,9 +12,10 @@ pub enum Exp<I> {
   // begin special constants
   DecInt(i32),
   HexInt(i32),
-  DecWord(i32),
-  HexWord(i32),
-  Real(f64),
+  DecWord(u32),
+  HexWord(u32),
+  /// the value and the literal text.
+  Real(f64, StrRef),
   String(StrRef),
   Char(u8),
   // end special constants
@@ -24,6 +25,7 @@ pub enum Exp<I> {
   /// requires vec.len() != 1
   Tuple(Vec<Located<Exp<I>>>),
   List(Vec<Located<Exp<I>>>),
+  Vector(Vec<Located<Exp<I>>>),
   /// requires vec.len() >= 2
   Sequence(Vec<Located<Exp<I>>>),
   /// requires !vec.is_empty()
@@ -226,8 +228,8 @@ pub enum Pat<I> {
   // begin special constants (NOTE no real)
   DecInt(i32),
   HexInt(i32),
-  DecWord(i32),
-  HexWord(i32),
+  DecWord(u32),
+  HexWord(u32),
   String(StrRef),
   Char(u8),
   // end special constants
@@ -237,6 +239,7 @@ pub enum Pat<I> {
   /// requires pats.len() != 1
   Tuple(Vec<Located<Pat<I>>>),
   List(Vec<Located<Pat<I>>>),
+  Vector(Vec<Located<Pat<I>>>),
   Ctor(Long<I>, Box<Located<Pat<I>>>),
   InfixCtor(Box<Located<Pat<I>>>, Located<I>, Box<Located<Pat<I>>>),
   Typed(Box<Located<Pat<I>>>, Located<Ty<I>>),
@@ -328,9 +331,23 @@ pub enum Spec<I> {
   /// requires !str_descs.is_empty()
   Structure(Vec<StrDesc<I>>),
   Include(Box<Located<SigExp<I>>>),
+  /// requires !fun_descs.is_empty(). not in the Definition, but supported by SML/NJ.
+  Functor(Vec<FunDesc<I>>),
   /// requires specs.len() != 1
   Seq(Vec<Located<Spec<I>>>),
-  Sharing(Box<Located<Spec<I>>>, Vec<Long<I>>),
+  /// the spec is every spec before the `sharing` in the sequence containing it. requires
+  /// longs.len() >= 2
+  Sharing(Box<Located<Spec<I>>>, SharingKind, Vec<Long<I>>),
+}
+
+/// What a `sharing` specification shares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SharingKind {
+  /// `sharing type`, of type constructors.
+  Type,
+  /// `sharing`, of structures. SML Definition Appendix A - this is sugar for sharing all the types
+  /// the structures have in common.
+  Structure,
 }
 
 /// A value description.
@@ -369,6 +386,19 @@ pub struct StrDesc<I> {
   pub exp: Located<SigExp<I>>,
 }
 
+/// A functor description.
+#[derive(Debug)]
+pub struct FunDesc<I> {
+  /// The name of the functor.
+  pub fun_id: Located<I>,
+  /// The name of the argument structure.
+  pub str_id: Located<I>,
+  /// The signature the argument structure conforms to.
+  pub param: Located<SigExp<I>>,
+  /// The signature the output structure conforms to.
+  pub res: Located<SigExp<I>>,
+}
+
 /// A functor binding.
 #[derive(Debug)]
 pub struct FunBind<I> {
@@ -0,0 +1,1740 @@
+//! A compact binary encoding, for keeping the results of analysis on disk.
+//!
+//! The names in an encoding are written out as strings, once each, so that it can be decoded with
+//! a different `StrStoreMut` than the one the names came from. Decoding returns `None` if the bytes
+//! are not a valid encoding, including if they are from a different `FORMAT`.
+
+use crate::ast::{
+  Arm, Cases, ConBind, DatBind, Dec, ExBind, ExBindInner, ExDesc, Exp, FValBind, FValBindCase,
+  FunBind, FunDesc, Label, Long, Pat, Row, SharingKind, SigBind, SigExp, Spec, StrBind, StrDec,
+  StrDesc, StrExp, TopDec, Ty, TyBind, TyDesc, ValBind, ValDesc,
+};
+use crate::intern::{StrRef, StrStore, StrStoreMut};
+use crate::loc::{Loc, Located};
+use crate::token::TyVar;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::hash::Hash;
+
+/// The version of the encoding, which starts every encoding. Changing how any value is encoded must
+/// increment this, so that encodings from before are rejected instead of decoded wrongly.
+pub const FORMAT: usize = 1;
+
+/// Encodes values into bytes.
+pub struct Encoder<'a> {
+  store: &'a StrStore,
+  buf: Vec<u8>,
+  /// The names encoded so far, in order, and the index of each.
+  names: Vec<StrRef>,
+  name_idx: HashMap<StrRef, usize>,
+}
+
+impl<'a> Encoder<'a> {
+  /// Returns a new Encoder for values whose names are in `store`.
+  pub fn new(store: &'a StrStore) -> Self {
+    Self {
+      store,
+      buf: Vec::new(),
+      names: Vec::new(),
+      name_idx: HashMap::new(),
+    }
+  }
+
+  /// Returns the encoding of everything encoded with this.
+  pub fn finish(self) -> Vec<u8> {
+    let mut ret = Encoder::new(self.store);
+    ret.usize(FORMAT);
+    ret.usize(self.names.len());
+    for &name in self.names.iter() {
+      ret.bytes(self.store.get(name).as_bytes());
+    }
+    ret.buf.extend(self.buf);
+    ret.buf
+  }
+
+  /// Encodes a byte.
+  pub fn byte(&mut self, b: u8) {
+    self.buf.push(b);
+  }
+
+  /// Encodes a number, in fewer bytes the smaller it is.
+  pub fn usize(&mut self, mut n: usize) {
+    while n >= 0x80 {
+      self.buf.push((n as u8) | 0x80);
+      n >>= 7;
+    }
+    self.buf.push(n as u8);
+  }
+
+  /// Encodes some bytes, preceded by how many there are.
+  pub fn bytes(&mut self, bs: &[u8]) {
+    self.usize(bs.len());
+    self.buf.extend_from_slice(bs);
+  }
+
+  /// Encodes a tag, the first byte of the encoding of an enum.
+  fn tag(&mut self, tag: u8) {
+    self.byte(tag);
+  }
+}
+
+/// Decodes values from bytes.
+pub struct Decoder<'a> {
+  bs: &'a [u8],
+  /// The names in the encoding, in order.
+  names: Vec<StrRef>,
+}
+
+impl<'a> Decoder<'a> {
+  /// Returns a new Decoder for the encoding `bs`, which adds the names in it to `store`. Returns
+  /// `None` if `bs` is from a different `FORMAT`.
+  pub fn new(bs: &'a [u8], store: &mut StrStoreMut) -> Option<Self> {
+    let mut ret = Self {
+      bs,
+      names: Vec::new(),
+    };
+    if ret.usize()? != FORMAT {
+      return None;
+    }
+    let len = ret.usize()?;
+    for _ in 0..len {
+      let name = std::str::from_utf8(ret.bytes()?).ok()?;
+      ret.names.push(store.insert(name.into()));
+    }
+    Some(ret)
+  }
+
+  /// Returns whether everything was decoded.
+  pub fn is_done(&self) -> bool {
+    self.bs.is_empty()
+  }
+
+  /// Decodes a byte.
+  pub fn byte(&mut self) -> Option<u8> {
+    let (&ret, rest) = self.bs.split_first()?;
+    self.bs = rest;
+    Some(ret)
+  }
+
+  /// Decodes a number.
+  pub fn usize(&mut self) -> Option<usize> {
+    let mut ret: usize = 0;
+    let mut shift = 0;
+    loop {
+      let b = self.byte()?;
+      let bits = usize::from(b & 0x7f);
+      if shift >= usize::BITS || (bits << shift) >> shift != bits {
+        return None;
+      }
+      ret |= bits << shift;
+      if b & 0x80 == 0 {
+        return Some(ret);
+      }
+      shift += 7;
+    }
+  }
+
+  /// Decodes some bytes.
+  pub fn bytes(&mut self) -> Option<&'a [u8]> {
+    let len = self.usize()?;
+    if len > self.bs.len() {
+      return None;
+    }
+    let (ret, rest) = self.bs.split_at(len);
+    self.bs = rest;
+    Some(ret)
+  }
+
+  /// Decodes a tag, the first byte of the encoding of an enum.
+  fn tag(&mut self) -> Option<u8> {
+    self.byte()
+  }
+}
+
+/// A value which can be encoded.
+pub trait Encode {
+  /// Encodes this with `e`.
+  fn encode(&self, e: &mut Encoder<'_>);
+}
+
+/// A value which can be decoded.
+pub trait Decode: Sized {
+  /// Decodes a value with `d`.
+  fn decode(d: &mut Decoder<'_>) -> Option<Self>;
+}
+
+/// Returns the encoding of `val`, whose names are in `store`.
+pub fn encode<T: Encode>(store: &StrStore, val: &T) -> Vec<u8> {
+  let mut e = Encoder::new(store);
+  val.encode(&mut e);
+  e.finish()
+}
+
+/// Returns the value encoded by `bs`, adding the names in it to `store`.
+pub fn decode<T: Decode>(store: &mut StrStoreMut, bs: &[u8]) -> Option<T> {
+  let mut d = Decoder::new(bs, store)?;
+  let ret = T::decode(&mut d)?;
+  if d.is_done() {
+    Some(ret)
+  } else {
+    None
+  }
+}
+
+impl Encode for StrRef {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    let idx = match e.name_idx.get(self) {
+      Some(&idx) => idx,
+      None => {
+        let idx = e.names.len();
+        e.names.push(*self);
+        e.name_idx.insert(*self, idx);
+        idx
+      }
+    };
+    e.usize(idx);
+  }
+}
+
+impl Decode for StrRef {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let idx = d.usize()?;
+    d.names.get(idx).copied()
+  }
+}
+
+impl Encode for usize {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    e.usize(*self);
+  }
+}
+
+impl Decode for usize {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    d.usize()
+  }
+}
+
+impl Encode for u8 {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    e.byte(*self);
+  }
+}
+
+impl Decode for u8 {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    d.byte()
+  }
+}
+
+impl Encode for u32 {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    e.usize(*self as usize);
+  }
+}
+
+impl Decode for u32 {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    use std::convert::TryFrom as _;
+    u32::try_from(d.usize()?).ok()
+  }
+}
+
+impl Encode for i32 {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    (*self as u32).encode(e);
+  }
+}
+
+impl Decode for i32 {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    Some(u32::decode(d)? as i32)
+  }
+}
+
+impl Encode for f64 {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    for b in self.to_bits().to_le_bytes().iter() {
+      e.byte(*b);
+    }
+  }
+}
+
+impl Decode for f64 {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let mut bs = [0; 8];
+    for b in bs.iter_mut() {
+      *b = d.byte()?;
+    }
+    Some(f64::from_bits(u64::from_le_bytes(bs)))
+  }
+}
+
+impl Encode for bool {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    e.byte(u8::from(*self));
+  }
+}
+
+impl Decode for bool {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    match d.byte()? {
+      0 => Some(false),
+      1 => Some(true),
+      _ => None,
+    }
+  }
+}
+
+impl Encode for String {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    e.bytes(self.as_bytes());
+  }
+}
+
+impl Decode for String {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let bs = d.bytes()?;
+    std::str::from_utf8(bs).ok().map(str::to_owned)
+  }
+}
+
+impl Encode for Loc {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    let range = std::ops::Range::from(*self);
+    e.usize(range.start);
+    e.usize(range.end - range.start);
+  }
+}
+
+impl Decode for Loc {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let start = d.usize()?;
+    let len = d.usize()?;
+    if len == 0 {
+      return None;
+    }
+    Some(Loc::new(start, start.checked_add(len)?))
+  }
+}
+
+impl<T: Encode> Encode for Located<T> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.loc.encode(e);
+    self.val.encode(e);
+  }
+}
+
+impl<T: Decode> Decode for Located<T> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let loc = Loc::decode(d)?;
+    Some(loc.wrap(T::decode(d)?))
+  }
+}
+
+impl<T: Encode> Encode for Box<T> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    (**self).encode(e);
+  }
+}
+
+impl<T: Decode> Decode for Box<T> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    T::decode(d).map(Box::new)
+  }
+}
+
+impl<T: Encode> Encode for Option<T> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    match self {
+      None => e.tag(0),
+      Some(x) => {
+        e.tag(1);
+        x.encode(e);
+      }
+    }
+  }
+}
+
+impl<T: Decode> Decode for Option<T> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    match d.tag()? {
+      0 => Some(None),
+      1 => Some(Some(T::decode(d)?)),
+      _ => None,
+    }
+  }
+}
+
+impl<A: Encode, B: Encode> Encode for (A, B) {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.0.encode(e);
+    self.1.encode(e);
+  }
+}
+
+impl<A: Decode, B: Decode> Decode for (A, B) {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let a = A::decode(d)?;
+    Some((a, B::decode(d)?))
+  }
+}
+
+/// Encodes the length of `xs`, then each of them.
+pub fn encode_iter<'a, T, I>(e: &mut Encoder<'_>, xs: I)
+where
+  T: Encode + 'a,
+  I: ExactSizeIterator<Item = &'a T>,
+{
+  e.usize(xs.len());
+  for x in xs {
+    x.encode(e);
+  }
+}
+
+/// Decodes what `encode_iter` encoded.
+pub fn decode_iter<T, C>(d: &mut Decoder<'_>) -> Option<C>
+where
+  T: Decode,
+  C: std::iter::FromIterator<T>,
+{
+  let len = d.usize()?;
+  // every value takes at least one byte, so don't trust a length longer than what is left.
+  if len > d.bs.len() {
+    return None;
+  }
+  (0..len).map(|_| T::decode(d)).collect()
+}
+
+impl<T: Encode> Encode for Vec<T> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    encode_iter(e, self.iter());
+  }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    decode_iter(d)
+  }
+}
+
+impl<T: Encode> Encode for BTreeSet<T> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    encode_iter(e, self.iter());
+  }
+}
+
+impl<T: Decode + Ord> Decode for BTreeSet<T> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    decode_iter(d)
+  }
+}
+
+impl<T: Encode> Encode for HashSet<T> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    encode_iter(e, self.iter());
+  }
+}
+
+impl<T: Decode + Eq + Hash> Decode for HashSet<T> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    decode_iter(d)
+  }
+}
+
+impl<K: Encode, V: Encode> Encode for BTreeMap<K, V> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    e.usize(self.len());
+    for (k, v) in self.iter() {
+      k.encode(e);
+      v.encode(e);
+    }
+  }
+}
+
+impl<K: Decode + Ord, V: Decode> Decode for BTreeMap<K, V> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    decode_iter(d)
+  }
+}
+
+impl<K: Encode, V: Encode> Encode for HashMap<K, V> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    e.usize(self.len());
+    for (k, v) in self.iter() {
+      k.encode(e);
+      v.encode(e);
+    }
+  }
+}
+
+impl<K: Decode + Eq + Hash, V: Decode> Decode for HashMap<K, V> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    decode_iter(d)
+  }
+}
+
+/// A map, for encoding the changes between two of them.
+pub trait Map<K, V> {
+  /// Returns the value for `k`.
+  fn get_val(&self, k: &K) -> Option<&V>;
+  /// Returns the entries.
+  fn entries(&self) -> Vec<(&K, &V)>;
+  /// Inserts an entry.
+  fn insert_entry(&mut self, k: K, v: V);
+  /// Removes the entry for `k`, returning whether there was one.
+  fn remove_entry(&mut self, k: &K) -> bool;
+}
+
+impl<K: Ord, V> Map<K, V> for BTreeMap<K, V> {
+  fn get_val(&self, k: &K) -> Option<&V> {
+    self.get(k)
+  }
+
+  fn entries(&self) -> Vec<(&K, &V)> {
+    self.iter().collect()
+  }
+
+  fn insert_entry(&mut self, k: K, v: V) {
+    self.insert(k, v);
+  }
+
+  fn remove_entry(&mut self, k: &K) -> bool {
+    self.remove(k).is_some()
+  }
+}
+
+impl<K: Eq + Hash, V> Map<K, V> for HashMap<K, V> {
+  fn get_val(&self, k: &K) -> Option<&V> {
+    self.get(k)
+  }
+
+  fn entries(&self) -> Vec<(&K, &V)> {
+    self.iter().collect()
+  }
+
+  fn insert_entry(&mut self, k: K, v: V) {
+    self.insert(k, v);
+  }
+
+  fn remove_entry(&mut self, k: &K) -> bool {
+    self.remove(k).is_some()
+  }
+}
+
+/// Encodes the changes from `before` to `after`: the keys no longer in it, then the entries which
+/// are new or changed.
+pub fn encode_map_delta<K, V, M>(e: &mut Encoder<'_>, before: &M, after: &M)
+where
+  K: Encode,
+  V: Encode + PartialEq,
+  M: Map<K, V>,
+{
+  let removed: Vec<_> = before
+    .entries()
+    .into_iter()
+    .filter(|&(k, _)| after.get_val(k).is_none())
+    .collect();
+  e.usize(removed.len());
+  for (k, _) in removed {
+    k.encode(e);
+  }
+  let changed: Vec<_> = after
+    .entries()
+    .into_iter()
+    .filter(|&(k, v)| before.get_val(k) != Some(v))
+    .collect();
+  e.usize(changed.len());
+  for (k, v) in changed {
+    k.encode(e);
+    v.encode(e);
+  }
+}
+
+/// Applies the changes encoded by `encode_map_delta` to `map`.
+pub fn decode_map_delta<K, V, M>(d: &mut Decoder<'_>, map: &mut M) -> Option<()>
+where
+  K: Decode,
+  V: Decode,
+  M: Map<K, V>,
+{
+  let removed: Vec<K> = decode_iter(d)?;
+  for k in removed {
+    if !map.remove_entry(&k) {
+      return None;
+    }
+  }
+  let changed: Vec<(K, V)> = decode_iter(d)?;
+  for (k, v) in changed {
+    map.insert_entry(k, v);
+  }
+  Some(())
+}
+
+/// Encodes the changes from `before` to `after`, like `encode_map_delta`.
+pub fn encode_set_delta<T: Encode + Eq + Hash>(
+  e: &mut Encoder<'_>,
+  before: &HashSet<T>,
+  after: &HashSet<T>,
+) {
+  encode_iter(e, before.difference(after).collect::<Vec<_>>().into_iter());
+  encode_iter(e, after.difference(before).collect::<Vec<_>>().into_iter());
+}
+
+/// Applies the changes encoded by `encode_set_delta` to `set`.
+pub fn decode_set_delta<T: Decode + Eq + Hash>(
+  d: &mut Decoder<'_>,
+  set: &mut HashSet<T>,
+) -> Option<()> {
+  let removed: Vec<T> = decode_iter(d)?;
+  for x in removed {
+    if !set.remove(&x) {
+      return None;
+    }
+  }
+  let added: Vec<T> = decode_iter(d)?;
+  set.extend(added);
+  Some(())
+}
+
+impl Encode for Label {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    match self {
+      Label::Num(n) => {
+        e.tag(0);
+        n.encode(e);
+      }
+      Label::Vid(name) => {
+        e.tag(1);
+        name.encode(e);
+      }
+    }
+  }
+}
+
+impl Decode for Label {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    match d.tag()? {
+      0 => Some(Label::Num(u32::decode(d)?)),
+      1 => Some(Label::Vid(StrRef::decode(d)?)),
+      _ => None,
+    }
+  }
+}
+
+impl<I: Encode> Encode for TyVar<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.name.encode(e);
+    self.equality.encode(e);
+  }
+}
+
+impl<I: Decode> Decode for TyVar<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let name = I::decode(d)?;
+    let equality = bool::decode(d)?;
+    Some(TyVar { name, equality })
+  }
+}
+
+impl<I: Encode> Encode for Long<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.structures.encode(e);
+    self.last.encode(e);
+  }
+}
+
+impl<I: Decode> Decode for Long<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let structures = Vec::decode(d)?;
+    let last = Located::decode(d)?;
+    Some(Long { structures, last })
+  }
+}
+
+impl<T: Encode> Encode for Row<T> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.lab.encode(e);
+    self.val.encode(e);
+  }
+}
+
+impl<T: Decode> Decode for Row<T> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let lab = Located::decode(d)?;
+    let val = T::decode(d)?;
+    Some(Row { lab, val })
+  }
+}
+
+impl<I: Encode> Encode for Cases<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.arms.encode(e);
+  }
+}
+
+impl<I: Decode> Decode for Cases<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    Some(Cases {
+      arms: Vec::decode(d)?,
+    })
+  }
+}
+
+impl<I: Encode> Encode for Arm<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.pat.encode(e);
+    self.exp.encode(e);
+  }
+}
+
+impl<I: Decode> Decode for Arm<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let pat = Located::decode(d)?;
+    let exp = Located::decode(d)?;
+    Some(Arm { pat, exp })
+  }
+}
+
+impl<I: Encode> Encode for Exp<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    match self {
+      Exp::DecInt(n) => {
+        e.tag(0);
+        n.encode(e);
+      }
+      Exp::HexInt(n) => {
+        e.tag(1);
+        n.encode(e);
+      }
+      Exp::DecWord(n) => {
+        e.tag(2);
+        n.encode(e);
+      }
+      Exp::HexWord(n) => {
+        e.tag(3);
+        n.encode(e);
+      }
+      Exp::Real(n, s) => {
+        e.tag(4);
+        n.encode(e);
+        s.encode(e);
+      }
+      Exp::String(s) => {
+        e.tag(5);
+        s.encode(e);
+      }
+      Exp::Char(c) => {
+        e.tag(6);
+        c.encode(e);
+      }
+      Exp::LongVid(vid) => {
+        e.tag(7);
+        vid.encode(e);
+      }
+      Exp::Record(rows) => {
+        e.tag(8);
+        rows.encode(e);
+      }
+      Exp::Select(lab) => {
+        e.tag(9);
+        lab.encode(e);
+      }
+      Exp::Tuple(exps) => {
+        e.tag(10);
+        exps.encode(e);
+      }
+      Exp::List(exps) => {
+        e.tag(11);
+        exps.encode(e);
+      }
+      Exp::Vector(exps) => {
+        e.tag(12);
+        exps.encode(e);
+      }
+      Exp::Sequence(exps) => {
+        e.tag(13);
+        exps.encode(e);
+      }
+      Exp::Let(dec, exps) => {
+        e.tag(14);
+        dec.encode(e);
+        exps.encode(e);
+      }
+      Exp::App(func, arg) => {
+        e.tag(15);
+        func.encode(e);
+        arg.encode(e);
+      }
+      Exp::InfixApp(lhs, func, rhs) => {
+        e.tag(16);
+        lhs.encode(e);
+        func.encode(e);
+        rhs.encode(e);
+      }
+      Exp::Typed(exp, ty) => {
+        e.tag(17);
+        exp.encode(e);
+        ty.encode(e);
+      }
+      Exp::Andalso(lhs, rhs) => {
+        e.tag(18);
+        lhs.encode(e);
+        rhs.encode(e);
+      }
+      Exp::Orelse(lhs, rhs) => {
+        e.tag(19);
+        lhs.encode(e);
+        rhs.encode(e);
+      }
+      Exp::Handle(exp, cases) => {
+        e.tag(20);
+        exp.encode(e);
+        cases.encode(e);
+      }
+      Exp::Raise(exp) => {
+        e.tag(21);
+        exp.encode(e);
+      }
+      Exp::If(cond, yes, no) => {
+        e.tag(22);
+        cond.encode(e);
+        yes.encode(e);
+        no.encode(e);
+      }
+      Exp::While(cond, body) => {
+        e.tag(23);
+        cond.encode(e);
+        body.encode(e);
+      }
+      Exp::Case(head, cases) => {
+        e.tag(24);
+        head.encode(e);
+        cases.encode(e);
+      }
+      Exp::Fn(cases) => {
+        e.tag(25);
+        cases.encode(e);
+      }
+    }
+  }
+}
+
+impl<I: Decode> Decode for Exp<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let ret = match d.tag()? {
+      0 => Exp::DecInt(i32::decode(d)?),
+      1 => Exp::HexInt(i32::decode(d)?),
+      2 => Exp::DecWord(u32::decode(d)?),
+      3 => Exp::HexWord(u32::decode(d)?),
+      4 => {
+        let n = f64::decode(d)?;
+        Exp::Real(n, StrRef::decode(d)?)
+      }
+      5 => Exp::String(StrRef::decode(d)?),
+      6 => Exp::Char(u8::decode(d)?),
+      7 => Exp::LongVid(Long::decode(d)?),
+      8 => Exp::Record(Vec::decode(d)?),
+      9 => Exp::Select(Located::decode(d)?),
+      10 => Exp::Tuple(Vec::decode(d)?),
+      11 => Exp::List(Vec::decode(d)?),
+      12 => Exp::Vector(Vec::decode(d)?),
+      13 => Exp::Sequence(Vec::decode(d)?),
+      14 => {
+        let dec = Located::decode(d)?;
+        Exp::Let(dec, Vec::decode(d)?)
+      }
+      15 => {
+        let func = Box::decode(d)?;
+        Exp::App(func, Box::decode(d)?)
+      }
+      16 => {
+        let lhs = Box::decode(d)?;
+        let func = Located::decode(d)?;
+        Exp::InfixApp(lhs, func, Box::decode(d)?)
+      }
+      17 => {
+        let exp = Box::decode(d)?;
+        Exp::Typed(exp, Located::decode(d)?)
+      }
+      18 => {
+        let lhs = Box::decode(d)?;
+        Exp::Andalso(lhs, Box::decode(d)?)
+      }
+      19 => {
+        let lhs = Box::decode(d)?;
+        Exp::Orelse(lhs, Box::decode(d)?)
+      }
+      20 => {
+        let exp = Box::decode(d)?;
+        Exp::Handle(exp, Cases::decode(d)?)
+      }
+      21 => Exp::Raise(Box::decode(d)?),
+      22 => {
+        let cond = Box::decode(d)?;
+        let yes = Box::decode(d)?;
+        Exp::If(cond, yes, Box::decode(d)?)
+      }
+      23 => {
+        let cond = Box::decode(d)?;
+        Exp::While(cond, Box::decode(d)?)
+      }
+      24 => {
+        let head = Box::decode(d)?;
+        Exp::Case(head, Cases::decode(d)?)
+      }
+      25 => Exp::Fn(Cases::decode(d)?),
+      _ => return None,
+    };
+    Some(ret)
+  }
+}
+
+impl<I: Encode> Encode for Dec<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    match self {
+      Dec::Val(ty_vars, val_binds) => {
+        e.tag(0);
+        ty_vars.encode(e);
+        val_binds.encode(e);
+      }
+      Dec::Fun(ty_vars, fval_binds) => {
+        e.tag(1);
+        ty_vars.encode(e);
+        fval_binds.encode(e);
+      }
+      Dec::Type(ty_binds) => {
+        e.tag(2);
+        ty_binds.encode(e);
+      }
+      Dec::Datatype(dat_binds, ty_binds) => {
+        e.tag(3);
+        dat_binds.encode(e);
+        ty_binds.encode(e);
+      }
+      Dec::DatatypeCopy(ty_con, long) => {
+        e.tag(4);
+        ty_con.encode(e);
+        long.encode(e);
+      }
+      Dec::Abstype(dat_binds, ty_binds, dec) => {
+        e.tag(5);
+        dat_binds.encode(e);
+        ty_binds.encode(e);
+        dec.encode(e);
+      }
+      Dec::Exception(ex_binds) => {
+        e.tag(6);
+        ex_binds.encode(e);
+      }
+      Dec::Local(fst, snd) => {
+        e.tag(7);
+        fst.encode(e);
+        snd.encode(e);
+      }
+      Dec::Open(longs) => {
+        e.tag(8);
+        longs.encode(e);
+      }
+      Dec::Seq(decs) => {
+        e.tag(9);
+        decs.encode(e);
+      }
+      Dec::Infix(n, names) => {
+        e.tag(10);
+        n.encode(e);
+        names.encode(e);
+      }
+      Dec::Infixr(n, names) => {
+        e.tag(11);
+        n.encode(e);
+        names.encode(e);
+      }
+      Dec::Nonfix(names) => {
+        e.tag(12);
+        names.encode(e);
+      }
+    }
+  }
+}
+
+impl<I: Decode> Decode for Dec<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let ret = match d.tag()? {
+      0 => {
+        let ty_vars = Vec::decode(d)?;
+        Dec::Val(ty_vars, Vec::decode(d)?)
+      }
+      1 => {
+        let ty_vars = Vec::decode(d)?;
+        Dec::Fun(ty_vars, Vec::decode(d)?)
+      }
+      2 => Dec::Type(Vec::decode(d)?),
+      3 => {
+        let dat_binds = Vec::decode(d)?;
+        Dec::Datatype(dat_binds, Vec::decode(d)?)
+      }
+      4 => {
+        let ty_con = Located::decode(d)?;
+        Dec::DatatypeCopy(ty_con, Long::decode(d)?)
+      }
+      5 => {
+        let dat_binds = Vec::decode(d)?;
+        let ty_binds = Vec::decode(d)?;
+        Dec::Abstype(dat_binds, ty_binds, Box::decode(d)?)
+      }
+      6 => Dec::Exception(Vec::decode(d)?),
+      7 => {
+        let fst = Box::decode(d)?;
+        Dec::Local(fst, Box::decode(d)?)
+      }
+      8 => Dec::Open(Vec::decode(d)?),
+      9 => Dec::Seq(Vec::decode(d)?),
+      10 => {
+        let n = Located::decode(d)?;
+        Dec::Infix(n, Vec::decode(d)?)
+      }
+      11 => {
+        let n = Located::decode(d)?;
+        Dec::Infixr(n, Vec::decode(d)?)
+      }
+      12 => Dec::Nonfix(Vec::decode(d)?),
+      _ => return None,
+    };
+    Some(ret)
+  }
+}
+
+impl<I: Encode> Encode for ValBind<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.rec.encode(e);
+    self.pat.encode(e);
+    self.exp.encode(e);
+  }
+}
+
+impl<I: Decode> Decode for ValBind<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let rec = bool::decode(d)?;
+    let pat = Located::decode(d)?;
+    let exp = Located::decode(d)?;
+    Some(ValBind { rec, pat, exp })
+  }
+}
+
+impl<I: Encode> Encode for FValBind<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.cases.encode(e);
+  }
+}
+
+impl<I: Decode> Decode for FValBind<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    Some(FValBind {
+      cases: Vec::decode(d)?,
+    })
+  }
+}
+
+impl<I: Encode> Encode for FValBindCase<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.vid.encode(e);
+    self.pats.encode(e);
+    self.ret_ty.encode(e);
+    self.body.encode(e);
+  }
+}
+
+impl<I: Decode> Decode for FValBindCase<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let vid = Located::decode(d)?;
+    let pats = Vec::decode(d)?;
+    let ret_ty = Option::decode(d)?;
+    let body = Located::decode(d)?;
+    Some(FValBindCase {
+      vid,
+      pats,
+      ret_ty,
+      body,
+    })
+  }
+}
+
+impl<I: Encode> Encode for TyBind<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.ty_vars.encode(e);
+    self.ty_con.encode(e);
+    self.ty.encode(e);
+  }
+}
+
+impl<I: Decode> Decode for TyBind<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let ty_vars = Vec::decode(d)?;
+    let ty_con = Located::decode(d)?;
+    let ty = Located::decode(d)?;
+    Some(TyBind {
+      ty_vars,
+      ty_con,
+      ty,
+    })
+  }
+}
+
+impl<I: Encode> Encode for DatBind<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.ty_vars.encode(e);
+    self.ty_con.encode(e);
+    self.cons.encode(e);
+  }
+}
+
+impl<I: Decode> Decode for DatBind<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let ty_vars = Vec::decode(d)?;
+    let ty_con = Located::decode(d)?;
+    let cons = Vec::decode(d)?;
+    Some(DatBind {
+      ty_vars,
+      ty_con,
+      cons,
+    })
+  }
+}
+
+impl<I: Encode> Encode for ConBind<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.vid.encode(e);
+    self.ty.encode(e);
+  }
+}
+
+impl<I: Decode> Decode for ConBind<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let vid = Located::decode(d)?;
+    let ty = Option::decode(d)?;
+    Some(ConBind { vid, ty })
+  }
+}
+
+impl<I: Encode> Encode for ExBind<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.vid.encode(e);
+    match &self.inner {
+      ExBindInner::Ty(ty) => {
+        e.tag(0);
+        ty.encode(e);
+      }
+      ExBindInner::Long(long) => {
+        e.tag(1);
+        long.encode(e);
+      }
+    }
+  }
+}
+
+impl<I: Decode> Decode for ExBind<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let vid = Located::decode(d)?;
+    let inner = match d.tag()? {
+      0 => ExBindInner::Ty(Option::decode(d)?),
+      1 => ExBindInner::Long(Long::decode(d)?),
+      _ => return None,
+    };
+    Some(ExBind { vid, inner })
+  }
+}
+
+impl<I: Encode> Encode for Pat<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    match self {
+      Pat::Wildcard => e.tag(0),
+      Pat::DecInt(n) => {
+        e.tag(1);
+        n.encode(e);
+      }
+      Pat::HexInt(n) => {
+        e.tag(2);
+        n.encode(e);
+      }
+      Pat::DecWord(n) => {
+        e.tag(3);
+        n.encode(e);
+      }
+      Pat::HexWord(n) => {
+        e.tag(4);
+        n.encode(e);
+      }
+      Pat::String(s) => {
+        e.tag(5);
+        s.encode(e);
+      }
+      Pat::Char(c) => {
+        e.tag(6);
+        c.encode(e);
+      }
+      Pat::LongVid(vid) => {
+        e.tag(7);
+        vid.encode(e);
+      }
+      Pat::Record(rows, rest_loc) => {
+        e.tag(8);
+        rows.encode(e);
+        rest_loc.encode(e);
+      }
+      Pat::Tuple(pats) => {
+        e.tag(9);
+        pats.encode(e);
+      }
+      Pat::List(pats) => {
+        e.tag(10);
+        pats.encode(e);
+      }
+      Pat::Vector(pats) => {
+        e.tag(11);
+        pats.encode(e);
+      }
+      Pat::Ctor(vid, arg) => {
+        e.tag(12);
+        vid.encode(e);
+        arg.encode(e);
+      }
+      Pat::InfixCtor(lhs, vid, rhs) => {
+        e.tag(13);
+        lhs.encode(e);
+        vid.encode(e);
+        rhs.encode(e);
+      }
+      Pat::Typed(pat, ty) => {
+        e.tag(14);
+        pat.encode(e);
+        ty.encode(e);
+      }
+      Pat::As(vid, ty, pat) => {
+        e.tag(15);
+        vid.encode(e);
+        ty.encode(e);
+        pat.encode(e);
+      }
+    }
+  }
+}
+
+impl<I: Decode> Decode for Pat<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let ret = match d.tag()? {
+      0 => Pat::Wildcard,
+      1 => Pat::DecInt(i32::decode(d)?),
+      2 => Pat::HexInt(i32::decode(d)?),
+      3 => Pat::DecWord(u32::decode(d)?),
+      4 => Pat::HexWord(u32::decode(d)?),
+      5 => Pat::String(StrRef::decode(d)?),
+      6 => Pat::Char(u8::decode(d)?),
+      7 => Pat::LongVid(Long::decode(d)?),
+      8 => {
+        let rows = Vec::decode(d)?;
+        Pat::Record(rows, Option::decode(d)?)
+      }
+      9 => Pat::Tuple(Vec::decode(d)?),
+      10 => Pat::List(Vec::decode(d)?),
+      11 => Pat::Vector(Vec::decode(d)?),
+      12 => {
+        let vid = Long::decode(d)?;
+        Pat::Ctor(vid, Box::decode(d)?)
+      }
+      13 => {
+        let lhs = Box::decode(d)?;
+        let vid = Located::decode(d)?;
+        Pat::InfixCtor(lhs, vid, Box::decode(d)?)
+      }
+      14 => {
+        let pat = Box::decode(d)?;
+        Pat::Typed(pat, Located::decode(d)?)
+      }
+      15 => {
+        let vid = Located::decode(d)?;
+        let ty = Option::decode(d)?;
+        Pat::As(vid, ty, Box::decode(d)?)
+      }
+      _ => return None,
+    };
+    Some(ret)
+  }
+}
+
+impl<I: Encode> Encode for Ty<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    match self {
+      Ty::TyVar(tv) => {
+        e.tag(0);
+        tv.encode(e);
+      }
+      Ty::Record(rows) => {
+        e.tag(1);
+        rows.encode(e);
+      }
+      Ty::Tuple(tys) => {
+        e.tag(2);
+        tys.encode(e);
+      }
+      Ty::TyCon(args, long) => {
+        e.tag(3);
+        args.encode(e);
+        long.encode(e);
+      }
+      Ty::Arrow(arg, res) => {
+        e.tag(4);
+        arg.encode(e);
+        res.encode(e);
+      }
+    }
+  }
+}
+
+impl<I: Decode> Decode for Ty<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let ret = match d.tag()? {
+      0 => Ty::TyVar(TyVar::decode(d)?),
+      1 => Ty::Record(Vec::decode(d)?),
+      2 => Ty::Tuple(Vec::decode(d)?),
+      3 => {
+        let args = Vec::decode(d)?;
+        Ty::TyCon(args, Long::decode(d)?)
+      }
+      4 => {
+        let arg = Box::decode(d)?;
+        Ty::Arrow(arg, Box::decode(d)?)
+      }
+      _ => return None,
+    };
+    Some(ret)
+  }
+}
+
+impl<I: Encode> Encode for StrExp<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    match self {
+      StrExp::Struct(str_dec) => {
+        e.tag(0);
+        str_dec.encode(e);
+      }
+      StrExp::LongStrId(long) => {
+        e.tag(1);
+        long.encode(e);
+      }
+      StrExp::Ascription(str_exp, sig_exp, opaque) => {
+        e.tag(2);
+        str_exp.encode(e);
+        sig_exp.encode(e);
+        opaque.encode(e);
+      }
+      StrExp::FunctorApp(fun_id, str_exp) => {
+        e.tag(3);
+        fun_id.encode(e);
+        str_exp.encode(e);
+      }
+      StrExp::Let(str_dec, str_exp) => {
+        e.tag(4);
+        str_dec.encode(e);
+        str_exp.encode(e);
+      }
+    }
+  }
+}
+
+impl<I: Decode> Decode for StrExp<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let ret = match d.tag()? {
+      0 => StrExp::Struct(Located::decode(d)?),
+      1 => StrExp::LongStrId(Long::decode(d)?),
+      2 => {
+        let str_exp = Box::decode(d)?;
+        let sig_exp = Located::decode(d)?;
+        StrExp::Ascription(str_exp, sig_exp, bool::decode(d)?)
+      }
+      3 => {
+        let fun_id = Located::decode(d)?;
+        StrExp::FunctorApp(fun_id, Box::decode(d)?)
+      }
+      4 => {
+        let str_dec = Located::decode(d)?;
+        StrExp::Let(str_dec, Box::decode(d)?)
+      }
+      _ => return None,
+    };
+    Some(ret)
+  }
+}
+
+impl<I: Encode> Encode for StrDec<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    match self {
+      StrDec::Dec(dec) => {
+        e.tag(0);
+        dec.encode(e);
+      }
+      StrDec::Structure(str_binds) => {
+        e.tag(1);
+        str_binds.encode(e);
+      }
+      StrDec::Local(fst, snd) => {
+        e.tag(2);
+        fst.encode(e);
+        snd.encode(e);
+      }
+      StrDec::Seq(str_decs) => {
+        e.tag(3);
+        str_decs.encode(e);
+      }
+    }
+  }
+}
+
+impl<I: Decode> Decode for StrDec<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let ret = match d.tag()? {
+      0 => StrDec::Dec(Located::decode(d)?),
+      1 => StrDec::Structure(Vec::decode(d)?),
+      2 => {
+        let fst = Box::decode(d)?;
+        StrDec::Local(fst, Box::decode(d)?)
+      }
+      3 => StrDec::Seq(Vec::decode(d)?),
+      _ => return None,
+    };
+    Some(ret)
+  }
+}
+
+impl<I: Encode> Encode for StrBind<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.id.encode(e);
+    self.exp.encode(e);
+  }
+}
+
+impl<I: Decode> Decode for StrBind<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let id = Located::decode(d)?;
+    let exp = Located::decode(d)?;
+    Some(StrBind { id, exp })
+  }
+}
+
+impl<I: Encode> Encode for SigExp<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    match self {
+      SigExp::Sig(spec) => {
+        e.tag(0);
+        spec.encode(e);
+      }
+      SigExp::SigId(sig_id) => {
+        e.tag(1);
+        sig_id.encode(e);
+      }
+      SigExp::Where(sig_exp, ty_vars, long, ty) => {
+        e.tag(2);
+        sig_exp.encode(e);
+        ty_vars.encode(e);
+        long.encode(e);
+        ty.encode(e);
+      }
+    }
+  }
+}
+
+impl<I: Decode> Decode for SigExp<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let ret = match d.tag()? {
+      0 => SigExp::Sig(Located::decode(d)?),
+      1 => SigExp::SigId(Located::decode(d)?),
+      2 => {
+        let sig_exp = Box::decode(d)?;
+        let ty_vars = Vec::decode(d)?;
+        let long = Long::decode(d)?;
+        SigExp::Where(sig_exp, ty_vars, long, Located::decode(d)?)
+      }
+      _ => return None,
+    };
+    Some(ret)
+  }
+}
+
+impl<I: Encode> Encode for SigBind<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.id.encode(e);
+    self.exp.encode(e);
+  }
+}
+
+impl<I: Decode> Decode for SigBind<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let id = Located::decode(d)?;
+    let exp = Located::decode(d)?;
+    Some(SigBind { id, exp })
+  }
+}
+
+impl<I: Encode> Encode for Spec<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    match self {
+      Spec::Val(val_descs) => {
+        e.tag(0);
+        val_descs.encode(e);
+      }
+      Spec::Type(ty_descs, equality) => {
+        e.tag(1);
+        ty_descs.encode(e);
+        equality.encode(e);
+      }
+      Spec::Datatype(dat_binds) => {
+        e.tag(2);
+        dat_binds.encode(e);
+      }
+      Spec::DatatypeCopy(ty_con, long) => {
+        e.tag(3);
+        ty_con.encode(e);
+        long.encode(e);
+      }
+      Spec::Exception(ex_descs) => {
+        e.tag(4);
+        ex_descs.encode(e);
+      }
+      Spec::Structure(str_descs) => {
+        e.tag(5);
+        str_descs.encode(e);
+      }
+      Spec::Include(sig_exp) => {
+        e.tag(6);
+        sig_exp.encode(e);
+      }
+      Spec::Functor(fun_descs) => {
+        e.tag(7);
+        fun_descs.encode(e);
+      }
+      Spec::Seq(specs) => {
+        e.tag(8);
+        specs.encode(e);
+      }
+      Spec::Sharing(spec, kind, longs) => {
+        e.tag(9);
+        spec.encode(e);
+        match kind {
+          SharingKind::Type => e.tag(0),
+          SharingKind::Structure => e.tag(1),
+        }
+        longs.encode(e);
+      }
+    }
+  }
+}
+
+impl<I: Decode> Decode for Spec<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let ret = match d.tag()? {
+      0 => Spec::Val(Vec::decode(d)?),
+      1 => {
+        let ty_descs = Vec::decode(d)?;
+        Spec::Type(ty_descs, bool::decode(d)?)
+      }
+      2 => Spec::Datatype(Vec::decode(d)?),
+      3 => {
+        let ty_con = Located::decode(d)?;
+        Spec::DatatypeCopy(ty_con, Long::decode(d)?)
+      }
+      4 => Spec::Exception(Vec::decode(d)?),
+      5 => Spec::Structure(Vec::decode(d)?),
+      6 => Spec::Include(Box::decode(d)?),
+      7 => Spec::Functor(Vec::decode(d)?),
+      8 => Spec::Seq(Vec::decode(d)?),
+      9 => {
+        let spec = Box::decode(d)?;
+        let kind = match d.tag()? {
+          0 => SharingKind::Type,
+          1 => SharingKind::Structure,
+          _ => return None,
+        };
+        Spec::Sharing(spec, kind, Vec::decode(d)?)
+      }
+      _ => return None,
+    };
+    Some(ret)
+  }
+}
+
+impl<I: Encode> Encode for ValDesc<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.vid.encode(e);
+    self.ty.encode(e);
+  }
+}
+
+impl<I: Decode> Decode for ValDesc<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let vid = Located::decode(d)?;
+    let ty = Located::decode(d)?;
+    Some(ValDesc { vid, ty })
+  }
+}
+
+impl<I: Encode> Encode for TyDesc<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.ty_vars.encode(e);
+    self.ty_con.encode(e);
+  }
+}
+
+impl<I: Decode> Decode for TyDesc<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let ty_vars = Vec::decode(d)?;
+    let ty_con = Located::decode(d)?;
+    Some(TyDesc { ty_vars, ty_con })
+  }
+}
+
+impl<I: Encode> Encode for ExDesc<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.vid.encode(e);
+    self.ty.encode(e);
+  }
+}
+
+impl<I: Decode> Decode for ExDesc<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let vid = Located::decode(d)?;
+    let ty = Option::decode(d)?;
+    Some(ExDesc { vid, ty })
+  }
+}
+
+impl<I: Encode> Encode for StrDesc<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.str_id.encode(e);
+    self.exp.encode(e);
+  }
+}
+
+impl<I: Decode> Decode for StrDesc<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let str_id = Located::decode(d)?;
+    let exp = Located::decode(d)?;
+    Some(StrDesc { str_id, exp })
+  }
+}
+
+impl<I: Encode> Encode for FunDesc<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.fun_id.encode(e);
+    self.str_id.encode(e);
+    self.param.encode(e);
+    self.res.encode(e);
+  }
+}
+
+impl<I: Decode> Decode for FunDesc<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let fun_id = Located::decode(d)?;
+    let str_id = Located::decode(d)?;
+    let param = Located::decode(d)?;
+    let res = Located::decode(d)?;
+    Some(FunDesc {
+      fun_id,
+      str_id,
+      param,
+      res,
+    })
+  }
+}
+
+impl<I: Encode> Encode for FunBind<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.fun_id.encode(e);
+    self.str_id.encode(e);
+    self.sig_exp.encode(e);
+    self.str_exp.encode(e);
+  }
+}
+
+impl<I: Decode> Decode for FunBind<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let fun_id = Located::decode(d)?;
+    let str_id = Located::decode(d)?;
+    let sig_exp = Located::decode(d)?;
+    let str_exp = Located::decode(d)?;
+    Some(FunBind {
+      fun_id,
+      str_id,
+      sig_exp,
+      str_exp,
+    })
+  }
+}
+
+impl<I: Encode> Encode for TopDec<I> {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    match self {
+      TopDec::StrDec(str_dec) => {
+        e.tag(0);
+        str_dec.encode(e);
+      }
+      TopDec::SigDec(sig_binds) => {
+        e.tag(1);
+        sig_binds.encode(e);
+      }
+      TopDec::FunDec(fun_binds) => {
+        e.tag(2);
+        fun_binds.encode(e);
+      }
+    }
+  }
+}
+
+impl<I: Decode> Decode for TopDec<I> {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let ret = match d.tag()? {
+      0 => TopDec::StrDec(Located::decode(d)?),
+      1 => TopDec::SigDec(Vec::decode(d)?),
+      2 => TopDec::FunDec(Vec::decode(d)?),
+      _ => return None,
+    };
+    Some(ret)
+  }
+}
+
+#[test]
+fn round_trip() {
+  let src = r#"
+structure S :> sig
+  type 'a t
+  datatype u = U of int | V
+  val f : ''a t -> u
+  exception E of string
+  structure T : sig end
+end = struct
+  type 'a t = 'a list * {a : int, 2 : 'a -> real}
+  datatype u = U of int | V
+  exception E of string
+  fun f ([], _) = U 0x1f
+    | f (x :: _, {a, ...}) = (case #[x, #"c"] of _ => V)
+  structure T = struct end
+end
+functor F (X : sig end) = let in S end
+local val r = ref 1.5e3 in infixr 4 @@ val _ = while !r > 0.0 do r := 0w3 handle e as E _ => () end
+"#;
+  let mut store = StrStoreMut::new();
+  let lexer = crate::lex::get(&mut store, src.as_bytes()).unwrap();
+  let features = crate::parse::LanguageFeatures::successor_ml();
+  let (top_decs, errors) = crate::parse::get_partial(lexer, features);
+  assert!(errors.is_empty());
+  let bs = encode(&store.finish(), &top_decs);
+  // decoding elsewhere gives the same names, so encoding again gives the same bytes.
+  let mut store = StrStoreMut::new();
+  let decoded: Vec<Located<TopDec<StrRef>>> = decode(&mut store, &bs).unwrap();
+  assert_eq!(decoded.len(), top_decs.len());
+  assert_eq!(encode(&store.finish(), &decoded), bs);
+  let mut store = StrStoreMut::new();
+  assert!(decode::<Vec<Located<TopDec<StrRef>>>>(&mut store, &bs[..bs.len() - 1]).is_none());
+  // an encoding from another format is rejected.
+  let mut other = bs.clone();
+  other[0] = (FORMAT + 1) as u8;
+  assert!(decode::<Vec<Located<TopDec<StrRef>>>>(&mut store, &other).is_none());
+}
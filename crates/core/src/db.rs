@@ -0,0 +1,613 @@
+//! The analysis of many files checked together, as queries on their contents.
+//!
+//! The inputs are the contents of the files, and which of them are checked first, in what order.
+//! Everything else is a query on the inputs, computed when first asked for and remembered along with
+//! what it was computed from: lexing and parsing a file, the order in which to check the files, and
+//! elaborating a file with what the files before it bind. A remembered result is used again exactly
+//! when what it was computed from is unchanged, so after an edit, asking for the same results again
+//! computes only what the edit affects.
+//!
+//! If given a cache directory, the results are also kept there, keyed by a hash of what they were
+//! computed from, so that a later Db checking the same files needn't compute them again. Only the
+//! results of lexing and parsing a file without errors, and of elaborating it, are kept. What a
+//! file binds is kept as the changes to the statics after the files before it, but everything
+//! else about the statics of the file is not kept, and is computed again when asked for.
+
+use crate::ast::TopDec;
+use crate::codec::{Decode as _, Decoder, Encode as _, Encoder};
+use crate::intern::{StrRef, StrStore, StrStoreMut};
+use crate::lex;
+use crate::loc::{Loc, Located};
+use crate::parse::{self, LanguageFeatures};
+use crate::project;
+use crate::statics::{self, BasisLevel, Fingerprint, Statics};
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// The version of the entries in the cache directory. Changing what an entry holds must increment
+/// this, so that entries from before aren't used. How an entry is encoded is versioned separately,
+/// by `codec::FORMAT`. The entries also aren't used by other versions of this crate, since the
+/// statics may differ.
+const VERSION: u32 = 1;
+
+/// The start of every entry in the cache directory.
+const MAGIC: &[u8] = b"millet cache\n";
+
+/// The inputs, and the remembered results of the queries on them. The files are identified by `K`.
+pub struct Db<K> {
+  basis: BasisLevel,
+  features: LanguageFeatures,
+  /// The contents of the prelude files, checked before every file.
+  prelude: Vec<Vec<u8>>,
+  /// Shared by every query, so that names mean the same in the results of each of them.
+  store: StrStoreMut,
+  /// The finished `store`, if nothing was added to it since.
+  finished: Option<Rc<StrStore>>,
+  /// Where to keep the results, if anywhere.
+  cache_dir: Option<PathBuf>,
+  /// The statics after the prelude, with which the first file is checked, and the key for them.
+  start: Option<(Rc<Statics>, Key)>,
+  files: BTreeMap<K, File>,
+  /// The files checked first, in order.
+  listed: Vec<K>,
+  /// The order in which to check the files which parsed well enough to check.
+  order: Option<Rc<Vec<K>>>,
+}
+
+/// A file, and the remembered results of the queries on it.
+struct File {
+  contents: Vec<u8>,
+  /// The results of lexing and parsing `contents`.
+  parsed: Option<Rc<Parsed>>,
+  /// The results of the last time this was elaborated, which may be for earlier contents.
+  elaborated: Option<Rc<Elaborated>>,
+}
+
+/// The results of lexing and parsing a file.
+pub struct Parsed {
+  /// The error from lexing, if any. If there is one, nothing was parsed.
+  pub lex_error: Option<Located<lex::Error>>,
+  /// The errors from parsing.
+  pub parse_errors: Vec<Located<parse::Error>>,
+  /// The locations of the comments.
+  pub comments: Vec<Loc>,
+  /// The top-level declarations which parsed.
+  pub top_decs: Vec<Located<TopDec<StrRef>>>,
+}
+
+impl Parsed {
+  /// Returns whether this went well enough to elaborate.
+  pub fn is_ok(&self) -> bool {
+    // the parser only recovers from unclosed constructs. after any other error, the top-level
+    // declarations are too incomplete to check.
+    match self.parse_errors.last() {
+      Some(e) if !matches!(e.val, parse::Error::Unclosed(..)) => false,
+      _ => self.lex_error.is_none(),
+    }
+  }
+}
+
+/// The results of elaborating a file.
+pub struct Elaborated {
+  /// What was elaborated.
+  parsed: Rc<Parsed>,
+  /// The statics before elaborating it, from the files before it.
+  input: Rc<Statics>,
+  /// Identifies `input` and the contents of the file, and so everything else here.
+  key: Key,
+  /// The statics after elaborating it, unless this is from the cache directory.
+  statics: Option<Rc<Statics>>,
+  /// The errors from elaborating it.
+  pub errors: Vec<Located<statics::Error>>,
+  /// What it binds.
+  fingerprint: Fingerprint,
+  /// The statics with which to check the next file.
+  next: Rc<Statics>,
+}
+
+impl<K: Clone + Ord> Db<K> {
+  /// Returns a Db with no files, which checks every file after `prelude` with the given basis and
+  /// features, and keeps the results in `cache_dir`, if given.
+  pub fn new(
+    basis: BasisLevel,
+    features: LanguageFeatures,
+    prelude: Vec<Vec<u8>>,
+    cache_dir: Option<PathBuf>,
+  ) -> Self {
+    Self {
+      basis,
+      features,
+      prelude,
+      store: StrStoreMut::new(),
+      finished: None,
+      cache_dir,
+      start: None,
+      files: BTreeMap::new(),
+      listed: Vec::new(),
+      order: None,
+    }
+  }
+
+  /// Sets the contents of `file`, adding it if it is not already here.
+  pub fn set_contents(&mut self, file: K, contents: &[u8]) {
+    match self.files.get_mut(&file) {
+      Some(f) if f.contents == contents => return,
+      Some(f) => {
+        f.contents = contents.to_owned();
+        f.parsed = None;
+      }
+      None => {
+        let f = File {
+          contents: contents.to_owned(),
+          parsed: None,
+          elaborated: None,
+        };
+        self.files.insert(file, f);
+      }
+    }
+    self.order = None;
+  }
+
+  /// Removes the files for which `f` returns false.
+  pub fn retain<F>(&mut self, mut f: F)
+  where
+    F: FnMut(&K) -> bool,
+  {
+    let len = self.files.len();
+    self.files.retain(|file, _| f(file));
+    if self.files.len() != len {
+      self.order = None;
+    }
+  }
+
+  /// Sets the files to check first, in order. The rest are checked after them, in an order based on
+  /// what they bind and mention, and otherwise in the order of `K`.
+  pub fn set_listed(&mut self, files: Vec<K>) {
+    if self.listed != files {
+      self.listed = files;
+      self.order = None;
+    }
+  }
+
+  /// Returns the store for the names in the results of the queries so far.
+  pub fn store(&mut self) -> Rc<StrStore> {
+    let store = &self.store;
+    let ret = self
+      .finished
+      .get_or_insert_with(|| Rc::new(store.clone().finish()));
+    Rc::clone(ret)
+  }
+
+  /// Returns the results of lexing and parsing `file`, if it is here.
+  pub fn parsed(&mut self, file: &K) -> Option<Rc<Parsed>> {
+    let f = self.files.get_mut(file)?;
+    if let Some(ret) = &f.parsed {
+      return Some(Rc::clone(ret));
+    }
+    self.finished = None;
+    let key = parsed_key(self.features, &f.contents);
+    let cached = self.cache_dir.as_deref().and_then(|dir| read(dir, key));
+    let store = &mut self.store;
+    if let Some(ret) = cached.and_then(|bs| decode_parsed(store, &bs)) {
+      let ret = Rc::new(ret);
+      f.parsed = Some(Rc::clone(&ret));
+      return Some(ret);
+    }
+    let mut ret = Parsed {
+      lex_error: None,
+      parse_errors: Vec::new(),
+      comments: Vec::new(),
+      top_decs: Vec::new(),
+    };
+    match lex::get(&mut self.store, &f.contents) {
+      Ok(lexer) => {
+        ret.comments = lexer.comments();
+        let (top_decs, errors) = parse::get_partial(lexer, self.features);
+        ret.top_decs = top_decs;
+        ret.parse_errors = errors;
+      }
+      Err(e) => ret.lex_error = Some(e),
+    }
+    let ret = Rc::new(ret);
+    f.parsed = Some(Rc::clone(&ret));
+    Some(ret)
+  }
+
+  /// Returns the order in which to check the files which parsed well enough to check.
+  pub fn order(&mut self) -> Rc<Vec<K>> {
+    if let Some(ret) = &self.order {
+      return Rc::clone(ret);
+    }
+    let files: Vec<_> = self.files.keys().cloned().collect();
+    let mut parsed = BTreeMap::new();
+    for file in files {
+      let p = self.parsed(&file).unwrap();
+      if p.is_ok() {
+        parsed.insert(file, p);
+      }
+    }
+    let mut ret = Vec::with_capacity(parsed.len());
+    for file in self.listed.iter() {
+      if parsed.contains_key(file) && !ret.contains(file) {
+        ret.push(file.clone());
+      }
+    }
+    let rest: Vec<_> = parsed
+      .iter()
+      .filter(|&(file, _)| !self.listed.contains(file))
+      .collect();
+    let rest_top_decs: Vec<_> = rest.iter().map(|(_, p)| p.top_decs.as_slice()).collect();
+    ret.extend(
+      project::order(&rest_top_decs)
+        .into_iter()
+        .map(|i| rest[i].0.clone()),
+    );
+    let ret = Rc::new(ret);
+    self.order = Some(Rc::clone(&ret));
+    ret
+  }
+
+  /// Returns the results of elaborating `file` after the files before it, if it is here and parsed
+  /// well enough to check.
+  pub fn elaborated(&mut self, file: &K) -> Option<Rc<Elaborated>> {
+    self.elaborate_in_order(file, false)
+  }
+
+  /// Returns the statics after elaborating `file` after the files before it, if it is here and
+  /// parsed well enough to check.
+  pub fn statics(&mut self, file: &K) -> Option<Rc<Statics>> {
+    let ret = self.elaborate_in_order(file, true)?;
+    ret.statics.clone()
+  }
+
+  /// Writes the results for the files here to the cache directory, if there is one, and removes the
+  /// other results there.
+  pub fn save(&mut self) -> io::Result<()> {
+    let dir = match &self.cache_dir {
+      Some(x) => x.clone(),
+      None => return Ok(()),
+    };
+    std::fs::create_dir_all(&dir)?;
+    // these may add names to the store, so they go first.
+    let order = self.order();
+    let mut key = self.start().1;
+    let store = self.store();
+    let mut keep = HashSet::new();
+    for f in self.files.values() {
+      let parsed = match &f.parsed {
+        Some(x) => x,
+        None => continue,
+      };
+      if parsed.lex_error.is_none() && parsed.parse_errors.is_empty() {
+        let key = parsed_key(self.features, &f.contents);
+        let mut e = Encoder::new(&store);
+        parsed.comments.encode(&mut e);
+        parsed.top_decs.encode(&mut e);
+        write(&dir, key, e)?;
+        keep.insert(key.file_name());
+      }
+    }
+    // only the results for the current order are kept, since the others can't be used again until
+    // the files before them change back.
+    for file in order.iter() {
+      let f = &self.files[file];
+      key = key.then(&f.contents);
+      let elaborated = match &f.elaborated {
+        Some(x) if x.key == key => x,
+        _ => continue,
+      };
+      let mut e = Encoder::new(&store);
+      elaborated.errors.encode(&mut e);
+      elaborated.fingerprint.encode(&mut e);
+      elaborated.next.encode_delta(&elaborated.input, &mut e);
+      write(&dir, key, e)?;
+      keep.insert(key.file_name());
+    }
+    for entry in std::fs::read_dir(&dir)? {
+      let entry = entry?;
+      let name = entry.file_name();
+      let name = match name.to_str() {
+        Some(x) => x,
+        None => continue,
+      };
+      if Key::is_file_name(name) && !keep.contains(name) {
+        std::fs::remove_file(entry.path())?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Returns the results of elaborating `file` after the files before it, with the statics if
+  /// `need_statics`.
+  fn elaborate_in_order(&mut self, file: &K, need_statics: bool) -> Option<Rc<Elaborated>> {
+    let order = self.order();
+    let idx = order.iter().position(|x| x == file)?;
+    // every file before it is asked for, but those whose input is unchanged are not checked again.
+    let (mut input, mut key) = self.start();
+    for file in order[..idx].iter() {
+      let elaborated = self.elaborate(file, input, key, false);
+      input = Rc::clone(&elaborated.next);
+      key = elaborated.key;
+    }
+    Some(self.elaborate(file, input, key, need_statics))
+  }
+
+  /// Returns the statics after the prelude, and the key for them. A prelude file which fails to lex
+  /// or parse is skipped, since the errors aren't in any of the files.
+  fn start(&mut self) -> (Rc<Statics>, Key) {
+    if let Some((ret, key)) = &self.start {
+      return (Rc::clone(ret), *key);
+    }
+    let mut s = Statics::with_basis(self.basis);
+    let mut key = Key::new(format!("start {:?} {:?}", self.basis, self.features).as_bytes());
+    for bs in self.prelude.iter() {
+      key = key.then(bs);
+      let lexer = match lex::get(&mut self.store, bs) {
+        Ok(x) => x,
+        Err(_) => continue,
+      };
+      let (top_decs, errors) = parse::get_partial(lexer, self.features);
+      if errors.is_empty() {
+        s.add_prelude(&top_decs);
+      }
+    }
+    self.finished = None;
+    let ret = Rc::new(s);
+    self.start = Some((Rc::clone(&ret), key));
+    (ret, key)
+  }
+
+  /// Returns the results of elaborating `file`, which parsed well enough to check, with `input`,
+  /// which has key `input_key`. Unless `need_statics`, the results may be from the cache directory.
+  fn elaborate(
+    &mut self,
+    file: &K,
+    input: Rc<Statics>,
+    input_key: Key,
+    need_statics: bool,
+  ) -> Rc<Elaborated> {
+    let parsed = self.parsed(file).unwrap();
+    let f = self.files.get_mut(file).unwrap();
+    let key = input_key.then(&f.contents);
+    let old = f.elaborated.take();
+    let same_input = old.as_ref().filter(|old| Rc::ptr_eq(&old.input, &input));
+    let same = same_input.filter(|old| Rc::ptr_eq(&old.parsed, &parsed));
+    if let Some(old) = same.filter(|old| !need_statics || old.statics.is_some()) {
+      let ret = Rc::clone(old);
+      f.elaborated = Some(Rc::clone(&ret));
+      return ret;
+    }
+    let cached = if need_statics {
+      None
+    } else {
+      self.cache_dir.as_deref().and_then(|dir| read(dir, key))
+    };
+    if cached.is_some() {
+      self.finished = None;
+    }
+    let store = &mut self.store;
+    let cached = cached.and_then(|bs| decode_elaborated(store, &input, &bs));
+    let (statics, errors, fingerprint, next) = match cached {
+      Some((errors, fingerprint, next)) => (None, errors, fingerprint, next),
+      None => {
+        let mut s = (*input).clone();
+        let errors: Vec<_> = parsed.top_decs.iter().flat_map(|x| s.get(x)).collect();
+        let fingerprint = s.fingerprint(&input);
+        let next = s.next_file();
+        (Some(Rc::new(s)), errors, fingerprint, next)
+      }
+    };
+    // if this binds the same as before, the files after it get the same input as before, and so
+    // needn't be checked again. otherwise they see what this one binds, but the locations are into
+    // this one.
+    let next = match same_input {
+      Some(old) if old.fingerprint == fingerprint => Rc::clone(&old.next),
+      _ => Rc::new(next),
+    };
+    let ret = Rc::new(Elaborated {
+      parsed,
+      input,
+      key,
+      statics,
+      errors,
+      fingerprint,
+      next,
+    });
+    let f = self.files.get_mut(file).unwrap();
+    f.elaborated = Some(Rc::clone(&ret));
+    ret
+  }
+}
+
+/// Identifies what some results were computed from, as a hash of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Key(u128);
+
+impl Key {
+  /// The FNV-1a offset basis and prime for 128 bits.
+  const OFFSET: u128 = 0x6c62272e07bb014262b821756295c58d;
+  const PRIME: u128 = 0x0000000001000000000000000000013b;
+
+  /// Returns the key for `bs`, for this version of this crate and of the entries.
+  fn new(bs: &[u8]) -> Self {
+    let version = format!("{} {}", env!("CARGO_PKG_VERSION"), VERSION);
+    Self(Self::OFFSET).then(version.as_bytes()).then(bs)
+  }
+
+  /// Returns the key for what this identifies followed by `bs`.
+  fn then(self, bs: &[u8]) -> Self {
+    let mut ret = self.0;
+    // the length goes first, so that no two different sequences of calls give the same bytes.
+    for &b in bs.len().to_le_bytes().iter().chain(bs) {
+      ret ^= u128::from(b);
+      ret = ret.wrapping_mul(Self::PRIME);
+    }
+    Self(ret)
+  }
+
+  /// Returns the name of the file in the cache directory for this.
+  fn file_name(self) -> String {
+    format!("{:032x}", self.0)
+  }
+
+  /// Returns whether `name` is the name of a file for a `Key`.
+  fn is_file_name(name: &str) -> bool {
+    name.len() == 32 && name.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+  }
+}
+
+/// Returns the key for the results of lexing and parsing `contents` with `features`.
+fn parsed_key(features: LanguageFeatures, contents: &[u8]) -> Key {
+  Key::new(format!("parsed {:?}", features).as_bytes()).then(contents)
+}
+
+/// Returns the contents of the entry for `key` in `dir`, if there is one.
+fn read(dir: &Path, key: Key) -> Option<Vec<u8>> {
+  let mut ret = std::fs::read(dir.join(key.file_name())).ok()?;
+  if !ret.starts_with(MAGIC) {
+    return None;
+  }
+  ret.drain(..MAGIC.len());
+  Some(ret)
+}
+
+/// Writes what was encoded with `e` as the entry for `key` in `dir`, unless the entry there already
+/// is exactly that. The entry is written to a temporary file which then replaces it, so that no one
+/// reads a partly written entry.
+fn write(dir: &Path, key: Key, e: Encoder<'_>) -> io::Result<()> {
+  let path = dir.join(key.file_name());
+  let mut bs = MAGIC.to_owned();
+  bs.extend(e.finish());
+  if std::fs::read(&path).ok().as_ref() == Some(&bs) {
+    return Ok(());
+  }
+  let tmp = dir.join(format!("{}.{}.tmp", key.file_name(), std::process::id()));
+  if let Err(e) = std::fs::write(&tmp, bs) {
+    let _ = std::fs::remove_file(&tmp);
+    return Err(e);
+  }
+  std::fs::rename(tmp, path)
+}
+
+/// Returns the results of lexing and parsing a file from an entry `bs`, if it is valid.
+fn decode_parsed(store: &mut StrStoreMut, bs: &[u8]) -> Option<Parsed> {
+  let mut d = Decoder::new(bs, store)?;
+  let comments = Vec::decode(&mut d)?;
+  let top_decs = Vec::decode(&mut d)?;
+  if !d.is_done() {
+    return None;
+  }
+  Some(Parsed {
+    lex_error: None,
+    parse_errors: Vec::new(),
+    comments,
+    top_decs,
+  })
+}
+
+/// Returns the errors, fingerprint, and statics for the next file from an entry `bs` for
+/// elaborating a file with `input`, if it is valid.
+fn decode_elaborated(
+  store: &mut StrStoreMut,
+  input: &Statics,
+  bs: &[u8],
+) -> Option<(Vec<Located<statics::Error>>, Fingerprint, Statics)> {
+  let mut d = Decoder::new(bs, store)?;
+  let errors = Vec::decode(&mut d)?;
+  let fingerprint = Fingerprint::decode(&mut d)?;
+  let next = Statics::decode_delta(input, &mut d)?;
+  if !d.is_done() {
+    return None;
+  }
+  Some((errors, fingerprint, next))
+}
+
+#[test]
+fn reuse() {
+  let mut db = Db::new(
+    BasisLevel::Minimal,
+    LanguageFeatures::default(),
+    Vec::new(),
+    None,
+  );
+  db.set_contents("a", b"val x = 3\n");
+  db.set_contents("b", b"val y = x + 1\n");
+  db.set_contents("c", b"val z = y + 1\n");
+  assert_eq!(*db.order(), ["a", "b", "c"]);
+  let c = db.elaborated(&"c").unwrap();
+  assert!(c.errors.is_empty());
+  assert!(Rc::ptr_eq(&c, &db.elaborated(&"c").unwrap()));
+  // b binds the same, so c isn't checked again.
+  db.set_contents("b", b"(* hi *)\nval y = 1 + x\n");
+  let b = db.elaborated(&"b").unwrap();
+  assert!(Rc::ptr_eq(&c, &db.elaborated(&"c").unwrap()));
+  // a doesn't change, so neither does b.
+  db.set_contents("a", b"val x = 3\n");
+  assert!(Rc::ptr_eq(&b, &db.elaborated(&"b").unwrap()));
+  // b binds something else, so c is checked again.
+  db.set_contents("b", b"val y = 1.0\n");
+  let c_again = db.elaborated(&"c").unwrap();
+  assert!(!Rc::ptr_eq(&c, &c_again));
+  assert_eq!(c_again.errors.len(), 1);
+  // a file which doesn't parse is left out.
+  db.set_contents("b", b"val = 3\n");
+  assert!(db.parsed(&"b").unwrap().lex_error.is_none());
+  assert!(db.elaborated(&"b").is_none());
+  assert_eq!(*db.order(), ["a", "c"]);
+}
+
+#[test]
+fn cache_dir() {
+  let dir = std::env::temp_dir().join(format!("millet-db-test-{}", std::process::id()));
+  let new_db = || {
+    let mut db = Db::new(
+      BasisLevel::Minimal,
+      LanguageFeatures::default(),
+      Vec::new(),
+      Some(dir.clone()),
+    );
+    db.set_contents("a", b"datatype t = A of int\n");
+    db.set_contents("b", b"fun f x = A x\n");
+    db.set_contents("c", b"val y = f 1.0\n");
+    db
+  };
+  let entries = || std::fs::read_dir(&dir).unwrap().count();
+  let mut db = new_db();
+  let c = db.elaborated(&"c").unwrap();
+  let store = db.store();
+  let want: Vec<_> = c.errors.iter().map(|e| e.val.message(&store)).collect();
+  assert_eq!(want.len(), 1);
+  db.save().unwrap();
+  assert_eq!(entries(), 6);
+  // another db gets the same results from the cache, without the statics.
+  let mut db = new_db();
+  let c = db.elaborated(&"c").unwrap();
+  assert!(c.statics.is_none());
+  let store = db.store();
+  let got: Vec<_> = c.errors.iter().map(|e| e.val.message(&store)).collect();
+  assert_eq!(want, got);
+  // asking for the statics checks it again, but not the files after it.
+  let b = db.elaborated(&"b").unwrap();
+  assert!(db.statics(&"b").is_some());
+  assert!(!Rc::ptr_eq(&b, &db.elaborated(&"b").unwrap()));
+  assert!(Rc::ptr_eq(&c, &db.elaborated(&"c").unwrap()));
+  // the results for contents no longer here are removed.
+  db.set_contents("b", b"fun f (_ : real) = A 1\n");
+  assert!(db.elaborated(&"c").unwrap().statics.is_some());
+  db.save().unwrap();
+  assert_eq!(entries(), 6);
+  // an entry which isn't valid is not used.
+  for entry in std::fs::read_dir(&dir).unwrap() {
+    std::fs::write(entry.unwrap().path(), b"millet cache\n\x01").unwrap();
+  }
+  let mut db = new_db();
+  assert!(db.elaborated(&"c").unwrap().statics.is_some());
+  // saving replaces them.
+  db.save().unwrap();
+  assert_eq!(entries(), 6);
+  let mut db = new_db();
+  assert!(db.elaborated(&"c").unwrap().statics.is_none());
+  std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,109 @@
+//! Classifying the text of a file for syntax highlighting.
+
+use crate::intern::StrStoreMut;
+use crate::lex;
+use crate::loc::Located;
+use crate::token::{IdentType, Token, ALPHA};
+use std::ops::Range;
+
+/// What kind of thing some text is, for the purposes of highlighting it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+  /// A reserved word which looks like an alphanumeric identifier, like `val`.
+  Keyword,
+  /// Any other reserved word or punctuation, like `=>` or `(`.
+  Punctuation,
+  /// An integer, word, or real constant. Includes numeric labels, like the `1` in `#1`.
+  Number,
+  /// A string or character constant.
+  String,
+  /// A type variable, like `'a`.
+  TyVar,
+  /// The structure name before a `.` in a qualified name, like the `List` in `List.map`.
+  Qualifier,
+  /// Any other alphanumeric identifier.
+  AlphaNumIdent,
+  /// A symbolic identifier, like `+`.
+  SymbolicIdent,
+  /// A comment, including any comments nested in it.
+  Comment,
+}
+
+/// Returns the classes of the tokens and comments in `bs`, in order. Whitespace is not classified.
+/// Since lexing stops at the first error, so does this.
+pub fn get(bs: &[u8]) -> Vec<Located<Class>> {
+  let mut store = StrStoreMut::new();
+  let mut tokens = lex::tokens(&mut store, bs);
+  let mut ts = Vec::new();
+  while let Some(Ok(tok)) = tokens.next() {
+    ts.push(tok);
+  }
+  let mut comments = tokens.comments().iter().copied().peekable();
+  let mut ret = Vec::with_capacity(ts.len());
+  for (idx, tok) in ts.iter().enumerate() {
+    let start = Range::from(tok.loc).start;
+    while let Some(loc) = comments.next_if(|&loc| Range::from(loc).start < start) {
+      ret.push(loc.wrap(Class::Comment));
+    }
+    let next = ts.get(idx + 1).map(|x| x.val);
+    ret.push(tok.loc.wrap(class(tok.val, next)));
+  }
+  ret.extend(comments.map(|loc| loc.wrap(Class::Comment)));
+  ret
+}
+
+/// Returns the class of `tok`, which is followed by `next`.
+fn class(tok: Token, next: Option<Token>) -> Class {
+  match tok {
+    Token::DecInt(..)
+    | Token::HexInt(_)
+    | Token::DecWord(_)
+    | Token::HexWord(_)
+    | Token::Real(..) => Class::Number,
+    Token::String(_) | Token::Char(_) => Class::String,
+    Token::TyVar(_) => Class::TyVar,
+    Token::Ident(_, IdentType::AlphaNum) => {
+      if next == Some(Token::Dot) {
+        Class::Qualifier
+      } else {
+        Class::AlphaNumIdent
+      }
+    }
+    Token::Ident(_, IdentType::Symbolic) => Class::SymbolicIdent,
+    _ => {
+      if ALPHA.iter().any(|&(_, kw)| kw == tok) {
+        Class::Keyword
+      } else {
+        Class::Punctuation
+      }
+    }
+  }
+}
+
+#[test]
+fn test_highlight() {
+  let src = "(* hi *) val x : 'a list = List.rev [#\"c\"] @ [] (* bye";
+  let got: Vec<_> = get(src.as_bytes())
+    .into_iter()
+    .map(|x| (&src[Range::from(x.loc)], x.val))
+    .collect();
+  let want = vec![
+    ("(* hi *)", Class::Comment),
+    ("val", Class::Keyword),
+    ("x", Class::AlphaNumIdent),
+    (":", Class::Punctuation),
+    ("'a", Class::TyVar),
+    ("list", Class::AlphaNumIdent),
+    ("=", Class::Punctuation),
+    ("List", Class::Qualifier),
+    (".", Class::Punctuation),
+    ("rev", Class::AlphaNumIdent),
+    ("[", Class::Punctuation),
+    ("#\"c\"", Class::String),
+    ("]", Class::Punctuation),
+    ("@", Class::SymbolicIdent),
+    ("[", Class::Punctuation),
+    ("]", Class::Punctuation),
+  ];
+  assert_eq!(got, want);
+}
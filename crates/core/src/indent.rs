@@ -0,0 +1,107 @@
+//! Finding what a line beginning with a closing keyword should be indented to match.
+
+use crate::loc::{Loc, Located};
+use crate::token::Token;
+use std::ops::Range;
+
+/// Returns the location of the token in `ts` which the token ending at the byte offset `idx` should
+/// line up with, if that token is `end`, `in`, or `|`. Only the tokens up to `idx` are needed.
+///
+/// - `end` lines up with the `let`, `local`, `struct`, `sig`, or `abstype` it closes.
+/// - `in` lines up with the `let` or `local` it belongs to.
+/// - `|` lines up with the `|` before it in the same match or datatype, or else the `case`, `fn`,
+///   `handle`, `fun`, `datatype`, or `abstype` which begins it.
+///
+/// Returns `None` if there is no such token, as when the opening token has not been written yet.
+pub fn get(ts: &[Located<Token>], idx: usize) -> Option<Loc> {
+  let mut i = 0;
+  let tok = loop {
+    let tok = *ts.get(i)?;
+    if Range::from(tok.loc).end == idx {
+      break tok;
+    }
+    i += 1;
+  };
+  let want: fn(&Token) -> bool = match tok.val {
+    Token::End => |t| is_end_opener(t),
+    Token::In => |t| matches!(t, Token::Let | Token::Local),
+    Token::Bar => |t| {
+      matches!(
+        t,
+        Token::Bar
+          | Token::Case
+          | Token::Fn
+          | Token::Handle
+          | Token::Fun
+          | Token::Datatype
+          | Token::Abstype
+      )
+    },
+    _ => return None,
+  };
+  // walk backwards, skipping over anything balanced, until reaching the wanted token with nothing
+  // left unclosed.
+  let mut depth = 0usize;
+  while i > 0 {
+    i -= 1;
+    let tok = ts[i];
+    if depth == 0 && want(&tok.val) {
+      return Some(tok.loc);
+    }
+    match tok.val {
+      Token::End | Token::RRound | Token::RSquare | Token::RCurly => depth += 1,
+      Token::LRound | Token::LSquare | Token::LCurly => {
+        if depth == 0 {
+          return None;
+        }
+        depth -= 1;
+      }
+      ref t if is_end_opener(t) => {
+        if depth == 0 {
+          return None;
+        }
+        depth -= 1;
+      }
+      _ => {}
+    }
+  }
+  None
+}
+
+/// Returns whether `t` is closed by an `end`.
+fn is_end_opener(t: &Token) -> bool {
+  matches!(
+    t,
+    Token::Let | Token::Local | Token::Struct | Token::Sig | Token::Abstype
+  )
+}
+
+#[test]
+fn test_get() {
+  let src = r#"
+structure S = struct
+  val x =
+    let
+      val f = fn
+        (1, _) => 1
+      | (_, 2) => 2
+      | _ => case (1, 2) of (_, _) => 3
+    in
+      f (3, 4)
+    end
+end
+"#;
+  let mut store = crate::intern::StrStoreMut::new();
+  let ts: Vec<_> = crate::lex::tokens(&mut store, src.as_bytes())
+    .collect::<Result<_, _>>()
+    .unwrap();
+  // the end of the nth occurrence of `s`.
+  let end_of = |s: &str, n: usize| src.match_indices(s).nth(n).unwrap().0 + s.len();
+  let get = |idx| get(&ts, idx).map(|loc| &src[Range::from(loc)]);
+  assert_eq!(get(end_of("|", 0)), Some("fn"));
+  assert_eq!(get(end_of("|", 1)), Some("|"));
+  assert_eq!(get(end_of("in\n", 0) - 1), Some("let"));
+  assert_eq!(get(end_of("end", 0)), Some("let"));
+  assert_eq!(get(end_of("end", 1)), Some("struct"));
+  assert_eq!(get(end_of("val", 0)), None);
+}
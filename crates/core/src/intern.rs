@@ -30,7 +30,7 @@ use std::fmt;
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
 pub struct StrRef(usize);
 
-const SPECIAL_STR_REF: usize = 41;
+const SPECIAL_STR_REF: usize = 236;
 
 impl fmt::Debug for StrRef {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -45,6 +45,11 @@ impl fmt::Debug for StrRef {
 /// New StrRefs should be appended to the bottom. This can help avoid big diffs.
 #[allow(missing_docs)]
 impl StrRef {
+  /// Returns whether this is one of the special `StrRef`s, which are the same in every store.
+  pub fn is_special(self) -> bool {
+    self.0 < SPECIAL_STR_REF
+  }
+
   pub const UNIT: Self = Self(0);
   pub const CHAR: Self = Self(1);
   pub const EXN: Self = Self(2);
@@ -86,9 +91,205 @@ impl StrRef {
   pub const MATCH: Self = Self(38);
   pub const BIND: Self = Self(39);
   pub const ABS: Self = Self(40);
+  pub const VECTOR: Self = Self(41);
+  pub const OPTION_UPPER: Self = Self(42);
+  pub const VAL_OF: Self = Self(43);
+  pub const GET_OPT: Self = Self(44);
+  pub const IS_SOME: Self = Self(45);
+  pub const MAP: Self = Self(46);
+  pub const LIST_UPPER: Self = Self(47);
+  pub const EMPTY: Self = Self(48);
+  pub const SUBSCRIPT: Self = Self(49);
+  pub const NULL: Self = Self(50);
+  pub const LENGTH: Self = Self(51);
+  pub const HD: Self = Self(52);
+  pub const TL: Self = Self(53);
+  pub const REV: Self = Self(54);
+  pub const APP: Self = Self(55);
+  pub const FILTER: Self = Self(56);
+  pub const FOLDL: Self = Self(57);
+  pub const FOLDR: Self = Self(58);
+  pub const NTH: Self = Self(59);
+  pub const EXISTS: Self = Self(60);
+  pub const ALL: Self = Self(61);
+  pub const CONCAT: Self = Self(62);
+  pub const STRING_UPPER: Self = Self(63);
+  pub const SIZE: Self = Self(64);
+  pub const SUB: Self = Self(65);
+  pub const SUBSTRING: Self = Self(66);
+  pub const EXPLODE: Self = Self(67);
+  pub const IMPLODE: Self = Self(68);
+  pub const FIELDS: Self = Self(69);
+  pub const TOKENS: Self = Self(70);
+  pub const COMPARE: Self = Self(71);
+  pub const STR: Self = Self(72);
+  pub const INT_UPPER: Self = Self(73);
+  pub const TO_STRING: Self = Self(74);
+  pub const FROM_STRING: Self = Self(75);
+  pub const MIN: Self = Self(76);
+  pub const MAX: Self = Self(77);
+  pub const MAX_INT: Self = Self(78);
+  pub const MIN_INT: Self = Self(79);
+  pub const DIV_UPPER: Self = Self(80);
+  pub const OVERFLOW: Self = Self(81);
+  pub const REAL_UPPER: Self = Self(82);
+  pub const MATH: Self = Self(83);
+  pub const FROM_INT: Self = Self(84);
+  pub const FLOOR: Self = Self(85);
+  pub const CEIL: Self = Self(86);
+  pub const ROUND: Self = Self(87);
+  pub const TRUNC: Self = Self(88);
+  pub const EQ_EQ: Self = Self(89);
+  pub const PI: Self = Self(90);
+  pub const E: Self = Self(91);
+  pub const SQRT: Self = Self(92);
+  pub const SIN: Self = Self(93);
+  pub const COS: Self = Self(94);
+  pub const TAN: Self = Self(95);
+  pub const ATAN: Self = Self(96);
+  pub const ATAN2: Self = Self(97);
+  pub const EXP: Self = Self(98);
+  pub const LN: Self = Self(99);
+  pub const POW: Self = Self(100);
+  pub const CHAR_UPPER: Self = Self(101);
+  pub const ORD: Self = Self(102);
+  pub const CHR: Self = Self(103);
+  pub const IS_DIGIT: Self = Self(104);
+  pub const IS_ALPHA: Self = Self(105);
+  pub const IS_ALPHA_NUM: Self = Self(106);
+  pub const IS_SPACE: Self = Self(107);
+  pub const IS_UPPER: Self = Self(108);
+  pub const IS_LOWER: Self = Self(109);
+  pub const TO_UPPER: Self = Self(110);
+  pub const TO_LOWER: Self = Self(111);
+  pub const CHR_UPPER: Self = Self(112);
+  pub const BOOL_UPPER: Self = Self(113);
+  pub const GENERAL: Self = Self(114);
+  pub const O: Self = Self(115);
+  pub const BEFORE: Self = Self(116);
+  pub const IGNORE: Self = Self(117);
+  pub const EXN_NAME: Self = Self(118);
+  pub const EXN_MESSAGE: Self = Self(119);
+  pub const FAIL: Self = Self(120);
+  pub const SIZE_UPPER: Self = Self(121);
+  pub const WORD_UPPER: Self = Self(122);
+  pub const WORD8_UPPER: Self = Self(123);
+  pub const WORD8: Self = Self(124);
+  pub const ANDB: Self = Self(125);
+  pub const ORB: Self = Self(126);
+  pub const XORB: Self = Self(127);
+  pub const NOTB: Self = Self(128);
+  pub const LT_LT: Self = Self(129);
+  pub const GT_GT: Self = Self(130);
+  pub const TILDE_GT_GT: Self = Self(131);
+  pub const TO_INT: Self = Self(132);
+  pub const ARRAY: Self = Self(133);
+  pub const ARRAY_UPPER: Self = Self(134);
+  pub const VECTOR_UPPER: Self = Self(135);
+  pub const FROM_LIST: Self = Self(136);
+  pub const UPDATE: Self = Self(137);
+  pub const TABULATE: Self = Self(138);
+  pub const LIST_PAIR: Self = Self(139);
+  pub const ZIP: Self = Self(140);
+  pub const UNZIP: Self = Self(141);
+  pub const UNEQUAL_LENGTHS: Self = Self(142);
+  pub const TEXT_IO: Self = Self(143);
+  pub const INSTREAM: Self = Self(144);
+  pub const OUTSTREAM: Self = Self(145);
+  pub const PRINT: Self = Self(146);
+  pub const OUTPUT: Self = Self(147);
+  pub const STD_IN: Self = Self(148);
+  pub const STD_OUT: Self = Self(149);
+  pub const STD_ERR: Self = Self(150);
+  pub const INPUT_LINE: Self = Self(151);
+  pub const INPUT_ALL: Self = Self(152);
+  pub const OPEN_IN: Self = Self(153);
+  pub const CLOSE_IN: Self = Self(154);
+  pub const OPEN_OUT: Self = Self(155);
+  pub const CLOSE_OUT: Self = Self(156);
+  pub const FLUSH_OUT: Self = Self(157);
+  pub const BANG: Self = Self(158);
+  pub const STRING_CVT: Self = Self(159);
+  pub const RADIX: Self = Self(160);
+  pub const BIN: Self = Self(161);
+  pub const OCT: Self = Self(162);
+  pub const DEC: Self = Self(163);
+  pub const HEX: Self = Self(164);
+  pub const READER: Self = Self(165);
+  pub const CS: Self = Self(166);
+  pub const PAD_LEFT: Self = Self(167);
+  pub const PAD_RIGHT: Self = Self(168);
+  pub const SPLITL: Self = Self(169);
+  pub const SPLITR: Self = Self(170);
+  pub const TAKEL: Self = Self(171);
+  pub const TAKER: Self = Self(172);
+  pub const DROPL: Self = Self(173);
+  pub const DROPR: Self = Self(174);
+  pub const SKIP_WS: Self = Self(175);
+  pub const SCAN_STRING: Self = Self(176);
+  pub const SUBSTRING_UPPER: Self = Self(177);
+  pub const FULL: Self = Self(178);
+  pub const SLICE: Self = Self(179);
+  pub const EXTRACT: Self = Self(180);
+  pub const GETC: Self = Self(181);
+  pub const FIRST: Self = Self(182);
+  pub const IS_EMPTY: Self = Self(183);
+  pub const TRIML: Self = Self(184);
+  pub const TRIMR: Self = Self(185);
+  pub const SCAN: Self = Self(186);
+  pub const INT_INF_UPPER: Self = Self(187);
+  pub const TO_LARGE: Self = Self(188);
+  pub const FROM_LARGE: Self = Self(189);
+  pub const INT_INF: Self = Self(190);
+  pub const OS: Self = Self(191);
+  pub const PROCESS: Self = Self(192);
+  pub const STATUS: Self = Self(193);
+  pub const EXIT: Self = Self(194);
+  pub const SUCCESS: Self = Self(195);
+  pub const FAILURE: Self = Self(196);
+  pub const IS_SUCCESS: Self = Self(197);
+  pub const COMMAND_LINE: Self = Self(198);
+  pub const NAME: Self = Self(199);
+  pub const ARGUMENTS: Self = Self(200);
+  pub const TIME_UPPER: Self = Self(201);
+  pub const TIME: Self = Self(202);
+  pub const NOW: Self = Self(203);
+  pub const TO_REAL: Self = Self(204);
+  pub const FROM_REAL: Self = Self(205);
+  pub const TO_SECONDS: Self = Self(206);
+  pub const FROM_SECONDS: Self = Self(207);
+  pub const TO_MILLISECONDS: Self = Self(208);
+  pub const FROM_MILLISECONDS: Self = Self(209);
+  pub const TIMER: Self = Self(210);
+  pub const CPU_TIMER: Self = Self(211);
+  pub const REAL_TIMER: Self = Self(212);
+  pub const START_CPU_TIMER: Self = Self(213);
+  pub const START_REAL_TIMER: Self = Self(214);
+  pub const CHECK_CPU_TIMER: Self = Self(215);
+  pub const CHECK_REAL_TIMER: Self = Self(216);
+  pub const USR: Self = Self(217);
+  pub const SYS: Self = Self(218);
+  pub const BYTE: Self = Self(219);
+  pub const WORD8_VECTOR: Self = Self(220);
+  pub const WORD8_ARRAY: Self = Self(221);
+  pub const BYTES_TO_STRING: Self = Self(222);
+  pub const STRING_TO_BYTES: Self = Self(223);
+  pub const BYTE_TO_CHAR: Self = Self(224);
+  pub const CHAR_TO_BYTE: Self = Self(225);
+  pub const MONO_VECTOR: Self = Self(226);
+  pub const MONO_ARRAY: Self = Self(227);
+  pub const CHAR_VECTOR: Self = Self(228);
+  pub const CHAR_ARRAY: Self = Self(229);
+  pub const REAL_VECTOR: Self = Self(230);
+  pub const REAL_ARRAY: Self = Self(231);
+  pub const ELEM: Self = Self(232);
+  pub const ZERO_TIME: Self = Self(233);
+  pub const WORD_CONSTANT: Self = Self(234);
+  pub const INT_CONSTANT: Self = Self(235);
 }
 
 /// A mutable factory of StrRefs. Allows creating new StrRefs from Strings.
+#[derive(Clone)]
 pub struct StrStoreMut {
   store: HashMap<String, StrRef>,
   next: usize,
@@ -141,6 +342,201 @@ impl StrStoreMut {
       s("Match") => StrRef::MATCH,
       s("Bind") => StrRef::BIND,
       s("abs") => StrRef::ABS,
+      s("vector") => StrRef::VECTOR,
+      s("Option") => StrRef::OPTION_UPPER,
+      s("valOf") => StrRef::VAL_OF,
+      s("getOpt") => StrRef::GET_OPT,
+      s("isSome") => StrRef::IS_SOME,
+      s("map") => StrRef::MAP,
+      s("List") => StrRef::LIST_UPPER,
+      s("Empty") => StrRef::EMPTY,
+      s("Subscript") => StrRef::SUBSCRIPT,
+      s("null") => StrRef::NULL,
+      s("length") => StrRef::LENGTH,
+      s("hd") => StrRef::HD,
+      s("tl") => StrRef::TL,
+      s("rev") => StrRef::REV,
+      s("app") => StrRef::APP,
+      s("filter") => StrRef::FILTER,
+      s("foldl") => StrRef::FOLDL,
+      s("foldr") => StrRef::FOLDR,
+      s("nth") => StrRef::NTH,
+      s("exists") => StrRef::EXISTS,
+      s("all") => StrRef::ALL,
+      s("concat") => StrRef::CONCAT,
+      s("String") => StrRef::STRING_UPPER,
+      s("size") => StrRef::SIZE,
+      s("sub") => StrRef::SUB,
+      s("substring") => StrRef::SUBSTRING,
+      s("explode") => StrRef::EXPLODE,
+      s("implode") => StrRef::IMPLODE,
+      s("fields") => StrRef::FIELDS,
+      s("tokens") => StrRef::TOKENS,
+      s("compare") => StrRef::COMPARE,
+      s("str") => StrRef::STR,
+      s("Int") => StrRef::INT_UPPER,
+      s("toString") => StrRef::TO_STRING,
+      s("fromString") => StrRef::FROM_STRING,
+      s("min") => StrRef::MIN,
+      s("max") => StrRef::MAX,
+      s("maxInt") => StrRef::MAX_INT,
+      s("minInt") => StrRef::MIN_INT,
+      s("Div") => StrRef::DIV_UPPER,
+      s("Overflow") => StrRef::OVERFLOW,
+      s("Real") => StrRef::REAL_UPPER,
+      s("Math") => StrRef::MATH,
+      s("fromInt") => StrRef::FROM_INT,
+      s("floor") => StrRef::FLOOR,
+      s("ceil") => StrRef::CEIL,
+      s("round") => StrRef::ROUND,
+      s("trunc") => StrRef::TRUNC,
+      s("==") => StrRef::EQ_EQ,
+      s("pi") => StrRef::PI,
+      s("e") => StrRef::E,
+      s("sqrt") => StrRef::SQRT,
+      s("sin") => StrRef::SIN,
+      s("cos") => StrRef::COS,
+      s("tan") => StrRef::TAN,
+      s("atan") => StrRef::ATAN,
+      s("atan2") => StrRef::ATAN2,
+      s("exp") => StrRef::EXP,
+      s("ln") => StrRef::LN,
+      s("pow") => StrRef::POW,
+      s("Char") => StrRef::CHAR_UPPER,
+      s("ord") => StrRef::ORD,
+      s("chr") => StrRef::CHR,
+      s("isDigit") => StrRef::IS_DIGIT,
+      s("isAlpha") => StrRef::IS_ALPHA,
+      s("isAlphaNum") => StrRef::IS_ALPHA_NUM,
+      s("isSpace") => StrRef::IS_SPACE,
+      s("isUpper") => StrRef::IS_UPPER,
+      s("isLower") => StrRef::IS_LOWER,
+      s("toUpper") => StrRef::TO_UPPER,
+      s("toLower") => StrRef::TO_LOWER,
+      s("Chr") => StrRef::CHR_UPPER,
+      s("Bool") => StrRef::BOOL_UPPER,
+      s("General") => StrRef::GENERAL,
+      s("o") => StrRef::O,
+      s("before") => StrRef::BEFORE,
+      s("ignore") => StrRef::IGNORE,
+      s("exnName") => StrRef::EXN_NAME,
+      s("exnMessage") => StrRef::EXN_MESSAGE,
+      s("Fail") => StrRef::FAIL,
+      s("Size") => StrRef::SIZE_UPPER,
+      s("Word") => StrRef::WORD_UPPER,
+      s("Word8") => StrRef::WORD8_UPPER,
+      s("word8") => StrRef::WORD8,
+      s("andb") => StrRef::ANDB,
+      s("orb") => StrRef::ORB,
+      s("xorb") => StrRef::XORB,
+      s("notb") => StrRef::NOTB,
+      s("<<") => StrRef::LT_LT,
+      s(">>") => StrRef::GT_GT,
+      s("~>>") => StrRef::TILDE_GT_GT,
+      s("toInt") => StrRef::TO_INT,
+      s("array") => StrRef::ARRAY,
+      s("Array") => StrRef::ARRAY_UPPER,
+      s("Vector") => StrRef::VECTOR_UPPER,
+      s("fromList") => StrRef::FROM_LIST,
+      s("update") => StrRef::UPDATE,
+      s("tabulate") => StrRef::TABULATE,
+      s("ListPair") => StrRef::LIST_PAIR,
+      s("zip") => StrRef::ZIP,
+      s("unzip") => StrRef::UNZIP,
+      s("UnequalLengths") => StrRef::UNEQUAL_LENGTHS,
+      s("TextIO") => StrRef::TEXT_IO,
+      s("instream") => StrRef::INSTREAM,
+      s("outstream") => StrRef::OUTSTREAM,
+      s("print") => StrRef::PRINT,
+      s("output") => StrRef::OUTPUT,
+      s("stdIn") => StrRef::STD_IN,
+      s("stdOut") => StrRef::STD_OUT,
+      s("stdErr") => StrRef::STD_ERR,
+      s("inputLine") => StrRef::INPUT_LINE,
+      s("inputAll") => StrRef::INPUT_ALL,
+      s("openIn") => StrRef::OPEN_IN,
+      s("closeIn") => StrRef::CLOSE_IN,
+      s("openOut") => StrRef::OPEN_OUT,
+      s("closeOut") => StrRef::CLOSE_OUT,
+      s("flushOut") => StrRef::FLUSH_OUT,
+      s("!") => StrRef::BANG,
+      s("StringCvt") => StrRef::STRING_CVT,
+      s("radix") => StrRef::RADIX,
+      s("BIN") => StrRef::BIN,
+      s("OCT") => StrRef::OCT,
+      s("DEC") => StrRef::DEC,
+      s("HEX") => StrRef::HEX,
+      s("reader") => StrRef::READER,
+      s("cs") => StrRef::CS,
+      s("padLeft") => StrRef::PAD_LEFT,
+      s("padRight") => StrRef::PAD_RIGHT,
+      s("splitl") => StrRef::SPLITL,
+      s("splitr") => StrRef::SPLITR,
+      s("takel") => StrRef::TAKEL,
+      s("taker") => StrRef::TAKER,
+      s("dropl") => StrRef::DROPL,
+      s("dropr") => StrRef::DROPR,
+      s("skipWS") => StrRef::SKIP_WS,
+      s("scanString") => StrRef::SCAN_STRING,
+      s("Substring") => StrRef::SUBSTRING_UPPER,
+      s("full") => StrRef::FULL,
+      s("slice") => StrRef::SLICE,
+      s("extract") => StrRef::EXTRACT,
+      s("getc") => StrRef::GETC,
+      s("first") => StrRef::FIRST,
+      s("isEmpty") => StrRef::IS_EMPTY,
+      s("triml") => StrRef::TRIML,
+      s("trimr") => StrRef::TRIMR,
+      s("scan") => StrRef::SCAN,
+      s("IntInf") => StrRef::INT_INF_UPPER,
+      s("toLarge") => StrRef::TO_LARGE,
+      s("fromLarge") => StrRef::FROM_LARGE,
+      s("intinf") => StrRef::INT_INF,
+      s("OS") => StrRef::OS,
+      s("Process") => StrRef::PROCESS,
+      s("status") => StrRef::STATUS,
+      s("exit") => StrRef::EXIT,
+      s("success") => StrRef::SUCCESS,
+      s("failure") => StrRef::FAILURE,
+      s("isSuccess") => StrRef::IS_SUCCESS,
+      s("CommandLine") => StrRef::COMMAND_LINE,
+      s("name") => StrRef::NAME,
+      s("arguments") => StrRef::ARGUMENTS,
+      s("Time") => StrRef::TIME_UPPER,
+      s("time") => StrRef::TIME,
+      s("now") => StrRef::NOW,
+      s("toReal") => StrRef::TO_REAL,
+      s("fromReal") => StrRef::FROM_REAL,
+      s("toSeconds") => StrRef::TO_SECONDS,
+      s("fromSeconds") => StrRef::FROM_SECONDS,
+      s("toMilliseconds") => StrRef::TO_MILLISECONDS,
+      s("fromMilliseconds") => StrRef::FROM_MILLISECONDS,
+      s("Timer") => StrRef::TIMER,
+      s("cpu_timer") => StrRef::CPU_TIMER,
+      s("real_timer") => StrRef::REAL_TIMER,
+      s("startCPUTimer") => StrRef::START_CPU_TIMER,
+      s("startRealTimer") => StrRef::START_REAL_TIMER,
+      s("checkCPUTimer") => StrRef::CHECK_CPU_TIMER,
+      s("checkRealTimer") => StrRef::CHECK_REAL_TIMER,
+      s("usr") => StrRef::USR,
+      s("sys") => StrRef::SYS,
+      s("Byte") => StrRef::BYTE,
+      s("Word8Vector") => StrRef::WORD8_VECTOR,
+      s("Word8Array") => StrRef::WORD8_ARRAY,
+      s("bytesToString") => StrRef::BYTES_TO_STRING,
+      s("stringToBytes") => StrRef::STRING_TO_BYTES,
+      s("byteToChar") => StrRef::BYTE_TO_CHAR,
+      s("charToByte") => StrRef::CHAR_TO_BYTE,
+      s("MONO_VECTOR") => StrRef::MONO_VECTOR,
+      s("MONO_ARRAY") => StrRef::MONO_ARRAY,
+      s("CharVector") => StrRef::CHAR_VECTOR,
+      s("CharArray") => StrRef::CHAR_ARRAY,
+      s("RealVector") => StrRef::REAL_VECTOR,
+      s("RealArray") => StrRef::REAL_ARRAY,
+      s("elem") => StrRef::ELEM,
+      s("zeroTime") => StrRef::ZERO_TIME,
+      s("word constant") => StrRef::WORD_CONSTANT,
+      s("int constant") => StrRef::INT_CONSTANT,
     ];
     assert_eq!(store.len(), SPECIAL_STR_REF);
     Self {
@@ -162,14 +558,13 @@ impl StrStoreMut {
 
   /// Converts this StrStoreMut into an StrStore, preventing further mutation.
   pub fn finish(self) -> StrStore {
-    let mut store = vec![String::new(); self.store.len()];
+    let mut store = vec![None; self.store.len()];
     for (s, id) in self.store {
       // each index should be assigned exactly once, based on the way we handed out StrRefs.
-      store[id.0] = s;
-    }
-    for s in store.iter() {
-      assert!(!s.is_empty());
+      store[id.0] = Some(s);
     }
+    // the empty string may be interned (from `""`), so check for missing strings with `Option`.
+    let store = store.into_iter().map(Option::unwrap).collect();
     StrStore { store }
   }
 }
@@ -188,4 +583,38 @@ impl StrStore {
       .expect("gave a StrStore a StrRef that didn't come from its StrStoreMut")
       .as_str()
   }
+
+  /// Returns the pretty-printed Debug representation of `val`, with each StrRef in it replaced by
+  /// the (Debug-quoted) string it references.
+  pub fn debug<T: fmt::Debug>(&self, val: &T) -> String {
+    let s = format!("{:#?}", val);
+    let mut ret = String::with_capacity(s.len());
+    let mut rest = s.as_str();
+    while let Some(idx) = rest.find("StrRef(") {
+      ret.push_str(&rest[..idx]);
+      rest = &rest[idx + "StrRef(".len()..];
+      let end = rest.find(')').unwrap();
+      let (offset, n) = match rest[..end].split_once(": ") {
+        Some(("special", n)) => (0, n),
+        Some(("regular", n)) => (SPECIAL_STR_REF, n),
+        _ => unreachable!("bad StrRef Debug"),
+      };
+      let id = StrRef(offset + n.parse::<usize>().unwrap());
+      ret.push_str(&format!("{:?}", self.get(id)));
+      rest = &rest[end + 1..];
+    }
+    ret.push_str(rest);
+    ret
+  }
+}
+
+#[test]
+fn test_debug() {
+  let mut store = StrStoreMut::new();
+  let foo = store.insert("foo".into());
+  let store = store.finish();
+  assert_eq!(
+    store.debug(&(StrRef::INT, Some(foo))),
+    "(\n    \"int\",\n    Some(\n        \"foo\",\n    ),\n)"
+  );
 }
@@ -3,20 +3,77 @@
 use crate::intern::{StrRef, StrStoreMut};
 use crate::loc::{Loc, Located};
 use crate::token::{IdentType, IsNumLab, Token, TyVar, ALPHA, OTHER, SYMBOLIC};
+use std::convert::TryFrom;
+use std::ops::Range;
 
 /// Transform a sequence of bytes into a sequence of tokens.
 pub fn get(store: &mut StrStoreMut, bs: &[u8]) -> Result<Lexer, Located<Error>> {
-  Ok(Lexer::new(TokenMaker::new(store, bs).build()?))
+  TokenMaker::new(store, bs).build()
+}
+
+/// Returns an iterator over the tokens in a sequence of bytes, which lexes only as much of the
+/// input as is asked for. Nothing is yielded after the first error.
+pub fn tokens<'s>(store: &'s mut StrStoreMut, bs: &'s [u8]) -> Tokens<'s> {
+  Tokens(TokenMaker::new(store, bs))
+}
+
+/// An iterator over tokens, as returned by `tokens`.
+pub struct Tokens<'s>(TokenMaker<'s>);
+
+impl Tokens<'_> {
+  /// Returns the locations of the comments lexed so far, in order. As with `Lexer::comments`,
+  /// comments nested in other comments are not included.
+  pub fn comments(&self) -> &[Loc] {
+    &self.0.comment_locs
+  }
+}
+
+impl Iterator for Tokens<'_> {
+  type Item = Result<Located<Token>, Located<Error>>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.0.next_tok()
+  }
 }
 
 /// A sequence of tokens.
 pub struct Lexer {
   ts: Vec<Located<Token>>,
+  trivia: Vec<Located<Trivia>>,
+}
+
+/// Something between tokens, which has no meaning to the rest of the implementation but which tools
+/// like a formatter must preserve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trivia {
+  /// A maximal run of whitespace.
+  Whitespace,
+  /// A comment, including any comments nested in it.
+  Comment,
+  /// A first line starting with `#!`, which names the interpreter of a script, as in
+  /// `#!/usr/bin/env sml`. Does not include the newline ending it.
+  Shebang,
 }
 
 impl Lexer {
-  fn new(ts: Vec<Located<Token>>) -> Self {
-    Self { ts }
+  fn new(ts: Vec<Located<Token>>, trivia: Vec<Located<Trivia>>) -> Self {
+    Self { ts, trivia }
+  }
+
+  /// Returns the trivia, in order. Together with the tokens, these cover the whole input.
+  pub fn trivia(&self) -> &[Located<Trivia>] {
+    &self.trivia
+  }
+
+  /// Returns the locations of the comments, in order. Comments nested in other comments are not
+  /// included, since they are part of the comment containing them.
+  pub fn comments(&self) -> Vec<Loc> {
+    self
+      .trivia
+      .iter()
+      .filter(|x| x.val == Trivia::Comment)
+      .map(|x| x.loc)
+      .collect()
   }
 
   /// Gets the ith token. Never returns `Some(EOF)`.
@@ -38,16 +95,41 @@ pub enum Error {
   UnmatchedOpenComment,
   IncompleteTyVar,
   UnknownByte(u8),
-  InvalidIntConstant(std::num::ParseIntError),
-  InvalidRealConstant(std::num::ParseFloatError),
+  IntConstantOutOfRange,
+  InvalidRealConstant,
   NegativeWordConstant,
   IncompleteNumConstant,
   UnclosedStringConstant,
   InvalidStringConstant,
   InvalidCharConstant,
+  WordConstantOutOfRange,
+  CharConstantOutOfRange,
+  NonAsciiCharConstant,
+  NewlineInStringConstant,
 }
 
 impl Error {
+  /// A stable numeric code identifying the kind of this error. See `doc/errors.md`.
+  pub fn code(&self) -> u16 {
+    match self {
+      Self::UnmatchedCloseComment => 1001,
+      Self::UnmatchedOpenComment => 1002,
+      Self::IncompleteTyVar => 1003,
+      Self::UnknownByte(_) => 1004,
+      Self::IntConstantOutOfRange => 1005,
+      Self::InvalidRealConstant => 1006,
+      Self::NegativeWordConstant => 1007,
+      Self::IncompleteNumConstant => 1008,
+      Self::UnclosedStringConstant => 1009,
+      Self::InvalidStringConstant => 1010,
+      Self::InvalidCharConstant => 1011,
+      Self::WordConstantOutOfRange => 1012,
+      Self::CharConstantOutOfRange => 1013,
+      Self::NonAsciiCharConstant => 1014,
+      Self::NewlineInStringConstant => 1015,
+    }
+  }
+
   /// A human-readable message describing this error.
   pub fn message(&self) -> String {
     match self {
@@ -55,38 +137,72 @@ impl Error {
       Self::UnmatchedOpenComment => "unmatched open comment".to_owned(),
       Self::IncompleteTyVar => "incomplete type variable".to_owned(),
       Self::UnknownByte(b) => format!("unknown byte: {:#x}", b),
-      Self::InvalidIntConstant(e) => format!("invalid integer constant: {}", e),
-      Self::InvalidRealConstant(e) => format!("invalid real constant: {}", e),
+      Self::IntConstantOutOfRange => {
+        format!(
+          "integer constant out of range: must be between {} and {}",
+          i32::MIN,
+          i32::MAX
+        )
+      }
+      Self::InvalidRealConstant => "invalid real constant".to_owned(),
       Self::NegativeWordConstant => "negative word constant".to_owned(),
       Self::IncompleteNumConstant => "incomplete numeric constant".to_owned(),
       Self::UnclosedStringConstant => "unclosed string constant".to_owned(),
       Self::InvalidStringConstant => "invalid string constant".to_owned(),
       Self::InvalidCharConstant => "invalid character constant".to_owned(),
+      Self::WordConstantOutOfRange => {
+        format!("word constant out of range: must be at most {}", u32::MAX)
+      }
+      Self::CharConstantOutOfRange => {
+        "character escape out of range: must denote a character between 0 and 255".to_owned()
+      }
+      Self::NonAsciiCharConstant => {
+        "non-ASCII character in character constant: use an escape instead".to_owned()
+      }
+      Self::NewlineInStringConstant => {
+        "string constant contains an unescaped newline: use `\\` line continuations".to_owned()
+      }
     }
   }
 }
 
-impl From<std::num::ParseIntError> for Error {
-  fn from(val: std::num::ParseIntError) -> Self {
-    Self::InvalidIntConstant(val)
-  }
-}
-
-impl From<std::num::ParseFloatError> for Error {
-  fn from(val: std::num::ParseFloatError) -> Self {
-    Self::InvalidRealConstant(val)
-  }
-}
-
 struct TokenMaker<'s> {
   store: &'s mut StrStoreMut,
   bs: &'s [u8],
   i: usize,
+  /// Where the error from `next_impl` is, if that is more specific than the whole token.
+  err_loc: Option<Loc>,
+  /// How many comments deep we are.
+  comments: usize,
+  /// The start of the outermost comment we are in, if any.
+  comment_start: usize,
+  /// The outermost comments so far.
+  comment_locs: Vec<Loc>,
+  /// The shebang line, if there was one.
+  shebang: Option<Loc>,
+  /// Whether there can be no more tokens, because of the end of the input or an error.
+  done: bool,
 }
 
 impl<'s> TokenMaker<'s> {
   fn new(store: &'s mut StrStoreMut, bs: &'s [u8]) -> Self {
-    Self { store, bs, i: 0 }
+    let shebang = if bs.starts_with(b"#!") {
+      let end = bs.iter().position(|&b| b == b'\n').unwrap_or(bs.len());
+      Some(Loc::new(0, end))
+    } else {
+      None
+    };
+    Self {
+      store,
+      bs,
+      i: shebang.map_or(0, |loc| Range::from(loc).end),
+      err_loc: None,
+      comments: 0,
+      comment_start: 0,
+      comment_locs: Vec::new(),
+      shebang,
+      done: false,
+    }
   }
 
   fn mk_str_ref(&mut self, bs: &[u8]) -> StrRef {
@@ -94,9 +210,30 @@ impl<'s> TokenMaker<'s> {
     self.store.insert(s.into())
   }
 
-  fn build(mut self) -> Result<Vec<Located<Token>>, Located<Error>> {
-    let mut comments: usize = 0;
+  fn build(mut self) -> Result<Lexer, Located<Error>> {
     let mut ret = Vec::new();
+    while let Some(tok) = self.next_tok() {
+      ret.push(tok?);
+    }
+    ret.shrink_to_fit();
+    let comments = std::mem::take(&mut self.comment_locs);
+    let trivia = mk_trivia(self.bs.len(), &ret, self.shebang, comments);
+    Ok(Lexer::new(ret, trivia))
+  }
+
+  /// Returns the next token, or `None` if there can be no more tokens.
+  fn next_tok(&mut self) -> Option<Result<Located<Token>, Located<Error>>> {
+    if self.done {
+      return None;
+    }
+    let ret = self.next_tok_impl();
+    if !matches!(ret, Some(Ok(_))) {
+      self.done = true;
+    }
+    ret
+  }
+
+  fn next_tok_impl(&mut self) -> Option<Result<Located<Token>, Located<Error>>> {
     while let Some(&b) = self.bs.get(self.i) {
       // newline
       if b == b'\n' {
@@ -105,21 +242,29 @@ impl<'s> TokenMaker<'s> {
       }
       // comment start
       if b == b'(' && self.bs.get(self.i + 1) == Some(&b'*') {
+        if self.comments == 0 {
+          self.comment_start = self.i;
+        }
         self.i += 2;
-        comments += 1;
+        self.comments += 1;
         continue;
       }
       // comment end
       if b == b'*' && self.bs.get(self.i + 1) == Some(&b')') {
-        if comments == 0 {
-          return Err(Loc::new(self.i, self.i + 2).wrap(Error::UnmatchedCloseComment));
+        if self.comments == 0 {
+          let loc = Loc::new(self.i, self.i + 2);
+          return Some(Err(loc.wrap(Error::UnmatchedCloseComment)));
         }
         self.i += 2;
-        comments -= 1;
+        self.comments -= 1;
+        if self.comments == 0 {
+          let loc = Loc::new(self.comment_start, self.i);
+          self.comment_locs.push(loc);
+        }
         continue;
       }
       // inside comment or formatting
-      if comments != 0 || is_formatting(b) {
+      if self.comments != 0 || is_formatting(b) {
         self.i += 1;
         continue;
       }
@@ -128,19 +273,136 @@ impl<'s> TokenMaker<'s> {
       let tok = self.next_impl(b);
       let end = self.i;
       let loc = Loc::new(start, end);
-      match tok {
-        Ok(tok) => ret.push(loc.wrap(tok)),
-        Err(err) => return Err(loc.wrap(err)),
-      }
+      return Some(match tok {
+        Ok(tok) => Ok(loc.wrap(tok)),
+        Err(err) => Err(self.err_loc.take().unwrap_or(loc).wrap(err)),
+      });
     }
-    if comments == 0 {
-      ret.shrink_to_fit();
-      Ok(ret)
+    if self.comments == 0 {
+      None
     } else {
-      Err(Loc::new(self.i - 3, self.i - 1).wrap(Error::UnmatchedOpenComment))
+      // the outermost comment is the one which runs to the end of the input.
+      let loc = Loc::new(self.comment_start, self.comment_start + 2);
+      Some(Err(loc.wrap(Error::UnmatchedOpenComment)))
     }
   }
 
+  /// `start` is the start of the real constant ending at the current index, after its leading `~`
+  /// if `neg`.
+  fn mk_real(&mut self, neg: bool, start: usize) -> Result<Token, Error> {
+    // the only difference from the syntax Rust accepts is the `~` of a negative exponent.
+    let bs = &self.bs[start..self.i];
+    let n = std::str::from_utf8(bs).unwrap().replace('~', "-");
+    let n: f64 = n.parse().map_err(|_| Error::InvalidRealConstant)?;
+    let text = if neg { start - 1 } else { start };
+    let text = self.mk_str_ref(&self.bs[text..self.i]);
+    Ok(Token::Real(if neg { -n } else { n }, text))
+  }
+
+  /// Handles the escape sequence beginning with the `\` at the current index in a string or
+  /// character constant, returning the character it denotes, if any. Afterwards, the current index
+  /// is at the last byte of the escape sequence, or just past it if it was invalid.
+  fn escape(&mut self) -> Result<Option<u8>, Error> {
+    self.i += 1;
+    let b = match self.bs.get(self.i) {
+      None => return Err(Error::UnclosedStringConstant),
+      Some(x) => *x,
+    };
+    let ret = match b {
+      b'a' => Some(7),
+      b'b' => Some(8),
+      b't' => Some(9),
+      b'n' => Some(10),
+      b'v' => Some(11),
+      b'f' => Some(12),
+      b'r' => Some(13),
+      b'"' => Some(b'"'),
+      b'\\' => Some(b'\\'),
+      b'^' => {
+        self.i += 1;
+        let b = match self.bs.get(self.i) {
+          None => return Err(Error::UnclosedStringConstant),
+          Some(x) => *x,
+        };
+        // SML Definition section 2.2: \^c is valid for 64 <= ord c <= 95.
+        if !matches!(b, 64..=95) {
+          self.i += 1;
+          return Err(Error::CharConstantOutOfRange);
+        }
+        Some(b - 64)
+      }
+      b'u' => {
+        if self.i + 4 >= self.bs.len() {
+          return Err(Error::UnclosedStringConstant);
+        }
+        match (
+          hex(self.bs[self.i + 1]),
+          hex(self.bs[self.i + 2]),
+          hex(self.bs[self.i + 3]),
+          hex(self.bs[self.i + 4]),
+        ) {
+          (Some(0), Some(0), Some(d1), Some(d2)) => {
+            self.i += 4;
+            Some(d1 * 16 + d2)
+          }
+          (Some(_), Some(_), Some(_), Some(_)) => {
+            self.i += 5;
+            return Err(Error::CharConstantOutOfRange);
+          }
+          _ => {
+            self.i += 1;
+            return Err(Error::InvalidStringConstant);
+          }
+        }
+      }
+      b => {
+        if let Some(d1) = dec(b) {
+          if self.i + 2 >= self.bs.len() {
+            return Err(Error::UnclosedStringConstant);
+          }
+          match (dec(self.bs[self.i + 1]), dec(self.bs[self.i + 2])) {
+            (Some(d2), Some(d3)) => {
+              self.i += 2;
+              let n = (u16::from(d1) * 10 + u16::from(d2)) * 10 + u16::from(d3);
+              match u8::try_from(n) {
+                Ok(n) => Some(n),
+                Err(_) => {
+                  self.i += 1;
+                  return Err(Error::CharConstantOutOfRange);
+                }
+              }
+            }
+            _ => {
+              self.i += 1;
+              return Err(Error::InvalidStringConstant);
+            }
+          }
+        } else if is_formatting(b) {
+          // a gap, `\f...f\`, which denotes nothing.
+          loop {
+            self.i += 1;
+            let b = match self.bs.get(self.i) {
+              None => return Err(Error::UnclosedStringConstant),
+              Some(x) => *x,
+            };
+            if b == b'\\' {
+              break;
+            }
+            if !is_formatting(b) {
+              self.i += 1;
+              return Err(Error::InvalidStringConstant);
+            }
+          }
+          None
+        } else {
+          self.i += 1;
+          return Err(Error::InvalidStringConstant);
+        }
+      }
+    };
+    Ok(ret)
+  }
+
   fn next_impl(&mut self, b: u8) -> Result<Token, Error> {
     // alphanumeric identifiers (including type variables) and alphabetic reserved words
     match alpha_num(b) {
@@ -237,18 +499,19 @@ impl<'s> TokenMaker<'s> {
           return if b == b'x' {
             // hex word
             self.i += 1;
-            self.pos_hex_int().map(Token::HexWord)
+            let digits = self.hex_digits()?;
+            word(digits, 16).map(Token::HexWord)
           } else {
             // decimal word
-            self.pos_dec_int().map(Token::DecWord)
+            let digits = self.dec_digits()?;
+            word(digits, 10).map(Token::DecWord)
           };
         }
         // hex integer
         if b == b'x' {
           self.i += 2;
-          let n = self.pos_hex_int()?;
-          let n = if neg { -n } else { n };
-          return Ok(Token::HexInt(n));
+          let digits = self.hex_digits()?;
+          return int(digits, 16, neg).map(Token::HexInt);
         }
         // at this point, we've just seen '0', we know there are more bytes after the '0', and the
         // first byte after the '0' is neither 'w' nor 'x'. then this is the beginning of either a
@@ -257,29 +520,24 @@ impl<'s> TokenMaker<'s> {
       } else {
         false
       };
-      let n = self.pos_dec_int()?;
-      let n = if neg { -n } else { n };
+      let start = self.i;
+      let digits = self.dec_digits()?;
       match self.bs.get(self.i) {
-        None => return Ok(mk_int(n, starts_with_zero)),
         Some(&b'.') => {
           // no advance, to fulfill requires of real_after_dec
-          let after_dec = self.real_after_dec()?;
-          match self.bs.get(self.i) {
-            None => return mk_real(n, after_dec, 0),
-            Some(&b'e') | Some(&b'E') => {
-              self.i += 1;
-              let exp = self.real_exp()?;
-              return mk_real(n, after_dec, exp);
-            }
-            Some(_) => return mk_real(n, after_dec, 0),
+          self.real_after_dec()?;
+          if let Some(&b'e') | Some(&b'E') = self.bs.get(self.i) {
+            self.i += 1;
+            self.real_exp()?;
           }
+          return self.mk_real(neg, start);
         }
         Some(&b'e') | Some(&b'E') => {
           self.i += 1;
-          let exp = self.real_exp()?;
-          return mk_real(n, 0.0, exp);
+          self.real_exp()?;
+          return self.mk_real(neg, start);
         }
-        Some(_) => return Ok(mk_int(n, starts_with_zero)),
+        None | Some(_) => return int(digits, 10, neg).map(|n| mk_int(n, starts_with_zero)),
       }
     }
     // character constant
@@ -292,100 +550,60 @@ impl<'s> TokenMaker<'s> {
     // string constants
     if b == b'"' {
       self.i += 1;
+      // the UTF-8 of the characters so far, and whether any were written literally as non-ASCII.
       let mut str_bs = Vec::new();
+      let mut non_ascii = false;
       while let Some(&b) = self.bs.get(self.i) {
         match b {
-          b'\n' => return Err(Error::UnclosedStringConstant),
+          b'\n' => {
+            // if the string goes on to the next line, it is missing a gap to continue it there.
+            // otherwise, it was probably never closed.
+            let mut lines = self.bs[self.i + 1..].split(|&b| b == b'\n');
+            if lines.next().unwrap_or_default().contains(&b'"') {
+              self.err_loc = Some(Loc::new(self.i, self.i + 1));
+              return Err(Error::NewlineInStringConstant);
+            }
+            return Err(Error::UnclosedStringConstant);
+          }
           b'"' => {
             self.i += 1;
             return if is_char {
-              if str_bs.len() == 1 {
-                let b = str_bs.pop().unwrap();
-                Ok(Token::Char(b))
-              } else {
-                Err(Error::InvalidCharConstant)
+              let string = String::from_utf8_lossy(&str_bs);
+              let mut chars = string.chars();
+              match (chars.next(), chars.next()) {
+                (Some(_), None) if non_ascii => Err(Error::NonAsciiCharConstant),
+                // only escapes denote non-ASCII characters, and they are all at most 255.
+                (Some(c), None) => Ok(Token::Char(c as u8)),
+                _ => Err(Error::InvalidCharConstant),
               }
             } else {
-              str_bs.shrink_to_fit();
-              let string = String::from_utf8(str_bs).unwrap();
+              let string = String::from_utf8_lossy(&str_bs).into_owned();
               let str_ref = self.store.insert(string.into());
               Ok(Token::String(str_ref))
             };
           }
           b'\\' => {
-            self.i += 1;
-            let b = match self.bs.get(self.i) {
-              None => return Err(Error::UnclosedStringConstant),
-              Some(x) => *x,
-            };
-            match b {
-              b'a' => str_bs.push(7),
-              b'b' => str_bs.push(8),
-              b't' => str_bs.push(9),
-              b'n' => str_bs.push(10),
-              b'v' => str_bs.push(11),
-              b'f' => str_bs.push(12),
-              b'r' => str_bs.push(13),
-              b'^' => {
-                self.i += 1;
-                let b = match self.bs.get(self.i) {
-                  None => return Err(Error::UnclosedStringConstant),
-                  Some(x) => *x,
-                };
-                str_bs.push(b - 64);
-              }
-              b'u' => {
-                if self.i + 4 >= self.bs.len() {
-                  return Err(Error::UnclosedStringConstant);
-                }
-                match (
-                  hex(self.bs[self.i + 1]),
-                  hex(self.bs[self.i + 2]),
-                  hex(self.bs[self.i + 3]),
-                  hex(self.bs[self.i + 4]),
-                ) {
-                  (Some(0), Some(0), Some(d1), Some(d2)) => {
-                    str_bs.push(d1 * 16 + d2);
-                    self.i += 4;
-                  }
-                  _ => return Err(Error::InvalidStringConstant),
-                }
+            let start = self.i;
+            match self.escape() {
+              Ok(None) => {}
+              Ok(Some(b)) => {
+                // escapes denote characters between 0 and 255, as in Latin-1.
+                let mut buf = [0; 2];
+                str_bs.extend_from_slice(char::from(b).encode_utf8(&mut buf).as_bytes());
               }
-              b'"' => str_bs.push(b'"'),
-              b'\\' => str_bs.push(b'\\'),
-              b => {
-                if let Some(d1) = dec(b) {
-                  if self.i + 2 >= self.bs.len() {
-                    return Err(Error::UnclosedStringConstant);
-                  }
-                  match (dec(self.bs[self.i + 1]), dec(self.bs[self.i + 2])) {
-                    (Some(d2), Some(d3)) => {
-                      str_bs.push((d1 * 10 + d2) * 10 + d3);
-                      self.i += 2;
-                    }
-                    _ => return Err(Error::InvalidStringConstant),
-                  }
-                } else if is_formatting(b) {
-                  loop {
-                    self.i += 1;
-                    let b = match self.bs.get(self.i) {
-                      None => return Err(Error::UnclosedStringConstant),
-                      Some(x) => *x,
-                    };
-                    if b == b'\\' {
-                      break;
-                    }
-                    if !is_formatting(b) {
-                      return Err(Error::InvalidStringConstant);
-                    }
-                  }
-                } else {
-                  return Err(Error::InvalidStringConstant);
+              Err(e) => {
+                // an unclosed string is better reported at the whole string.
+                if !matches!(e, Error::UnclosedStringConstant) {
+                  self.err_loc = Some(Loc::new(start, self.i));
                 }
+                return Err(e);
               }
             }
           }
-          b => str_bs.push(b),
+          b => {
+            non_ascii |= !b.is_ascii();
+            str_bs.push(b);
+          }
         }
         self.i += 1;
       }
@@ -422,10 +640,19 @@ impl<'s> TokenMaker<'s> {
     Err(Error::UnknownByte(b))
   }
 
-  fn pos_dec_int(&mut self) -> Result<i32, Error> {
+  fn dec_digits(&mut self) -> Result<&'s [u8], Error> {
+    self.digits(u8::is_ascii_digit)
+  }
+
+  fn hex_digits(&mut self) -> Result<&'s [u8], Error> {
+    self.digits(u8::is_ascii_hexdigit)
+  }
+
+  /// Advances past a non-empty sequence of digits, and returns those digits.
+  fn digits(&mut self, is_digit: fn(&u8) -> bool) -> Result<&'s [u8], Error> {
     let start = self.i;
     while let Some(b) = self.bs.get(self.i) {
-      if !b.is_ascii_digit() {
+      if !is_digit(b) {
         break;
       }
       self.i += 1;
@@ -433,58 +660,24 @@ impl<'s> TokenMaker<'s> {
     if start == self.i {
       return Err(Error::IncompleteNumConstant);
     }
-    let n = std::str::from_utf8(&self.bs[start..self.i]).unwrap();
-    let n = i32::from_str_radix(n, 10)?;
-    Ok(n)
+    Ok(&self.bs[start..self.i])
   }
 
   // Requires that self.bs[self.i] currently be on a '.'
-  fn real_after_dec(&mut self) -> Result<f64, Error> {
-    let start = self.i;
+  fn real_after_dec(&mut self) -> Result<(), Error> {
     self.i += 1;
-    while let Some(b) = self.bs.get(self.i) {
-      if !b.is_ascii_digit() {
-        break;
-      }
-      self.i += 1;
+    match self.dec_digits() {
+      Ok(_) => Ok(()),
+      Err(_) => Err(Error::InvalidRealConstant),
     }
-    if start == self.i {
-      return Err(Error::IncompleteNumConstant);
-    }
-    let n = std::str::from_utf8(&self.bs[start..self.i]).unwrap();
-    let n: f64 = n.parse()?;
-    Ok(n)
   }
 
-  fn pos_hex_int(&mut self) -> Result<i32, Error> {
-    let start = self.i;
-    while let Some(b) = self.bs.get(self.i) {
-      if !b.is_ascii_hexdigit() {
-        break;
-      }
+  fn real_exp(&mut self) -> Result<(), Error> {
+    if self.bs.get(self.i) == Some(&b'~') {
       self.i += 1;
     }
-    if start == self.i {
-      return Err(Error::IncompleteNumConstant);
-    }
-    let n = std::str::from_utf8(&self.bs[start..self.i]).unwrap();
-    let n = i32::from_str_radix(n, 16)?;
-    Ok(n)
-  }
-
-  fn real_exp(&mut self) -> Result<i32, Error> {
-    let b = match self.bs.get(self.i) {
-      None => return Err(Error::IncompleteNumConstant),
-      Some(x) => *x,
-    };
-    let neg = if b == b'~' {
-      self.i += 1;
-      true
-    } else {
-      false
-    };
-    let n = self.pos_dec_int()?;
-    Ok(if neg { -n } else { n })
+    self.dec_digits()?;
+    Ok(())
   }
 }
 
@@ -514,6 +707,45 @@ fn alpha_num(b: u8) -> Option<AlphaNum> {
   }
 }
 
+/// Returns the trivia of input of length `len` with tokens `ts`, shebang line `shebang`, and
+/// comments `comments`, which is the shebang line and comments plus whatever is not a token or
+/// comment, which must be whitespace.
+fn mk_trivia(
+  len: usize,
+  ts: &[Located<Token>],
+  shebang: Option<Loc>,
+  comments: Vec<Loc>,
+) -> Vec<Located<Trivia>> {
+  let mut ret = Vec::with_capacity(comments.len() * 2 + 2);
+  let mut ts = ts.iter().map(|x| Range::from(x.loc)).peekable();
+  let mut comments = comments.into_iter().peekable();
+  let mut idx = 0;
+  if let Some(loc) = shebang {
+    ret.push(loc.wrap(Trivia::Shebang));
+    idx = Range::from(loc).end;
+  }
+  while idx < len {
+    let next_tok = ts.peek().map(|x| x.start);
+    let next_comment = comments.peek().map(|&x| Range::from(x).start);
+    let next = match (next_tok, next_comment) {
+      (Some(a), Some(b)) => a.min(b),
+      (Some(a), None) | (None, Some(a)) => a,
+      (None, None) => len,
+    };
+    if idx < next {
+      ret.push(Loc::new(idx, next).wrap(Trivia::Whitespace));
+      idx = next;
+    } else if next_tok == Some(idx) {
+      idx = ts.next().unwrap().end;
+    } else {
+      let loc = comments.next().unwrap();
+      ret.push(loc.wrap(Trivia::Comment));
+      idx = Range::from(loc).end;
+    }
+  }
+  ret
+}
+
 fn is_formatting(b: u8) -> bool {
   matches!(b, b' ' | b'\t' | b'\n' | 12)
 }
@@ -550,10 +782,21 @@ fn mk_int(n: i32, starts_with_zero: bool) -> Token {
   Token::DecInt(n, is_num_lab)
 }
 
-fn mk_real(before_dec: i32, after_dec: f64, exp: i32) -> Result<Token, Error> {
-  let before_dec: f64 = before_dec.into();
-  let exp: f64 = exp.into();
-  Ok(Token::Real((before_dec + after_dec) * 10_f64.powf(exp)))
+/// Returns the value of the integer constant with the given `digits` in the given `radix`, negated
+/// if `neg`. Errors if the value does not fit in an `int`.
+fn int(digits: &[u8], radix: u32, neg: bool) -> Result<i32, Error> {
+  let n = std::str::from_utf8(digits).unwrap();
+  // the digits have already been checked, so the only possible error is overflow.
+  let n = u32::from_str_radix(n, radix).map_err(|_| Error::IntConstantOutOfRange)?;
+  let n = if neg { -i64::from(n) } else { i64::from(n) };
+  i32::try_from(n).map_err(|_| Error::IntConstantOutOfRange)
+}
+
+/// Returns the value of the word constant with the given `digits` in the given `radix`. Errors if
+/// the value does not fit in a `word`.
+fn word(digits: &[u8], radix: u32) -> Result<u32, Error> {
+  let n = std::str::from_utf8(digits).unwrap();
+  u32::from_str_radix(n, radix).map_err(|_| Error::WordConstantOutOfRange)
 }
 
 #[test]
@@ -592,3 +835,95 @@ fn test_hex() {
   assert_eq!(hex(b'*'), None);
   assert_eq!(hex(b'?'), None);
 }
+
+#[test]
+fn test_trivia() {
+  let src = "val x (* a (* b *) *) =\n  3 (* c *)\n";
+  let mut store = StrStoreMut::new();
+  let lexer = get(&mut store, src.as_bytes()).unwrap();
+  let got: Vec<_> = lexer
+    .trivia()
+    .iter()
+    .map(|x| (x.val, &src[Range::from(x.loc)]))
+    .collect();
+  let want = vec![
+    (Trivia::Whitespace, " "),
+    (Trivia::Whitespace, " "),
+    (Trivia::Comment, "(* a (* b *) *)"),
+    (Trivia::Whitespace, " "),
+    (Trivia::Whitespace, "\n  "),
+    (Trivia::Whitespace, " "),
+    (Trivia::Comment, "(* c *)"),
+    (Trivia::Whitespace, "\n"),
+  ];
+  assert_eq!(got, want);
+}
+
+#[test]
+fn test_shebang() {
+  let src = "#!/usr/bin/env sml\nval x = 3\n";
+  let mut store = StrStoreMut::new();
+  let lexer = get(&mut store, src.as_bytes()).unwrap();
+  assert_eq!(lexer.get(0).unwrap().val, Token::Val);
+  let fst = lexer.trivia()[0];
+  assert_eq!(fst.val, Trivia::Shebang);
+  assert_eq!(&src[Range::from(fst.loc)], "#!/usr/bin/env sml");
+  // `#!` anywhere else is a symbolic identifier.
+  let lexer = get(&mut store, b"val x = 3 #!").unwrap();
+  assert!(matches!(lexer.get(4).unwrap().val, Token::Ident(..)));
+}
+
+#[test]
+fn test_real_text() {
+  let mut store = StrStoreMut::new();
+  let lexer = get(&mut store, b"~1.50e~2").unwrap();
+  let store = store.finish();
+  match lexer.get(0).unwrap().val {
+    Token::Real(n, text) => {
+      assert_eq!(n, -0.015);
+      assert_eq!(store.get(text), "~1.50e~2");
+    }
+    tok => panic!("not a real: {:?}", tok),
+  }
+}
+
+#[test]
+fn test_escapes() {
+  let src = r#""\a\t\n\^@\^_\065é\200\"\\ \
+     \end""#;
+  let mut store = StrStoreMut::new();
+  let lexer = get(&mut store, src.as_bytes()).unwrap();
+  let store = store.finish();
+  match lexer.get(0).unwrap().val {
+    Token::String(s) => assert_eq!(store.get(s), "\x07\t\n\x00\x1fA\u{e9}\u{c8}\"\\ end"),
+    tok => panic!("not a string: {:?}", tok),
+  }
+}
+
+#[test]
+fn test_escape_loc() {
+  let src = "\"ok \\q ok\"";
+  let mut store = StrStoreMut::new();
+  let err = match get(&mut store, src.as_bytes()) {
+    Ok(_) => panic!("lexed an invalid escape"),
+    Err(e) => e,
+  };
+  assert!(matches!(err.val, Error::InvalidStringConstant));
+  assert_eq!(&src[Range::from(err.loc)], "\\q");
+}
+
+#[test]
+fn test_tokens() {
+  let src = b"val x = 3 *) and more";
+  let mut store = StrStoreMut::new();
+  let got: Vec<_> = tokens(&mut store, src)
+    .take(4)
+    .map(|x| x.unwrap().val)
+    .collect();
+  assert_eq!(got[0], Token::Val);
+  assert_eq!(got[2], Token::Equal);
+  let mut iter = tokens(&mut store, src).skip(4);
+  let err = iter.next().unwrap().unwrap_err();
+  assert!(matches!(err.val, Error::UnmatchedCloseComment));
+  assert!(iter.next().is_none());
+}
@@ -3,10 +3,17 @@
 #![deny(missing_docs)]
 
 pub mod ast;
+pub mod codec;
+pub mod db;
+pub mod highlight;
+pub mod indent;
 pub mod intern;
 pub mod lex;
+pub mod line_index;
 pub mod loc;
 pub mod parse;
+pub mod project;
+pub mod selection;
 pub mod statics;
 pub mod token;
 
@@ -0,0 +1,104 @@
+//! Conversions between byte offsets and lines and columns.
+
+use std::ops::Range;
+
+/// The lines of some text, for converting between byte offsets into it and lines and columns.
+#[derive(Debug)]
+pub struct LineIndex {
+  /// The byte offset of the start of each line. Never empty.
+  starts: Vec<usize>,
+  /// The length of the text.
+  len: usize,
+}
+
+/// A zero-based line and column. The column is in bytes from the start of the line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineCol {
+  /// The line.
+  pub line: usize,
+  /// The column.
+  pub col: usize,
+}
+
+impl LineIndex {
+  /// Returns the index of the lines of `bs`.
+  pub fn new(bs: &[u8]) -> Self {
+    let mut starts = vec![0];
+    starts.extend(
+      bs.iter()
+        .enumerate()
+        .filter_map(|(idx, &b)| if b == b'\n' { Some(idx + 1) } else { None }),
+    );
+    Self {
+      starts,
+      len: bs.len(),
+    }
+  }
+
+  /// Returns the number of lines. Text ending with a newline has an empty line after it.
+  pub fn line_count(&self) -> usize {
+    self.starts.len()
+  }
+
+  /// Returns the line containing the byte offset `idx`. A newline is part of the line it ends.
+  /// Offsets past the end of the text are on the last line.
+  pub fn line(&self, idx: usize) -> usize {
+    match self.starts.binary_search(&idx) {
+      Ok(line) => line,
+      Err(line) => line - 1,
+    }
+  }
+
+  /// Returns the byte offsets of the contents of `line`, which do not include the newline ending
+  /// it. Returns `None` if there is no such line.
+  pub fn line_range(&self, line: usize) -> Option<Range<usize>> {
+    let start = *self.starts.get(line)?;
+    let end = match self.starts.get(line + 1) {
+      Some(&next) => next - 1,
+      None => self.len,
+    };
+    Some(start..end)
+  }
+
+  /// Returns the line and column of the byte offset `idx`.
+  pub fn line_col(&self, idx: usize) -> LineCol {
+    let line = self.line(idx);
+    LineCol {
+      line,
+      col: idx - self.starts[line],
+    }
+  }
+
+  /// The inverse of `line_col`. Returns `None` if `line_col` is neither in the text nor at the end
+  /// of a line.
+  pub fn byte_idx(&self, line_col: LineCol) -> Option<usize> {
+    let range = self.line_range(line_col.line)?;
+    let ret = range.start + line_col.col;
+    if ret <= range.end {
+      Some(ret)
+    } else {
+      None
+    }
+  }
+}
+
+#[test]
+fn test_line_index() {
+  let lines = LineIndex::new(b"ab\n\ncd\n");
+  let lc = |line, col| LineCol { line, col };
+  assert_eq!(lines.line_count(), 4);
+  assert_eq!(lines.line_col(0), lc(0, 0));
+  assert_eq!(lines.line_col(2), lc(0, 2));
+  assert_eq!(lines.line_col(3), lc(1, 0));
+  assert_eq!(lines.line_col(5), lc(2, 1));
+  assert_eq!(lines.line_col(7), lc(3, 0));
+  assert_eq!(lines.line_range(0), Some(0..2));
+  assert_eq!(lines.line_range(1), Some(3..3));
+  assert_eq!(lines.line_range(3), Some(7..7));
+  assert_eq!(lines.line_range(4), None);
+  for idx in 0..=7 {
+    assert_eq!(lines.byte_idx(lines.line_col(idx)), Some(idx));
+  }
+  assert_eq!(lines.byte_idx(lc(1, 1)), None);
+  assert_eq!(lines.byte_idx(lc(4, 0)), None);
+}
@@ -2,13 +2,13 @@
 
 use crate::ast::{
   Arm, Cases, ConBind, DatBind, Dec, ExBind, ExBindInner, ExDesc, Exp, FValBind, FValBindCase,
-  FunBind, Label, Long, Pat, Row, SigBind, SigExp, Spec, StrBind, StrDec, StrDesc, StrExp, TopDec,
-  Ty, TyBind, TyDesc, TyPrec, ValBind, ValDesc,
+  FunBind, FunDesc, Label, Long, Pat, Row, SharingKind, SigBind, SigExp, Spec, StrBind, StrDec,
+  StrDesc, StrExp, TopDec, Ty, TyBind, TyDesc, TyPrec, ValBind, ValDesc,
 };
 use crate::intern::{StrRef, StrStore};
 use crate::lex::Lexer;
 use crate::loc::{Loc, Located};
-use crate::token::{IdentType, IsNumLab, Token, TyVar};
+use crate::token::{self, IdentType, IsNumLab, Token, TyVar, ALPHA};
 use maplit::hashmap;
 use std::collections::HashMap;
 use std::convert::TryInto as _;
@@ -18,49 +18,180 @@ pub type Result<T> = std::result::Result<T, Located<Error>>;
 
 /// Parse the tokens in the Lexer into a sequence of top-level definitions.
 pub fn get(lexer: Lexer) -> Result<Vec<Located<TopDec<StrRef>>>> {
+  get_with(lexer, LanguageFeatures::default())
+}
+
+/// Like `get`, but allowing the syntax extensions in `features`.
+pub fn get_with(lexer: Lexer, features: LanguageFeatures) -> Result<Vec<Located<TopDec<StrRef>>>> {
+  let (ret, mut errors) = get_partial(lexer, features);
+  if errors.is_empty() {
+    Ok(ret)
+  } else {
+    Err(errors.swap_remove(0))
+  }
+}
+
+/// Like `get_with`, but recovers from `Unclosed` errors where possible, returning every top-level
+/// definition which did parse along with the errors. A declaration with an unclosed construct is
+/// skipped, and parsing resumes where the parser gave up on it, if that is the start of another
+/// top-level declaration. Parsing stops after any other error, which is always last.
+pub fn get_partial(
+  lexer: Lexer,
+  features: LanguageFeatures,
+) -> (Vec<Located<TopDec<StrRef>>>, Vec<Located<Error>>) {
   let mut ret = Vec::new();
+  let mut errors = Vec::new();
   let last_loc = match lexer.last_loc() {
     Some(x) => x,
-    None => return Ok(ret),
+    None => return (ret, errors),
   };
-  let mut p = Parser::new(lexer, last_loc);
+  let mut p = Parser::new(lexer, last_loc, features);
   loop {
     if let Token::EOF = p.peek().val {
       break;
     }
-    ret.push(p.top_dec()?);
+    match p.top_dec() {
+      Ok(x) => ret.push(x),
+      Err(e) => {
+        let unclosed = matches!(e.val, Error::Unclosed(..));
+        if unclosed {
+          p.flush_top_decs(true);
+          ret.extend(p.take_top_str_decs());
+        }
+        let resume = unclosed && p.resume();
+        errors.push(e);
+        if !resume {
+          break;
+        }
+      }
+    }
   }
   ret.shrink_to_fit();
+  (ret, errors)
+}
+
+/// Parse the tokens in the Lexer into a single expression.
+pub fn exp(lexer: Lexer) -> Result<Located<Exp<StrRef>>> {
+  fragment(lexer, Parser::exp)
+}
+
+/// Parse the tokens in the Lexer into a single pattern.
+pub fn pat(lexer: Lexer) -> Result<Located<Pat<StrRef>>> {
+  fragment(lexer, Parser::pat)
+}
+
+/// Parse the tokens in the Lexer into a single type.
+pub fn ty(lexer: Lexer) -> Result<Located<Ty<StrRef>>> {
+  fragment(lexer, Parser::ty)
+}
+
+/// Parse all the tokens in the Lexer with `f`.
+fn fragment<T>(lexer: Lexer, f: fn(&mut Parser) -> Result<T>) -> Result<T> {
+  // with no tokens, report the missing fragment at the start.
+  let last_loc = lexer.last_loc().unwrap_or_else(|| Loc::new(0, 1));
+  let mut p = Parser::new(lexer, last_loc, LanguageFeatures::default());
+  let ret = f(&mut p)?;
+  p.eat(Token::EOF)?;
   Ok(ret)
 }
 
+/// Optional extensions to the syntax of the SML Definition, from Successor ML. By default, none are
+/// allowed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LanguageFeatures {
+  /// A `|` before the first arm of a match, or the first constructor of a datatype.
+  pub leading_bar: bool,
+  /// The declaration `do exp`, which is sugar for `val () = exp`.
+  pub do_dec: bool,
+  /// A `;` after the last expression of a sequence, as in `(a; b;)` or `let ... in a; b; end`.
+  pub trailing_semicolon: bool,
+  /// Vector expressions and patterns, as in `#[a, b]`.
+  pub vector: bool,
+}
+
+impl LanguageFeatures {
+  /// Returns the features with every extension allowed.
+  pub fn successor_ml() -> Self {
+    Self {
+      leading_bar: true,
+      do_dec: true,
+      trailing_semicolon: true,
+      vector: true,
+    }
+  }
+}
+
 /// An error emitted when parsing.
 #[derive(Debug)]
 #[allow(missing_docs)]
 pub enum Error {
-  ExpectedButFound(&'static str, &'static str),
+  /// The identifiers since the last keyword, nearest first, any of which may be a misspelled
+  /// keyword.
+  ExpectedButFound(&'static str, &'static str, Vec<StrRef>),
   InfixWithoutOp(StrRef),
   NotInfix(StrRef),
-  RealPat,
+  RealPat(StrRef),
   NegativeFixity,
   SameFixityDiffAssoc,
+  TooDeep,
+  /// The opener, and where the parser gave up.
+  Unclosed(&'static str, Loc),
 }
 
 impl Error {
+  /// A stable numeric code identifying the kind of this error. See `doc/errors.md`.
+  pub fn code(&self) -> u16 {
+    match self {
+      Self::ExpectedButFound(..) => 1101,
+      Self::InfixWithoutOp(_) => 1102,
+      Self::NotInfix(_) => 1103,
+      Self::RealPat(_) => 1104,
+      Self::NegativeFixity => 1105,
+      Self::SameFixityDiffAssoc => 1106,
+      Self::TooDeep => 1107,
+      Self::Unclosed(..) => 1108,
+    }
+  }
+
+  /// Other locations related to this error, each with a description of how it is related.
+  pub fn related(&self) -> Vec<Located<&'static str>> {
+    match self {
+      Self::Unclosed(_, loc) => vec![loc.wrap("gave up looking for the end here")],
+      _ => Vec::new(),
+    }
+  }
+
   /// A human-readable message describing this error.
   pub fn message(&self, store: &StrStore) -> String {
     match self {
-      Self::ExpectedButFound(exp, fnd) => format!("expected {}, found {}", exp, fnd),
+      Self::ExpectedButFound(exp, fnd, idents) => {
+        let near_miss = idents.iter().find_map(|&id| {
+          let name = store.get(id);
+          token::near_miss(name.as_bytes()).map(|tok| (name, tok))
+        });
+        match near_miss {
+          None => format!("expected {}, found {}", exp, fnd),
+          Some((name, tok)) => format!(
+            "expected {}, found {}: perhaps `{}` should be {}",
+            exp,
+            fnd,
+            name,
+            tok.desc()
+          ),
+        }
+      }
       Self::InfixWithoutOp(id) => format!(
         "infix identifier used without preceding `op`: {}",
         store.get(*id)
       ),
       Self::NotInfix(id) => format!("non-infix identifier used as infix: {}", store.get(*id)),
-      Self::RealPat => "real constant used as a pattern".to_owned(),
+      Self::RealPat(text) => format!("real constant used as a pattern: {}", store.get(*text)),
       Self::NegativeFixity => "fixity is negative".to_owned(),
       Self::SameFixityDiffAssoc => {
         "consecutive infix identifiers with same fixity but different associativity".to_owned()
       }
+      Self::TooDeep => format!("nested too deeply: must be at most {} deep", MAX_DEPTH),
+      Self::Unclosed(opener, _) => format!("unclosed {}", opener),
     }
   }
 }
@@ -69,9 +200,36 @@ struct Parser {
   lexer: Lexer,
   i: usize,
   ops: HashMap<StrRef, OpInfo>,
+  /// The fixities declared in the structures in scope, which `open` brings into scope.
+  str_ops: HashMap<StrRef, StrOps>,
+  /// The fixities declared in the structure expression parsed last.
+  last_str_ops: StrOps,
   last_loc: Loc,
+  /// How deeply nested the construct being parsed is.
+  depth: usize,
+  /// The openers of the constructs being parsed which have not been closed yet, innermost last.
+  open: Vec<Located<Token>>,
+  /// The index of the token where the parser gave up on the last unclosed construct.
+  gave_up: usize,
+  /// The structure-level declarations parsed so far in the current top-level declaration.
+  top_str_decs: Vec<Located<StrDec<StrRef>>>,
+  /// The core declarations after those, which will become one more structure-level declaration.
+  top_decs: Vec<Located<Dec<StrRef>>>,
+  features: LanguageFeatures,
 }
 
+/// How deeply expressions, patterns, types, declarations, and so on may be nested. Parsing,
+/// checking, and even dropping the AST is recursive, so very deep ones would overflow the stack.
+pub const MAX_DEPTH: usize = 1000;
+
+/// How much stack parsing, checking, and dropping input nested up to `MAX_DEPTH` deep needs. They
+/// should run on a thread with this much stack. The most any one way of nesting was measured to
+/// need, in a debug build, was about 24 MiB.
+pub const STACK_SIZE: usize = 64 * 1024 * 1024;
+
+/// How many tokens back from an error to look for a misspelled keyword.
+const MAX_NEAR_MISS_DISTANCE: usize = 20;
+
 // NOTE the `maybe` family of functions return Result<Option<T>>. these functions return:
 // - Ok(Some(..)) if they did parse a T
 // - Ok(None) if they couldn't parse a T but didn't consume any tokens
@@ -79,14 +237,19 @@ struct Parser {
 
 impl Parser {
   /// constructs a new Parser.
-  fn new(lexer: Lexer, last_loc: Loc) -> Self {
+  fn new(lexer: Lexer, last_loc: Loc, features: LanguageFeatures) -> Self {
     Self {
       lexer,
       last_loc,
       i: 0,
       ops: hashmap![
         StrRef::CONS => OpInfo::right(5),
+        StrRef::AT => OpInfo::right(5),
+        StrRef::CARAT => OpInfo::left(6),
+        StrRef::O => OpInfo::left(3),
+        StrRef::BEFORE => OpInfo::left(0),
         StrRef::EQ => OpInfo::left(4),
+        StrRef::NEQ => OpInfo::left(4),
         StrRef::ASSIGN => OpInfo::left(3),
         StrRef::DIV => OpInfo::left(7),
         StrRef::MOD => OpInfo::left(7),
@@ -99,9 +262,100 @@ impl Parser {
         StrRef::LT_EQ => OpInfo::left(4),
         StrRef::GT_EQ => OpInfo::left(4),
       ],
+      str_ops: HashMap::new(),
+      last_str_ops: StrOps::default(),
+      depth: 0,
+      open: Vec::new(),
+      gave_up: 0,
+      top_str_decs: Vec::new(),
+      top_decs: Vec::new(),
+      features,
+    }
+  }
+
+  /// Skips a `|` before the first of some alternatives, if that is allowed.
+  fn maybe_leading_bar(&mut self) {
+    if self.features.leading_bar && self.peek().val == Token::Bar {
+      self.skip();
     }
   }
 
+  /// Returns whether the next token is `close`, which ends a `;`-separated sequence of expressions,
+  /// and a trailing `;` is allowed. If so, skips it.
+  fn trailing_semicolon(&mut self, close: Token) -> bool {
+    let ret = self.features.trailing_semicolon && self.peek().val == close;
+    if ret {
+      self.skip();
+    }
+    ret
+  }
+
+  /// Notes that the construct at `loc` is one level deeper. Callers should restore `self.depth`
+  /// after parsing it.
+  fn deeper(&mut self, loc: Loc) -> Result<()> {
+    self.depth += 1;
+    if self.depth > MAX_DEPTH {
+      Err(loc.wrap(Error::TooDeep))
+    } else {
+      Ok(())
+    }
+  }
+
+  /// Returns the fixities in scope, to later be restored with `restore_ops`.
+  fn save_ops(&self) -> (HashMap<StrRef, OpInfo>, HashMap<StrRef, StrOps>) {
+    (self.ops.clone(), self.str_ops.clone())
+  }
+
+  /// Restores the fixities in scope to `saved`, which came from `save_ops`. Returns the fixities
+  /// declared since then.
+  fn restore_ops(&mut self, saved: (HashMap<StrRef, OpInfo>, HashMap<StrRef, StrOps>)) -> StrOps {
+    let (ops, str_ops) = saved;
+    let mut ret = StrOps::default();
+    for (&id, &op_info) in self.ops.iter() {
+      if ops.get(&id) != Some(&op_info) {
+        ret.ops.insert(id, Some(op_info));
+      }
+    }
+    for &id in ops.keys() {
+      if !self.ops.contains_key(&id) {
+        ret.ops.insert(id, None);
+      }
+    }
+    for (&id, s) in self.str_ops.iter() {
+      if str_ops.get(&id) != Some(s) {
+        ret.structures.insert(id, s.clone());
+      }
+    }
+    self.ops = ops;
+    self.str_ops = str_ops;
+    ret
+  }
+
+  /// Brings into scope the fixities declared in the structure `long`, if they are known.
+  fn open_ops(&mut self, long: &Long<StrRef>) {
+    let str_ops = match self.get_str_ops(long) {
+      Some(x) => x.clone(),
+      None => return,
+    };
+    for (id, op_info) in str_ops.ops {
+      match op_info {
+        Some(op_info) => self.ops.insert(id, op_info),
+        None => self.ops.remove(&id),
+      };
+    }
+    self.str_ops.extend(str_ops.structures);
+  }
+
+  /// Returns the fixities declared in the structure `long`, if they are known.
+  fn get_str_ops(&self, long: &Long<StrRef>) -> Option<&StrOps> {
+    let mut path = long.structures.iter().chain(std::iter::once(&long.last));
+    let mut ret = self.str_ops.get(&path.next().unwrap().val)?;
+    for id in path {
+      ret = ret.structures.get(&id.val)?;
+    }
+    Some(ret)
+  }
+
   /// gets the current token. does not advance the parser.
   fn peek(&self) -> Located<Token> {
     match self.lexer.get(self.i) {
@@ -137,9 +391,104 @@ impl Parser {
     }
   }
 
-  /// returns an ExpectedButFound error, where we expected `want` but got `tok`.
+  /// returns whether the parser is at the `#[` starting a vector, if vectors are allowed.
+  fn vector_start(&self) -> bool {
+    self.features.vector
+      && matches!(self.peek().val, Token::Pound)
+      && matches!(self.lexer.get(self.i + 1), Some(tok) if tok.val == Token::LSquare)
+  }
+
+  /// parses the comma-separated elements of a list or vector, after the opening `[`, through the
+  /// closing `]`.
+  fn list_rest<T>(&mut self, f: fn(&mut Self) -> Result<T>) -> Result<Vec<T>> {
+    let mut ret = Vec::new();
+    if let Token::RSquare = self.peek().val {
+      self.skip();
+    } else {
+      loop {
+        ret.push(f(self)?);
+        let tok = self.peek();
+        self.skip();
+        match tok.val {
+          Token::RSquare => break,
+          Token::Comma => continue,
+          _ => return self.fail("`]` or `,`", tok),
+        }
+      }
+    }
+    ret.shrink_to_fit();
+    Ok(ret)
+  }
+
+  /// returns an ExpectedButFound error, where we expected `want` but got `tok`, along with the
+  /// identifiers before it which may be misspelled keywords.
+  /// if we got to the end of the file inside some construct, or we wanted the token which would
+  /// close the innermost such construct, instead returns an Unclosed error at its opener.
   fn fail<T>(&mut self, want: &'static str, tok: Located<Token>) -> Result<T> {
-    Err(tok.loc.wrap(Error::ExpectedButFound(want, tok.val.desc())))
+    let idx = if self.peek().loc == tok.loc {
+      self.i
+    } else {
+      self.i - 1
+    };
+    if let Some(&opener) = self.open.last() {
+      let close = match opener.val {
+        Token::LRound => Token::RRound,
+        _ => Token::End,
+      };
+      if tok.val == Token::EOF || want.contains(close.desc()) {
+        self.gave_up = idx;
+        return Err(opener.loc.wrap(Error::Unclosed(opener.val.desc(), tok.loc)));
+      }
+    }
+    // a misspelled keyword is an identifier, so there will be no keywords after it.
+    let before = (0..idx)
+      .rev()
+      .take(MAX_NEAR_MISS_DISTANCE)
+      .filter_map(|i| self.lexer.get(i))
+      .take_while(|tok| ALPHA.iter().all(|&(_, kw)| kw != tok.val));
+    let idents = std::iter::once(tok)
+      .chain(before)
+      .filter_map(|tok| match tok.val {
+        Token::Ident(id, IdentType::AlphaNum) => Some(id),
+        _ => None,
+      })
+      .collect();
+    Err(
+      tok
+        .loc
+        .wrap(Error::ExpectedButFound(want, tok.val.desc(), idents)),
+    )
+  }
+
+  /// after an Unclosed error, moves the parser to where it gave up, if another top-level
+  /// declaration starts there. returns whether it did.
+  fn resume(&mut self) -> bool {
+    let starts_top_dec = match self.lexer.get(self.gave_up) {
+      Some(tok) => matches!(
+        tok.val,
+        Token::Val
+          | Token::Fun
+          | Token::Type
+          | Token::Datatype
+          | Token::Abstype
+          | Token::Exception
+          | Token::Local
+          | Token::Open
+          | Token::Infix
+          | Token::Infixr
+          | Token::Nonfix
+          | Token::Structure
+          | Token::Signature
+          | Token::Functor
+      ),
+      None => false,
+    };
+    if starts_top_dec {
+      self.i = self.gave_up;
+      self.open.clear();
+      self.depth = 0;
+    }
+    starts_top_dec
   }
 
   fn top_dec(&mut self) -> Result<Located<TopDec<StrRef>>> {
@@ -181,7 +530,7 @@ impl Parser {
             str_exp,
           });
           if let Token::And = self.peek().val {
-            continue;
+            self.skip();
           } else {
             break;
           }
@@ -190,18 +539,70 @@ impl Parser {
         TopDec::FunDec(fun_binds)
       }
       _ => {
-        let sd = self.str_dec()?;
-        if let StrDec::Seq(ref xs) = sd.val {
-          if xs.is_empty() {
-            return self.fail("a top-level declaration", self.peek());
+        // the same as `str_dec`, but the declarations are kept in the parser as they are parsed,
+        // so that they may be recovered if a later one is unclosed.
+        loop {
+          // like in `str_dec`, `local` continues a sequence of core declarations, but otherwise
+          // starts a structure-level one.
+          let str_local = self.top_decs.is_empty() && self.peek().val == Token::Local;
+          if let Some(x) = if str_local { None } else { self.maybe_dec()? } {
+            self.top_decs.push(x);
+          } else {
+            self.flush_top_decs(false);
+            match self.maybe_str_dec()? {
+              Some(x) => self.top_str_decs.push(x),
+              None => break,
+            }
+          }
+          if let Token::Semicolon = self.peek().val {
+            self.skip();
           }
         }
-        TopDec::StrDec(sd)
+        match self.take_top_str_decs() {
+          Some(x) => return Ok(x),
+          None => return self.fail("a top-level declaration", self.peek()),
+        }
       }
     };
     Ok(self.wrap(begin, ret))
   }
 
+  /// moves the core declarations parsed so far in the current top-level declaration into one
+  /// structure-level declaration. if `recovering`, the parser may be past the end of them.
+  fn flush_top_decs(&mut self, recovering: bool) {
+    let mut xs = std::mem::take(&mut self.top_decs);
+    let (begin, end) = match (xs.first(), xs.last()) {
+      (Some(fst), Some(lst)) => (fst.loc, lst.loc),
+      _ => return,
+    };
+    let dec = match xs.len() {
+      1 => xs.pop().unwrap(),
+      _ => begin.span(end).wrap(Dec::Seq(xs)),
+    };
+    let sd = StrDec::Dec(dec);
+    let sd = if recovering {
+      begin.span(end).wrap(sd)
+    } else {
+      self.wrap(begin, sd)
+    };
+    self.top_str_decs.push(sd);
+  }
+
+  /// returns the structure-level declarations parsed so far in the current top-level declaration as
+  /// a top-level declaration, if there were any.
+  fn take_top_str_decs(&mut self) -> Option<Located<TopDec<StrRef>>> {
+    let mut xs = std::mem::take(&mut self.top_str_decs);
+    let sd = match xs.len() {
+      0 => return None,
+      1 => xs.pop().unwrap(),
+      _ => {
+        let loc = xs.first().unwrap().loc.span(xs.last().unwrap().loc);
+        loc.wrap(StrDec::Seq(xs))
+      }
+    };
+    Some(sd.loc.wrap(TopDec::StrDec(sd)))
+  }
+
   fn str_exp_sugar(&mut self) -> Result<Located<StrExp<StrRef>>> {
     let sig_exp = match self.peek().val {
       Token::Colon => {
@@ -217,7 +618,7 @@ impl Parser {
     self.eat(Token::Equal)?;
     let mut ret = self.str_exp()?;
     if let Some((se, opaque)) = sig_exp {
-      ret = ret.loc.wrap(StrExp::Ascription(ret.into(), se, opaque));
+      ret = self.wrap(ret.loc, StrExp::Ascription(ret.into(), se, opaque));
     }
     Ok(ret)
   }
@@ -225,38 +626,50 @@ impl Parser {
   fn str_exp(&mut self) -> Result<Located<StrExp<StrRef>>> {
     let tok = self.peek();
     let begin = tok.loc;
+    let depth = self.depth;
+    self.deeper(begin)?;
     let mut ret = match tok.val {
       Token::Struct => {
         self.skip();
-        let ops = self.ops.clone();
+        self.open.push(tok);
+        let ops = self.save_ops();
         let dec = self.str_dec()?;
         self.eat(Token::End)?;
-        self.ops = ops;
+        self.open.pop();
+        self.last_str_ops = self.restore_ops(ops);
         StrExp::Struct(dec)
       }
       Token::Let => {
         self.skip();
-        let ops = self.ops.clone();
+        self.open.push(tok);
+        let ops = self.save_ops();
         let dec = self.str_dec()?;
         self.eat(Token::In)?;
         let exp = self.str_exp()?;
         self.eat(Token::End)?;
-        self.ops = ops;
+        self.open.pop();
+        self.restore_ops(ops);
         StrExp::Let(dec, exp.into())
       }
       Token::Ident(id, IdentType::AlphaNum) => {
         self.skip();
-        if let Token::LRound = self.peek().val {
+        let tok = self.peek();
+        if let Token::LRound = tok.val {
           self.skip();
+          self.open.push(tok);
           let exp = match self.maybe_str_dec()? {
             Some(sd) => sd.loc.wrap(StrExp::Struct(sd)),
             None => self.str_exp()?,
           };
           self.eat(Token::RRound)?;
+          self.open.pop();
+          // we don't know what fixities the functor's result has.
+          self.last_str_ops = StrOps::default();
           StrExp::FunctorApp(begin.wrap(id), exp.into())
         } else {
           self.i -= 1;
           let long_id = self.long_alpha_num_id()?;
+          self.last_str_ops = self.get_str_ops(&long_id).cloned().unwrap_or_default();
           StrExp::LongStrId(long_id)
         }
       }
@@ -277,6 +690,7 @@ impl Parser {
         _ => break,
       };
     }
+    self.depth = depth;
     Ok(self.wrap(begin, ret))
   }
 
@@ -287,9 +701,11 @@ impl Parser {
       Token::Structure => {
         self.skip();
         let mut str_binds = Vec::new();
+        let mut str_ops = Vec::new();
         loop {
           let id = self.alpha_num_id()?;
           let exp = self.str_exp_sugar()?;
+          str_ops.push((id.val, std::mem::take(&mut self.last_str_ops)));
           str_binds.push(StrBind { id, exp });
           if let Token::And = self.peek().val {
             self.skip();
@@ -297,17 +713,19 @@ impl Parser {
             break;
           }
         }
+        // the bindings are simultaneous, so none of them is in scope in any of the others.
+        self.str_ops.extend(str_ops);
         str_binds.shrink_to_fit();
         StrDec::Structure(str_binds)
       }
       Token::Local => {
         self.skip();
-        let ops = self.ops.clone();
+        let ops = self.save_ops();
         let fst = self.str_dec()?;
         self.eat(Token::In)?;
         let snd = self.str_dec()?;
         self.eat(Token::End)?;
-        self.ops = ops;
+        self.restore_ops(ops);
         StrDec::Local(fst.into(), snd.into())
       }
       _ => {
@@ -333,8 +751,10 @@ impl Parser {
     self.skip();
     let mut ret = match tok.val {
       Token::Sig => {
+        self.open.push(tok);
         let spec = self.spec()?;
         self.eat(Token::End)?;
+        self.open.pop();
         SigExp::Sig(spec)
       }
       Token::Ident(id, IdentType::AlphaNum) => SigExp::SigId(begin.wrap(id)),
@@ -342,11 +762,24 @@ impl Parser {
     };
     while let Token::Where = self.peek().val {
       self.skip();
-      let ty_vars = self.ty_var_seq()?;
-      let ty_con = self.long_id(true)?;
-      self.eat(Token::Equal)?;
-      let ty = self.ty()?;
-      ret = SigExp::Where(self.wrap(begin, ret).into(), ty_vars, ty_con, ty);
+      loop {
+        self.eat(Token::Type)?;
+        let ty_vars = self.ty_var_seq()?;
+        let ty_con = self.long_id(true)?;
+        self.eat(Token::Equal)?;
+        let ty = self.ty()?;
+        ret = SigExp::Where(self.wrap(begin, ret).into(), ty_vars, ty_con, ty);
+        // SML Definition Appendix A - `where type ... and type ...` is sugar for `where type ...
+        // where type ...`. any other `and` is not part of this signature expression.
+        let and_type = match (self.peek().val, self.lexer.get(self.i + 1)) {
+          (Token::And, Some(tok)) => tok.val == Token::Type,
+          _ => false,
+        };
+        if !and_type {
+          break;
+        }
+        self.skip();
+      }
     }
     Ok(self.wrap(begin, ret))
   }
@@ -354,7 +787,7 @@ impl Parser {
   fn maybe_spec(&mut self) -> Result<Option<Located<Spec<StrRef>>>> {
     let tok = self.peek();
     let begin = tok.loc;
-    let mut ret = match tok.val {
+    let ret = match tok.val {
       Token::Val => {
         self.skip();
         let mut val_descs = Vec::new();
@@ -374,7 +807,7 @@ impl Parser {
       }
       Token::Type => {
         self.skip();
-        Spec::Type(self.ty_descs()?, false)
+        self.type_spec()?
       }
       Token::Eqtype => {
         self.skip();
@@ -438,27 +871,109 @@ impl Parser {
           Spec::Seq(sig_ids)
         }
       }
+      Token::Functor => {
+        self.skip();
+        let mut fun_descs = Vec::new();
+        loop {
+          let fun_id = self.alpha_num_id()?;
+          self.eat(Token::LRound)?;
+          let str_id = self.alpha_num_id()?;
+          self.eat(Token::Colon)?;
+          let param = self.sig_exp()?;
+          self.eat(Token::RRound)?;
+          self.eat(Token::Colon)?;
+          let res = self.sig_exp()?;
+          fun_descs.push(FunDesc {
+            fun_id,
+            str_id,
+            param,
+            res,
+          });
+          if let Token::And = self.peek().val {
+            self.skip();
+          } else {
+            break;
+          }
+        }
+        fun_descs.shrink_to_fit();
+        Spec::Functor(fun_descs)
+      }
       _ => return Ok(None),
     };
-    while let Token::Sharing = self.peek().val {
+    Ok(Some(self.wrap(begin, ret)))
+  }
+
+  /// parses the rest of a `sharing` specification, which shares things in `spec`.
+  fn sharing(&mut self, spec: Located<Spec<StrRef>>) -> Result<Located<Spec<StrRef>>> {
+    self.eat(Token::Sharing)?;
+    let kind = if let Token::Type = self.peek().val {
       self.skip();
-      self.eat(Token::Type)?;
-      let mut ty_cons = Vec::new();
-      loop {
-        ty_cons.push(self.long_id(true)?);
-        if let Token::Equal = self.peek().val {
-          self.skip();
-        } else {
-          break;
-        }
+      SharingKind::Type
+    } else {
+      SharingKind::Structure
+    };
+    let mut longs = Vec::new();
+    loop {
+      longs.push(match kind {
+        SharingKind::Type => self.long_id(true)?,
+        SharingKind::Structure => self.long_alpha_num_id()?,
+      });
+      if let Token::Equal = self.peek().val {
+        self.skip();
+      } else {
+        break;
+      }
+    }
+    if longs.len() < 2 {
+      return self.fail("an identifier", self.peek());
+    }
+    longs.shrink_to_fit();
+    Ok(self.wrap(spec.loc, Spec::Sharing(spec.into(), kind, longs)))
+  }
+
+  /// parses the rest of a `type` specification. SML Definition Appendix A - `type tyvarseq tycon =
+  /// ty` is sugar for `include sig type tyvarseq tycon end where type tyvarseq tycon = ty`.
+  fn type_spec(&mut self) -> Result<Spec<StrRef>> {
+    let mut ty_descs = Vec::new();
+    let mut abbrevs = Vec::new();
+    loop {
+      let begin = self.peek().loc;
+      let ty_vars = self.ty_var_seq()?;
+      let ty_con = self.ident()?;
+      if let Token::Equal = self.peek().val {
+        self.skip();
+        let ty = self.ty()?;
+        let ty_desc = TyDesc {
+          ty_vars: ty_vars.clone(),
+          ty_con,
+        };
+        let spec = self.wrap(begin, Spec::Type(vec![ty_desc], false));
+        let sig_exp = self.wrap(begin, SigExp::Sig(spec));
+        let long = Long {
+          structures: Vec::new(),
+          last: ty_con,
+        };
+        let sig_exp = self.wrap(begin, SigExp::Where(sig_exp.into(), ty_vars, long, ty));
+        abbrevs.push(self.wrap(begin, Spec::Include(sig_exp.into())));
+      } else {
+        ty_descs.push(TyDesc { ty_vars, ty_con });
       }
-      if ty_cons.len() < 2 {
-        return self.fail("an identifier", self.peek());
+      if let Token::And = self.peek().val {
+        self.skip();
+      } else {
+        break;
       }
-      ty_cons.shrink_to_fit();
-      ret = Spec::Sharing(self.wrap(begin, ret).into(), ty_cons);
     }
-    Ok(Some(self.wrap(begin, ret)))
+    if abbrevs.is_empty() {
+      ty_descs.shrink_to_fit();
+      return Ok(Spec::Type(ty_descs, false));
+    }
+    if !ty_descs.is_empty() {
+      let loc = ty_descs[0].ty_con.loc;
+      abbrevs.insert(0, loc.wrap(Spec::Type(ty_descs, false)));
+    }
+    abbrevs.shrink_to_fit();
+    Ok(Spec::Seq(abbrevs))
   }
 
   fn ty_descs(&mut self) -> Result<Vec<TyDesc<StrRef>>> {
@@ -478,7 +993,26 @@ impl Parser {
   }
 
   fn spec(&mut self) -> Result<Located<Spec<StrRef>>> {
-    self.semicolon_seq(Self::maybe_spec, Spec::Seq)
+    // SML Definition (78) - `sharing` applies to all of the specs before it, so this is like
+    // `semicolon_seq`, but it gathers those specs up whenever it sees a `sharing`.
+    let depth = self.depth;
+    self.deeper(self.peek().loc)?;
+    let mut xs = Vec::new();
+    loop {
+      if let Token::Sharing = self.peek().val {
+        let spec = self.seq(xs, Spec::Seq);
+        xs = vec![self.sharing(spec)?];
+      } else if let Some(x) = self.maybe_spec()? {
+        xs.push(x);
+      } else {
+        break;
+      }
+      if let Token::Semicolon = self.peek().val {
+        self.skip();
+      }
+    }
+    self.depth = depth;
+    Ok(self.seq(xs, Spec::Seq))
   }
 
   fn maybe_at_exp(&mut self) -> Result<Option<Located<Exp<StrRef>>>> {
@@ -501,9 +1035,9 @@ impl Parser {
         self.skip();
         Exp::HexWord(n)
       }
-      Token::Real(n) => {
+      Token::Real(n, text) => {
         self.skip();
-        Exp::Real(n)
+        Exp::Real(n, text)
       }
       Token::String(s) => {
         self.skip();
@@ -519,26 +1053,12 @@ impl Parser {
       }
       Token::LCurly => {
         self.skip();
-        let mut rows = Vec::new();
-        if let Token::RCurly = self.peek().val {
-          self.skip();
-        } else {
-          loop {
-            let lab = self.label()?;
-            self.eat(Token::Equal)?;
-            let val = self.exp()?;
-            rows.push(Row { lab, val });
-            let tok = self.peek();
-            self.skip();
-            match tok.val {
-              Token::RCurly => break,
-              Token::Comma => continue,
-              _ => return self.fail("`}` or `,`", tok),
-            }
-          }
-        }
-        rows.shrink_to_fit();
-        Exp::Record(rows)
+        self.record_exp()?
+      }
+      Token::Pound if self.vector_start() => {
+        self.skip();
+        self.skip();
+        Exp::Vector(self.list_rest(Self::exp)?)
       }
       Token::Pound => {
         self.skip();
@@ -546,85 +1066,15 @@ impl Parser {
       }
       Token::LRound => {
         self.skip();
-        if let Token::RRound = self.peek().val {
-          self.skip();
-          return Ok(Some(self.wrap(begin, Exp::Tuple(Vec::new()))));
-        }
-        let fst = self.exp()?;
-        let tok = self.peek();
-        self.skip();
-        match tok.val {
-          Token::RRound => fst.val,
-          Token::Comma => {
-            let mut exprs = vec![fst];
-            loop {
-              exprs.push(self.exp()?);
-              let tok = self.peek();
-              self.skip();
-              match tok.val {
-                Token::RRound => break,
-                Token::Comma => continue,
-                _ => return self.fail("`)` or `,`", tok),
-              }
-            }
-            Exp::Tuple(exprs)
-          }
-          Token::Semicolon => {
-            let mut exprs = vec![fst];
-            loop {
-              exprs.push(self.exp()?);
-              let tok = self.peek();
-              self.skip();
-              match tok.val {
-                Token::RRound => break,
-                Token::Semicolon => continue,
-                _ => return self.fail("`)` or `;`", tok),
-              }
-            }
-            Exp::Sequence(exprs)
-          }
-          _ => return self.fail("`)`, `,`, or `;`", tok),
-        }
+        self.paren_exp(tok)?
       }
       Token::LSquare => {
         self.skip();
-        let mut exprs = Vec::new();
-        if let Token::RSquare = self.peek().val {
-          self.skip();
-        } else {
-          loop {
-            exprs.push(self.exp()?);
-            let tok = self.peek();
-            self.skip();
-            match tok.val {
-              Token::RSquare => break,
-              Token::Comma => continue,
-              _ => return self.fail("`]` or `,`", tok),
-            }
-          }
-        }
-        exprs.shrink_to_fit();
-        Exp::List(exprs)
+        Exp::List(self.list_rest(Self::exp)?)
       }
       Token::Let => {
         self.skip();
-        let ops = self.ops.clone();
-        let dec = self.dec()?;
-        self.eat(Token::In)?;
-        let mut exprs = Vec::new();
-        loop {
-          exprs.push(self.exp()?);
-          let tok = self.peek();
-          self.skip();
-          match tok.val {
-            Token::End => break,
-            Token::Semicolon => continue,
-            _ => return self.fail("`end` or `;`", tok),
-          }
-        }
-        self.ops = ops;
-        exprs.shrink_to_fit();
-        Exp::Let(dec, exprs)
+        self.let_exp(tok)?
       }
       Token::Ident(..) => Exp::LongVid(self.long_id(false)?),
       Token::Equal => {
@@ -640,6 +1090,111 @@ impl Parser {
     Ok(Some(self.wrap(begin, ret)))
   }
 
+  /// Parses the rest of a record expression, after the `{`.
+  fn record_exp(&mut self) -> Result<Exp<StrRef>> {
+    let mut rows = Vec::new();
+    if let Token::RCurly = self.peek().val {
+      self.skip();
+    } else {
+      loop {
+        let lab = self.label()?;
+        self.eat(Token::Equal)?;
+        let val = self.exp()?;
+        rows.push(Row { lab, val });
+        let tok = self.peek();
+        self.skip();
+        match tok.val {
+          Token::RCurly => break,
+          Token::Comma => continue,
+          _ => return self.fail("`}` or `,`", tok),
+        }
+      }
+    }
+    rows.shrink_to_fit();
+    Ok(Exp::Record(rows))
+  }
+
+  /// Parses the rest of a parenthesized expression, tuple, or sequence, after the `(` token `open`.
+  fn paren_exp(&mut self, open: Located<Token>) -> Result<Exp<StrRef>> {
+    if let Token::RRound = self.peek().val {
+      self.skip();
+      return Ok(Exp::Tuple(Vec::new()));
+    }
+    self.open.push(open);
+    let fst = self.exp()?;
+    let tok = self.peek();
+    self.skip();
+    let ret = match tok.val {
+      Token::RRound => fst.val,
+      Token::Comma => {
+        let mut exprs = vec![fst];
+        loop {
+          exprs.push(self.exp()?);
+          let tok = self.peek();
+          self.skip();
+          match tok.val {
+            Token::RRound => break,
+            Token::Comma => continue,
+            _ => return self.fail("`)` or `,`", tok),
+          }
+        }
+        Exp::Tuple(exprs)
+      }
+      Token::Semicolon => {
+        let mut exprs = vec![fst];
+        loop {
+          if self.trailing_semicolon(Token::RRound) {
+            break;
+          }
+          exprs.push(self.exp()?);
+          let tok = self.peek();
+          self.skip();
+          match tok.val {
+            Token::RRound => break,
+            Token::Semicolon => continue,
+            _ => return self.fail("`)` or `;`", tok),
+          }
+        }
+        if exprs.len() == 1 {
+          exprs.pop().unwrap().val
+        } else {
+          Exp::Sequence(exprs)
+        }
+      }
+      _ => return self.fail("`)`, `,`, or `;`", tok),
+    };
+    self.open.pop();
+    Ok(ret)
+  }
+
+  /// Parses the rest of a `let` expression, after the `let` token `open`.
+  fn let_exp(&mut self, open: Located<Token>) -> Result<Exp<StrRef>> {
+    self.open.push(open);
+    let ops = self.save_ops();
+    let dec = self.dec()?;
+    self.eat(Token::In)?;
+    let mut exprs = Vec::new();
+    loop {
+      exprs.push(self.exp()?);
+      let tok = self.peek();
+      self.skip();
+      match tok.val {
+        Token::End => break,
+        Token::Semicolon => {
+          if self.trailing_semicolon(Token::End) {
+            break;
+          }
+          continue;
+        }
+        _ => return self.fail("`end` or `;`", tok),
+      }
+    }
+    self.open.pop();
+    self.restore_ops(ops);
+    exprs.shrink_to_fit();
+    Ok(Exp::Let(dec, exprs))
+  }
+
   fn at_exp(&mut self) -> Result<Located<Exp<StrRef>>> {
     match self.maybe_at_exp()? {
       Some(x) => Ok(x),
@@ -746,122 +1301,143 @@ impl Parser {
   fn exp_prec(&mut self, min_prec: Option<OpInfo>) -> Result<Located<Exp<StrRef>>> {
     let tok = self.peek();
     let begin = tok.loc;
+    let depth = self.depth;
+    self.deeper(begin)?;
+    // every case is in its own function, so that the stack frame of this one, which is in every
+    // level of recursion, stays small. see `STACK_SIZE`.
     let ret = match tok.val {
-      Token::Raise => {
-        self.skip();
-        let e = self.exp()?;
-        Exp::Raise(e.into())
-      }
-      Token::If => {
-        self.skip();
-        let e_cond = self.exp()?;
-        self.eat(Token::Then)?;
-        let e_then = self.exp()?;
-        self.eat(Token::Else)?;
-        let e_else = self.exp()?;
-        Exp::If(e_cond.into(), e_then.into(), e_else.into())
-      }
-      Token::While => {
-        self.skip();
-        let e_cond = self.exp()?;
-        self.eat(Token::Do)?;
-        let e_body = self.exp()?;
-        Exp::While(e_cond.into(), e_body.into())
-      }
-      Token::Case => {
-        self.skip();
-        let e_head = self.exp()?;
-        self.eat(Token::Of)?;
-        let cases = self.cases()?;
-        Exp::Case(e_head.into(), cases)
-      }
+      Token::Raise => self.raise_exp()?,
+      Token::If => self.if_exp()?,
+      Token::While => self.while_exp()?,
+      Token::Case => self.case_exp()?,
       Token::Fn => {
         self.skip();
-        let cases = self.cases()?;
-        Exp::Fn(cases)
+        Exp::Fn(self.cases()?)
       }
-      _ => {
-        let mut exp = self.at_exp()?;
-        loop {
-          let tok = self.peek();
-          exp = exp.loc.wrap(match tok.val {
-            Token::Ident(..) | Token::Equal => {
-              let id = match tok.val {
-                Token::Ident(id, _) => id,
-                Token::Equal => StrRef::EQ,
-                _ => unreachable!(),
-              };
-              self.skip();
-              if self.peek().val == Token::Dot {
-                self.i -= 1;
-                let long = self.long_id(true)?;
-                let rhs = exp.loc.wrap(Exp::LongVid(long));
-                Exp::App(exp.into(), rhs.into())
-              } else {
-                match self.ops.get(&id) {
-                  Some(&op_info) => {
-                    if op_info.should_break(min_prec, tok.loc)? {
-                      self.i -= 1;
-                      break;
-                    }
-                    let rhs = self.exp_prec(Some(op_info))?;
-                    Exp::InfixApp(exp.into(), tok.loc.wrap(id), rhs.into())
-                  }
-                  None => {
-                    // not a LongVid because we just saw not Dot after this.
-                    let rhs = exp.loc.wrap(Exp::LongVid(Long {
-                      structures: Vec::new(),
-                      last: tok.loc.wrap(id),
-                    }));
-                    Exp::App(exp.into(), rhs.into())
-                  }
+      _ => self.infix_exp(min_prec)?,
+    };
+    self.depth = depth;
+    Ok(self.wrap(begin, ret))
+  }
+
+  fn raise_exp(&mut self) -> Result<Exp<StrRef>> {
+    self.skip();
+    let e = self.exp()?;
+    Ok(Exp::Raise(e.into()))
+  }
+
+  fn if_exp(&mut self) -> Result<Exp<StrRef>> {
+    self.skip();
+    let e_cond = self.exp()?;
+    self.eat(Token::Then)?;
+    let e_then = self.exp()?;
+    self.eat(Token::Else)?;
+    let e_else = self.exp()?;
+    Ok(Exp::If(e_cond.into(), e_then.into(), e_else.into()))
+  }
+
+  fn while_exp(&mut self) -> Result<Exp<StrRef>> {
+    self.skip();
+    let e_cond = self.exp()?;
+    self.eat(Token::Do)?;
+    let e_body = self.exp()?;
+    Ok(Exp::While(e_cond.into(), e_body.into()))
+  }
+
+  fn case_exp(&mut self) -> Result<Exp<StrRef>> {
+    self.skip();
+    let e_head = self.exp()?;
+    self.eat(Token::Of)?;
+    let cases = self.cases()?;
+    Ok(Exp::Case(e_head.into(), cases))
+  }
+
+  /// Parses an application or infix expression, or anything after it which extends it, like `:`,
+  /// `andalso`, or `handle`, as long as it binds tighter than `min_prec`.
+  fn infix_exp(&mut self, min_prec: Option<OpInfo>) -> Result<Exp<StrRef>> {
+    let mut exp = self.at_exp()?;
+    loop {
+      let tok = self.peek();
+      let loc = exp.loc;
+      let val = match tok.val {
+        Token::Ident(..) | Token::Equal => {
+          let id = match tok.val {
+            Token::Ident(id, _) => id,
+            Token::Equal => StrRef::EQ,
+            _ => unreachable!(),
+          };
+          self.skip();
+          if self.peek().val == Token::Dot {
+            self.i -= 1;
+            let long = self.long_id(true)?;
+            let rhs = self.wrap(tok.loc, Exp::LongVid(long));
+            Exp::App(exp.into(), rhs.into())
+          } else {
+            match self.ops.get(&id) {
+              Some(&op_info) => {
+                if op_info.should_break(min_prec, tok.loc)? {
+                  self.i -= 1;
+                  break;
                 }
+                let rhs = self.exp_prec(Some(op_info))?;
+                Exp::InfixApp(exp.into(), tok.loc.wrap(id), rhs.into())
               }
-            }
-            Token::Colon => {
-              if min_prec.is_some() {
-                break;
-              }
-              self.skip();
-              let ty = self.ty()?;
-              Exp::Typed(exp.into(), ty)
-            }
-            Token::Andalso => {
-              if min_prec.is_some() {
-                break;
-              }
-              self.skip();
-              let rhs = self.exp()?;
-              Exp::Andalso(exp.into(), rhs.into())
-            }
-            Token::Orelse => {
-              if min_prec.is_some() {
-                break;
-              }
-              self.skip();
-              let rhs = self.exp()?;
-              Exp::Orelse(exp.into(), rhs.into())
-            }
-            Token::Handle => {
-              if min_prec.is_some() {
-                break;
+              None => {
+                // not a LongVid because we just saw not Dot after this.
+                let rhs = tok.loc.wrap(Exp::LongVid(Long {
+                  structures: Vec::new(),
+                  last: tok.loc.wrap(id),
+                }));
+                Exp::App(exp.into(), rhs.into())
               }
-              self.skip();
-              Exp::Handle(exp.into(), self.cases()?)
             }
-            _ => match self.maybe_at_exp()? {
-              Some(rhs) => Exp::App(exp.into(), rhs.into()),
-              None => break,
-            },
-          });
+          }
+        }
+        Token::Colon => {
+          if min_prec.is_some() {
+            break;
+          }
+          self.skip();
+          let ty = self.ty()?;
+          Exp::Typed(exp.into(), ty)
+        }
+        Token::Andalso => {
+          if min_prec.is_some() {
+            break;
+          }
+          self.skip();
+          let rhs = self.exp()?;
+          Exp::Andalso(exp.into(), rhs.into())
         }
-        exp.val
-      }
-    };
-    Ok(self.wrap(begin, ret))
+        Token::Orelse => {
+          if min_prec.is_some() {
+            break;
+          }
+          self.skip();
+          let rhs = self.exp()?;
+          Exp::Orelse(exp.into(), rhs.into())
+        }
+        Token::Handle => {
+          if min_prec.is_some() {
+            break;
+          }
+          self.skip();
+          Exp::Handle(exp.into(), self.cases()?)
+        }
+        _ => match self.maybe_at_exp()? {
+          Some(rhs) => Exp::App(exp.into(), rhs.into()),
+          None => break,
+        },
+      };
+      exp = self.wrap(loc, val);
+      // each time around, the expression so far is nested in a new one.
+      self.deeper(tok.loc)?;
+    }
+    Ok(exp.val)
   }
 
   fn cases(&mut self) -> Result<Cases<StrRef>> {
+    self.maybe_leading_bar();
     let mut arms = Vec::new();
     loop {
       let pat = self.pat()?;
@@ -884,51 +1460,24 @@ impl Parser {
     let ret = match tok.val {
       Token::Val => {
         self.skip();
-        let ty_vars = self.ty_var_seq()?;
-        let mut val_binds = Vec::new();
-        loop {
-          let rec = if let Token::Rec = self.peek().val {
-            self.skip();
-            true
-          } else {
-            false
-          };
-          let pat = self.pat()?;
-          self.eat(Token::Equal)?;
-          let exp = self.exp()?;
-          val_binds.push(ValBind { rec, pat, exp });
-          if let Token::And = self.peek().val {
-            self.skip();
-          } else {
-            break;
-          }
-        }
-        val_binds.shrink_to_fit();
-        Dec::Val(ty_vars, val_binds)
+        self.val_dec()?
+      }
+      Token::Do if self.features.do_dec => {
+        self.skip();
+        let exp = self.exp()?;
+        let pat = begin.wrap(Pat::Tuple(Vec::new()));
+        Dec::Val(
+          Vec::new(),
+          vec![ValBind {
+            rec: false,
+            pat,
+            exp,
+          }],
+        )
       }
       Token::Fun => {
         self.skip();
-        let ty_vars = self.ty_var_seq()?;
-        let mut cases = Vec::new();
-        let mut binds = Vec::new();
-        loop {
-          cases.push(self.fval_bind_case()?);
-          let tok = self.peek();
-          if let Token::Bar = tok.val {
-            self.skip();
-            continue;
-          }
-          cases.shrink_to_fit();
-          binds.push(FValBind { cases });
-          if let Token::And = tok.val {
-            self.skip();
-            cases = Vec::new();
-            continue;
-          }
-          break;
-        }
-        binds.shrink_to_fit();
-        Dec::Fun(ty_vars, binds)
+        self.fun_dec()?
       }
       Token::Type => {
         self.skip();
@@ -948,58 +1497,15 @@ impl Parser {
       },
       Token::Abstype => {
         self.skip();
-        let mut dat_binds = vec![self.dat_bind(true)?];
-        while let Token::And = self.peek().val {
-          self.skip();
-          dat_binds.push(self.dat_bind(true)?);
-        }
-        let ty_binds = if let Token::Withtype = self.peek().val {
-          self.skip();
-          self.ty_binds()?
-        } else {
-          Vec::new()
-        };
-        self.eat(Token::With)?;
-        let dec = self.dec()?;
-        self.eat(Token::End)?;
-        Dec::Abstype(dat_binds, ty_binds, dec.into())
+        self.abstype_dec()?
       }
       Token::Exception => {
         self.skip();
-        let mut ex_binds = Vec::new();
-        loop {
-          if let Token::Op = self.peek().val {
-            self.skip();
-          }
-          let vid = self.ident()?;
-          let inner = if let Token::Equal = self.peek().val {
-            self.skip();
-            if let Token::Op = self.peek().val {
-              self.skip();
-            }
-            ExBindInner::Long(self.long_id(true)?)
-          } else {
-            ExBindInner::Ty(self.maybe_of_ty()?)
-          };
-          ex_binds.push(ExBind { vid, inner });
-          if let Token::And = self.peek().val {
-            self.skip();
-          } else {
-            break;
-          }
-        }
-        ex_binds.shrink_to_fit();
-        Dec::Exception(ex_binds)
+        self.exception_dec()?
       }
       Token::Local => {
         self.skip();
-        let ops = self.ops.clone();
-        let fst = self.dec()?;
-        self.eat(Token::In)?;
-        let snd = self.dec()?;
-        self.eat(Token::End)?;
-        self.ops = ops;
-        Dec::Local(fst.into(), snd.into())
+        self.local_dec()?
       }
       Token::Open => {
         self.skip();
@@ -1012,6 +1518,9 @@ impl Parser {
             break;
           }
         }
+        for long in str_ids.iter() {
+          self.open_ops(long);
+        }
         str_ids.shrink_to_fit();
         Dec::Open(str_ids)
       }
@@ -1046,24 +1555,136 @@ impl Parser {
     Ok(Some(self.wrap(begin, ret)))
   }
 
+  /// Parses the rest of a `val` declaration, after the `val`.
+  fn val_dec(&mut self) -> Result<Dec<StrRef>> {
+    let ty_vars = self.ty_var_seq()?;
+    let mut val_binds = Vec::new();
+    loop {
+      let rec = if let Token::Rec = self.peek().val {
+        self.skip();
+        true
+      } else {
+        false
+      };
+      let pat = self.pat()?;
+      self.eat(Token::Equal)?;
+      let exp = self.exp()?;
+      val_binds.push(ValBind { rec, pat, exp });
+      if let Token::And = self.peek().val {
+        self.skip();
+      } else {
+        break;
+      }
+    }
+    val_binds.shrink_to_fit();
+    Ok(Dec::Val(ty_vars, val_binds))
+  }
+
+  /// Parses the rest of a `fun` declaration, after the `fun`.
+  fn fun_dec(&mut self) -> Result<Dec<StrRef>> {
+    let ty_vars = self.ty_var_seq()?;
+    let mut cases = Vec::new();
+    let mut binds = Vec::new();
+    loop {
+      cases.push(self.fval_bind_case()?);
+      let tok = self.peek();
+      if let Token::Bar = tok.val {
+        self.skip();
+        continue;
+      }
+      cases.shrink_to_fit();
+      binds.push(FValBind { cases });
+      if let Token::And = tok.val {
+        self.skip();
+        cases = Vec::new();
+        continue;
+      }
+      break;
+    }
+    binds.shrink_to_fit();
+    Ok(Dec::Fun(ty_vars, binds))
+  }
+
+  /// Parses the rest of an `abstype` declaration, after the `abstype`.
+  fn abstype_dec(&mut self) -> Result<Dec<StrRef>> {
+    let mut dat_binds = vec![self.dat_bind(true)?];
+    while let Token::And = self.peek().val {
+      self.skip();
+      dat_binds.push(self.dat_bind(true)?);
+    }
+    let ty_binds = if let Token::Withtype = self.peek().val {
+      self.skip();
+      self.ty_binds()?
+    } else {
+      Vec::new()
+    };
+    self.eat(Token::With)?;
+    let dec = self.dec()?;
+    self.eat(Token::End)?;
+    Ok(Dec::Abstype(dat_binds, ty_binds, dec.into()))
+  }
+
+  /// Parses the rest of an `exception` declaration, after the `exception`.
+  fn exception_dec(&mut self) -> Result<Dec<StrRef>> {
+    let mut ex_binds = Vec::new();
+    loop {
+      if let Token::Op = self.peek().val {
+        self.skip();
+      }
+      let vid = self.ident()?;
+      let inner = if let Token::Equal = self.peek().val {
+        self.skip();
+        if let Token::Op = self.peek().val {
+          self.skip();
+        }
+        ExBindInner::Long(self.long_id(true)?)
+      } else {
+        ExBindInner::Ty(self.maybe_of_ty()?)
+      };
+      ex_binds.push(ExBind { vid, inner });
+      if let Token::And = self.peek().val {
+        self.skip();
+      } else {
+        break;
+      }
+    }
+    ex_binds.shrink_to_fit();
+    Ok(Dec::Exception(ex_binds))
+  }
+
+  /// Parses the rest of a `local` declaration, after the `local`.
+  fn local_dec(&mut self) -> Result<Dec<StrRef>> {
+    let ops = self.save_ops();
+    let fst = self.dec()?;
+    self.eat(Token::In)?;
+    let snd = self.dec()?;
+    self.eat(Token::End)?;
+    self.restore_ops(ops);
+    Ok(Dec::Local(fst.into(), snd.into()))
+  }
+
   fn dec(&mut self) -> Result<Located<Dec<StrRef>>> {
     self.semicolon_seq(Self::maybe_dec, Dec::Seq)
   }
 
   fn fval_bind_case(&mut self) -> Result<FValBindCase<StrRef>> {
     let cur = self.i;
+    let open = self.open.len();
     let (vid, pats) = if let Ok((vid, pat)) = self.fval_bind_case_no_parens() {
       (vid, vec![pat])
     } else {
       // NOTE unbounded backtrack
       self.i = cur;
+      self.open.truncate(open);
       let tok = self.peek();
       self.skip();
       let (vid, pat) = match tok.val {
         Token::Op => (self.ident()?, self.at_pat()?),
         Token::LRound => {
+          self.open.push(tok);
           let x = self.fval_bind_case_no_parens()?;
           self.eat(Token::RRound)?;
+          self.open.pop();
           x
         }
         Token::Ident(vid, _) => {
@@ -1098,7 +1719,7 @@ impl Parser {
       return Err(vid.loc.wrap(Error::NotInfix(vid.val)));
     }
     let snd = self.at_pat()?;
-    Ok((vid, fst.loc.wrap(Pat::Tuple(vec![fst, snd]))))
+    Ok((vid, fst.loc.span(snd.loc).wrap(Pat::Tuple(vec![fst, snd]))))
   }
 
   fn ty_binds(&mut self) -> Result<Vec<TyBind<StrRef>>> {
@@ -1153,6 +1774,7 @@ impl Parser {
   }
 
   fn con_binds(&mut self, allow_op: bool) -> Result<Vec<ConBind<StrRef>>> {
+    self.maybe_leading_bar();
     let mut ret = Vec::new();
     loop {
       if allow_op {
@@ -1249,7 +1871,7 @@ impl Parser {
         self.skip();
         Pat::HexWord(n)
       }
-      Token::Real(..) => return Err(begin.wrap(Error::RealPat)),
+      Token::Real(_, text) => return Err(begin.wrap(Error::RealPat(text))),
       Token::String(s) => {
         self.skip();
         Pat::String(s)
@@ -1264,113 +1886,114 @@ impl Parser {
       }
       Token::LCurly => {
         self.skip();
-        let mut rows = Vec::new();
-        let mut rest_loc = None;
-        if let Token::RCurly = self.peek().val {
-          self.skip();
-        } else {
-          loop {
-            let tok = self.peek();
-            if let Token::DotDotDot = tok.val {
-              self.skip();
-              rest_loc = Some(tok.loc);
-              if let Token::RCurly = self.peek().val {
-                self.skip();
-                break;
-              }
-              return self.fail("`}`", tok);
-            }
-            let lab = self.label()?;
-            let tok = self.peek();
-            let val = if let Token::Equal = tok.val {
-              self.skip();
-              self.pat()?
-            } else {
-              let vid = match lab.val {
-                Label::Vid(x) => lab.loc.wrap(x),
-                Label::Num(..) => return self.fail("`=`", tok),
-              };
-              let ty = self.maybe_colon_ty()?;
-              let as_pat = self.maybe_as_pat()?;
-              match as_pat {
-                Some(as_pat) => vid
-                  .loc
-                  .span(as_pat.loc)
-                  .wrap(Pat::As(vid, ty, as_pat.into())),
-                None => {
-                  let pat = vid.loc.wrap(Pat::LongVid(Long {
-                    structures: Vec::new(),
-                    last: vid,
-                  }));
-                  match ty {
-                    None => pat,
-                    Some(ty) => vid.loc.span(ty.loc).wrap(Pat::Typed(pat.into(), ty)),
-                  }
-                }
-              }
-            };
-            rows.push(Row { lab, val });
-            let tok = self.peek();
-            self.skip();
-            match tok.val {
-              Token::RCurly => break,
-              Token::Comma => continue,
-              _ => return self.fail("`}` or `,`", tok),
-            }
-          }
-        }
-        rows.shrink_to_fit();
-        Pat::Record(rows, rest_loc)
+        self.record_pat()?
       }
       Token::LRound => {
         self.skip();
+        self.paren_pat(tok)?
+      }
+      Token::LSquare => {
+        self.skip();
+        Pat::List(self.list_rest(Self::pat)?)
+      }
+      Token::Pound if self.vector_start() => {
+        self.skip();
+        self.skip();
+        Pat::Vector(self.list_rest(Self::pat)?)
+      }
+      Token::Ident(..) => Pat::LongVid(self.long_id(false)?),
+      _ => return Ok(None),
+    };
+    Ok(Some(self.wrap(begin, ret)))
+  }
+
+  /// Parses the rest of a record pattern, after the `{`.
+  fn record_pat(&mut self) -> Result<Pat<StrRef>> {
+    let mut rows = Vec::new();
+    let mut rest_loc = None;
+    if let Token::RCurly = self.peek().val {
+      self.skip();
+    } else {
+      loop {
         let tok = self.peek();
-        let mut pats = Vec::new();
-        if let Token::RRound = tok.val {
+        if let Token::DotDotDot = tok.val {
           self.skip();
-        } else {
-          loop {
-            pats.push(self.pat()?);
-            let tok = self.peek();
+          rest_loc = Some(tok.loc);
+          if let Token::RCurly = self.peek().val {
             self.skip();
-            match tok.val {
-              Token::RRound => break,
-              Token::Comma => continue,
-              _ => return self.fail("`)` or `,`", tok),
-            }
+            break;
           }
+          return self.fail("`}`", tok);
         }
-        if pats.len() == 1 {
-          pats.pop().unwrap().val
-        } else {
-          pats.shrink_to_fit();
-          Pat::Tuple(pats)
-        }
-      }
-      Token::LSquare => {
-        self.skip();
-        let mut pats = Vec::new();
-        if let Token::RSquare = self.peek().val {
+        let lab = self.label()?;
+        let tok = self.peek();
+        let val = if let Token::Equal = tok.val {
           self.skip();
+          self.pat()?
         } else {
-          loop {
-            pats.push(self.pat()?);
-            let tok = self.peek();
-            self.skip();
-            match tok.val {
-              Token::RSquare => break,
-              Token::Comma => continue,
-              _ => return self.fail("`]` or `,`", tok),
+          let vid = match lab.val {
+            Label::Vid(x) => lab.loc.wrap(x),
+            Label::Num(..) => return self.fail("`=`", tok),
+          };
+          let ty = self.maybe_colon_ty()?;
+          let as_pat = self.maybe_as_pat()?;
+          match as_pat {
+            Some(as_pat) => vid
+              .loc
+              .span(as_pat.loc)
+              .wrap(Pat::As(vid, ty, as_pat.into())),
+            None => {
+              let pat = vid.loc.wrap(Pat::LongVid(Long {
+                structures: Vec::new(),
+                last: vid,
+              }));
+              match ty {
+                None => pat,
+                Some(ty) => vid.loc.span(ty.loc).wrap(Pat::Typed(pat.into(), ty)),
+              }
             }
           }
+        };
+        rows.push(Row { lab, val });
+        let tok = self.peek();
+        self.skip();
+        match tok.val {
+          Token::RCurly => break,
+          Token::Comma => continue,
+          _ => return self.fail("`}` or `,`", tok),
         }
-        pats.shrink_to_fit();
-        Pat::List(pats)
       }
-      Token::Ident(..) => Pat::LongVid(self.long_id(false)?),
-      _ => return Ok(None),
-    };
-    Ok(Some(self.wrap(begin, ret)))
+    }
+    rows.shrink_to_fit();
+    Ok(Pat::Record(rows, rest_loc))
+  }
+
+  /// Parses the rest of a parenthesized or tuple pattern, after the `(` token `open`.
+  fn paren_pat(&mut self, open: Located<Token>) -> Result<Pat<StrRef>> {
+    self.open.push(open);
+    let tok = self.peek();
+    let mut pats = Vec::new();
+    if let Token::RRound = tok.val {
+      self.skip();
+    } else {
+      loop {
+        pats.push(self.pat()?);
+        let tok = self.peek();
+        self.skip();
+        match tok.val {
+          Token::RRound => break,
+          Token::Comma => continue,
+          _ => return self.fail("`)` or `,`", tok),
+        }
+      }
+    }
+    self.open.pop();
+    if pats.len() == 1 {
+      Ok(pats.pop().unwrap().val)
+    } else {
+      pats.shrink_to_fit();
+      Ok(Pat::Tuple(pats))
+    }
   }
 
   fn at_pat(&mut self) -> Result<Located<Pat<StrRef>>> {
@@ -1385,6 +2008,8 @@ impl Parser {
   }
 
   fn pat_prec(&mut self, min_prec: Option<OpInfo>) -> Result<Located<Pat<StrRef>>> {
+    let depth = self.depth;
+    self.deeper(self.peek().loc)?;
     let mut ret = self.at_pat()?;
     if let Pat::LongVid(long_vid) = ret.val {
       let pat = self.pat_long_vid(ret.loc, long_vid)?;
@@ -1417,7 +2042,9 @@ impl Parser {
         _ => break,
       };
       ret = self.wrap(loc, pat);
+      self.deeper(tok.loc)?;
     }
+    self.depth = depth;
     Ok(ret)
   }
 
@@ -1464,6 +2091,59 @@ impl Parser {
     }
   }
 
+  /// Parses the rest of a record type, after the `{`.
+  fn record_ty(&mut self) -> Result<Ty<StrRef>> {
+    let mut rows = Vec::new();
+    if let Token::RCurly = self.peek().val {
+      self.skip();
+    } else {
+      loop {
+        let lab = self.label()?;
+        self.eat(Token::Colon)?;
+        let val = self.ty()?;
+        rows.push(Row { lab, val });
+        let tok = self.peek();
+        self.skip();
+        match tok.val {
+          Token::RCurly => break,
+          Token::Comma => continue,
+          _ => return self.fail("`}` or `,`", tok),
+        }
+      }
+    }
+    rows.shrink_to_fit();
+    Ok(Ty::Record(rows))
+  }
+
+  /// Parses the rest of a parenthesized type, or a type constructor applied to a sequence of
+  /// types, after the `(` token `open`.
+  fn paren_ty(&mut self, open: Located<Token>) -> Result<Ty<StrRef>> {
+    self.open.push(open);
+    let mut types = Vec::new();
+    loop {
+      types.push(self.ty()?);
+      let tok = self.peek();
+      self.skip();
+      match tok.val {
+        Token::RRound => break,
+        Token::Comma => continue,
+        _ => return self.fail("`)` or `,`", tok),
+      }
+    }
+    self.open.pop();
+    let long_ty_con = match self.peek().val {
+      // `*` is never a type constructor, so this is the start of a tuple type.
+      Token::Ident(id, _) if id == StrRef::STAR => None,
+      _ => self.maybe_long_id()?,
+    };
+    types.shrink_to_fit();
+    match (types.len(), long_ty_con) {
+      (1, None) => Ok(types.pop().unwrap().val),
+      (_, None) => self.fail("an identifier", self.peek()),
+      (_, Some(x)) => Ok(Ty::TyCon(types, x)),
+    }
+  }
+
   fn ty(&mut self) -> Result<Located<Ty<StrRef>>> {
     self.ty_prec(TyPrec::Arrow)
   }
@@ -1471,6 +2151,8 @@ impl Parser {
   fn ty_prec(&mut self, min_prec: TyPrec) -> Result<Located<Ty<StrRef>>> {
     let tok = self.peek();
     let begin = tok.loc;
+    let depth = self.depth;
+    self.deeper(begin)?;
     let mut ret = match tok.val {
       Token::TyVar(tv) => {
         self.skip();
@@ -1478,53 +2160,18 @@ impl Parser {
       }
       Token::LCurly => {
         self.skip();
-        let mut rows = Vec::new();
-        if let Token::RCurly = self.peek().val {
-          self.skip();
-        } else {
-          loop {
-            let lab = self.label()?;
-            self.eat(Token::Colon)?;
-            let val = self.ty()?;
-            rows.push(Row { lab, val });
-            let tok = self.peek();
-            self.skip();
-            match tok.val {
-              Token::RCurly => break,
-              Token::Comma => continue,
-              _ => return self.fail("`}` or `,`", tok),
-            }
-          }
-        }
-        rows.shrink_to_fit();
-        Ty::Record(rows)
+        self.record_ty()?
       }
       Token::LRound => {
         self.skip();
-        let mut types = Vec::new();
-        loop {
-          types.push(self.ty()?);
-          let tok = self.peek();
-          self.skip();
-          match tok.val {
-            Token::RRound => break,
-            Token::Comma => continue,
-            _ => return self.fail("`(` or `,`", tok),
-          }
-        }
-        let long_ty_con = self.maybe_long_id()?;
-        types.shrink_to_fit();
-        match (types.len(), long_ty_con) {
-          (1, None) => types.pop().unwrap().val,
-          (_, None) => return self.fail("an identifier", self.peek()),
-          (_, Some(x)) => Ty::TyCon(types, x),
-        }
+        self.paren_ty(tok)?
       }
       Token::Ident(ref id, _) => {
         if *id == StrRef::STAR {
           // can't use self.fail here since the error message will say 'expected a type, found a
           // symbolic identifier' which is just confusing.
-          return Err(tok.loc.wrap(Error::ExpectedButFound("a type", "`*`")));
+          let err = Error::ExpectedButFound("a type", "`*`", Vec::new());
+          return Err(tok.loc.wrap(err));
         }
         let long_ty_con = self.long_id(true)?;
         Ty::TyCon(Vec::new(), long_ty_con)
@@ -1569,7 +2216,9 @@ impl Parser {
         }
         _ => break,
       }
+      self.deeper(tok.loc)?;
     }
+    self.depth = depth;
     Ok(self.wrap(begin, ret))
   }
 
@@ -1578,6 +2227,8 @@ impl Parser {
     F: Fn(&mut Self) -> Result<Option<Located<T>>>,
     G: FnOnce(Vec<Located<T>>) -> T,
   {
+    let depth = self.depth;
+    self.deeper(self.peek().loc)?;
     let mut xs = Vec::new();
     while let Some(x) = one(self)? {
       xs.push(x);
@@ -1585,8 +2236,17 @@ impl Parser {
         self.skip();
       }
     }
+    self.depth = depth;
+    Ok(self.seq(xs, seq))
+  }
+
+  /// returns the sequence of `xs`, which is the sole element if there is just one.
+  fn seq<T, G>(&self, mut xs: Vec<Located<T>>, seq: G) -> Located<T>
+  where
+    G: FnOnce(Vec<Located<T>>) -> T,
+  {
     xs.shrink_to_fit();
-    let ret = match xs.len() {
+    match xs.len() {
       // NOTE we conjure up a 'fake' loc in the 0 case
       0 => self.peek().loc.wrap(seq(Vec::new())),
       1 => xs.pop().unwrap(),
@@ -1596,8 +2256,7 @@ impl Parser {
         .loc
         .span(xs.last().unwrap().loc)
         .wrap(seq(xs)),
-    };
-    Ok(ret)
+    }
   }
 
   fn maybe_of_ty(&mut self) -> Result<Option<Located<Ty<StrRef>>>> {
@@ -1654,7 +2313,16 @@ enum DatatypeDec<I> {
   Copy(Located<I>, Long<I>),
 }
 
-#[derive(Clone, Copy)]
+/// The fixities declared in a structure, which `open` brings into scope. This follows SML/NJ rather
+/// than the Definition, under which fixities are never exported from a structure.
+#[derive(Clone, Default, PartialEq)]
+struct StrOps {
+  /// `None` for identifiers made nonfix.
+  ops: HashMap<StrRef, Option<OpInfo>>,
+  structures: HashMap<StrRef, StrOps>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
 struct OpInfo {
   num: u32,
   assoc: Assoc,
@@ -1706,3 +2374,127 @@ fn option_compare() {
   assert!(Some(3) == Some(3));
   assert!(Some(3) < Some(5));
 }
+
+#[test]
+fn fragments() {
+  let mut store = crate::intern::StrStoreMut::new();
+  let mut lex = |s: &str| crate::lex::get(&mut store, s.as_bytes()).unwrap();
+  match exp(lex("1 + 2 * 3")).unwrap().val {
+    Exp::InfixApp(lhs, op, rhs) => {
+      assert!(matches!(lhs.val, Exp::DecInt(1)));
+      assert_eq!(op.val, StrRef::PLUS);
+      assert!(matches!(rhs.val, Exp::InfixApp(..)));
+    }
+    e => panic!("not an infix application: {:?}", e),
+  }
+  assert!(matches!(pat(lex("(x, _)")).unwrap().val, Pat::Tuple(_)));
+  assert!(matches!(
+    ty(lex("int -> 'a list")).unwrap().val,
+    Ty::Arrow(..)
+  ));
+  assert!(exp(lex("")).is_err());
+  assert!(exp(lex("1 2 val")).is_err());
+}
+
+#[test]
+fn spans() {
+  let src = "f a b : int";
+  let mut store = crate::intern::StrStoreMut::new();
+  let lexer = crate::lex::get(&mut store, src.as_bytes()).unwrap();
+  let text = |loc: Loc| &src[std::ops::Range::from(loc)];
+  let e = exp(lexer).unwrap();
+  assert_eq!(text(e.loc), "f a b : int");
+  let e = match e.val {
+    Exp::Typed(e, _) => e,
+    e => panic!("not typed: {:?}", e),
+  };
+  assert_eq!(text(e.loc), "f a b");
+  match e.val {
+    Exp::App(func, _) => assert_eq!(text(func.loc), "f a"),
+    e => panic!("not an application: {:?}", e),
+  }
+}
+
+#[test]
+fn successor_ml() {
+  let srcs = [
+    "val _ = case 1 of | 1 => 2 | _ => 3",
+    "val _ = fn | x => x",
+    "datatype t = | A | B",
+    "do print \"hi\"",
+    "val _ = (1; 2;)",
+    "val _ = let in 1; 2; end",
+    "val _ = #[1, 2]",
+    "val _ = fn #[x] => x",
+  ];
+  for src in srcs.iter() {
+    let mut store = crate::intern::StrStoreMut::new();
+    let lex =
+      |store: &mut crate::intern::StrStoreMut| crate::lex::get(store, src.as_bytes()).unwrap();
+    assert!(get(lex(&mut store)).is_err(), "{}", src);
+    let got = get_with(lex(&mut store), LanguageFeatures::successor_ml());
+    assert!(got.is_ok(), "{}: {:?}", src, got);
+  }
+}
+
+#[test]
+fn unclosed() {
+  let src = "val a = 1 val b = (a + 1 val c = a structure S = struct val d = c";
+  let mut store = crate::intern::StrStoreMut::new();
+  let lexer = crate::lex::get(&mut store, src.as_bytes()).unwrap();
+  let (top_decs, errors) = get_partial(lexer, LanguageFeatures::default());
+  assert_eq!(top_decs.len(), 2, "{:?} {:?}", top_decs, errors);
+  let text = |loc: Loc| &src[std::ops::Range::from(loc)];
+  let got: Vec<_> = errors
+    .iter()
+    .map(|e| match e.val {
+      Error::Unclosed(opener, gave_up) => (text(e.loc), opener, text(gave_up)),
+      ref e => panic!("not unclosed: {:?}", e),
+    })
+    .collect();
+  assert_eq!(got, [("(", "`(`", "val"), ("struct", "`struct`", "c")]);
+}
+
+#[test]
+fn sharing() {
+  let src = "signature S = sig
+  type t; type u sharing type t = u
+  structure A : sig end and B : sig end
+  sharing A = B
+  functor F (X : sig end) : sig end
+end";
+  let mut store = crate::intern::StrStoreMut::new();
+  let lexer = crate::lex::get(&mut store, src.as_bytes()).unwrap();
+  let top_decs = get(lexer).unwrap();
+  let text = |loc: Loc| &src[std::ops::Range::from(loc)];
+  let spec = match &top_decs[0].val {
+    TopDec::SigDec(sig_binds) => match &sig_binds[0].exp.val {
+      SigExp::Sig(spec) => spec,
+      e => panic!("not a sig: {:?}", e),
+    },
+    d => panic!("not a signature declaration: {:?}", d),
+  };
+  let specs = match &spec.val {
+    Spec::Seq(specs) => specs,
+    s => panic!("not a seq: {:?}", s),
+  };
+  assert_eq!(specs.len(), 2);
+  assert!(matches!(specs[1].val, Spec::Functor(ref xs) if xs.len() == 1));
+  let (inner, kind, longs) = match &specs[0].val {
+    Spec::Sharing(inner, kind, longs) => (inner, kind, longs),
+    s => panic!("not sharing: {:?}", s),
+  };
+  assert_eq!(*kind, SharingKind::Structure);
+  assert_eq!(longs.len(), 2);
+  let specs = match &inner.val {
+    Spec::Seq(specs) => specs,
+    s => panic!("not a seq: {:?}", s),
+  };
+  assert_eq!(specs.len(), 2);
+  assert!(matches!(specs[1].val, Spec::Structure(ref xs) if xs.len() == 2));
+  assert_eq!(text(specs[0].loc), "type t; type u sharing type t = u");
+  assert!(matches!(
+    specs[0].val,
+    Spec::Sharing(_, SharingKind::Type, _)
+  ));
+}
@@ -0,0 +1,479 @@
+//! Projects, which are many files checked in order, each seeing what the ones before it bind.
+
+use crate::ast::{
+  Cases, DatBind, Dec, ExBindInner, Exp, Long, Pat, Row, SigExp, Spec, StrDec, StrExp, TopDec, Ty,
+};
+use crate::intern::StrRef;
+use crate::loc::Located;
+use std::collections::HashSet;
+
+/// Returns an order in which to check `files`, as indices into `files`.
+///
+/// A file which binds a name at the top level comes before the other files which mention that
+/// name, unless they bind it anywhere themselves. This doesn't account for scope, so e.g. a file
+/// with both a function taking an argument `x` and a use of another file's `x` may come too early.
+/// Files in a cycle, and files with no reason to go in a particular order, stay in the order they
+/// had in `files`.
+pub fn order(files: &[&[Located<TopDec<StrRef>>]]) -> Vec<usize> {
+  let names: Vec<_> = files.iter().map(|top_decs| Names::get(top_decs)).collect();
+  // deps[i] are the files which must come before file i.
+  let deps: Vec<Vec<usize>> = names
+    .iter()
+    .enumerate()
+    .map(|(i, names_i)| {
+      names
+        .iter()
+        .enumerate()
+        .filter(|&(j, names_j)| {
+          i != j
+            && names_i
+              .mentioned
+              .iter()
+              .any(|x| names_j.bound.contains(x) && !names_i.binds(x))
+        })
+        .map(|(j, _)| j)
+        .collect()
+    })
+    .collect();
+  let mut done = vec![false; files.len()];
+  let mut ret = Vec::with_capacity(files.len());
+  while ret.len() < files.len() {
+    let ready = (0..files.len()).find(|&i| !done[i] && deps[i].iter().all(|&j| done[j]));
+    // in a cycle, nothing is ready, so take the first file left.
+    let i = ready.unwrap_or_else(|| (0..files.len()).find(|&i| !done[i]).unwrap());
+    done[i] = true;
+    ret.push(i);
+  }
+  ret
+}
+
+/// A name, in one of the namespaces.
+#[derive(PartialEq, Eq, Hash)]
+enum Name {
+  Val(StrRef),
+  Ty(StrRef),
+  Str(StrRef),
+  Sig(StrRef),
+  Fun(StrRef),
+}
+
+/// The names in a file.
+#[derive(Default)]
+struct Names {
+  /// The names bound at the top level.
+  bound: HashSet<Name>,
+  /// The names bound anywhere else.
+  local: HashSet<Name>,
+  /// The names mentioned anywhere. For long names, only the first structure is mentioned.
+  mentioned: HashSet<Name>,
+}
+
+impl Names {
+  fn get(top_decs: &[Located<TopDec<StrRef>>]) -> Self {
+    let mut ret = Self::default();
+    for td in top_decs {
+      ret.top_dec(td);
+    }
+    ret
+  }
+
+  /// Returns whether `name` is bound anywhere.
+  fn binds(&self, name: &Name) -> bool {
+    self.bound.contains(name) || self.local.contains(name)
+  }
+
+  /// Records `name` as bound, at the top level iff `top`.
+  fn bind(&mut self, name: Name, top: bool) {
+    if top {
+      self.bound.insert(name);
+    } else {
+      self.local.insert(name);
+    }
+  }
+
+  fn top_dec(&mut self, td: &Located<TopDec<StrRef>>) {
+    match &td.val {
+      TopDec::StrDec(sd) => self.str_dec(sd, true),
+      TopDec::SigDec(sig_binds) => {
+        for sig_bind in sig_binds {
+          self.bound.insert(Name::Sig(sig_bind.id.val));
+          self.sig_exp(&sig_bind.exp);
+        }
+      }
+      TopDec::FunDec(fun_binds) => {
+        for fun_bind in fun_binds {
+          self.bound.insert(Name::Fun(fun_bind.fun_id.val));
+          self.local.insert(Name::Str(fun_bind.str_id.val));
+          self.sig_exp(&fun_bind.sig_exp);
+          self.str_exp(&fun_bind.str_exp);
+        }
+      }
+    }
+  }
+
+  /// `top` is whether `sd` is at the top level, so what it binds is bound by the file.
+  fn str_dec(&mut self, sd: &Located<StrDec<StrRef>>, top: bool) {
+    match &sd.val {
+      StrDec::Dec(d) => self.dec(d, top),
+      StrDec::Structure(str_binds) => {
+        for str_bind in str_binds {
+          self.bind(Name::Str(str_bind.id.val), top);
+          self.str_exp(&str_bind.exp);
+        }
+      }
+      StrDec::Local(fst, snd) => {
+        self.str_dec(fst, false);
+        self.str_dec(snd, top);
+      }
+      StrDec::Seq(sds) => {
+        for sd in sds {
+          self.str_dec(sd, top);
+        }
+      }
+    }
+  }
+
+  fn str_exp(&mut self, se: &Located<StrExp<StrRef>>) {
+    match &se.val {
+      StrExp::Struct(sd) => self.str_dec(sd, false),
+      StrExp::LongStrId(long) => self.long(long, Name::Str),
+      StrExp::Ascription(se, sig, _) => {
+        self.str_exp(se);
+        self.sig_exp(sig);
+      }
+      StrExp::FunctorApp(fun_id, se) => {
+        self.mentioned.insert(Name::Fun(fun_id.val));
+        self.str_exp(se);
+      }
+      StrExp::Let(sd, se) => {
+        self.str_dec(sd, false);
+        self.str_exp(se);
+      }
+    }
+  }
+
+  fn sig_exp(&mut self, se: &Located<SigExp<StrRef>>) {
+    match &se.val {
+      SigExp::Sig(sp) => self.spec(sp),
+      SigExp::SigId(sig_id) => {
+        self.mentioned.insert(Name::Sig(sig_id.val));
+      }
+      SigExp::Where(se, _, _, t) => {
+        self.sig_exp(se);
+        self.ty(t);
+      }
+    }
+  }
+
+  fn spec(&mut self, sp: &Located<Spec<StrRef>>) {
+    match &sp.val {
+      Spec::Val(val_descs) => {
+        for val_desc in val_descs {
+          self.ty(&val_desc.ty);
+        }
+      }
+      Spec::Type(..) => {}
+      Spec::Datatype(dat_binds) => {
+        for dat_bind in dat_binds {
+          self.dat_bind(dat_bind, false);
+        }
+      }
+      Spec::DatatypeCopy(_, long) => self.long(long, Name::Ty),
+      Spec::Exception(ex_descs) => {
+        for ex_desc in ex_descs {
+          if let Some(t) = &ex_desc.ty {
+            self.ty(t);
+          }
+        }
+      }
+      Spec::Structure(str_descs) => {
+        for str_desc in str_descs {
+          self.sig_exp(&str_desc.exp);
+        }
+      }
+      Spec::Include(se) => self.sig_exp(se),
+      Spec::Functor(fun_descs) => {
+        for fun_desc in fun_descs {
+          self.sig_exp(&fun_desc.param);
+          self.sig_exp(&fun_desc.res);
+        }
+      }
+      Spec::Seq(specs) => {
+        for sp in specs {
+          self.spec(sp);
+        }
+      }
+      Spec::Sharing(sp, _, _) => self.spec(sp),
+    }
+  }
+
+  /// `top` is whether `d` is at the top level, so what it binds is bound by the file.
+  fn dec(&mut self, d: &Located<Dec<StrRef>>, top: bool) {
+    match &d.val {
+      Dec::Val(_, val_binds) => {
+        for val_bind in val_binds {
+          self.pat(&val_bind.pat, top);
+          self.exp(&val_bind.exp);
+        }
+      }
+      Dec::Fun(_, fval_binds) => {
+        for fval_bind in fval_binds {
+          for case in fval_bind.cases.iter() {
+            self.bind(Name::Val(case.vid.val), top);
+            for p in case.pats.iter() {
+              self.pat(p, false);
+            }
+            if let Some(t) = &case.ret_ty {
+              self.ty(t);
+            }
+            self.exp(&case.body);
+          }
+        }
+      }
+      Dec::Type(ty_binds) => {
+        for ty_bind in ty_binds {
+          self.bind(Name::Ty(ty_bind.ty_con.val), top);
+          self.ty(&ty_bind.ty);
+        }
+      }
+      Dec::Datatype(dat_binds, ty_binds) => {
+        for dat_bind in dat_binds {
+          self.dat_bind(dat_bind, top);
+        }
+        for ty_bind in ty_binds {
+          self.bind(Name::Ty(ty_bind.ty_con.val), top);
+          self.ty(&ty_bind.ty);
+        }
+      }
+      Dec::DatatypeCopy(ty_con, long) => {
+        self.bind(Name::Ty(ty_con.val), top);
+        self.long(long, Name::Ty);
+      }
+      Dec::Abstype(dat_binds, ty_binds, d) => {
+        // the constructors are not visible outside the abstype.
+        for dat_bind in dat_binds {
+          self.bind(Name::Ty(dat_bind.ty_con.val), top);
+          self.dat_bind(dat_bind, false);
+        }
+        for ty_bind in ty_binds {
+          self.bind(Name::Ty(ty_bind.ty_con.val), top);
+          self.ty(&ty_bind.ty);
+        }
+        self.dec(d, top);
+      }
+      Dec::Exception(ex_binds) => {
+        for ex_bind in ex_binds {
+          self.bind(Name::Val(ex_bind.vid.val), top);
+          match &ex_bind.inner {
+            ExBindInner::Ty(None) => {}
+            ExBindInner::Ty(Some(t)) => self.ty(t),
+            ExBindInner::Long(long) => self.long(long, Name::Val),
+          }
+        }
+      }
+      Dec::Local(fst, snd) => {
+        self.dec(fst, false);
+        self.dec(snd, top);
+      }
+      Dec::Open(longs) => {
+        for long in longs {
+          self.long(long, Name::Str);
+        }
+      }
+      Dec::Seq(decs) => {
+        for d in decs {
+          self.dec(d, top);
+        }
+      }
+      Dec::Infix(..) | Dec::Infixr(..) | Dec::Nonfix(..) => {}
+    }
+  }
+
+  fn dat_bind(&mut self, dat_bind: &DatBind<StrRef>, top: bool) {
+    self.bind(Name::Ty(dat_bind.ty_con.val), top);
+    for con_bind in dat_bind.cons.iter() {
+      self.bind(Name::Val(con_bind.vid.val), top);
+      if let Some(t) = &con_bind.ty {
+        self.ty(t);
+      }
+    }
+  }
+
+  fn exp(&mut self, e: &Located<Exp<StrRef>>) {
+    match &e.val {
+      Exp::DecInt(_)
+      | Exp::HexInt(_)
+      | Exp::DecWord(_)
+      | Exp::HexWord(_)
+      | Exp::Real(..)
+      | Exp::String(_)
+      | Exp::Char(_)
+      | Exp::Select(_) => {}
+      Exp::LongVid(long) => self.long(long, Name::Val),
+      Exp::Record(rows) => {
+        for row in rows {
+          self.exp(&row.val);
+        }
+      }
+      Exp::Tuple(es) | Exp::List(es) | Exp::Vector(es) | Exp::Sequence(es) => {
+        for e in es {
+          self.exp(e);
+        }
+      }
+      Exp::Let(d, es) => {
+        self.dec(d, false);
+        for e in es {
+          self.exp(e);
+        }
+      }
+      Exp::App(func, arg) => {
+        self.exp(func);
+        self.exp(arg);
+      }
+      Exp::InfixApp(lhs, func, rhs) => {
+        self.mentioned.insert(Name::Val(func.val));
+        self.exp(lhs);
+        self.exp(rhs);
+      }
+      Exp::Typed(e, t) => {
+        self.exp(e);
+        self.ty(t);
+      }
+      Exp::Andalso(lhs, rhs) | Exp::Orelse(lhs, rhs) | Exp::While(lhs, rhs) => {
+        self.exp(lhs);
+        self.exp(rhs);
+      }
+      Exp::Handle(e, cases) => {
+        self.exp(e);
+        self.cases(cases);
+      }
+      Exp::Raise(e) => self.exp(e),
+      Exp::If(cond, yes, no) => {
+        self.exp(cond);
+        self.exp(yes);
+        self.exp(no);
+      }
+      Exp::Case(e, cases) => {
+        self.exp(e);
+        self.cases(cases);
+      }
+      Exp::Fn(cases) => self.cases(cases),
+    }
+  }
+
+  fn cases(&mut self, cases: &Cases<StrRef>) {
+    for arm in cases.arms.iter() {
+      self.pat(&arm.pat, false);
+      self.exp(&arm.exp);
+    }
+  }
+
+  /// `top` is whether `p` is at the top level, so the variables it binds are bound by the file.
+  /// Since a pattern doesn't say whether a name is a variable or a constructor, every short name is
+  /// both bound and mentioned.
+  fn pat(&mut self, p: &Located<Pat<StrRef>>, top: bool) {
+    match &p.val {
+      Pat::Wildcard
+      | Pat::DecInt(_)
+      | Pat::HexInt(_)
+      | Pat::DecWord(_)
+      | Pat::HexWord(_)
+      | Pat::String(_)
+      | Pat::Char(_) => {}
+      Pat::LongVid(long) => {
+        if long.structures.is_empty() {
+          self.bind(Name::Val(long.last.val), top);
+        }
+        self.long(long, Name::Val);
+      }
+      Pat::Record(rows, _) => {
+        for row in rows {
+          self.pat(&row.val, top);
+        }
+      }
+      Pat::Tuple(ps) | Pat::List(ps) | Pat::Vector(ps) => {
+        for p in ps {
+          self.pat(p, top);
+        }
+      }
+      Pat::Ctor(long, p) => {
+        self.long(long, Name::Val);
+        self.pat(p, top);
+      }
+      Pat::InfixCtor(lhs, ctor, rhs) => {
+        self.mentioned.insert(Name::Val(ctor.val));
+        self.pat(lhs, top);
+        self.pat(rhs, top);
+      }
+      Pat::Typed(p, t) => {
+        self.pat(p, top);
+        self.ty(t);
+      }
+      Pat::As(vid, t, p) => {
+        self.bind(Name::Val(vid.val), top);
+        if let Some(t) = t {
+          self.ty(t);
+        }
+        self.pat(p, top);
+      }
+    }
+  }
+
+  fn ty(&mut self, t: &Located<Ty<StrRef>>) {
+    match &t.val {
+      Ty::TyVar(_) => {}
+      Ty::Record(rows) => {
+        for Row { val, .. } in rows {
+          self.ty(val);
+        }
+      }
+      Ty::Tuple(ts) => {
+        for t in ts {
+          self.ty(t);
+        }
+      }
+      Ty::TyCon(args, long) => {
+        for t in args {
+          self.ty(t);
+        }
+        self.long(long, Name::Ty);
+      }
+      Ty::Arrow(lhs, rhs) => {
+        self.ty(lhs);
+        self.ty(rhs);
+      }
+    }
+  }
+
+  /// Records `long` as mentioned. If it is short, it is a name made with `short`, else its first
+  /// structure is.
+  fn long(&mut self, long: &Long<StrRef>, short: fn(StrRef) -> Name) {
+    let name = match long.structures.first() {
+      None => short(long.last.val),
+      Some(str_id) => Name::Str(str_id.val),
+    };
+    self.mentioned.insert(name);
+  }
+}
+
+#[test]
+fn test_order() {
+  let srcs = [
+    "val x = A.y + f 1",
+    "structure A = struct val y = B.z end",
+    "fun f x = x\nstructure B = struct val z = 3 end",
+    "signature S = sig end\nstructure C : S = A",
+    "structure D = F (C)",
+    "functor F (X : S) = X",
+  ];
+  let mut store = crate::intern::StrStoreMut::new();
+  let files: Vec<_> = srcs
+    .iter()
+    .map(|src| {
+      let lexer = crate::lex::get(&mut store, src.as_bytes()).unwrap();
+      crate::parse::get(lexer).unwrap()
+    })
+    .collect();
+  let files: Vec<_> = files.iter().map(Vec::as_slice).collect();
+  assert_eq!(order(&files), vec![2, 1, 0, 3, 5, 4]);
+}
@@ -0,0 +1,628 @@
+//! Selection ranges, for growing a selection outward through the AST.
+
+use crate::ast::{
+  Arm, Cases, ConBind, DatBind, Dec, ExBind, ExBindInner, Exp, FValBind, FValBindCase, Long, Pat,
+  Row, SigExp, Spec, StrDec, StrExp, TopDec, Ty, TyBind,
+};
+use crate::loc::{Loc, Located};
+use std::ops::Range;
+
+/// Returns the locations of the parts of `top_decs` which contain the byte offset `idx`, from
+/// innermost to outermost. Each location strictly contains the one before it.
+///
+/// The location of a parenthesized AST node includes the parentheses, so where possible the
+/// location without them is derived from the node's children.
+pub fn get<I>(top_decs: &[Located<TopDec<I>>], idx: usize) -> Vec<Loc> {
+  let mut f = Finder {
+    idx,
+    ret: Vec::new(),
+  };
+  for td in top_decs {
+    top_dec(&mut f, td);
+  }
+  // the locations were recorded from the outside in. keep only those which contain the one inside
+  // them, since two adjacent siblings may both contain `idx` when it is at the boundary.
+  let mut ret: Vec<Loc> = Vec::with_capacity(f.ret.len());
+  for loc in f.ret.into_iter().rev() {
+    match ret.last() {
+      None => ret.push(loc),
+      Some(&inner) => {
+        if loc != inner && contains(loc, inner) {
+          ret.push(loc);
+        }
+      }
+    }
+  }
+  ret
+}
+
+struct Finder {
+  idx: usize,
+  ret: Vec<Loc>,
+}
+
+impl Finder {
+  /// Returns whether `loc` contains the index, and if so records `loc`.
+  fn enter(&mut self, loc: Loc) -> bool {
+    let range = Range::from(loc);
+    let ret = range.start <= self.idx && self.idx <= range.end;
+    if ret {
+      self.ret.push(loc);
+    }
+    ret
+  }
+}
+
+/// Returns whether `outer` contains `inner`.
+fn contains(outer: Loc, inner: Loc) -> bool {
+  let outer = Range::from(outer);
+  let inner = Range::from(inner);
+  outer.start <= inner.start && inner.end <= outer.end
+}
+
+/// Returns the smallest location containing both `a` and `b`.
+fn span(a: Loc, b: Loc) -> Loc {
+  let a = Range::from(a);
+  let b = Range::from(b);
+  Loc::new(a.start.min(b.start), a.end.max(b.end))
+}
+
+fn top_dec<I>(f: &mut Finder, td: &Located<TopDec<I>>) {
+  if !f.enter(td.loc) {
+    return;
+  }
+  match &td.val {
+    TopDec::StrDec(sd) => str_dec(f, sd),
+    TopDec::SigDec(sig_binds) => {
+      for sig_bind in sig_binds {
+        if f.enter(span(sig_bind.id.loc, sig_bind.exp.loc)) {
+          f.enter(sig_bind.id.loc);
+          sig_exp(f, &sig_bind.exp);
+        }
+      }
+    }
+    TopDec::FunDec(fun_binds) => {
+      for fun_bind in fun_binds {
+        if f.enter(span(fun_bind.fun_id.loc, fun_bind.str_exp.loc)) {
+          f.enter(fun_bind.fun_id.loc);
+          f.enter(fun_bind.str_id.loc);
+          sig_exp(f, &fun_bind.sig_exp);
+          str_exp(f, &fun_bind.str_exp);
+        }
+      }
+    }
+  }
+}
+
+fn str_dec<I>(f: &mut Finder, sd: &Located<StrDec<I>>) {
+  if !f.enter(sd.loc) {
+    return;
+  }
+  match &sd.val {
+    StrDec::Dec(d) => dec(f, d),
+    StrDec::Structure(str_binds) => {
+      for str_bind in str_binds {
+        if f.enter(span(str_bind.id.loc, str_bind.exp.loc)) {
+          f.enter(str_bind.id.loc);
+          str_exp(f, &str_bind.exp);
+        }
+      }
+    }
+    StrDec::Local(fst, snd) => {
+      str_dec(f, fst);
+      str_dec(f, snd);
+    }
+    StrDec::Seq(sds) => {
+      for sd in sds {
+        str_dec(f, sd);
+      }
+    }
+  }
+}
+
+fn str_exp<I>(f: &mut Finder, se: &Located<StrExp<I>>) {
+  if !f.enter(se.loc) {
+    return;
+  }
+  match &se.val {
+    StrExp::Struct(sd) => str_dec(f, sd),
+    StrExp::LongStrId(long) => self::long(f, long),
+    StrExp::Ascription(se, sig, _) => {
+      str_exp(f, se);
+      sig_exp(f, sig);
+    }
+    StrExp::FunctorApp(fun_id, se) => {
+      f.enter(fun_id.loc);
+      str_exp(f, se);
+    }
+    StrExp::Let(sd, se) => {
+      str_dec(f, sd);
+      str_exp(f, se);
+    }
+  }
+}
+
+fn sig_exp<I>(f: &mut Finder, se: &Located<SigExp<I>>) {
+  if !f.enter(se.loc) {
+    return;
+  }
+  match &se.val {
+    SigExp::Sig(sp) => spec(f, sp),
+    SigExp::SigId(sig_id) => {
+      f.enter(sig_id.loc);
+    }
+    SigExp::Where(se, ty_vars, long, t) => {
+      sig_exp(f, se);
+      for tv in ty_vars {
+        f.enter(tv.loc);
+      }
+      self::long(f, long);
+      ty(f, t);
+    }
+  }
+}
+
+fn spec<I>(f: &mut Finder, sp: &Located<Spec<I>>) {
+  if !f.enter(sp.loc) {
+    return;
+  }
+  match &sp.val {
+    Spec::Val(val_descs) => {
+      for val_desc in val_descs {
+        if f.enter(span(val_desc.vid.loc, val_desc.ty.loc)) {
+          f.enter(val_desc.vid.loc);
+          ty(f, &val_desc.ty);
+        }
+      }
+    }
+    Spec::Type(ty_descs, _) => {
+      for ty_desc in ty_descs {
+        for tv in ty_desc.ty_vars.iter() {
+          f.enter(tv.loc);
+        }
+        f.enter(ty_desc.ty_con.loc);
+      }
+    }
+    Spec::Datatype(dat_binds) => {
+      for x in dat_binds {
+        dat_bind(f, x);
+      }
+    }
+    Spec::DatatypeCopy(ty_con, long) => {
+      f.enter(ty_con.loc);
+      self::long(f, long);
+    }
+    Spec::Exception(ex_descs) => {
+      for ex_desc in ex_descs {
+        let loc = ex_desc
+          .ty
+          .as_ref()
+          .map_or(ex_desc.vid.loc, |t| span(ex_desc.vid.loc, t.loc));
+        if f.enter(loc) {
+          f.enter(ex_desc.vid.loc);
+          if let Some(t) = &ex_desc.ty {
+            ty(f, t);
+          }
+        }
+      }
+    }
+    Spec::Structure(str_descs) => {
+      for str_desc in str_descs {
+        if f.enter(span(str_desc.str_id.loc, str_desc.exp.loc)) {
+          f.enter(str_desc.str_id.loc);
+          sig_exp(f, &str_desc.exp);
+        }
+      }
+    }
+    Spec::Include(se) => sig_exp(f, se),
+    Spec::Seq(specs) => {
+      for sp in specs {
+        spec(f, sp);
+      }
+    }
+    Spec::Functor(fun_descs) => {
+      for fun_desc in fun_descs {
+        if f.enter(span(fun_desc.fun_id.loc, fun_desc.res.loc)) {
+          f.enter(fun_desc.fun_id.loc);
+          f.enter(fun_desc.str_id.loc);
+          sig_exp(f, &fun_desc.param);
+          sig_exp(f, &fun_desc.res);
+        }
+      }
+    }
+    Spec::Sharing(sp, _, longs) => {
+      spec(f, sp);
+      for long in longs {
+        self::long(f, long);
+      }
+    }
+  }
+}
+
+fn dec<I>(f: &mut Finder, d: &Located<Dec<I>>) {
+  if !f.enter(d.loc) {
+    return;
+  }
+  match &d.val {
+    Dec::Val(ty_vars, val_binds) => {
+      for tv in ty_vars {
+        f.enter(tv.loc);
+      }
+      for val_bind in val_binds {
+        if f.enter(span(val_bind.pat.loc, val_bind.exp.loc)) {
+          pat(f, &val_bind.pat);
+          exp(f, &val_bind.exp);
+        }
+      }
+    }
+    Dec::Fun(ty_vars, fval_binds) => {
+      for tv in ty_vars {
+        f.enter(tv.loc);
+      }
+      for x in fval_binds {
+        fval_bind(f, x);
+      }
+    }
+    Dec::Type(ty_binds) => {
+      for x in ty_binds {
+        ty_bind(f, x);
+      }
+    }
+    Dec::Datatype(dat_binds, ty_binds) => {
+      for x in dat_binds {
+        dat_bind(f, x);
+      }
+      for x in ty_binds {
+        ty_bind(f, x);
+      }
+    }
+    Dec::DatatypeCopy(ty_con, long) => {
+      f.enter(ty_con.loc);
+      self::long(f, long);
+    }
+    Dec::Abstype(dat_binds, ty_binds, d) => {
+      for x in dat_binds {
+        dat_bind(f, x);
+      }
+      for x in ty_binds {
+        ty_bind(f, x);
+      }
+      dec(f, d);
+    }
+    Dec::Exception(ex_binds) => {
+      for x in ex_binds {
+        ex_bind(f, x);
+      }
+    }
+    Dec::Local(fst, snd) => {
+      dec(f, fst);
+      dec(f, snd);
+    }
+    Dec::Open(longs) => {
+      for long in longs {
+        self::long(f, long);
+      }
+    }
+    Dec::Seq(decs) => {
+      for d in decs {
+        dec(f, d);
+      }
+    }
+    Dec::Infix(n, vids) | Dec::Infixr(n, vids) => {
+      f.enter(n.loc);
+      for vid in vids {
+        f.enter(vid.loc);
+      }
+    }
+    Dec::Nonfix(vids) => {
+      for vid in vids {
+        f.enter(vid.loc);
+      }
+    }
+  }
+}
+
+fn fval_bind<I>(f: &mut Finder, fval_bind: &FValBind<I>) {
+  let first = fval_bind.cases.first().unwrap();
+  let last = fval_bind.cases.last().unwrap();
+  if !f.enter(span(first.vid.loc, last.body.loc)) {
+    return;
+  }
+  for case in fval_bind.cases.iter() {
+    fval_bind_case(f, case);
+  }
+}
+
+fn fval_bind_case<I>(f: &mut Finder, case: &FValBindCase<I>) {
+  if !f.enter(span(case.vid.loc, case.body.loc)) {
+    return;
+  }
+  f.enter(case.vid.loc);
+  for p in case.pats.iter() {
+    pat(f, p);
+  }
+  if let Some(t) = &case.ret_ty {
+    ty(f, t);
+  }
+  exp(f, &case.body);
+}
+
+fn ty_bind<I>(f: &mut Finder, ty_bind: &TyBind<I>) {
+  let start = ty_bind
+    .ty_vars
+    .first()
+    .map_or(ty_bind.ty_con.loc, |tv| tv.loc);
+  if !f.enter(span(start, ty_bind.ty.loc)) {
+    return;
+  }
+  for tv in ty_bind.ty_vars.iter() {
+    f.enter(tv.loc);
+  }
+  f.enter(ty_bind.ty_con.loc);
+  ty(f, &ty_bind.ty);
+}
+
+fn dat_bind<I>(f: &mut Finder, dat_bind: &DatBind<I>) {
+  let start = dat_bind
+    .ty_vars
+    .first()
+    .map_or(dat_bind.ty_con.loc, |tv| tv.loc);
+  let end = con_bind_loc(dat_bind.cons.last().unwrap());
+  if !f.enter(span(start, end)) {
+    return;
+  }
+  for tv in dat_bind.ty_vars.iter() {
+    f.enter(tv.loc);
+  }
+  f.enter(dat_bind.ty_con.loc);
+  for con_bind in dat_bind.cons.iter() {
+    if f.enter(con_bind_loc(con_bind)) {
+      f.enter(con_bind.vid.loc);
+      if let Some(t) = &con_bind.ty {
+        ty(f, t);
+      }
+    }
+  }
+}
+
+fn con_bind_loc<I>(con_bind: &ConBind<I>) -> Loc {
+  con_bind
+    .ty
+    .as_ref()
+    .map_or(con_bind.vid.loc, |t| span(con_bind.vid.loc, t.loc))
+}
+
+fn ex_bind<I>(f: &mut Finder, ex_bind: &ExBind<I>) {
+  let loc = match &ex_bind.inner {
+    ExBindInner::Ty(None) => ex_bind.vid.loc,
+    ExBindInner::Ty(Some(t)) => span(ex_bind.vid.loc, t.loc),
+    ExBindInner::Long(long) => span(ex_bind.vid.loc, long.loc()),
+  };
+  if !f.enter(loc) {
+    return;
+  }
+  f.enter(ex_bind.vid.loc);
+  match &ex_bind.inner {
+    ExBindInner::Ty(None) => {}
+    ExBindInner::Ty(Some(t)) => ty(f, t),
+    ExBindInner::Long(long) => self::long(f, long),
+  }
+}
+
+fn exp<I>(f: &mut Finder, e: &Located<Exp<I>>) {
+  if !f.enter(e.loc) {
+    return;
+  }
+  match &e.val {
+    Exp::DecInt(_)
+    | Exp::HexInt(_)
+    | Exp::DecWord(_)
+    | Exp::HexWord(_)
+    | Exp::Real(..)
+    | Exp::String(_)
+    | Exp::Char(_) => {}
+    Exp::LongVid(long) => self::long(f, long),
+    Exp::Record(rows) => {
+      for r in rows {
+        row(f, r, exp);
+      }
+    }
+    Exp::Select(lab) => {
+      f.enter(lab.loc);
+    }
+    Exp::Tuple(exps) | Exp::List(exps) | Exp::Vector(exps) | Exp::Sequence(exps) => {
+      for e in exps {
+        exp(f, e);
+      }
+    }
+    Exp::Let(d, exps) => {
+      dec(f, d);
+      for e in exps {
+        exp(f, e);
+      }
+    }
+    Exp::App(lhs, rhs) | Exp::Andalso(lhs, rhs) | Exp::Orelse(lhs, rhs) => {
+      f.enter(span(lhs.loc, rhs.loc));
+      exp(f, lhs);
+      exp(f, rhs);
+    }
+    Exp::While(cond, body) => {
+      exp(f, cond);
+      exp(f, body);
+    }
+    Exp::InfixApp(lhs, op, rhs) => {
+      f.enter(span(lhs.loc, rhs.loc));
+      exp(f, lhs);
+      f.enter(op.loc);
+      exp(f, rhs);
+    }
+    Exp::Typed(e, t) => {
+      f.enter(span(e.loc, t.loc));
+      exp(f, e);
+      ty(f, t);
+    }
+    Exp::Handle(e, cs) => {
+      f.enter(span(e.loc, cs.arms.last().unwrap().exp.loc));
+      exp(f, e);
+      cases(f, cs);
+    }
+    Exp::Case(e, cs) => {
+      exp(f, e);
+      cases(f, cs);
+    }
+    Exp::Raise(e) => exp(f, e),
+    Exp::If(cond, then_e, else_e) => {
+      exp(f, cond);
+      exp(f, then_e);
+      exp(f, else_e);
+    }
+    Exp::Fn(cs) => cases(f, cs),
+  }
+}
+
+fn cases<I>(f: &mut Finder, cs: &Cases<I>) {
+  let first = cs.arms.first().unwrap();
+  let last = cs.arms.last().unwrap();
+  if !f.enter(span(first.pat.loc, last.exp.loc)) {
+    return;
+  }
+  for x in cs.arms.iter() {
+    arm(f, x);
+  }
+}
+
+fn arm<I>(f: &mut Finder, arm: &Arm<I>) {
+  if f.enter(span(arm.pat.loc, arm.exp.loc)) {
+    pat(f, &arm.pat);
+    exp(f, &arm.exp);
+  }
+}
+
+fn pat<I>(f: &mut Finder, p: &Located<Pat<I>>) {
+  if !f.enter(p.loc) {
+    return;
+  }
+  match &p.val {
+    Pat::Wildcard
+    | Pat::DecInt(_)
+    | Pat::HexInt(_)
+    | Pat::DecWord(_)
+    | Pat::HexWord(_)
+    | Pat::String(_)
+    | Pat::Char(_) => {}
+    Pat::LongVid(long) => self::long(f, long),
+    Pat::Record(rows, rest) => {
+      for r in rows {
+        row(f, r, pat);
+      }
+      if let Some(rest) = rest {
+        f.enter(*rest);
+      }
+    }
+    Pat::Tuple(pats) | Pat::List(pats) | Pat::Vector(pats) => {
+      for p in pats {
+        pat(f, p);
+      }
+    }
+    Pat::Ctor(long, p) => {
+      f.enter(span(long.loc(), p.loc));
+      self::long(f, long);
+      pat(f, p);
+    }
+    Pat::InfixCtor(lhs, op, rhs) => {
+      f.enter(span(lhs.loc, rhs.loc));
+      pat(f, lhs);
+      f.enter(op.loc);
+      pat(f, rhs);
+    }
+    Pat::Typed(p, t) => {
+      f.enter(span(p.loc, t.loc));
+      pat(f, p);
+      ty(f, t);
+    }
+    Pat::As(vid, t, p) => {
+      f.enter(span(vid.loc, p.loc));
+      f.enter(vid.loc);
+      if let Some(t) = t {
+        ty(f, t);
+      }
+      pat(f, p);
+    }
+  }
+}
+
+fn ty<I>(f: &mut Finder, t: &Located<Ty<I>>) {
+  if !f.enter(t.loc) {
+    return;
+  }
+  match &t.val {
+    Ty::TyVar(_) => {}
+    Ty::Record(rows) => {
+      for r in rows {
+        row(f, r, ty);
+      }
+    }
+    Ty::Tuple(tys) => {
+      f.enter(span(tys.first().unwrap().loc, tys.last().unwrap().loc));
+      for t in tys {
+        ty(f, t);
+      }
+    }
+    Ty::TyCon(args, long) => {
+      if let Some(t) = args.first() {
+        f.enter(span(t.loc, long.loc()));
+      }
+      for t in args {
+        ty(f, t);
+      }
+      self::long(f, long);
+    }
+    Ty::Arrow(arg, res) => {
+      f.enter(span(arg.loc, res.loc));
+      ty(f, arg);
+      ty(f, res);
+    }
+  }
+}
+
+fn row<T>(f: &mut Finder, row: &Row<Located<T>>, g: fn(&mut Finder, &Located<T>)) {
+  if f.enter(span(row.lab.loc, row.val.loc)) {
+    f.enter(row.lab.loc);
+    g(f, &row.val);
+  }
+}
+
+fn long<I>(f: &mut Finder, long: &Long<I>) {
+  if !f.enter(long.loc()) {
+    return;
+  }
+  for s in long.structures.iter() {
+    f.enter(s.loc);
+  }
+  f.enter(long.last.loc);
+}
+
+#[test]
+fn test_get() {
+  let src = "val x = f (1 + 2)\nval y = ((3))";
+  let mut store = crate::intern::StrStoreMut::new();
+  let lexer = crate::lex::get(&mut store, src.as_bytes()).unwrap();
+  let top_decs = crate::parse::get(lexer).unwrap();
+  let get = |needle: char| -> Vec<&str> {
+    get(&top_decs, src.find(needle).unwrap())
+      .into_iter()
+      .map(|loc| &src[Range::from(loc)])
+      .collect()
+  };
+  let want = vec![
+    "1",
+    "1 + 2",
+    "(1 + 2)",
+    "f (1 + 2)",
+    "x = f (1 + 2)",
+    "val x = f (1 + 2)",
+    src,
+  ];
+  assert_eq!(get('1'), want);
+  assert_eq!(get('3'), vec!["((3))", "y = ((3))", "val y = ((3))", src]);
+}
@@ -1,183 +1,321 @@
 //! Check declarations and expressions.
 
-use crate::ast::{Cases, DatBind, Dec, ExBindInner, Exp, Label, Long, TyBind};
+use crate::ast::{
+  Cases, DatBind, Dec, ExBind, ExBindInner, Exp, FValBind, Label, Long, Pat as AstPat, Row,
+  Ty as AstTy, TyBind, ValBind,
+};
 use crate::intern::StrRef;
-use crate::loc::Located;
+use crate::loc::{Loc, Located};
 use crate::statics::ck::util::{
-  env_ins, env_merge, generalize, get_env, get_ty_sym, get_val_info, insert_ty_vars, instantiate,
+  ck_unused, env_ins, env_merge, generalize, get_env, get_struct, get_ty_sym, get_val_info,
+  insert_ty_vars, instantiate, int_constant, word_constant,
 };
-use crate::statics::ck::{exhaustive, pat, ty};
+use crate::statics::ck::{exhaustive, pat, ty, ty_var};
+use crate::statics::ty_rzn::TyRealization;
 use crate::statics::types::{
-  Cx, Env, Error, Item, Pat, Result, State, StrEnv, Ty, TyEnv, TyInfo, TyScheme, TyVar, Tys,
-  ValEnv, ValInfo,
+  Cx, Env, Error, Info, Item, Pat, Result, State, StrEnv, Sym, Ty, TyEnv, TyInfo, TyScheme, TyVar,
+  Tys, ValEnv, ValInfo,
 };
+use crate::token::TyVar as AstTyVar;
 use maplit::btreemap;
 use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Range;
 
 fn ck_exp(cx: &Cx, st: &mut State, exp: &Located<Exp<StrRef>>) -> Result<Ty> {
+  let ty = ck_exp_impl(cx, st, exp)?;
+  // identifiers will have already recorded more specific information.
+  st.info
+    .entry(exp.loc)
+    .or_insert_with(|| Info::ty(ty.clone()));
+  Ok(ty)
+}
+
+fn ck_exp_impl(cx: &Cx, st: &mut State, exp: &Located<Exp<StrRef>>) -> Result<Ty> {
   // The special constants are as per SML Definition (1). Note that SML Definition (5) is handled by
   // the parser and SML Definition (7) is handled by having atomic and non-atomic expressions be
   // part of the same enum.
+  //
+  // every case which recurses is in its own function, so that the stack frame of this one, which is
+  // in every level of recursion, stays small. see `parse::STACK_SIZE`.
   match &exp.val {
-    Exp::DecInt(_) | Exp::HexInt(_) => Ok(Ty::INT),
-    Exp::DecWord(_) | Exp::HexWord(_) => Ok(Ty::WORD),
-    Exp::Real(_) => Ok(Ty::REAL),
+    Exp::DecInt(_) | Exp::HexInt(_) => Ok(int_constant(st)),
+    Exp::DecWord(n) | Exp::HexWord(n) => Ok(word_constant(st, exp.loc, *n)),
+    Exp::Real(..) => Ok(Ty::REAL),
     Exp::String(_) => Ok(Ty::STRING),
     Exp::Char(_) => Ok(Ty::CHAR),
     // SML Definition (2). Note that Subst, instantiate, generalize, unify, etc are all borne from
     // the comment on this rule: "The instantiation of type schemes allows different occurrences of
     // a single longvid to assume different types."
-    Exp::LongVid(vid) => {
-      let val_info = get_val_info(get_env(&cx.env, vid)?, vid.last)?;
-      Ok(instantiate(st, &val_info.ty_scheme))
-    }
+    Exp::LongVid(vid) => ck_long_vid(cx, st, exp.loc, vid),
     // SML Definition (3)
-    Exp::Record(rows) => {
-      let mut ty_rows = BTreeMap::new();
-      // SML Definition (6)
-      for row in rows {
-        let ty = ck_exp(cx, st, &row.val)?;
-        if ty_rows.insert(row.lab.val, ty).is_some() {
-          return Err(row.lab.loc.wrap(Error::DuplicateLabel(row.lab.val)));
-        }
-      }
-      Ok(Ty::Record(ty_rows))
+    Exp::Record(rows) => ck_record(cx, st, rows),
+    // SML Definition Appendix A - `#lab` is sugar for `fn {lab = x, ...} => x`
+    Exp::Select(lab) => {
+      let ret_ty = Ty::Var(st.new_ty_var(false));
+      let record_tv = st.new_ty_var(false);
+      let rows = exp.loc.wrap(btreemap![lab.val => ret_ty.clone()]);
+      st.subst.insert_record(record_tv, rows);
+      Ok(Ty::Arrow(Ty::Var(record_tv).into(), ret_ty.into()))
     }
-    Exp::Select(..) => Err(exp.loc.wrap(Error::Todo("record selectors"))),
     // SML Definition Appendix A - tuples are sugar for records
-    Exp::Tuple(exps) => {
-      let mut ty_rows = BTreeMap::new();
-      for (idx, exp) in exps.iter().enumerate() {
-        let ty = ck_exp(cx, st, exp)?;
-        assert!(ty_rows.insert(Label::tuple(idx), ty).is_none());
-      }
-      Ok(Ty::Record(ty_rows))
-    }
+    Exp::Tuple(exps) => ck_tuple(cx, st, exps),
     // SML Definition Appendix A - lists are sugar for cons + nil
-    Exp::List(exps) => {
-      let elem = Ty::Var(st.new_ty_var(false));
-      for exp in exps {
-        let ty = ck_exp(cx, st, exp)?;
-        st.unify(exp.loc, elem.clone(), ty)?;
-      }
-      Ok(Ty::list(elem))
-    }
+    Exp::List(exps) => Ok(Ty::list(ck_elems(cx, st, exps)?)),
+    Exp::Vector(exps) => Ok(Ty::vector(ck_elems(cx, st, exps)?)),
     // SML Definition Appendix A - sequences ignore all but the last expression
-    Exp::Sequence(exps) => {
-      let mut ret = None;
-      for exp in exps {
-        ret = Some(ck_exp(cx, st, exp)?);
-      }
-      Ok(ret.unwrap())
-    }
+    Exp::Sequence(exps) => ck_sequence(cx, st, exps),
     // SML Definition (4)
-    Exp::Let(dec, exps) => {
-      let gen_syms = st.generated_syms();
-      let env = ck(cx, st, dec)?;
-      let mut cx = cx.clone();
-      cx.o_plus(env);
-      let mut last = None;
-      for exp in exps {
-        last = Some((exp.loc, ck_exp(&cx, st, exp)?));
-      }
-      let (loc, mut ty) = last.unwrap();
-      ty.apply(&st.subst);
-      if !gen_syms.contains(&ty.ty_names()) {
-        return Err(loc.wrap(Error::TyNameEscape));
-      }
-      Ok(ty)
-    }
+    Exp::Let(dec, exps) => ck_let(cx, st, dec, exps),
     // SML Definition (8)
-    Exp::App(func, arg) => {
-      let func_ty = ck_exp(cx, st, func)?;
-      let arg_ty = ck_exp(cx, st, arg)?;
-      // we don't actually _need_ to case on func_ty, since the Var case is actually correct for
-      // _all_ types. we just do this to produce better error messages in the Record and Ctor cases.
-      match func_ty {
-        Ty::Var(tv) => {
-          if st.subst.is_bound(&tv) {
-            Err(exp.loc.wrap(Error::NotArrowTy(func_ty)))
-          } else {
-            let ret_ty = Ty::Var(st.new_ty_var(false));
-            let arrow_ty = Ty::Arrow(arg_ty.into(), ret_ty.clone().into());
-            st.unify(exp.loc, func_ty, arrow_ty)?;
-            Ok(ret_ty)
-          }
-        }
-        Ty::Arrow(func_arg_ty, func_ret_ty) => {
-          st.unify(exp.loc, *func_arg_ty, arg_ty)?;
-          Ok(*func_ret_ty)
-        }
-        Ty::Record(_) | Ty::Ctor(_, _) => Err(exp.loc.wrap(Error::NotArrowTy(func_ty))),
-      }
-    }
+    Exp::App(func, arg) => ck_app(cx, st, exp.loc, func, arg),
     // SML Definition (8). Infix application is the same as `op`ing the infix operator and applying
     // it to a tuple (lhs, rhs).
-    Exp::InfixApp(lhs, func, rhs) => {
-      let val_info = get_val_info(&cx.env, *func)?;
-      let func_ty = instantiate(st, &val_info.ty_scheme);
-      let lhs_ty = ck_exp(cx, st, lhs)?;
-      let rhs_ty = ck_exp(cx, st, rhs)?;
-      let ret_ty = Ty::Var(st.new_ty_var(false));
-      let arrow_ty = Ty::Arrow(Ty::pair(lhs_ty, rhs_ty).into(), ret_ty.clone().into());
-      st.unify(exp.loc, func_ty, arrow_ty)?;
-      Ok(ret_ty)
-    }
+    Exp::InfixApp(lhs, func, rhs) => ck_infix_app(cx, st, exp.loc, lhs, *func, rhs),
     // SML Definition (9)
-    Exp::Typed(inner, ty) => {
-      let exp_ty = ck_exp(cx, st, inner)?;
-      let ty_ty = ty::ck(cx, &st.tys, ty)?;
-      st.unify(exp.loc, ty_ty, exp_ty.clone())?;
-      Ok(exp_ty)
-    }
+    Exp::Typed(inner, ty) => ck_typed(cx, st, exp.loc, inner, ty),
     // SML Definition Appendix A - boolean operators are sugar for `if`
-    Exp::Andalso(lhs, rhs) | Exp::Orelse(lhs, rhs) => {
-      let lhs_ty = ck_exp(cx, st, lhs)?;
-      let rhs_ty = ck_exp(cx, st, rhs)?;
-      st.unify(lhs.loc, Ty::BOOL, lhs_ty)?;
-      st.unify(rhs.loc, Ty::BOOL, rhs_ty)?;
-      Ok(Ty::BOOL)
-    }
+    Exp::Andalso(lhs, rhs) | Exp::Orelse(lhs, rhs) => ck_bool_op(cx, st, lhs, rhs),
     // SML Definition (10)
-    Exp::Handle(head, cases) => {
-      let head_ty = ck_exp(cx, st, head)?;
-      let (pats, arg_ty, res_ty) = ck_cases(cx, st, cases)?;
-      exhaustive::ck_handle(pats)?;
-      st.unify(exp.loc, Ty::EXN, arg_ty)?;
-      st.unify(exp.loc, head_ty.clone(), res_ty)?;
-      Ok(head_ty)
-    }
+    Exp::Handle(head, cases) => ck_handle(cx, st, exp.loc, head, cases),
     // SML Definition (11)
-    Exp::Raise(exp) => {
-      let exp_ty = ck_exp(cx, st, exp)?;
-      st.unify(exp.loc, Ty::EXN, exp_ty)?;
-      Ok(Ty::Var(st.new_ty_var(false)))
-    }
+    Exp::Raise(exp) => ck_raise(cx, st, exp),
     // SML Definition Appendix A - `if` is sugar for casing
-    Exp::If(cond, then_e, else_e) => {
-      let cond_ty = ck_exp(cx, st, cond)?;
-      let then_ty = ck_exp(cx, st, then_e)?;
-      let else_ty = ck_exp(cx, st, else_e)?;
-      st.unify(cond.loc, Ty::BOOL, cond_ty)?;
-      st.unify(exp.loc, then_ty.clone(), else_ty)?;
-      Ok(then_ty)
-    }
-    Exp::While(..) => Err(exp.loc.wrap(Error::Todo("`while`"))),
+    Exp::If(cond, then_e, else_e) => ck_if(cx, st, exp.loc, cond, then_e, else_e),
+    // SML Definition Appendix A - `while` is sugar for a recursive function
+    Exp::While(cond, body) => ck_while(cx, st, cond, body),
     // SML Definition Appendix A - `case` is sugar for application to a `fn`
-    Exp::Case(head, cases) => {
-      let head_ty = ck_exp(cx, st, head)?;
-      let (pats, arg_ty, res_ty) = ck_cases(cx, st, cases)?;
-      exhaustive::ck_match(pats, exp.loc)?;
-      st.unify(exp.loc, head_ty, arg_ty)?;
-      Ok(res_ty)
-    }
+    Exp::Case(head, cases) => ck_case(cx, st, exp.loc, head, cases),
     // SML Definition (12)
-    Exp::Fn(cases) => {
-      let (pats, arg_ty, res_ty) = ck_cases(cx, st, cases)?;
-      exhaustive::ck_match(pats, exp.loc)?;
-      Ok(Ty::Arrow(arg_ty.into(), res_ty.into()))
+    Exp::Fn(cases) => ck_fn(cx, st, exp.loc, cases),
+  }
+}
+
+fn ck_long_vid(cx: &Cx, st: &mut State, loc: Loc, vid: &Long<StrRef>) -> Result<Ty> {
+  let val_info = get_val_info(get_env(&cx.env, vid)?, vid.last)?;
+  if let Some(def) = val_info.def {
+    st.used.insert(def.val);
+  }
+  let ty = instantiate(st, vid.last.val, &val_info.ty_scheme);
+  let info = Info::val(vid.last.val, val_info, ty.clone());
+  st.info.insert(loc, info);
+  Ok(ty)
+}
+
+fn ck_record(cx: &Cx, st: &mut State, rows: &[Row<Located<Exp<StrRef>>>]) -> Result<Ty> {
+  let mut ty_rows = BTreeMap::new();
+  // SML Definition (6)
+  for row in rows {
+    let ty = ck_exp(cx, st, &row.val)?;
+    if ty_rows.insert(row.lab.val, ty).is_some() {
+      let prev = rows.iter().find(|r| r.lab.val == row.lab.val).unwrap();
+      let err = Error::DuplicateLabel(row.lab.val, prev.lab.loc);
+      return Err(row.lab.loc.wrap(err));
+    }
+  }
+  Ok(Ty::Record(ty_rows))
+}
+
+fn ck_tuple(cx: &Cx, st: &mut State, exps: &[Located<Exp<StrRef>>]) -> Result<Ty> {
+  let mut ty_rows = BTreeMap::new();
+  for (idx, exp) in exps.iter().enumerate() {
+    let ty = ck_exp(cx, st, exp)?;
+    assert!(ty_rows.insert(Label::tuple(idx), ty).is_none());
+  }
+  Ok(Ty::Record(ty_rows))
+}
+
+/// Returns the type of the elements of a list or vector.
+fn ck_elems(cx: &Cx, st: &mut State, exps: &[Located<Exp<StrRef>>]) -> Result<Ty> {
+  let elem = Ty::Var(st.new_ty_var(false));
+  for exp in exps {
+    let ty = ck_exp(cx, st, exp)?;
+    st.unify(exp.loc, elem.clone(), ty)?;
+  }
+  Ok(elem)
+}
+
+fn ck_sequence(cx: &Cx, st: &mut State, exps: &[Located<Exp<StrRef>>]) -> Result<Ty> {
+  let mut ret = None;
+  for exp in exps {
+    ret = Some(ck_exp(cx, st, exp)?);
+  }
+  Ok(ret.unwrap())
+}
+
+fn ck_let(
+  cx: &Cx,
+  st: &mut State,
+  dec: &Located<Dec<StrRef>>,
+  exps: &[Located<Exp<StrRef>>],
+) -> Result<Ty> {
+  let gen_syms = st.generated_syms();
+  let pat_vars = st.next_pat_var();
+  let env = ck(cx, st, dec)?;
+  let mut cx = cx.clone();
+  cx.o_plus(env.clone());
+  let mut last = None;
+  for exp in exps {
+    last = Some((exp.loc, ck_exp(&cx, st, exp)?));
+  }
+  // only check the variables bound by this `let`, not any brought into scope by `open`.
+  let mut val_env = env.val_env;
+  val_env.retain(|_, val_info| val_info.def.is_some_and(|def| def.val >= pat_vars));
+  ck_unused(st, &val_env);
+  let (loc, mut ty) = last.unwrap();
+  ty.apply(&st.subst);
+  if !gen_syms.contains(&ty.ty_names()) {
+    return Err(loc.wrap(Error::TyNameEscape));
+  }
+  Ok(ty)
+}
+
+fn ck_app(
+  cx: &Cx,
+  st: &mut State,
+  loc: Loc,
+  func: &Located<Exp<StrRef>>,
+  arg: &Located<Exp<StrRef>>,
+) -> Result<Ty> {
+  let func_ty = ck_exp(cx, st, func)?;
+  let arg_ty = ck_exp(cx, st, arg)?;
+  // we don't actually _need_ to case on func_ty, since the Var case is actually correct for _all_
+  // types. we just do this to produce better error messages in the Record and Ctor cases.
+  match func_ty {
+    Ty::Var(tv) => {
+      if st.subst.is_bound(&tv) {
+        Err(loc.wrap(Error::NotArrowTy(func_ty)))
+      } else {
+        let ret_ty = Ty::Var(st.new_ty_var(false));
+        let arrow_ty = Ty::Arrow(arg_ty.into(), ret_ty.clone().into());
+        st.unify(loc, func_ty, arrow_ty)?;
+        Ok(ret_ty)
+      }
+    }
+    Ty::Arrow(func_arg_ty, func_ret_ty) => {
+      st.unify(loc, *func_arg_ty, arg_ty)?;
+      Ok(*func_ret_ty)
     }
+    Ty::Record(_) | Ty::Ctor(_, _) => Err(loc.wrap(Error::NotArrowTy(func_ty))),
+  }
+}
+
+fn ck_infix_app(
+  cx: &Cx,
+  st: &mut State,
+  loc: Loc,
+  lhs: &Located<Exp<StrRef>>,
+  func: Located<StrRef>,
+  rhs: &Located<Exp<StrRef>>,
+) -> Result<Ty> {
+  let val_info = get_val_info(&cx.env, func)?;
+  if let Some(def) = val_info.def {
+    st.used.insert(def.val);
   }
+  let func_ty = instantiate(st, func.val, &val_info.ty_scheme);
+  let info = Info::val(func.val, val_info, func_ty.clone());
+  st.info.insert(func.loc, info);
+  let lhs_ty = ck_exp(cx, st, lhs)?;
+  let rhs_ty = ck_exp(cx, st, rhs)?;
+  let ret_ty = Ty::Var(st.new_ty_var(false));
+  let arrow_ty = Ty::Arrow(Ty::pair(lhs_ty, rhs_ty).into(), ret_ty.clone().into());
+  st.unify(loc, func_ty, arrow_ty)?;
+  Ok(ret_ty)
+}
+
+fn ck_typed(
+  cx: &Cx,
+  st: &mut State,
+  loc: Loc,
+  inner: &Located<Exp<StrRef>>,
+  ty: &Located<AstTy<StrRef>>,
+) -> Result<Ty> {
+  let exp_ty = ck_exp(cx, st, inner)?;
+  let ty_ty = ty::ck(cx, st, ty)?;
+  st.unify(loc, ty_ty, exp_ty.clone())?;
+  Ok(exp_ty)
+}
+
+fn ck_bool_op(
+  cx: &Cx,
+  st: &mut State,
+  lhs: &Located<Exp<StrRef>>,
+  rhs: &Located<Exp<StrRef>>,
+) -> Result<Ty> {
+  let lhs_ty = ck_exp(cx, st, lhs)?;
+  let rhs_ty = ck_exp(cx, st, rhs)?;
+  st.unify(lhs.loc, Ty::BOOL, lhs_ty)?;
+  st.unify(rhs.loc, Ty::BOOL, rhs_ty)?;
+  Ok(Ty::BOOL)
+}
+
+fn ck_handle(
+  cx: &Cx,
+  st: &mut State,
+  loc: Loc,
+  head: &Located<Exp<StrRef>>,
+  cases: &Cases<StrRef>,
+) -> Result<Ty> {
+  let head_ty = ck_exp(cx, st, head)?;
+  let (pats, arg_ty, res_ty) = ck_cases(cx, st, cases)?;
+  exhaustive::ck_handle(&st.subst, pats)?;
+  st.unify(loc, Ty::EXN, arg_ty)?;
+  st.unify(loc, head_ty.clone(), res_ty)?;
+  Ok(head_ty)
+}
+
+fn ck_raise(cx: &Cx, st: &mut State, exp: &Located<Exp<StrRef>>) -> Result<Ty> {
+  let exp_ty = ck_exp(cx, st, exp)?;
+  st.unify(exp.loc, Ty::EXN, exp_ty)?;
+  Ok(Ty::Var(st.new_ty_var(false)))
+}
+
+fn ck_if(
+  cx: &Cx,
+  st: &mut State,
+  loc: Loc,
+  cond: &Located<Exp<StrRef>>,
+  then_e: &Located<Exp<StrRef>>,
+  else_e: &Located<Exp<StrRef>>,
+) -> Result<Ty> {
+  let cond_ty = ck_exp(cx, st, cond)?;
+  let then_ty = ck_exp(cx, st, then_e)?;
+  let else_ty = ck_exp(cx, st, else_e)?;
+  st.unify(cond.loc, Ty::BOOL, cond_ty)?;
+  st.unify(loc, then_ty.clone(), else_ty)?;
+  Ok(then_ty)
+}
+
+fn ck_while(
+  cx: &Cx,
+  st: &mut State,
+  cond: &Located<Exp<StrRef>>,
+  body: &Located<Exp<StrRef>>,
+) -> Result<Ty> {
+  let cond_ty = ck_exp(cx, st, cond)?;
+  let body_ty = ck_exp(cx, st, body)?;
+  st.unify(cond.loc, Ty::BOOL, cond_ty)?;
+  st.unify(body.loc, Ty::unit(), body_ty)?;
+  Ok(Ty::unit())
+}
+
+fn ck_case(
+  cx: &Cx,
+  st: &mut State,
+  loc: Loc,
+  head: &Located<Exp<StrRef>>,
+  cases: &Cases<StrRef>,
+) -> Result<Ty> {
+  let head_ty = ck_exp(cx, st, head)?;
+  let (pats, arg_ty, res_ty) = ck_cases(cx, st, cases)?;
+  st.unify(loc, head_ty, arg_ty)?;
+  exhaustive::ck_match(st, pats, loc)?;
+  Ok(res_ty)
+}
+
+fn ck_fn(cx: &Cx, st: &mut State, loc: Loc, cases: &Cases<StrRef>) -> Result<Ty> {
+  let (pats, arg_ty, res_ty) = ck_cases(cx, st, cases)?;
+  exhaustive::ck_match(st, pats, loc)?;
+  Ok(Ty::Arrow(arg_ty.into(), res_ty.into()))
 }
 
 /// SML Definition (13)
@@ -190,8 +328,9 @@ fn ck_cases(cx: &Cx, st: &mut State, cases: &Cases<StrRef>) -> Result<(Vec<Locat
     let (val_env, pat_ty, pat) = pat::ck(cx, st, &arm.pat)?;
     pats.push(arm.pat.loc.wrap(pat));
     let mut cx = cx.clone();
-    cx.env.val_env.extend(val_env);
+    cx.env.val_env.extend(val_env.clone());
     let exp_ty = ck_exp(&cx, st, &arm.exp)?;
+    ck_unused(st, &val_env);
     st.unify(arm.pat.loc, arg_ty.clone(), pat_ty)?;
     st.unify(arm.exp.loc, res_ty.clone(), exp_ty)?;
   }
@@ -213,9 +352,62 @@ fn ck_binding(name: Located<StrRef>) -> Result<()> {
   Ok(())
 }
 
+/// Returns whether `exp` is non-expansive, as per SML Definition section 4.7. Only the types of
+/// values bound to non-expansive expressions may be generalized.
+fn is_nonexpansive(cx: &Cx, exp: &Located<Exp<StrRef>>) -> bool {
+  match &exp.val {
+    Exp::DecInt(_)
+    | Exp::HexInt(_)
+    | Exp::DecWord(_)
+    | Exp::HexWord(_)
+    | Exp::Real(..)
+    | Exp::String(_)
+    | Exp::Char(_)
+    | Exp::LongVid(_)
+    | Exp::Select(_)
+    | Exp::Fn(_) => true,
+    Exp::Record(rows) => rows.iter().all(|row| is_nonexpansive(cx, &row.val)),
+    Exp::Tuple(exps) | Exp::List(exps) | Exp::Vector(exps) => {
+      exps.iter().all(|exp| is_nonexpansive(cx, exp))
+    }
+    Exp::Typed(exp, _) => is_nonexpansive(cx, exp),
+    Exp::App(func, arg) => match &func.val {
+      Exp::LongVid(vid) => is_non_ref_con(cx, vid) && is_nonexpansive(cx, arg),
+      _ => false,
+    },
+    Exp::InfixApp(lhs, func, rhs) => {
+      let vid = Long {
+        structures: Vec::new(),
+        last: *func,
+      };
+      is_non_ref_con(cx, &vid) && is_nonexpansive(cx, lhs) && is_nonexpansive(cx, rhs)
+    }
+    Exp::Sequence(_)
+    | Exp::Let(..)
+    | Exp::Andalso(..)
+    | Exp::Orelse(..)
+    | Exp::Handle(..)
+    | Exp::Raise(_)
+    | Exp::If(..)
+    | Exp::While(..)
+    | Exp::Case(..) => false,
+  }
+}
+
+/// Returns whether `vid` is a constructor or exception other than `ref`, so that applying it to a
+/// non-expansive expression is non-expansive.
+fn is_non_ref_con(cx: &Cx, vid: &Long<StrRef>) -> bool {
+  vid.last.val != StrRef::REF
+    && get_env(&cx.env, vid)
+      .ok()
+      .and_then(|env| env.val_env.get(&vid.last.val))
+      .is_some_and(|val_info| !val_info.id_status.is_val())
+}
+
 struct FunInfo {
   args: Vec<TyVar>,
   ret: TyVar,
+  loc: Loc,
 }
 
 fn fun_infos_to_ve(fun_infos: &HashMap<StrRef, FunInfo>) -> ValEnv {
@@ -229,180 +421,514 @@ fn fun_infos_to_ve(fun_infos: &HashMap<StrRef, FunInfo>) -> ValEnv {
         .fold(Ty::Var(fun_info.ret), |ac, &tv| {
           Ty::Arrow(Ty::Var(tv).into(), ac.into())
         });
-      (name, ValInfo::val(TyScheme::mono(ty)))
+      let val_info = ValInfo::val(TyScheme::mono(ty)).bound_at(fun_info.loc);
+      (name, val_info)
     })
     .collect()
 }
 
 pub fn ck(cx: &Cx, st: &mut State, dec: &Located<Dec<StrRef>>) -> Result<Env> {
+  let env = ck_impl(cx, st, dec)?;
+  let range = Range::from(dec.loc);
+  for val_info in env.val_env.values() {
+    // skip identifiers bound elsewhere, as by `open`. nested declarations will already have
+    // recorded the identifiers they bind.
+    if let Some(loc) = val_info.loc {
+      if range.contains(&Range::from(loc).start) {
+        st.decs.entry(loc).or_insert(dec.loc);
+      }
+    }
+  }
+  Ok(env)
+}
+
+fn ck_impl(cx: &Cx, st: &mut State, dec: &Located<Dec<StrRef>>) -> Result<Env> {
+  // every case which recurses is in its own function, so that the stack frame of this one, which is
+  // in every level of recursion, stays small. see `parse::STACK_SIZE`.
   match &dec.val {
     // SML Definition (15)
-    Dec::Val(ty_vars, val_binds) => {
-      let mut cx_cl;
-      let cx = if ty_vars.is_empty() {
-        cx
-      } else {
-        cx_cl = cx.clone();
-        insert_ty_vars(&mut cx_cl, st, ty_vars)?;
-        &cx_cl
-      };
-      let mut val_env = ValEnv::new();
-      // SML Definition (25)
-      for val_bind in val_binds {
-        // SML Definition (26)
-        if val_bind.rec {
-          return Err(dec.loc.wrap(Error::Todo("recursive val binds")));
-        }
-        let (other, pat_ty, pat) = pat::ck(cx, st, &val_bind.pat)?;
-        for &name in other.keys() {
-          ck_binding(val_bind.pat.loc.wrap(name))?;
-        }
-        let exp_ty = ck_exp(cx, st, &val_bind.exp)?;
-        st.unify(dec.loc, pat_ty.clone(), exp_ty)?;
-        exhaustive::ck_bind(pat, val_bind.pat.loc)?;
-        for (name, mut val_info) in other {
-          generalize(cx, st, ty_vars, &mut val_info.ty_scheme);
-          let name = val_bind.pat.loc.wrap(name);
-          env_ins(&mut val_env, name, val_info, Item::Val)?;
-        }
+    Dec::Val(ty_vars, val_binds) => ck_val(cx, st, dec.loc, ty_vars, val_binds),
+    // SML Definition Appendix A - `fun` is sugar for `val rec` and `case`
+    Dec::Fun(ty_vars, fval_binds) => ck_fun(cx, st, ty_vars, fval_binds),
+    // SML Definition (16)
+    Dec::Type(ty_binds) => ck_ty_binds(cx, st, ty_binds),
+    // SML Definition (17)
+    Dec::Datatype(dat_binds, ty_binds) => ck_datatype(cx, st, dat_binds, ty_binds),
+    // SML Definition (18)
+    Dec::DatatypeCopy(ty_con, long) => ck_dat_copy(cx, &st.tys, *ty_con, long),
+    // SML Definition (19)
+    Dec::Abstype(dat_binds, ty_binds, inner) => {
+      ck_abstype(cx, st, dec.loc, dat_binds, ty_binds, inner)
+    }
+    // SML Definition (20)
+    Dec::Exception(ex_binds) => ck_exception(cx, st, ex_binds),
+    // SML Definition (21)
+    Dec::Local(fst, snd) => ck_local(cx, st, fst, snd),
+    // SML Definition (22)
+    Dec::Open(longs) => {
+      let mut env = Env::default();
+      for long in longs {
+        env.extend(get_struct(&cx.env, long)?.clone());
       }
-      Ok(val_env.into())
+      Ok(env)
     }
-    // SML Definition Appendix A - `fun` is sugar for `val rec` and `case`
-    Dec::Fun(ty_vars, fval_binds) => {
-      let mut cx_cl;
-      let cx = if ty_vars.is_empty() {
-        cx
-      } else {
-        cx_cl = cx.clone();
-        insert_ty_vars(&mut cx_cl, st, ty_vars)?;
-        &cx_cl
-      };
-      let mut fun_infos = HashMap::with_capacity(fval_binds.len());
-      for fval_bind in fval_binds {
-        let first = fval_bind.cases.first().unwrap();
-        let info = FunInfo {
-          args: first.pats.iter().map(|_| st.new_ty_var(false)).collect(),
-          ret: st.new_ty_var(false),
-        };
-        // copied from env_ins in util
-        if fun_infos.insert(first.vid.val, info).is_some() {
-          let err = Error::Duplicate(Item::Val, first.vid.val);
-          return Err(first.vid.loc.wrap(err));
+    // SML Definition (23), SML Definition (24)
+    Dec::Seq(decs) => ck_seq(cx, st, decs),
+    Dec::Infix(..) | Dec::Infixr(..) | Dec::Nonfix(..) => Ok(Env::default()),
+  }
+}
+
+/// SML Definition (15)
+fn ck_val(
+  cx: &Cx,
+  st: &mut State,
+  loc: Loc,
+  ty_vars: &[Located<AstTyVar<StrRef>>],
+  val_binds: &[ValBind<StrRef>],
+) -> Result<Env> {
+  let ty_vars = ty_var::get_val(cx, ty_vars, val_binds)?;
+  let mut cx_cl;
+  let cx = if ty_vars.is_empty() {
+    cx
+  } else {
+    cx_cl = cx.clone();
+    insert_ty_vars(&mut cx_cl, st, &ty_vars)?;
+    &cx_cl
+  };
+  let mut val_env = ValEnv::new();
+  // the names bound to expansive expressions, whose types may not be generalized.
+  let mut expansive = HashSet::new();
+  // `rec` applies to the val bind it precedes and all those after it.
+  let rec_idx = val_binds
+    .iter()
+    .position(|val_bind| val_bind.rec)
+    .unwrap_or(val_binds.len());
+  let (val_binds, rec_val_binds) = val_binds.split_at(rec_idx);
+  // SML Definition (25)
+  for val_bind in val_binds {
+    let (other, pat_ty, pat) = pat::ck(cx, st, &val_bind.pat)?;
+    for &name in other.keys() {
+      ck_binding(val_bind.pat.loc.wrap(name))?;
+    }
+    let exp_ty = ck_exp(cx, st, &val_bind.exp)?;
+    st.unify(loc, pat_ty.clone(), exp_ty)?;
+    exhaustive::ck_bind(st, pat, val_bind.pat.loc);
+    let nonexpansive = is_nonexpansive(cx, &val_bind.exp);
+    for (name, val_info) in other {
+      let name = val_bind.pat.loc.wrap(name);
+      env_ins(&mut val_env, name, val_info, Item::Val)?;
+      if !nonexpansive {
+        expansive.insert(name.val);
+      }
+    }
+  }
+  // SML Definition (26)
+  if !rec_val_binds.is_empty() {
+    let mut rec_val_env = ValEnv::new();
+    let mut pats = Vec::with_capacity(rec_val_binds.len());
+    for val_bind in rec_val_binds {
+      // SML Definition section 2.9 - the expression must be a `fn`.
+      if !matches!(val_bind.exp.val, Exp::Fn(_)) {
+        return Err(val_bind.exp.loc.wrap(Error::RecValNotFn));
+      }
+      let (other, pat_ty, pat) = pat::ck(cx, st, &val_bind.pat)?;
+      for (name, val_info) in other {
+        let name = val_bind.pat.loc.wrap(name);
+        ck_binding(name)?;
+        env_ins(&mut val_env, name, val_info.clone(), Item::Val)?;
+        rec_val_env.insert(name.val, val_info);
+      }
+      pats.push((pat_ty, pat));
+    }
+    let mut cx = cx.clone();
+    cx.env.val_env.extend(rec_val_env);
+    for (val_bind, (pat_ty, pat)) in rec_val_binds.iter().zip(pats) {
+      let exp_ty = ck_exp(&cx, st, &val_bind.exp)?;
+      st.unify(loc, pat_ty, exp_ty)?;
+      exhaustive::ck_bind(st, pat, val_bind.pat.loc);
+    }
+  }
+  generalize(cx, st, &ty_vars, &mut val_env, &expansive)?;
+  Ok(val_env.into())
+}
+
+/// SML Definition Appendix A - `fun` is sugar for `val rec` and `case`
+fn ck_fun(
+  cx: &Cx,
+  st: &mut State,
+  ty_vars: &[Located<AstTyVar<StrRef>>],
+  fval_binds: &[FValBind<StrRef>],
+) -> Result<Env> {
+  let ty_vars = ty_var::get_fun(cx, ty_vars, fval_binds)?;
+  let mut cx_cl;
+  let cx = if ty_vars.is_empty() {
+    cx
+  } else {
+    cx_cl = cx.clone();
+    insert_ty_vars(&mut cx_cl, st, &ty_vars)?;
+    &cx_cl
+  };
+  let mut fun_infos = HashMap::with_capacity(fval_binds.len());
+  for fval_bind in fval_binds {
+    let first = fval_bind.cases.first().unwrap();
+    let info = FunInfo {
+      args: first.pats.iter().map(|_| st.new_ty_var(false)).collect(),
+      ret: st.new_ty_var(false),
+      loc: first.vid.loc,
+    };
+    // copied from env_ins in util
+    if fun_infos.insert(first.vid.val, info).is_some() {
+      let prev = fval_binds
+        .iter()
+        .map(|fval_bind| fval_bind.cases.first().unwrap().vid)
+        .find(|vid| vid.val == first.vid.val)
+        .unwrap();
+      let err = Error::Duplicate(Item::Val, first.vid.val, Some(prev.loc));
+      return Err(first.vid.loc.wrap(err));
+    }
+  }
+  for fval_bind in fval_binds {
+    let first = fval_bind.cases.first().unwrap().vid;
+    let name = first.val;
+    let info = fun_infos.get(&name).unwrap();
+    let mut arg_pats = Vec::with_capacity(fval_bind.cases.len());
+    for case in fval_bind.cases.iter() {
+      if name != case.vid.val {
+        let err = Error::FunDecNameMismatch(name, case.vid.val, first.loc);
+        return Err(case.vid.loc.wrap(err));
+      }
+      if info.args.len() != case.pats.len() {
+        let err = Error::FunDecWrongNumPats(info.args.len(), case.pats.len());
+        let begin = case.pats.first().unwrap().loc;
+        let end = case.pats.last().unwrap().loc;
+        return Err(begin.span(end).wrap(err));
+      }
+      let mut pats_val_env = ValEnv::new();
+      let mut arg_pat = Vec::with_capacity(info.args.len());
+      for (pat, &tv) in case.pats.iter().zip(info.args.iter()) {
+        let (ve, pat_ty, new_pat) = pat::ck(cx, st, pat)?;
+        st.unify(pat.loc, Ty::Var(tv), pat_ty)?;
+        env_merge(&mut pats_val_env, ve, pat.loc, Item::Val)?;
+        arg_pat.push(new_pat);
+      }
+      let begin = case.pats.first().unwrap().loc;
+      let end = case.pats.last().unwrap().loc;
+      arg_pats.push(begin.span(end).wrap(Pat::record(arg_pat)));
+      if let Some(ty) = &case.ret_ty {
+        let new_ty = ty::ck(cx, st, ty)?;
+        st.unify(ty.loc, Ty::Var(info.ret), new_ty)?;
+      }
+      let mut cx = cx.clone();
+      // no dupe checking here - intentionally shadow.
+      cx.env.val_env.extend(fun_infos_to_ve(&fun_infos));
+      cx.env.val_env.extend(pats_val_env.clone());
+      let body_ty = ck_exp(&cx, st, &case.body)?;
+      ck_unused(st, &pats_val_env);
+      st.unify(case.body.loc, Ty::Var(info.ret), body_ty)?;
+    }
+    let begin = fval_bind.cases.first().unwrap().vid.loc;
+    let end = fval_bind.cases.last().unwrap().body.loc;
+    exhaustive::ck_match(st, arg_pats, begin.span(end))?;
+  }
+  let mut val_env = fun_infos_to_ve(&fun_infos);
+  generalize(cx, st, &ty_vars, &mut val_env, &HashSet::new())?;
+  for fval_bind in fval_binds {
+    for case in fval_bind.cases.iter() {
+      let val_info = val_env.get(&case.vid.val).unwrap();
+      let info = Info::val(case.vid.val, val_info, val_info.ty_scheme.ty.clone());
+      st.info.insert(case.vid.loc, info);
+    }
+  }
+  Ok(val_env.into())
+}
+
+/// SML Definition (17)
+fn ck_datatype(
+  cx: &Cx,
+  st: &mut State,
+  dat_binds: &[DatBind<StrRef>],
+  ty_binds: &[TyBind<StrRef>],
+) -> Result<Env> {
+  let mut env = ck_dat_binds(cx.clone(), st, dat_binds)?;
+  // SML Definition Appendix A - `datatype withtype` is sugar for `datatype; type`
+  let mut cx = cx.clone();
+  cx.o_plus(env.clone());
+  env.extend(ck_ty_binds(&cx, st, ty_binds)?);
+  Ok(env)
+}
+
+/// SML Definition (19)
+fn ck_abstype(
+  cx: &Cx,
+  st: &mut State,
+  loc: Loc,
+  dat_binds: &[DatBind<StrRef>],
+  ty_binds: &[TyBind<StrRef>],
+  inner: &Located<Dec<StrRef>>,
+) -> Result<Env> {
+  let dat_env = ck_dat_binds(cx.clone(), st, dat_binds)?;
+  let mut cx = cx.clone();
+  cx.o_plus(dat_env.clone());
+  // SML Definition Appendix A - `abstype withtype` is sugar for `abstype` with a `type` at the
+  // start of the `with`.
+  let ty_bind_env = ck_ty_binds(&cx, st, ty_binds)?;
+  cx.o_plus(ty_bind_env.clone());
+  let inner_env = ck(&cx, st, inner)?;
+  // Abs(TE, E) replaces every type name bound by TE with a fresh one which has no constructors
+  // and does not admit equality. the constructors themselves are not in the result.
+  let mut env: Env = dat_env.ty_env.clone().into();
+  env.extend(ty_bind_env);
+  env.extend(inner_env);
+  let mut ty_rzn = TyRealization::default();
+  let mut new_syms = Vec::with_capacity(dat_env.ty_env.inner.len());
+  for &old in dat_env.ty_env.inner.values() {
+    let new = st.new_sym(loc.wrap(old.name()));
+    ty_rzn.insert_sym(old, new);
+    new_syms.push((old, new));
+  }
+  for (old, new) in new_syms {
+    let mut ty_fcn = st.tys.get(&old).ty_fcn.clone();
+    ty_rzn.get_ty(&mut ty_fcn.ty);
+    let ty_info = TyInfo {
+      ty_fcn,
+      val_env: ValEnv::new(),
+      equality: false,
+    };
+    st.tys.insert(new, ty_info);
+  }
+  ty_rzn.get_env(&mut st.tys, &mut env);
+  Ok(env)
+}
+
+/// SML Definition (20)
+fn ck_exception(cx: &Cx, st: &mut State, ex_binds: &[ExBind<StrRef>]) -> Result<Env> {
+  let mut val_env = ValEnv::new();
+  for ex_bind in ex_binds {
+    let val_info = match &ex_bind.inner {
+      // SML Definition (30)
+      ExBindInner::Ty(ty) => match ty {
+        None => ValInfo::exn(),
+        Some(ty) => ValInfo::exn_fn(ty::ck(cx, st, ty)?),
+      },
+      // SML Definition (31)
+      ExBindInner::Long(vid) => {
+        let val_info = get_val_info(get_env(&cx.env, vid)?, vid.last)?;
+        if !val_info.id_status.is_exn() {
+          return Err(vid.loc().wrap(Error::ExnWrongIdStatus(val_info.id_status)));
         }
+        val_info.clone()
+      }
+    }
+    .bound_at(ex_bind.vid.loc);
+    let info = Info::val(ex_bind.vid.val, &val_info, val_info.ty_scheme.ty.clone());
+    st.info.insert(ex_bind.vid.loc, info);
+    env_ins(&mut val_env, ex_bind.vid, val_info, Item::Val)?;
+  }
+  Ok(val_env.into())
+}
+
+/// SML Definition (21)
+fn ck_local(
+  cx: &Cx,
+  st: &mut State,
+  fst: &Located<Dec<StrRef>>,
+  snd: &Located<Dec<StrRef>>,
+) -> Result<Env> {
+  let fst_env = ck(cx, st, fst)?;
+  let mut cx = cx.clone();
+  cx.o_plus(fst_env);
+  ck(&cx, st, snd)
+}
+
+/// SML Definition (23), SML Definition (24)
+fn ck_seq(cx: &Cx, st: &mut State, decs: &[Located<Dec<StrRef>>]) -> Result<Env> {
+  let mut cx = cx.clone();
+  let mut ret = Env::default();
+  for dec in decs {
+    // if there is an error, record it and keep going, so we can report more errors.
+    match ck(&cx, st, dec) {
+      Ok(env) => {
+        cx.o_plus(env.clone());
+        ret.extend(env);
+      }
+      Err(e) => {
+        st.errors.push(e);
+        let env = failed(&cx, st, dec);
+        cx.o_plus(env.clone());
+        ret.extend(env);
+      }
+    }
+  }
+  Ok(ret)
+}
+
+pub fn failed(cx: &Cx, st: &mut State, dec: &Located<Dec<StrRef>>) -> Env {
+  let mut env = Env::default();
+  match &dec.val {
+    Dec::Val(_, val_binds) => {
+      let mut vids = Vec::new();
+      for val_bind in val_binds {
+        pat_vids(cx, &val_bind.pat, &mut vids);
       }
+      for vid in vids {
+        let val_info = ValInfo::val(any_ty_scheme(st)).bound_at(vid.loc);
+        env.val_env.insert(vid.val, val_info);
+      }
+    }
+    Dec::Fun(_, fval_binds) => {
       for fval_bind in fval_binds {
-        let name = fval_bind.cases.first().unwrap().vid.val;
-        let info = fun_infos.get(&name).unwrap();
-        let mut arg_pats = Vec::with_capacity(fval_bind.cases.len());
-        for case in fval_bind.cases.iter() {
-          if name != case.vid.val {
-            let err = Error::FunDecNameMismatch(name, case.vid.val);
-            return Err(case.vid.loc.wrap(err));
-          }
-          if info.args.len() != case.pats.len() {
-            let err = Error::FunDecWrongNumPats(info.args.len(), case.pats.len());
-            let begin = case.pats.first().unwrap().loc;
-            let end = case.pats.last().unwrap().loc;
-            return Err(begin.span(end).wrap(err));
-          }
-          let mut pats_val_env = ValEnv::new();
-          let mut arg_pat = Vec::with_capacity(info.args.len());
-          for (pat, &tv) in case.pats.iter().zip(info.args.iter()) {
-            let (ve, pat_ty, new_pat) = pat::ck(cx, st, pat)?;
-            st.unify(pat.loc, Ty::Var(tv), pat_ty)?;
-            env_merge(&mut pats_val_env, ve, pat.loc, Item::Val)?;
-            arg_pat.push(new_pat);
-          }
-          let begin = case.pats.first().unwrap().loc;
-          let end = case.pats.last().unwrap().loc;
-          arg_pats.push(begin.span(end).wrap(Pat::record(arg_pat)));
-          if let Some(ty) = &case.ret_ty {
-            let new_ty = ty::ck(cx, &st.tys, ty)?;
-            st.unify(ty.loc, Ty::Var(info.ret), new_ty)?;
+        let vid = fval_bind.cases.first().unwrap().vid;
+        let val_info = ValInfo::val(any_ty_scheme(st)).bound_at(vid.loc);
+        env.val_env.insert(vid.val, val_info);
+      }
+    }
+    Dec::Type(ty_binds) => failed_ty_binds(st, ty_binds, &mut env),
+    Dec::Datatype(dat_binds, ty_binds) => {
+      for dat_bind in dat_binds {
+        let sym = abstract_ty(st, dat_bind.ty_con, dat_bind.ty_vars.len());
+        let ty_fcn = st.tys.get(&sym).ty_fcn.clone();
+        let mut val_env = ValEnv::new();
+        for con_bind in dat_bind.cons.iter() {
+          let mut ty_scheme = ty_fcn.clone();
+          if con_bind.ty.is_some() {
+            let arg = st.new_ty_var(false);
+            ty_scheme.ty = Ty::Arrow(Ty::Var(arg).into(), ty_scheme.ty.into());
+            ty_scheme.ty_vars.push(arg);
           }
-          let mut cx = cx.clone();
-          // no dupe checking here - intentionally shadow.
-          cx.env.val_env.extend(fun_infos_to_ve(&fun_infos));
-          cx.env.val_env.extend(pats_val_env);
-          let body_ty = ck_exp(&cx, st, &case.body)?;
-          st.unify(case.body.loc, Ty::Var(info.ret), body_ty)?;
+          let val_info = ValInfo::ctor(ty_scheme).bound_at(con_bind.vid.loc);
+          val_env.insert(con_bind.vid.val, val_info);
         }
-        let begin = fval_bind.cases.first().unwrap().vid.loc;
-        let end = fval_bind.cases.last().unwrap().body.loc;
-        exhaustive::ck_match(arg_pats, begin.span(end))?;
+        env.ty_env.inner.insert(dat_bind.ty_con.val, sym);
+        env.val_env.extend(val_env.clone());
+        st.tys.finish_datatype(&sym, val_env);
       }
-      let mut val_env = fun_infos_to_ve(&fun_infos);
-      for val_info in val_env.values_mut() {
-        generalize(cx, st, ty_vars, &mut val_info.ty_scheme);
-      }
-      Ok(val_env.into())
+      failed_ty_binds(st, ty_binds, &mut env);
     }
-    // SML Definition (16)
-    Dec::Type(ty_binds) => ck_ty_binds(cx, st, ty_binds),
-    // SML Definition (17)
-    Dec::Datatype(dat_binds, ty_binds) => {
-      let mut env = ck_dat_binds(cx.clone(), st, dat_binds)?;
-      // SML Definition Appendix A - `datatype withtype` is sugar for `datatype; type`
-      let mut cx = cx.clone();
-      cx.o_plus(env.clone());
-      env.extend(ck_ty_binds(&cx, st, ty_binds)?);
-      Ok(env)
+    Dec::DatatypeCopy(ty_con, _) => {
+      let sym = abstract_ty(st, *ty_con, 0);
+      env.ty_env.inner.insert(ty_con.val, sym);
+    }
+    Dec::Abstype(dat_binds, ty_binds, inner) => {
+      // the constructors are not in the result, as with the regular checking.
+      for dat_bind in dat_binds {
+        let sym = abstract_ty(st, dat_bind.ty_con, dat_bind.ty_vars.len());
+        env.ty_env.inner.insert(dat_bind.ty_con.val, sym);
+      }
+      failed_ty_binds(st, ty_binds, &mut env);
+      env.extend(failed(cx, st, inner));
     }
-    // SML Definition (18)
-    Dec::DatatypeCopy(ty_con, long) => ck_dat_copy(cx, &st.tys, *ty_con, long),
-    // SML Definition (19)
-    Dec::Abstype(..) => Err(dec.loc.wrap(Error::Todo("`abstype`"))),
-    // SML Definition (20)
     Dec::Exception(ex_binds) => {
-      let mut val_env = ValEnv::new();
       for ex_bind in ex_binds {
         let val_info = match &ex_bind.inner {
-          // SML Definition (30)
-          ExBindInner::Ty(ty) => match ty {
-            None => ValInfo::exn(),
-            Some(ty) => ValInfo::exn_fn(ty::ck(cx, &st.tys, ty)?),
-          },
-          // SML Definition (31)
-          ExBindInner::Long(vid) => {
-            let val_info = get_val_info(get_env(&cx.env, vid)?, vid.last)?;
-            if !val_info.id_status.is_exn() {
-              return Err(vid.loc().wrap(Error::ExnWrongIdStatus(val_info.id_status)));
+          ExBindInner::Ty(None) => ValInfo::exn(),
+          ExBindInner::Ty(Some(_)) | ExBindInner::Long(_) => {
+            let arg = st.new_ty_var(false);
+            ValInfo {
+              ty_scheme: TyScheme {
+                ty_vars: vec![arg],
+                ty: Ty::Arrow(Ty::Var(arg).into(), Ty::EXN.into()),
+                overload: None,
+              },
+              ..ValInfo::exn()
             }
-            val_info.clone()
           }
         };
-        env_ins(&mut val_env, ex_bind.vid, val_info, Item::Val)?;
+        env
+          .val_env
+          .insert(ex_bind.vid.val, val_info.bound_at(ex_bind.vid.loc));
       }
-      Ok(val_env.into())
     }
-    // SML Definition (21)
-    Dec::Local(fst, snd) => {
-      let fst_env = ck(cx, st, fst)?;
+    Dec::Local(_, snd) => env = failed(cx, st, snd),
+    Dec::Seq(decs) => {
       let mut cx = cx.clone();
-      cx.o_plus(fst_env);
-      ck(&cx, st, snd)
+      for dec in decs {
+        let dec_env = failed(&cx, st, dec);
+        cx.o_plus(dec_env.clone());
+        env.extend(dec_env);
+      }
     }
-    // SML Definition (22)
-    Dec::Open(longs) => {
-      let mut env = Env::default();
-      for long in longs {
-        env.extend(get_env(&cx.env, long)?.clone());
+    Dec::Open(..) | Dec::Infix(..) | Dec::Infixr(..) | Dec::Nonfix(..) => {}
+  }
+  env
+}
+
+/// Returns `forall 'a. 'a`, which matches any type.
+fn any_ty_scheme(st: &mut State) -> TyScheme {
+  let tv = st.new_ty_var(false);
+  TyScheme {
+    ty_vars: vec![tv],
+    ty: Ty::Var(tv),
+    overload: None,
+  }
+}
+
+/// Returns a fresh symbol for an abstract type `ty_con` with `arity` arguments, which has no
+/// constructors and does not admit equality.
+fn abstract_ty(st: &mut State, ty_con: Located<StrRef>, arity: usize) -> Sym {
+  let sym = st.new_sym(ty_con);
+  let ty_vars: Vec<_> = (0..arity).map(|_| st.new_ty_var(false)).collect();
+  let ty_args = ty_vars.iter().copied().map(Ty::Var).collect();
+  let ty_info = TyInfo {
+    ty_fcn: TyScheme {
+      ty_vars,
+      ty: Ty::Ctor(ty_args, sym),
+      overload: None,
+    },
+    val_env: ValEnv::new(),
+    equality: false,
+  };
+  st.tys.insert(sym, ty_info);
+  sym
+}
+
+fn failed_ty_binds(st: &mut State, ty_binds: &[TyBind<StrRef>], env: &mut Env) {
+  for ty_bind in ty_binds {
+    let sym = abstract_ty(st, ty_bind.ty_con, ty_bind.ty_vars.len());
+    env.ty_env.inner.insert(ty_bind.ty_con.val, sym);
+  }
+}
+
+/// Pushes the variables bound by `pat` onto `ac`. As in `pat::ck`, an unqualified identifier is a
+/// variable unless `cx` says it is a constructor or exception.
+fn pat_vids(cx: &Cx, pat: &Located<AstPat<StrRef>>, ac: &mut Vec<Located<StrRef>>) {
+  match &pat.val {
+    AstPat::Wildcard
+    | AstPat::DecInt(_)
+    | AstPat::HexInt(_)
+    | AstPat::DecWord(_)
+    | AstPat::HexWord(_)
+    | AstPat::String(_)
+    | AstPat::Char(_) => {}
+    AstPat::LongVid(long) => {
+      let is_ctor = cx
+        .env
+        .val_env
+        .get(&long.last.val)
+        .is_some_and(|x| !x.id_status.is_val());
+      if long.structures.is_empty() && !is_ctor {
+        ac.push(long.last);
       }
-      Ok(env)
     }
-    // SML Definition (23), SML Definition (24)
-    Dec::Seq(decs) => {
-      let mut cx = cx.clone();
-      let mut ret = Env::default();
-      for dec in decs {
-        cx.o_plus(ret.clone());
-        let env = ck(&cx, st, dec)?;
-        ret.extend(env);
+    AstPat::Record(rows, _) => {
+      for row in rows {
+        pat_vids(cx, &row.val, ac);
       }
-      Ok(ret)
     }
-    Dec::Infix(..) | Dec::Infixr(..) | Dec::Nonfix(..) => Ok(Env::default()),
+    AstPat::Tuple(pats) | AstPat::List(pats) | AstPat::Vector(pats) => {
+      for pat in pats {
+        pat_vids(cx, pat, ac);
+      }
+    }
+    AstPat::Ctor(_, arg) => pat_vids(cx, arg, ac),
+    AstPat::InfixCtor(lhs, _, rhs) => {
+      pat_vids(cx, lhs, ac);
+      pat_vids(cx, rhs, ac);
+    }
+    AstPat::Typed(pat, _) => pat_vids(cx, pat, ac),
+    AstPat::As(vid, _, pat) => {
+      ac.push(*vid);
+      pat_vids(cx, pat, ac);
+    }
   }
 }
 
@@ -419,22 +945,22 @@ fn ck_ty_binds(cx: &Cx, st: &mut State, ty_binds: &[TyBind<StrRef>]) -> Result<E
       insert_ty_vars(&mut cx_cl, st, &ty_bind.ty_vars)?;
       &cx_cl
     };
-    let ty = ty::ck(cx, &st.tys, &ty_bind.ty)?;
+    let ty = ty::ck(cx, st, &ty_bind.ty)?;
     let sym = st.new_sym(ty_bind.ty_con);
     env_ins(&mut ty_env.inner, ty_bind.ty_con, sym, Item::Ty)?;
-    // TODO better equality checks
-    let equality = ty.is_equality(&st.tys);
+    let ty_vars: Vec<_> = ty_bind
+      .ty_vars
+      .iter()
+      .map(|tv| {
+        let tv = *cx.ty_vars.get(&tv.val).unwrap();
+        st.subst.remove_bound(&tv);
+        tv
+      })
+      .collect();
+    let equality = ty.is_equality_with(&st.tys, &ty_vars);
     let info = TyInfo {
       ty_fcn: TyScheme {
-        ty_vars: ty_bind
-          .ty_vars
-          .iter()
-          .map(|tv| {
-            let tv = *cx.ty_vars.get(&tv.val).unwrap();
-            st.subst.remove_bound(&tv);
-            tv
-          })
-          .collect(),
+        ty_vars,
         ty,
         overload: None,
       },
@@ -468,11 +994,12 @@ pub fn ck_dat_binds(mut cx: Cx, st: &mut State, dat_binds: &[DatBind<StrRef>]) -
     cx.env.ty_env.inner.insert(dat_bind.ty_con.val, sym);
     // no mapping from ast ty vars to statics ty vars here. we just need some ty vars to make the
     // `TyScheme`. pretty much copied from `insert_ty_vars`.
-    let mut set = HashSet::new();
+    let mut seen = HashMap::new();
     let mut ty_vars = Vec::new();
     for tv in dat_bind.ty_vars.iter() {
-      if !set.insert(tv.val.name) {
-        return Err(tv.loc.wrap(Error::Duplicate(Item::TyVar, tv.val.name)));
+      if let Some(prev) = seen.insert(tv.val.name, tv.loc) {
+        let err = Error::Duplicate(Item::TyVar, tv.val.name, Some(prev));
+        return Err(tv.loc.wrap(err));
       }
       let new_tv = st.new_ty_var(tv.val.equality);
       ty_vars.push(new_tv);
@@ -487,10 +1014,12 @@ pub fn ck_dat_binds(mut cx: Cx, st: &mut State, dat_binds: &[DatBind<StrRef>]) -
     st.tys.insert_datatype(sym, ty_fcn);
     syms.push(sym);
   }
+  // the types of the arguments of the ctors of each datatype.
+  let mut all_arg_tys = Vec::with_capacity(syms.len());
   // SML Definition (28), SML Definition (81)
   for (dat_bind, sym) in dat_binds.iter().zip(syms) {
-    // note that we have to `get` here and then `get_mut` again later because of the borrow checker.
-    let ty_fcn = &st.tys.get(&sym).ty_fcn;
+    // note that we have to clone here, and `get_mut` again later, because of the borrow checker.
+    let ty_fcn = st.tys.get(&sym).ty_fcn.clone();
     let mut cx_cl;
     let cx = if dat_bind.ty_vars.is_empty() {
       &cx
@@ -514,7 +1043,7 @@ pub fn ck_dat_binds(mut cx: Cx, st: &mut State, dat_binds: &[DatBind<StrRef>]) -
     };
     // this ValEnv is specific to this `DatBind`.
     let mut bind_val_env = ValEnv::new();
-    let mut equality = true;
+    let mut arg_tys = Vec::new();
     // SML Definition (29), SML Definition (82)
     for con_bind in dat_bind.cons.iter() {
       ck_binding(con_bind.vid)?;
@@ -522,18 +1051,20 @@ pub fn ck_dat_binds(mut cx: Cx, st: &mut State, dat_binds: &[DatBind<StrRef>]) -
       // that is being defined.
       let mut ty = ty_fcn.ty.clone();
       if let Some(arg_ty) = &con_bind.ty {
-        // if there is an `of t`, then the type of the ctor is `t -> T`. we must also update whether
-        // `T` respects equality based on whether `t` does. TODO this doesn't handle the equality
-        // check correctly.
-        let t = ty::ck(&cx, &st.tys, arg_ty)?;
-        equality = equality && t.is_equality(&st.tys);
+        // if there is an `of t`, then the type of the ctor is `t -> T`. we must also later check
+        // whether `T` respects equality based on whether `t` does.
+        let t = ty::ck(cx, st, arg_ty)?;
+        arg_tys.push(t.clone());
         ty = Ty::Arrow(t.into(), ty.into());
       }
       let val_info = ValInfo::ctor(TyScheme {
         ty_vars: ty_fcn.ty_vars.clone(),
         ty,
         overload: None,
-      });
+      })
+      .bound_at(con_bind.vid.loc);
+      let info = Info::val(con_bind.vid.val, &val_info, val_info.ty_scheme.ty.clone());
+      st.info.insert(con_bind.vid.loc, info);
       // insert the `ValInfo` into the _overall_ `ValEnv` with dupe checking.
       env_ins(&mut val_env, con_bind.vid, val_info.clone(), Item::Val)?;
       // _also_ insert the `ValInfo` into the `DatBind`-specific `ValEnv`, but this time dupe
@@ -542,7 +1073,27 @@ pub fn ck_dat_binds(mut cx: Cx, st: &mut State, dat_binds: &[DatBind<StrRef>]) -
     }
     // now the `ValEnv` is complete, so we may update `st.tys` with the true definition of this
     // datatype.
-    st.tys.finish_datatype(&sym, bind_val_env, equality);
+    st.tys.finish_datatype(&sym, bind_val_env);
+    all_arg_tys.push((sym, arg_tys));
+  }
+  // a datatype respects equality iff the argument types of all its ctors do, assuming that the type
+  // variables of the datatype do. this may depend on whether the other datatypes respect equality,
+  // so we iterate until we reach a fixed point.
+  loop {
+    let mut changed = false;
+    for (sym, arg_tys) in all_arg_tys.iter() {
+      let info = st.tys.get(sym);
+      let equality = arg_tys
+        .iter()
+        .all(|t| t.is_equality_with(&st.tys, &info.ty_fcn.ty_vars));
+      if info.equality && !equality {
+        st.tys.remove_equality(sym);
+        changed = true;
+      }
+    }
+    if !changed {
+      break;
+    }
   }
   Ok(Env {
     ty_env,
@@ -80,6 +80,11 @@ fn ck_val_info(cx: Cx, got: &ValInfo, want: &ValInfo) -> Result<()> {
 
 fn ck_ty_info(cx: Cx, got: &TyInfo, want: &TyInfo) -> Result<()> {
   ck_ty_fcn_eq(cx, &got.ty_fcn, &want.ty_fcn)?;
+  // the type function of `got` might be an abbreviation, so check it directly rather than trusting
+  // `got.equality`.
+  if want.equality && !got.ty_fcn.ty.is_equality_with(cx.tys, &got.ty_fcn.ty_vars) {
+    return Err(cx.loc.wrap(Error::NotEquality(got.ty_fcn.ty.clone())));
+  }
   if want.val_env.is_empty() {
     return Ok(());
   }
@@ -121,5 +126,11 @@ fn ck_generalizes(cx: Cx, mut want: TyScheme, mut got: TyScheme) -> Result<()> {
   }
   cx.ty_rzn.get_ty(&mut want.ty);
   cx.ty_rzn.get_ty(&mut got.ty);
-  Subst::default().unify(cx.loc, &cx.tys, want.ty, got.ty)
+  // the type variables bound by `want` may not be instantiated, but those bound by `got` may. this
+  // allows e.g. `'a -> 'a` to match a spec of `int -> int` but not the other way around.
+  let mut subst = Subst::default();
+  for &tv in want.ty_vars.iter() {
+    subst.insert_bound(tv);
+  }
+  subst.unify(cx.loc, cx.tys, want.ty, got.ty)
 }
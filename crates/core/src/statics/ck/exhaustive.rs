@@ -16,32 +16,36 @@
 //!   strings.
 
 use crate::loc::{Loc, Located};
-use crate::statics::types::{Con, Error, Pat, Result, Span};
+use crate::statics::types::{Con, Error, Pat, Result, Span, State, Subst};
 use std::collections::HashSet;
 
-/// Returns `Ok(())` iff the pats are exhaustive and not redundant.
-pub fn ck_match(pats: Vec<Located<Pat>>, loc: Loc) -> Result<()> {
-  match ck(pats) {
-    Res::Exhaustive => Ok(()),
-    Res::NonExhaustive => Err(loc.wrap(Error::NonExhaustiveMatch)),
-    Res::Unreachable(loc) => Err(loc.wrap(Error::UnreachablePattern)),
+/// Returns `Ok(())` iff the pats are not redundant. If they are not exhaustive, records a warning
+/// in the `State`.
+pub fn ck_match(st: &mut State, pats: Vec<Located<Pat>>, loc: Loc) -> Result<()> {
+  let res = ck(&st.subst, pats);
+  if let Some(loc) = res.unreachable {
+    return Err(loc.wrap(Error::UnreachablePattern));
   }
+  if !res.exhaustive {
+    st.errors.push(loc.wrap(Error::NonExhaustiveMatch));
+  }
+  Ok(())
 }
 
-/// Returns `Ok(())` iff the singular pat is exhaustive.
-pub fn ck_bind(pat: Pat, loc: Loc) -> Result<()> {
-  match ck(vec![loc.wrap(pat)]) {
-    Res::Exhaustive => Ok(()),
-    Res::NonExhaustive => Err(loc.wrap(Error::NonExhaustiveBinding)),
-    Res::Unreachable(_) => unreachable!(),
+/// Records a warning in the `State` iff the singular pat is not exhaustive.
+pub fn ck_bind(st: &mut State, pat: Pat, loc: Loc) {
+  let res = ck(&st.subst, vec![loc.wrap(pat)]);
+  assert!(res.unreachable.is_none());
+  if !res.exhaustive {
+    st.errors.push(loc.wrap(Error::NonExhaustiveBinding));
   }
 }
 
 /// Returns `Ok(())` iff the pats are not redundant.
-pub fn ck_handle(pats: Vec<Located<Pat>>) -> Result<()> {
-  match ck(pats) {
-    Res::Exhaustive | Res::NonExhaustive => Ok(()),
-    Res::Unreachable(loc) => Err(loc.wrap(Error::UnreachablePattern)),
+pub fn ck_handle(subst: &Subst, pats: Vec<Located<Pat>>) -> Result<()> {
+  match ck(subst, pats).unreachable {
+    None => Ok(()),
+    Some(loc) => Err(loc.wrap(Error::UnreachablePattern)),
   }
 }
 
@@ -101,26 +105,24 @@ type Cx = HashSet<Loc>;
 type Pats = std::vec::IntoIter<Located<Pat>>;
 
 /// A determination of what the patterns were.
-enum Res {
-  /// They were exhaustive.
-  Exhaustive,
-  /// They were not exhaustive.
-  NonExhaustive,
-  /// There was a pattern which can never be reached.
-  Unreachable(Loc),
+struct Res {
+  /// Whether they were exhaustive.
+  exhaustive: bool,
+  /// The first pattern which can never be reached, if any.
+  unreachable: Option<Loc>,
 }
 
 /// The main function, which the exported functions ultimately call.
-fn ck(pats: Vec<Located<Pat>>) -> Res {
+fn ck(subst: &Subst, mut pats: Vec<Located<Pat>>) -> Res {
+  for pat in pats.iter_mut() {
+    pat.val.apply(subst);
+  }
   let mut cx: Cx = pats.iter().map(|x| x.loc).collect();
-  if fail(&mut cx, Desc::Neg(vec![]), pats.into_iter()) {
-    // Must choose the minimum loc to get the first unreachable pattern.
-    match cx.into_iter().min() {
-      None => Res::Exhaustive,
-      Some(loc) => Res::Unreachable(loc),
-    }
-  } else {
-    Res::NonExhaustive
+  let exhaustive = fail(&mut cx, Desc::Neg(vec![]), pats.into_iter());
+  // Must choose the minimum loc to get the first unreachable pattern.
+  Res {
+    exhaustive,
+    unreachable: cx.into_iter().min(),
   }
 }
 
@@ -235,13 +237,16 @@ fn succeed_with(
 fn do_match(cx: &mut Cx, pat: Located<Pat>, d: Desc, work: Work, pats: Pats) -> bool {
   match pat.val {
     Pat::Anything => succeed(cx, pat.loc, augment(work, d), pats),
+    Pat::FlexRecord(..) => unreachable!("FlexRecord not applied"),
     Pat::Con(con, args) => match static_match(con, &d) {
       StaticMatch::Yes => succeed_with(cx, pat.loc, work, con, args, d, pats),
       StaticMatch::No => fail(cx, build_desc(d, work), pats),
       StaticMatch::Maybe(mut cons) => {
         cons.push(con);
-        succeed_with(cx, pat.loc, work.clone(), con, args, d, pats.clone())
-          && fail(cx, build_desc(Desc::Neg(cons), work), pats)
+        // Don't short-circuit, so that we always find every reachable pattern.
+        let yes = succeed_with(cx, pat.loc, work.clone(), con, args, d, pats.clone());
+        let no = fail(cx, build_desc(Desc::Neg(cons), work), pats);
+        yes && no
       }
     },
   }
@@ -19,6 +19,8 @@ mod pat;
 mod sig_match;
 mod top_dec;
 mod ty;
+mod ty_var;
 mod util;
 
 pub use top_dec::ck as ck_top_dec;
+pub use top_dec::ck_sig_exp;
@@ -1,12 +1,14 @@
 //! Check patterns.
 
-use crate::ast::{Label, Long, Pat as AstPat};
+use crate::ast::{Label, Long, Pat as AstPat, Row, Ty as AstTy};
 use crate::intern::StrRef;
 use crate::loc::{Loc, Located};
 use crate::statics::ck::ty;
-use crate::statics::ck::util::{env_ins, env_merge, get_env, get_val_info, instantiate};
+use crate::statics::ck::util::{
+  env_ins, env_merge, get_env, get_val_info, instantiate, int_constant, word_constant,
+};
 use crate::statics::types::{
-  Con, Cx, Error, Item, Pat, Result, Span, State, Sym, Ty, TyScheme, Tys, ValEnv, ValInfo,
+  Con, Cx, Error, Info, Item, Pat, Result, Span, State, Sym, Ty, Tys, ValEnv, ValInfo,
 };
 use maplit::btreemap;
 use std::collections::BTreeMap;
@@ -15,92 +17,29 @@ pub fn ck(cx: &Cx, st: &mut State, pat: &Located<AstPat<StrRef>>) -> Result<(Val
   // Wildcard is by SML Definition (32), special constants are by SML Definition (33). Additionally,
   // SML Definition (37) is handled by the parser, and SML Definition (40) is handed because atomic
   // and non-atomic Pats are both in the same enum.
+  //
+  // every case which recurses is in its own function, so that the stack frame of this one, which is
+  // in every level of recursion, stays small. see `parse::STACK_SIZE`.
   match &pat.val {
     AstPat::Wildcard => Ok((ValEnv::new(), Ty::Var(st.new_ty_var(false)), Pat::Anything)),
-    AstPat::DecInt(n) | AstPat::HexInt(n) => Ok((ValEnv::new(), Ty::INT, Pat::zero(Con::Int(*n)))),
+    AstPat::DecInt(n) | AstPat::HexInt(n) => {
+      let ty = int_constant(st);
+      Ok((ValEnv::new(), ty, Pat::zero(Con::Int(*n))))
+    }
     AstPat::DecWord(n) | AstPat::HexWord(n) => {
-      Ok((ValEnv::new(), Ty::WORD, Pat::zero(Con::Word(*n))))
+      let ty = word_constant(st, pat.loc, *n);
+      Ok((ValEnv::new(), ty, Pat::zero(Con::Word(*n))))
     }
     AstPat::String(s) => Ok((ValEnv::new(), Ty::STRING, Pat::zero(Con::String(*s)))),
     AstPat::Char(c) => Ok((ValEnv::new(), Ty::CHAR, Pat::zero(Con::Char(*c)))),
-    AstPat::LongVid(vid) => {
-      let ty_scheme = get_env(&cx.env, vid)?
-        .val_env
-        .get(&vid.last.val)
-        .and_then(|val_info| {
-          if val_info.id_status.is_val() {
-            None
-          } else {
-            Some(&val_info.ty_scheme)
-          }
-        });
-      match ty_scheme {
-        // SML Definition (34)
-        None => {
-          let a = Ty::Var(st.new_ty_var(false));
-          let val_info = ValInfo::val(TyScheme::mono(a.clone()));
-          Ok((btreemap![vid.last.val => val_info], a, Pat::Anything))
-        }
-        // SML Definition (35)
-        Some(ty_scheme) => {
-          let ty = instantiate(st, ty_scheme);
-          let sym = match ty {
-            Ty::Ctor(_, sym) => sym,
-            _ => return Err(pat.loc.wrap(Error::PatNotConsTy(ty))),
-          };
-          let span = get_span(&st.tys, sym);
-          let pat = Pat::zero(Con::Ctor(vid.last.val, span));
-          Ok((ValEnv::new(), ty, pat))
-        }
-      }
-    }
+    AstPat::LongVid(vid) => ck_long_vid(cx, st, pat.loc, vid),
     // SML Definition (36)
-    AstPat::Record(rows, rest_loc) => {
-      // SML Definition (38)
-      if let Some(loc) = rest_loc {
-        return Err(loc.wrap(Error::Todo("rest patterns")));
-      }
-      let mut val_env = ValEnv::new();
-      let mut ty_rows = BTreeMap::new();
-      let mut new_pats = BTreeMap::new();
-      // SML Definition (39)
-      for row in rows {
-        let (other_ve, ty, pat) = ck(cx, st, &row.val)?;
-        if new_pats.insert(row.lab.val, pat).is_some() {
-          return Err(row.lab.loc.wrap(Error::DuplicateLabel(row.lab.val)));
-        }
-        env_merge(&mut val_env, other_ve, row.val.loc, Item::Val)?;
-        assert!(ty_rows.insert(row.lab.val, ty).is_none());
-      }
-      let new_pats: Vec<_> = new_pats.into_iter().map(|(_, pat)| pat).collect();
-      let pat = Pat::record(new_pats);
-      Ok((val_env, Ty::Record(ty_rows), pat))
-    }
+    AstPat::Record(rows, rest_loc) => ck_record(cx, st, pat.loc, rows, *rest_loc),
     // SML Definition Appendix A - tuple patterns are sugar for records
-    AstPat::Tuple(pats) => {
-      let mut val_env = ValEnv::new();
-      let mut ty_rows = BTreeMap::new();
-      let mut new_pats = Vec::with_capacity(pats.len());
-      for (idx, pat) in pats.iter().enumerate() {
-        let (other_ve, ty, new_pat) = ck(cx, st, pat)?;
-        env_merge(&mut val_env, other_ve, pat.loc, Item::Val)?;
-        assert!(ty_rows.insert(Label::tuple(idx), ty).is_none());
-        new_pats.push(new_pat);
-      }
-      let pat = Pat::record(new_pats);
-      Ok((val_env, Ty::Record(ty_rows), pat))
-    }
+    AstPat::Tuple(pats) => ck_tuple(cx, st, pats),
     // SML Definition Appendix A - list patterns are sugar for constructors
     AstPat::List(pats) => {
-      let elem = Ty::Var(st.new_ty_var(false));
-      let mut val_env = ValEnv::new();
-      let mut new_pats = Vec::with_capacity(pats.len());
-      for pat in pats {
-        let (other_ve, ty, new_pat) = ck(cx, st, pat)?;
-        env_merge(&mut val_env, other_ve, pat.loc, Item::Val)?;
-        st.unify(pat.loc, elem.clone(), ty)?;
-        new_pats.push(new_pat);
-      }
+      let (val_env, elem, new_pats) = ck_elems(cx, st, pats)?;
       let pat = new_pats.into_iter().rev().fold(
         Pat::zero(Con::Ctor(StrRef::NIL, Span::Finite(2))),
         |ac, x| {
@@ -112,56 +51,212 @@ pub fn ck(cx: &Cx, st: &mut State, pat: &Located<AstPat<StrRef>>) -> Result<(Val
       );
       Ok((val_env, Ty::list(elem), pat))
     }
-    // SML Definition (41)
-    AstPat::Ctor(long, arg) => {
-      let (val_env, arg_ty, arg_pat) = ck(cx, st, arg)?;
-      let (ty, pat) = ctor(cx, st, pat.loc, long, arg_ty, arg_pat)?;
-      Ok((val_env, ty, pat))
+    AstPat::Vector(pats) => {
+      let (val_env, elem, new_pats) = ck_elems(cx, st, pats)?;
+      let pat = Pat::Con(Con::Vector(new_pats.len()), new_pats);
+      Ok((val_env, Ty::vector(elem), pat))
     }
+    // SML Definition (41)
+    AstPat::Ctor(long, arg) => ck_ctor(cx, st, pat.loc, long, arg),
     // SML Definition (41). Infix ctors are the same as `op`ing the ctor and applying it to the
     // tuple (lhs, rhs).
-    AstPat::InfixCtor(lhs, vid, rhs) => {
-      let (mut val_env, lhs_ty, lhs_pat) = ck(cx, st, lhs)?;
-      let (other_ve, rhs_ty, rhs_pat) = ck(cx, st, rhs)?;
-      env_merge(&mut val_env, other_ve, pat.loc, Item::Val)?;
-      let arg_ty = Ty::pair(lhs_ty, rhs_ty);
-      let arg_pat = Pat::record(vec![lhs_pat, rhs_pat]);
-      let long = Long {
-        structures: vec![],
-        last: *vid,
-      };
-      let (ty, pat) = ctor(cx, st, pat.loc, &long, arg_ty, arg_pat)?;
-      Ok((val_env, ty, pat))
-    }
+    AstPat::InfixCtor(lhs, vid, rhs) => ck_infix_ctor(cx, st, pat.loc, lhs, *vid, rhs),
     // SML Definition (42)
-    AstPat::Typed(inner_pat, ty) => {
-      let (val_env, pat_ty, inner_pat) = ck(cx, st, inner_pat)?;
-      let ty = ty::ck(cx, &st.tys, ty)?;
-      st.unify(pat.loc, ty, pat_ty.clone())?;
-      Ok((val_env, pat_ty, inner_pat))
-    }
+    AstPat::Typed(inner_pat, ty) => ck_typed(cx, st, pat.loc, inner_pat, ty),
     // SML Definition (43)
-    AstPat::As(vid, ty, inner_pat) => {
-      if cx
-        .env
-        .val_env
-        .get(&vid.val)
-        .map_or(false, |x| !x.id_status.is_val())
-      {
-        return Err(vid.loc.wrap(Error::NonVarInAs(vid.val)));
-      }
-      let (mut val_env, pat_ty, inner_pat) = ck(cx, st, inner_pat)?;
-      if let Some(ty) = ty {
-        let ty = ty::ck(cx, &st.tys, ty)?;
-        st.unify(pat.loc, ty, pat_ty.clone())?;
+    AstPat::As(vid, ty, inner_pat) => ck_as(cx, st, pat.loc, *vid, ty.as_ref(), inner_pat),
+  }
+}
+
+fn ck_long_vid(cx: &Cx, st: &mut State, loc: Loc, vid: &Long<StrRef>) -> Result<(ValEnv, Ty, Pat)> {
+  let env = get_env(&cx.env, vid)?;
+  let val_info = env.val_env.get(&vid.last.val).and_then(|val_info| {
+    if val_info.id_status.is_val() {
+      None
+    } else {
+      Some(val_info)
+    }
+  });
+  match val_info {
+    // SML Definition (34)
+    None => {
+      // only unqualified identifiers may be variables.
+      if !vid.structures.is_empty() {
+        get_val_info(env, vid.last)?;
+        return Err(vid.loc().wrap(Error::PatWrongIdStatus));
       }
-      let val_info = ValInfo::val(TyScheme::mono(pat_ty.clone()));
-      env_ins(&mut val_env, *vid, val_info, Item::Val)?;
-      Ok((val_env, pat_ty, inner_pat))
+      let a = Ty::Var(st.new_ty_var(false));
+      let val_info = ValInfo::pat_var(a.clone(), st.new_pat_var(vid.last.loc));
+      let info = Info::val(vid.last.val, &val_info, a.clone());
+      st.info.insert(vid.last.loc, info);
+      Ok((btreemap![vid.last.val => val_info], a, Pat::Anything))
+    }
+    // SML Definition (35)
+    Some(val_info) => {
+      let ty = instantiate(st, vid.last.val, &val_info.ty_scheme);
+      let info = Info::val(vid.last.val, val_info, ty.clone());
+      st.info.insert(vid.last.loc, info);
+      let sym = match ty {
+        Ty::Ctor(_, sym) => sym,
+        _ => return Err(loc.wrap(Error::PatNotConsTy(ty))),
+      };
+      let pat = Pat::zero(get_con(&st.tys, vid, sym));
+      Ok((ValEnv::new(), ty, pat))
     }
   }
 }
 
+/// SML Definition (36)
+fn ck_record(
+  cx: &Cx,
+  st: &mut State,
+  loc: Loc,
+  rows: &[Row<Located<AstPat<StrRef>>>],
+  rest_loc: Option<Loc>,
+) -> Result<(ValEnv, Ty, Pat)> {
+  let mut val_env = ValEnv::new();
+  let mut ty_rows = BTreeMap::new();
+  let mut new_pats = BTreeMap::new();
+  // SML Definition (39)
+  for row in rows {
+    let (other_ve, ty, pat) = ck(cx, st, &row.val)?;
+    if new_pats.insert(row.lab.val, pat).is_some() {
+      let prev = rows.iter().find(|r| r.lab.val == row.lab.val).unwrap();
+      let err = Error::DuplicateLabel(row.lab.val, prev.lab.loc);
+      return Err(row.lab.loc.wrap(err));
+    }
+    env_merge(&mut val_env, other_ve, row.val.loc, Item::Val)?;
+    assert!(ty_rows.insert(row.lab.val, ty).is_none());
+  }
+  match rest_loc {
+    None => {
+      let new_pats: Vec<_> = new_pats.into_values().collect();
+      Ok((val_env, Ty::Record(ty_rows), Pat::record(new_pats)))
+    }
+    // SML Definition (38)
+    Some(_) => {
+      let tv = st.new_ty_var(false);
+      st.subst.insert_record(tv, loc.wrap(ty_rows));
+      Ok((val_env, Ty::Var(tv), Pat::FlexRecord(tv, new_pats)))
+    }
+  }
+}
+
+fn ck_tuple(
+  cx: &Cx,
+  st: &mut State,
+  pats: &[Located<AstPat<StrRef>>],
+) -> Result<(ValEnv, Ty, Pat)> {
+  let mut val_env = ValEnv::new();
+  let mut ty_rows = BTreeMap::new();
+  let mut new_pats = Vec::with_capacity(pats.len());
+  for (idx, pat) in pats.iter().enumerate() {
+    let (other_ve, ty, new_pat) = ck(cx, st, pat)?;
+    env_merge(&mut val_env, other_ve, pat.loc, Item::Val)?;
+    assert!(ty_rows.insert(Label::tuple(idx), ty).is_none());
+    new_pats.push(new_pat);
+  }
+  let pat = Pat::record(new_pats);
+  Ok((val_env, Ty::Record(ty_rows), pat))
+}
+
+/// Checks the elements of a list or vector pattern, which must all have the same type. Returns
+/// that type.
+fn ck_elems(
+  cx: &Cx,
+  st: &mut State,
+  pats: &[Located<AstPat<StrRef>>],
+) -> Result<(ValEnv, Ty, Vec<Pat>)> {
+  let elem = Ty::Var(st.new_ty_var(false));
+  let mut val_env = ValEnv::new();
+  let mut new_pats = Vec::with_capacity(pats.len());
+  for pat in pats {
+    let (other_ve, ty, new_pat) = ck(cx, st, pat)?;
+    env_merge(&mut val_env, other_ve, pat.loc, Item::Val)?;
+    st.unify(pat.loc, elem.clone(), ty)?;
+    new_pats.push(new_pat);
+  }
+  Ok((val_env, elem, new_pats))
+}
+
+/// SML Definition (41)
+fn ck_ctor(
+  cx: &Cx,
+  st: &mut State,
+  loc: Loc,
+  long: &Long<StrRef>,
+  arg: &Located<AstPat<StrRef>>,
+) -> Result<(ValEnv, Ty, Pat)> {
+  let (val_env, arg_ty, arg_pat) = ck(cx, st, arg)?;
+  let (ty, pat) = ctor(cx, st, loc, long, arg_ty, arg_pat)?;
+  Ok((val_env, ty, pat))
+}
+
+/// SML Definition (41)
+fn ck_infix_ctor(
+  cx: &Cx,
+  st: &mut State,
+  loc: Loc,
+  lhs: &Located<AstPat<StrRef>>,
+  vid: Located<StrRef>,
+  rhs: &Located<AstPat<StrRef>>,
+) -> Result<(ValEnv, Ty, Pat)> {
+  let (mut val_env, lhs_ty, lhs_pat) = ck(cx, st, lhs)?;
+  let (other_ve, rhs_ty, rhs_pat) = ck(cx, st, rhs)?;
+  env_merge(&mut val_env, other_ve, loc, Item::Val)?;
+  let arg_ty = Ty::pair(lhs_ty, rhs_ty);
+  let arg_pat = Pat::record(vec![lhs_pat, rhs_pat]);
+  let long = Long {
+    structures: vec![],
+    last: vid,
+  };
+  let (ty, pat) = ctor(cx, st, loc, &long, arg_ty, arg_pat)?;
+  Ok((val_env, ty, pat))
+}
+
+/// SML Definition (42)
+fn ck_typed(
+  cx: &Cx,
+  st: &mut State,
+  loc: Loc,
+  inner_pat: &Located<AstPat<StrRef>>,
+  ty: &Located<AstTy<StrRef>>,
+) -> Result<(ValEnv, Ty, Pat)> {
+  let (val_env, pat_ty, inner_pat) = ck(cx, st, inner_pat)?;
+  let ty = ty::ck(cx, st, ty)?;
+  st.unify(loc, ty, pat_ty.clone())?;
+  Ok((val_env, pat_ty, inner_pat))
+}
+
+/// SML Definition (43)
+fn ck_as(
+  cx: &Cx,
+  st: &mut State,
+  loc: Loc,
+  vid: Located<StrRef>,
+  ty: Option<&Located<AstTy<StrRef>>>,
+  inner_pat: &Located<AstPat<StrRef>>,
+) -> Result<(ValEnv, Ty, Pat)> {
+  if cx
+    .env
+    .val_env
+    .get(&vid.val)
+    .is_some_and(|x| !x.id_status.is_val())
+  {
+    return Err(vid.loc.wrap(Error::NonVarInAs(vid.val)));
+  }
+  let (mut val_env, pat_ty, inner_pat) = ck(cx, st, inner_pat)?;
+  if let Some(ty) = ty {
+    let ty = ty::ck(cx, st, ty)?;
+    st.unify(loc, ty, pat_ty.clone())?;
+  }
+  let val_info = ValInfo::pat_var(pat_ty.clone(), st.new_pat_var(vid.loc));
+  let info = Info::val(vid.val, &val_info, pat_ty.clone());
+  st.info.insert(vid.loc, info);
+  env_ins(&mut val_env, vid, val_info, Item::Val)?;
+  Ok((val_env, pat_ty, inner_pat))
+}
+
 /// SML Definition (41)
 fn ctor(
   cx: &Cx,
@@ -175,7 +270,7 @@ fn ctor(
   if val_info.id_status.is_val() {
     return Err(long.loc().wrap(Error::PatWrongIdStatus));
   }
-  let (ctor_arg_ty, mut ctor_res_ty) = match instantiate(st, &val_info.ty_scheme) {
+  let (ctor_arg_ty, mut ctor_res_ty) = match instantiate(st, long.last.val, &val_info.ty_scheme) {
     Ty::Arrow(x, y) => (*x, *y),
     ty => return Err(loc.wrap(Error::PatNotArrowTy(ty))),
   };
@@ -185,20 +280,30 @@ fn ctor(
     Ty::Ctor(_, sym) => sym,
     _ => unreachable!(),
   };
-  let span = get_span(&st.tys, sym);
-  let pat = Pat::Con(Con::Ctor(long.last.val, span), vec![arg_pat]);
+  let pat = Pat::Con(get_con(&st.tys, long, sym), vec![arg_pat]);
   Ok((ctor_res_ty, pat))
 }
 
-fn get_span(tys: &Tys, sym: Sym) -> Span {
+/// Returns the `Con` for the constructor or exception named by `long`, whose type has the type name
+/// `sym`.
+fn get_con(tys: &Tys, long: &Long<StrRef>, sym: Sym) -> Con {
   assert!(sym != Sym::CHAR);
   assert!(sym != Sym::STRING);
   assert!(sym != Sym::WORD);
+  assert!(sym != Sym::WORD8);
   assert!(sym != Sym::INT);
+  assert!(sym != Sym::INT_INF);
   assert!(sym != Sym::REAL);
   if sym == Sym::EXN {
-    Span::PosInf
+    // we don't track which exception a name refers to. but two unqualified names in the same match
+    // must refer to the same exception.
+    let loc = if long.structures.is_empty() {
+      None
+    } else {
+      Some(long.loc())
+    };
+    Con::Exn(long.last.val, loc)
   } else {
-    Span::Finite(tys.get(&sym).val_env.len())
+    Con::Ctor(long.last.val, Span::Finite(tys.get(&sym).val_env.len()))
   }
 }
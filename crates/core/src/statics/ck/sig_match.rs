@@ -26,37 +26,91 @@ use crate::loc::Loc;
 use crate::statics::ck::enrich;
 use crate::statics::ck::util::get_ty_sym;
 use crate::statics::ty_rzn::TyRealization;
-use crate::statics::types::{Env, Result, Sig, State, TyEnv};
+use crate::statics::types::{Env, Error, Item, Result, Sig, State, TyEnv, TyNameSet, Tys};
 
 /// Returns `Ok((E, ty_rzn))` iff `sig >= E` (and `ty_rzn` is the witness) and `env >> E`.
 pub fn ck(st: &mut State, loc: Loc, env: Env, sig: &Sig) -> Result<(Env, TyRealization)> {
   let mut ty_rzn = TyRealization::default();
-  for &bound_ty_sym in sig.ty_names.iter() {
-    let ty_name = loc.wrap(bound_ty_sym.name());
-    let env_ty_sym = get_ty_sym(&env, ty_name)?;
-    let ty_fcn = st.tys.get(&env_ty_sym).ty_fcn.clone();
-    ty_rzn.insert_ty_fcn(bound_ty_sym, ty_fcn);
-  }
+  realize(&st.tys, loc, &sig.ty_names, &env, &sig.env, &mut ty_rzn)?;
   enrich::ck(loc, &st.tys, &ty_rzn, &env, &sig.env)?;
-  let env = Env {
-    str_env: env
-      .str_env
-      .into_iter()
-      .filter(|(name, _)| sig.env.str_env.contains_key(name))
-      .collect(),
-    ty_env: TyEnv {
-      inner: env
-        .ty_env
-        .inner
-        .into_iter()
-        .filter(|(name, _)| sig.env.ty_env.inner.contains_key(name))
-        .collect(),
-    },
-    val_env: env
-      .val_env
-      .into_iter()
-      .filter(|(name, _)| sig.env.val_env.contains_key(name))
+  let env = instance(&ty_rzn, &sig.ty_names, &env, &sig.env);
+  Ok((env, ty_rzn))
+}
+
+/// Updates `ty_rzn` to map every type name in `ty_names` which is bound by `want` to the type
+/// function of the type with the same name (and path of structures) in `got`.
+fn realize(
+  tys: &Tys,
+  loc: Loc,
+  ty_names: &TyNameSet,
+  got: &Env,
+  want: &Env,
+  ty_rzn: &mut TyRealization,
+) -> Result<()> {
+  for (name, want) in want.str_env.iter() {
+    match got.str_env.get(name) {
+      None => return Err(loc.wrap(Error::Undefined(Item::Struct, *name))),
+      Some(got) => realize(tys, loc, ty_names, got, want, ty_rzn)?,
+    }
+  }
+  for (&name, want) in want.ty_env.inner.iter() {
+    // the same type name may be bound more than once, e.g. by datatype replication.
+    if !ty_names.contains(want) || ty_rzn.contains_key(want) {
+      continue;
+    }
+    let got = get_ty_sym(got, loc.wrap(name))?;
+    let want_len = tys.get(want).ty_fcn.ty_vars.len();
+    let ty_fcn = tys.get(&got).ty_fcn.clone();
+    if want_len != ty_fcn.ty_vars.len() {
+      let err = Error::WrongNumTyArgs(want_len, ty_fcn.ty_vars.len());
+      return Err(loc.wrap(err));
+    }
+    ty_rzn.insert_ty_fcn(*want, ty_fcn);
+  }
+  Ok(())
+}
+
+/// Returns the instance of `want` given by `ty_rzn`, where `ty_rzn` was computed by `realize`. Every
+/// type bound by `want` is replaced by the type with the same name in `got`, and the types of values
+/// are realized accordingly. Note that everything else, like the identifier status of values, is
+/// taken from `want`, so e.g. a constructor in `got` is only a value in the instance if `want` says
+/// so.
+fn instance(ty_rzn: &TyRealization, ty_names: &TyNameSet, got: &Env, want: &Env) -> Env {
+  let str_env = want
+    .str_env
+    .iter()
+    .map(|(&name, want)| {
+      let got = got.str_env.get(&name).unwrap();
+      (name, instance(ty_rzn, ty_names, got, want))
+    })
+    .collect();
+  let ty_env = TyEnv {
+    inner: want
+      .ty_env
+      .inner
+      .iter()
+      .map(|(&name, &sym)| {
+        let sym = if ty_names.contains(&sym) {
+          *got.ty_env.inner.get(&name).unwrap()
+        } else {
+          sym
+        };
+        (name, sym)
+      })
       .collect(),
   };
-  Ok((env, ty_rzn))
+  let val_env = want
+    .val_env
+    .iter()
+    .map(|(&name, val_info)| {
+      let mut val_info = val_info.clone();
+      ty_rzn.get_ty(&mut val_info.ty_scheme.ty);
+      (name, val_info)
+    })
+    .collect();
+  Env {
+    str_env,
+    ty_env,
+    val_env,
+  }
 }
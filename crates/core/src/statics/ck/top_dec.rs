@@ -2,30 +2,42 @@
 
 use crate::ast::{SigExp, Spec, StrDec, StrExp, TopDec};
 use crate::intern::StrRef;
-use crate::loc::Located;
-use crate::statics::ck::util::{env_ins, get_env};
-use crate::statics::ck::{dec, sig_match, ty};
+use crate::loc::{Loc, Located};
+use crate::statics::ck::util::{
+  ck_word_constants, env_ins, get_env, get_struct, get_ty_sym, insert_ty_vars,
+};
+use crate::statics::ck::{dec, sig_match, ty, ty_var};
 use crate::statics::ty_rzn::TyRealization;
 use crate::statics::types::{
-  Basis, Env, Error, FunEnv, FunSig, Item, Result, Sig, SigEnv, State, StrEnv, Ty, TyEnv, TyInfo,
-  TyScheme, ValEnv, ValInfo,
+  Basis, Env, Error, FunEnv, FunSig, GeneratedSyms, Item, Result, Sig, SigEnv, State, StrEnv, Sym,
+  Ty, TyEnv, TyInfo, TyNameSet, TyScheme, ValEnv, ValInfo,
 };
+use std::collections::HashMap;
 
 pub fn ck(bs: &mut Basis, st: &mut State, top_dec: &Located<TopDec<StrRef>>) -> Result<()> {
   match &top_dec.val {
     // SML Definition (87)
-    TopDec::StrDec(str_dec) => {
-      let env = ck_str_dec(bs, st, str_dec)?;
-      bs.env.extend(env);
-    }
+    TopDec::StrDec(str_dec) => match ck_str_dec(bs, st, str_dec) {
+      Ok(env) => {
+        // nothing after this top-level declaration may determine the types in it any further.
+        st.subst.use_overloaded_defaults();
+        ck_no_free_ty_vars(st, &env, top_dec.loc);
+        bs.env.extend(env);
+      }
+      Err(e) => {
+        let env = failed_str_dec(bs, st, str_dec);
+        bs.env.extend(env);
+        return Err(e);
+      }
+    },
     // SML Definition (88)
     TopDec::SigDec(sig_binds) => {
       let mut sig_env = SigEnv::new();
       // SML Definition (66), SML Definition (67)
       for sig_bind in sig_binds {
-        let env = ck_sig_exp(bs, st, &sig_bind.exp)?;
+        let sig = ck_sig_exp(bs, st, &sig_bind.exp)?;
         // allow shadowing.
-        sig_env.insert(sig_bind.id.val, env_to_sig(env));
+        sig_env.insert(sig_bind.id.val, sig);
       }
       bs.sig_env.extend(sig_env);
     }
@@ -34,13 +46,15 @@ pub fn ck(bs: &mut Basis, st: &mut State, top_dec: &Located<TopDec<StrRef>>) ->
       let mut fun_env = FunEnv::new();
       // SML Definition (86)
       for fun_bind in fun_binds {
-        let sig_env = ck_sig_exp(bs, st, &fun_bind.sig_exp)?;
+        let input = ck_sig_exp(bs, st, &fun_bind.sig_exp)?;
         let mut bs = bs.clone();
-        bs.env.str_env.insert(fun_bind.str_id.val, sig_env.clone());
+        bs.env
+          .str_env
+          .insert(fun_bind.str_id.val, input.env.clone());
         let str_env = ck_str_exp(&bs, st, &fun_bind.str_exp)?;
         // TODO might not be right? a lot of stuff going on with ty names.
         let fun_sig = FunSig {
-          input: env_to_sig(sig_env),
+          input,
           output: env_to_sig(str_env),
         };
         // allow shadowing.
@@ -50,9 +64,29 @@ pub fn ck(bs: &mut Basis, st: &mut State, top_dec: &Located<TopDec<StrRef>>) ->
     }
   }
   st.subst.use_overloaded_defaults();
+  ck_word_constants(st);
   Ok(())
 }
 
+/// SML Definition (87) - no free type variables may enter the basis. Reports each value in `env`
+/// whose type still has some, as it does if it was bound to an expansive expression whose type the
+/// rest of the top-level declaration did not determine. `loc` is where the declaration is.
+fn ck_no_free_ty_vars(st: &mut State, env: &Env, loc: Loc) {
+  for env in env.str_env.values() {
+    ck_no_free_ty_vars(st, env, loc);
+  }
+  for (&name, val_info) in env.val_env.iter() {
+    let mut ty = val_info.ty_scheme.ty.clone();
+    ty.apply(&st.subst);
+    let bound = &val_info.ty_scheme.ty_vars;
+    if ty.free_ty_vars().iter().all(|tv| bound.contains(tv)) {
+      continue;
+    }
+    let loc = val_info.loc.unwrap_or(loc);
+    st.errors.push(loc.wrap(Error::UnresolvedTy(name, ty)));
+  }
+}
+
 /// SML Definition (65)
 fn env_to_sig(env: Env) -> Sig {
   // TODO what about signature specs inside this sig?
@@ -67,17 +101,11 @@ fn ck_str_exp(bs: &Basis, st: &mut State, str_exp: &Located<StrExp<StrRef>>) ->
     // SML Definition (50)
     StrExp::Struct(str_dec) => ck_str_dec(bs, st, str_dec),
     // SML Definition (51)
-    StrExp::LongStrId(long) => match get_env(&bs.env, long)?.str_env.get(&long.last.val) {
-      None => {
-        let err = Error::Undefined(Item::Struct, long.last.val);
-        Err(long.last.loc.wrap(err))
-      }
-      Some(env) => Ok(env.clone()),
-    },
+    StrExp::LongStrId(long) => Ok(get_struct(&bs.env, long)?.clone()),
     // SML Definition (52), SML Definition (53)
     StrExp::Ascription(lhs, rhs, opaque) => {
       let env = ck_str_exp(bs, st, lhs)?;
-      let mut sig = env_to_sig(ck_sig_exp(bs, st, rhs)?);
+      let mut sig = ck_sig_exp(bs, st, rhs)?;
       let (env, _) = sig_match::ck(st, lhs.loc, env, &sig)?;
       if *opaque {
         let mut ty_rzn = TyRealization::default();
@@ -126,8 +154,7 @@ fn ck_str_dec(bs: &Basis, st: &mut State, str_dec: &Located<StrDec<StrRef>>) ->
       // SML Definition (61)
       for str_bind in str_binds {
         let env = ck_str_exp(bs, st, &str_bind.exp)?;
-        // allow shadowing.
-        str_env.insert(str_bind.id.val, env);
+        env_ins(&mut str_env, str_bind.id, env, Item::Struct)?;
       }
       Ok(str_env.into())
     }
@@ -143,30 +170,165 @@ fn ck_str_dec(bs: &Basis, st: &mut State, str_dec: &Located<StrDec<StrRef>>) ->
       let mut bs = bs.clone();
       let mut ret = Env::default();
       for str_dec in str_decs {
-        bs.env.extend(ret.clone());
-        ret.extend(ck_str_dec(&bs, st, str_dec)?);
+        // as with `Dec::Seq`, record errors and keep going.
+        match ck_str_dec(&bs, st, str_dec) {
+          Ok(env) => {
+            bs.env.extend(env.clone());
+            ret.extend(env);
+          }
+          Err(e) => {
+            st.errors.push(e);
+            let env = failed_str_dec(&bs, st, str_dec);
+            bs.env.extend(env.clone());
+            ret.extend(env);
+          }
+        }
       }
       Ok(ret)
     }
   }
 }
 
-fn ck_sig_exp(bs: &Basis, st: &mut State, sig_exp: &Located<SigExp<StrRef>>) -> Result<Env> {
+/// Returns an environment binding what `str_dec` would have declared, had checking it not failed.
+/// See `dec::failed`.
+fn failed_str_dec(bs: &Basis, st: &mut State, str_dec: &Located<StrDec<StrRef>>) -> Env {
+  match &str_dec.val {
+    StrDec::Dec(dec) => dec::failed(&bs.to_cx(), st, dec),
+    // TODO we don't know what a failed structure would have contained, so we don't bind it.
+    StrDec::Structure(..) => Env::default(),
+    StrDec::Local(_, snd) => failed_str_dec(bs, st, snd),
+    StrDec::Seq(str_decs) => {
+      let mut bs = bs.clone();
+      let mut ret = Env::default();
+      for str_dec in str_decs {
+        let env = failed_str_dec(&bs, st, str_dec);
+        bs.env.extend(env.clone());
+        ret.extend(env);
+      }
+      ret
+    }
+  }
+}
+
+pub fn ck_sig_exp(bs: &Basis, st: &mut State, sig_exp: &Located<SigExp<StrRef>>) -> Result<Sig> {
   match &sig_exp.val {
     // SML Definition (62)
-    SigExp::Sig(spec) => ck_spec(bs, st, spec),
+    SigExp::Sig(spec) => {
+      let gen_syms = st.generated_syms();
+      let env = ck_spec(bs, st, spec)?;
+      // only the type names generated by the specs are bound by the signature. others, like those
+      // of a replicated datatype, are fixed.
+      let mut ty_names = TyNameSet::new();
+      get_new_ty_names(&gen_syms, &env, &mut ty_names);
+      Ok(Sig { ty_names, env })
+    }
     // SML Definition (63)
     SigExp::SigId(sig_id) => match bs.sig_env.get(&sig_id.val) {
       None => Err(sig_id.loc.wrap(Error::Undefined(Item::Sig, sig_id.val))),
-      // TODO I don't understand the check to see if the type names of the sig are disjoint from the
-      // type names of the basis. It seems like this will _always_ be the case since when we process
-      // a `signature` top dec, we add the type names of that sig to the basis. (Why do we do that?
-      // I don't know). Is the whole "you may need to rename bound type names" thing made not
-      // necessary by `Sym`, which is meant to be globally unique?
-      Some(sig) => Ok(sig.env.clone()),
+      // each use of a signature gets fresh type names for the ones it binds, so that e.g. two
+      // structures specified with the same signature have distinct abstract types.
+      Some(sig) => {
+        let mut sig = sig.clone();
+        let mut ty_rzn = TyRealization::default();
+        let mut ty_names = TyNameSet::new();
+        for &old in sig.ty_names.iter() {
+          let new = st.new_sym(sig_id.loc.wrap(old.name()));
+          ty_rzn.insert_sym(old, new);
+          ty_names.insert(new);
+        }
+        ty_rzn.get_env(&mut st.tys, &mut sig.env);
+        sig.ty_names = ty_names;
+        Ok(sig)
+      }
     },
     // SML Definition (64)
-    SigExp::Where(_, _, _, _) => Err(sig_exp.loc.wrap(Error::Todo("`where`"))),
+    SigExp::Where(inner, ty_vars, long, ty) => {
+      let mut sig = ck_sig_exp(bs, st, inner)?;
+      let mut cx = bs.to_cx();
+      insert_ty_vars(&mut cx, st, ty_vars)?;
+      let ty_loc = ty.loc;
+      let ty = ty::ck(&cx, st, ty)?;
+      let ty_vars: Vec<_> = ty_vars
+        .iter()
+        .map(|tv| {
+          let tv = *cx.ty_vars.get(&tv.val).unwrap();
+          st.subst.remove_bound(&tv);
+          tv
+        })
+        .collect();
+      let old = get_ty_sym(get_env(&sig.env, long)?, long.last)?;
+      if !sig.ty_names.remove(&old) {
+        return Err(long.loc().wrap(Error::NotFlexible(long.last.val)));
+      }
+      let old_info = st.tys.get(&old);
+      if old_info.ty_fcn.ty_vars.len() != ty_vars.len() {
+        let err = Error::WrongNumTyArgs(old_info.ty_fcn.ty_vars.len(), ty_vars.len());
+        return Err(long.loc().wrap(err));
+      }
+      let equality = ty.is_equality_with(&st.tys, &ty_vars);
+      if old_info.equality && !equality {
+        return Err(ty_loc.wrap(Error::NotEquality(ty)));
+      }
+      let ty_fcn = TyScheme {
+        ty_vars,
+        ty,
+        overload: None,
+      };
+      // the realized type gets a new symbol, as with a type abbreviation.
+      let new = st.new_sym(long.last);
+      st.tys.insert(
+        new,
+        TyInfo {
+          ty_fcn: ty_fcn.clone(),
+          val_env: ValEnv::new(),
+          equality,
+        },
+      );
+      replace_sym(&mut sig.env, old, new);
+      let mut ty_rzn = TyRealization::default();
+      ty_rzn.insert_ty_fcn(old, ty_fcn);
+      // the datatypes bound by the signature may mention the realized type in their constructors,
+      // so we must also generate new symbols for them with the realization applied.
+      let datatypes: Vec<_> = sig
+        .ty_names
+        .iter()
+        .copied()
+        .filter(|sym| !st.tys.get(sym).val_env.is_empty())
+        .collect();
+      for sym in datatypes {
+        let new = st.new_sym(sig_exp.loc.wrap(sym.name()));
+        ty_rzn.insert_sym(sym, new);
+        sig.ty_names.remove(&sym);
+        sig.ty_names.insert(new);
+      }
+      ty_rzn.get_env(&mut st.tys, &mut sig.env);
+      Ok(sig)
+    }
+  }
+}
+
+/// Replaces every occurrence of `old` in the `TyEnv`s of `env` with `new`.
+fn replace_sym(env: &mut Env, old: Sym, new: Sym) {
+  for env in env.str_env.values_mut() {
+    replace_sym(env, old, new);
+  }
+  for sym in env.ty_env.inner.values_mut() {
+    if *sym == old {
+      *sym = new;
+    }
+  }
+}
+
+/// Adds to `ac` the type names in `env`, including those in the structures of `env`, which were not
+/// generated before `gen_syms` was.
+fn get_new_ty_names(gen_syms: &GeneratedSyms, env: &Env, ac: &mut TyNameSet) {
+  for env in env.str_env.values() {
+    get_new_ty_names(gen_syms, env, ac);
+  }
+  for sym in env.ty_env.inner.values() {
+    if !gen_syms.contains_sym(sym) {
+      ac.insert(*sym);
+    }
   }
 }
 
@@ -178,10 +340,36 @@ fn ck_spec(bs: &Basis, st: &mut State, spec: &Located<Spec<StrRef>>) -> Result<E
       let mut val_env = ValEnv::new();
       // SML Definition (79)
       for val_desc in val_descs {
-        let ty = ty::ck(&cx, &st.tys, &val_desc.ty)?;
-        // TODO generalize? closure?
-        let val_info = ValInfo::val(TyScheme::mono(ty));
-        env_ins(&mut val_env, val_desc.vid, val_info, Item::Val)?;
+        let ty_vars = ty_var::get_ty(&val_desc.ty);
+        let mut cx_cl;
+        let cx = if ty_vars.is_empty() {
+          &cx
+        } else {
+          cx_cl = cx.clone();
+          insert_ty_vars(&mut cx_cl, st, &ty_vars)?;
+          &cx_cl
+        };
+        let ty = ty::ck(cx, st, &val_desc.ty)?;
+        // the type is closed, so every type variable in it is bound by the `TyScheme`.
+        let ty_vars = ty_vars
+          .iter()
+          .map(|tv| {
+            let tv = *cx.ty_vars.get(&tv.val).unwrap();
+            st.subst.remove_bound(&tv);
+            tv
+          })
+          .collect();
+        let ty_scheme = TyScheme {
+          ty_vars,
+          ty,
+          overload: None,
+        };
+        env_ins(
+          &mut val_env,
+          val_desc.vid,
+          ValInfo::val(ty_scheme),
+          Item::Val,
+        )?;
       }
       Ok(val_env.into())
     }
@@ -190,16 +378,28 @@ fn ck_spec(bs: &Basis, st: &mut State, spec: &Located<Spec<StrRef>>) -> Result<E
       let mut ty_env = TyEnv::default();
       // SML Definition (80)
       for ty_desc in ty_descs {
-        if let Some(tv) = ty_desc.ty_vars.first() {
-          return Err(tv.loc.wrap(Error::Todo("type variables in spec")));
+        // as with `ck_dat_binds`, we just need some ty vars to make the `TyScheme`.
+        let mut seen = HashMap::new();
+        let mut ty_vars = Vec::new();
+        for tv in ty_desc.ty_vars.iter() {
+          if let Some(prev) = seen.insert(tv.val.name, tv.loc) {
+            let err = Error::Duplicate(Item::TyVar, tv.val.name, Some(prev));
+            return Err(tv.loc.wrap(err));
+          }
+          ty_vars.push(st.new_ty_var(tv.val.equality));
         }
         let sym = st.new_sym(ty_desc.ty_con);
-        // TODO equality check
         env_ins(&mut ty_env.inner, ty_desc.ty_con, sym, Item::Ty)?;
+        let ty_args: Vec<_> = ty_vars.iter().copied().map(Ty::Var).collect();
+        let ty_fcn = TyScheme {
+          ty_vars,
+          ty: Ty::Ctor(ty_args, sym),
+          overload: None,
+        };
         st.tys.insert(
           sym,
           TyInfo {
-            ty_fcn: TyScheme::mono(Ty::Ctor(vec![], sym)),
+            ty_fcn,
             val_env: ValEnv::new(),
             equality: *equality,
           },
@@ -219,7 +419,7 @@ fn ck_spec(bs: &Basis, st: &mut State, spec: &Located<Spec<StrRef>>) -> Result<E
       for ex_desc in ex_descs {
         let val_info = match &ex_desc.ty {
           None => ValInfo::exn(),
-          Some(ty) => ValInfo::exn_fn(ty::ck(&cx, &st.tys, ty)?),
+          Some(ty) => ValInfo::exn_fn(ty::ck(&cx, st, ty)?),
         };
         env_ins(&mut val_env, ex_desc.vid, val_info, Item::Val)?;
       }
@@ -230,26 +430,27 @@ fn ck_spec(bs: &Basis, st: &mut State, spec: &Located<Spec<StrRef>>) -> Result<E
       let mut str_env = StrEnv::new();
       // SML Definition (84)
       for str_desc in str_descs {
-        let env = ck_sig_exp(bs, st, &str_desc.exp)?;
-        // allow shadowing.
-        str_env.insert(str_desc.str_id.val, env);
+        let sig = ck_sig_exp(bs, st, &str_desc.exp)?;
+        env_ins(&mut str_env, str_desc.str_id, sig.env, Item::Struct)?;
       }
       Ok(str_env.into())
     }
     // SML Definition (75)
-    Spec::Include(sig_exp) => ck_sig_exp(bs, st, sig_exp),
+    Spec::Include(sig_exp) => Ok(ck_sig_exp(bs, st, sig_exp)?.env),
     // SML Definition (76), SML Definition (77)
     Spec::Seq(specs) => {
       let mut bs = bs.clone();
       let mut ret = Env::default();
       for spec in specs {
-        bs.env.extend(ret.clone());
         let env = ck_spec(&bs, st, spec)?;
+        bs.env.extend(env.clone());
         ret.maybe_extend(env, spec.loc)?;
       }
       Ok(ret)
     }
+    // not in the Definition
+    Spec::Functor(_) => Err(spec.loc.wrap(Error::Todo("`functor` specifications"))),
     // SML Definition (78)
-    Spec::Sharing(_, _) => Err(spec.loc.wrap(Error::Todo("`sharing`"))),
+    Spec::Sharing(_, _, _) => Err(spec.loc.wrap(Error::Todo("`sharing`"))),
   }
 }
@@ -4,15 +4,24 @@ use crate::ast::{Label, Ty as AstTy};
 use crate::intern::StrRef;
 use crate::loc::Located;
 use crate::statics::ck::util::{get_env, get_ty_sym};
-use crate::statics::types::{Cx, Error, Item, Result, Ty, Tys};
+use crate::statics::types::{Cx, Error, Info, Result, State, Ty};
 use std::collections::BTreeMap;
 
-pub fn ck(cx: &Cx, tys: &Tys, ty: &Located<AstTy<StrRef>>) -> Result<Ty> {
+pub fn ck(cx: &Cx, st: &mut State, ty: &Located<AstTy<StrRef>>) -> Result<Ty> {
+  let ret = ck_impl(cx, st, ty)?;
+  // record the type with any abbreviations expanded, for hovering over the type expression.
+  st.info
+    .entry(ty.loc)
+    .or_insert_with(|| Info::ty(ret.clone()));
+  Ok(ret)
+}
+
+fn ck_impl(cx: &Cx, st: &mut State, ty: &Located<AstTy<StrRef>>) -> Result<Ty> {
   // SML Definition (48) is handled by the parser
   match &ty.val {
     // SML Definition (44)
     AstTy::TyVar(tv) => match cx.ty_vars.get(tv) {
-      None => Err(ty.loc.wrap(Error::Undefined(Item::TyVar, tv.name))),
+      None => Err(ty.loc.wrap(Error::UnboundTyVar(tv.name))),
       Some(x) => Ok(Ty::Var(*x)),
     },
     // SML Definition (45)
@@ -20,9 +29,11 @@ pub fn ck(cx: &Cx, tys: &Tys, ty: &Located<AstTy<StrRef>>) -> Result<Ty> {
       let mut ty_rows = BTreeMap::new();
       // SML Definition (49)
       for row in rows {
-        let ty = ck(cx, tys, &row.val)?;
+        let ty = ck(cx, st, &row.val)?;
         if ty_rows.insert(row.lab.val, ty).is_some() {
-          return Err(row.lab.loc.wrap(Error::DuplicateLabel(row.lab.val)));
+          let prev = rows.iter().find(|r| r.lab.val == row.lab.val).unwrap();
+          let err = Error::DuplicateLabel(row.lab.val, prev.lab.loc);
+          return Err(row.lab.loc.wrap(err));
         }
       }
       Ok(Ty::Record(ty_rows))
@@ -31,7 +42,7 @@ pub fn ck(cx: &Cx, tys: &Tys, ty: &Located<AstTy<StrRef>>) -> Result<Ty> {
     AstTy::Tuple(ts) => {
       let mut ty_rows = BTreeMap::new();
       for (idx, ty) in ts.iter().enumerate() {
-        let ty = ck(cx, tys, ty)?;
+        let ty = ck(cx, st, ty)?;
         assert!(ty_rows.insert(Label::tuple(idx), ty).is_none());
       }
       Ok(Ty::Record(ty_rows))
@@ -40,21 +51,21 @@ pub fn ck(cx: &Cx, tys: &Tys, ty: &Located<AstTy<StrRef>>) -> Result<Ty> {
     AstTy::TyCon(args, name) => {
       let env = get_env(&cx.env, name)?;
       let sym = get_ty_sym(env, name.last)?;
-      let ty_fcn = &tys.get(&sym).ty_fcn;
+      let ty_fcn = st.tys.get(&sym).ty_fcn.clone();
       if ty_fcn.ty_vars.len() != args.len() {
         let err = Error::WrongNumTyArgs(ty_fcn.ty_vars.len(), args.len());
         return Err(ty.loc.wrap(err));
       }
       let mut new_args = Vec::with_capacity(ty_fcn.ty_vars.len());
       for ty in args {
-        new_args.push(ck(cx, tys, ty)?);
+        new_args.push(ck(cx, st, ty)?);
       }
       Ok(ty_fcn.apply_args(new_args))
     }
     // SML Definition (47)
     AstTy::Arrow(arg, res) => {
-      let arg = ck(cx, tys, arg)?;
-      let res = ck(cx, tys, res)?;
+      let arg = ck(cx, st, arg)?;
+      let res = ck(cx, st, res)?;
       Ok(Ty::Arrow(arg.into(), res.into()))
     }
   }
@@ -0,0 +1,231 @@
+//! Determine which type variables are scoped at a value declaration.
+//!
+//! As described in SML Definition section 4.6, a type variable is scoped at a `val` (or `fun`) if it
+//! is explicitly bound in the type variable sequence of that declaration, or if it occurs unguarded
+//! in that declaration and is not already scoped at an enclosing value declaration. An occurrence is
+//! unguarded if it is not part of a smaller value declaration.
+
+use crate::ast::{Cases, Dec, ExBindInner, Exp, FValBind, Pat, Ty, ValBind};
+use crate::intern::StrRef;
+use crate::loc::Located;
+use crate::statics::types::{Cx, Error, Result};
+use crate::token::TyVar as AstTyVar;
+
+type TyVars = Vec<Located<AstTyVar<StrRef>>>;
+
+/// Returns the type variables scoped at a `val` with the given explicit type variables and bindings.
+pub fn get_val(
+  cx: &Cx,
+  ty_vars: &[Located<AstTyVar<StrRef>>],
+  val_binds: &[ValBind<StrRef>],
+) -> Result<TyVars> {
+  let mut ac = Vec::new();
+  for val_bind in val_binds {
+    pat(&mut ac, &val_bind.pat);
+    exp(&mut ac, &val_bind.exp);
+  }
+  finish(cx, ty_vars, ac)
+}
+
+/// Returns the type variables scoped at a `fun` with the given explicit type variables and bindings.
+pub fn get_fun(
+  cx: &Cx,
+  ty_vars: &[Located<AstTyVar<StrRef>>],
+  fval_binds: &[FValBind<StrRef>],
+) -> Result<TyVars> {
+  let mut ac = Vec::new();
+  for fval_bind in fval_binds {
+    for case in fval_bind.cases.iter() {
+      for p in case.pats.iter() {
+        pat(&mut ac, p);
+      }
+      if let Some(t) = &case.ret_ty {
+        ty(&mut ac, t);
+      }
+      exp(&mut ac, &case.body);
+    }
+  }
+  finish(cx, ty_vars, ac)
+}
+
+/// Returns the type variables in `t`, in order of first occurrence.
+pub fn get_ty(t: &Located<Ty<StrRef>>) -> TyVars {
+  let mut ac = Vec::new();
+  ty(&mut ac, t);
+  let mut ret: TyVars = Vec::new();
+  for tv in ac {
+    if !ret.iter().any(|x| x.val == tv.val) {
+      ret.push(tv);
+    }
+  }
+  ret
+}
+
+/// Checks that none of the explicit `ty_vars` are already in scope, then returns them followed by
+/// the unguarded type variables in `ac` which are not already in scope, in order of first
+/// occurrence.
+fn finish(cx: &Cx, ty_vars: &[Located<AstTyVar<StrRef>>], ac: TyVars) -> Result<TyVars> {
+  for tv in ty_vars {
+    if cx.ty_vars.contains_key(&tv.val) {
+      return Err(tv.loc.wrap(Error::TyVarInScope(tv.val.name)));
+    }
+  }
+  let mut ret = ty_vars.to_vec();
+  for tv in ac {
+    if cx.ty_vars.contains_key(&tv.val) || ret.iter().any(|x| x.val == tv.val) {
+      continue;
+    }
+    ret.push(tv);
+  }
+  Ok(ret)
+}
+
+fn exp(ac: &mut TyVars, e: &Located<Exp<StrRef>>) {
+  match &e.val {
+    Exp::DecInt(_)
+    | Exp::HexInt(_)
+    | Exp::DecWord(_)
+    | Exp::HexWord(_)
+    | Exp::Real(..)
+    | Exp::String(_)
+    | Exp::Char(_)
+    | Exp::LongVid(_)
+    | Exp::Select(_) => {}
+    Exp::Record(rows) => {
+      for row in rows {
+        exp(ac, &row.val);
+      }
+    }
+    Exp::Tuple(exps) | Exp::List(exps) | Exp::Vector(exps) | Exp::Sequence(exps) => {
+      for e in exps {
+        exp(ac, e);
+      }
+    }
+    Exp::Let(d, exps) => {
+      dec(ac, d);
+      for e in exps {
+        exp(ac, e);
+      }
+    }
+    Exp::App(lhs, rhs)
+    | Exp::InfixApp(lhs, _, rhs)
+    | Exp::Andalso(lhs, rhs)
+    | Exp::Orelse(lhs, rhs)
+    | Exp::While(lhs, rhs) => {
+      exp(ac, lhs);
+      exp(ac, rhs);
+    }
+    Exp::Typed(e, t) => {
+      exp(ac, e);
+      ty(ac, t);
+    }
+    Exp::Handle(e, cs) | Exp::Case(e, cs) => {
+      exp(ac, e);
+      cases(ac, cs);
+    }
+    Exp::Raise(e) => exp(ac, e),
+    Exp::If(cond, then_e, else_e) => {
+      exp(ac, cond);
+      exp(ac, then_e);
+      exp(ac, else_e);
+    }
+    Exp::Fn(cs) => cases(ac, cs),
+  }
+}
+
+fn cases(ac: &mut TyVars, cs: &Cases<StrRef>) {
+  for arm in cs.arms.iter() {
+    pat(ac, &arm.pat);
+    exp(ac, &arm.exp);
+  }
+}
+
+fn dec(ac: &mut TyVars, d: &Located<Dec<StrRef>>) {
+  match &d.val {
+    // occurrences in these are guarded by the smaller value declaration.
+    Dec::Val(..) | Dec::Fun(..) => {}
+    // type variables in these must be bound by the type variable sequences of the bindings.
+    Dec::Type(..) | Dec::Datatype(..) | Dec::DatatypeCopy(..) => {}
+    Dec::Abstype(_, _, d) => dec(ac, d),
+    Dec::Exception(ex_binds) => {
+      for ex_bind in ex_binds {
+        if let ExBindInner::Ty(Some(t)) = &ex_bind.inner {
+          ty(ac, t);
+        }
+      }
+    }
+    Dec::Local(fst, snd) => {
+      dec(ac, fst);
+      dec(ac, snd);
+    }
+    Dec::Seq(decs) => {
+      for d in decs {
+        dec(ac, d);
+      }
+    }
+    Dec::Open(..) | Dec::Infix(..) | Dec::Infixr(..) | Dec::Nonfix(..) => {}
+  }
+}
+
+fn pat(ac: &mut TyVars, p: &Located<Pat<StrRef>>) {
+  match &p.val {
+    Pat::Wildcard
+    | Pat::DecInt(_)
+    | Pat::HexInt(_)
+    | Pat::DecWord(_)
+    | Pat::HexWord(_)
+    | Pat::String(_)
+    | Pat::Char(_)
+    | Pat::LongVid(_) => {}
+    Pat::Record(rows, _) => {
+      for row in rows {
+        pat(ac, &row.val);
+      }
+    }
+    Pat::Tuple(pats) | Pat::List(pats) | Pat::Vector(pats) => {
+      for p in pats {
+        pat(ac, p);
+      }
+    }
+    Pat::Ctor(_, p) => pat(ac, p),
+    Pat::InfixCtor(lhs, _, rhs) => {
+      pat(ac, lhs);
+      pat(ac, rhs);
+    }
+    Pat::Typed(p, t) => {
+      pat(ac, p);
+      ty(ac, t);
+    }
+    Pat::As(_, t, p) => {
+      if let Some(t) = t {
+        ty(ac, t);
+      }
+      pat(ac, p);
+    }
+  }
+}
+
+fn ty(ac: &mut TyVars, t: &Located<Ty<StrRef>>) {
+  match &t.val {
+    Ty::TyVar(tv) => ac.push(t.loc.wrap(*tv)),
+    Ty::Record(rows) => {
+      for row in rows {
+        ty(ac, &row.val);
+      }
+    }
+    Ty::Tuple(tys) => {
+      for t in tys {
+        ty(ac, t);
+      }
+    }
+    Ty::TyCon(args, _) => {
+      for t in args {
+        ty(ac, t);
+      }
+    }
+    Ty::Arrow(arg, res) => {
+      ty(ac, arg);
+      ty(ac, res);
+    }
+  }
+}
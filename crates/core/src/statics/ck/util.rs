@@ -8,15 +8,16 @@ use crate::ast::Long;
 use crate::intern::StrRef;
 use crate::loc::{Loc, Located};
 use crate::statics::types::{
-  Cx, Env, Error, Item, Result, State, Subst, Sym, Ty, TyScheme, ValInfo,
+  Cx, Env, Error, Item, Result, State, Subst, Sym, Ty, TyScheme, TyVarSet, ValEnv, ValInfo,
 };
 use crate::token::TyVar as AstTyVar;
 use std::collections::BTreeMap;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 /// Replaces all type variables, in the type in this TyScheme, which are bound by that same
-/// TyScheme, with fresh type variables, and returns that type.
-pub fn instantiate(st: &mut State, ty_scheme: &TyScheme) -> Ty {
+/// TyScheme, with fresh type variables, and returns that type. `name` is the identifier whose type
+/// scheme this is.
+pub fn instantiate(st: &mut State, name: StrRef, ty_scheme: &TyScheme) -> Ty {
   let mut subst = Subst::default();
   match &ty_scheme.overload {
     None => {
@@ -43,7 +44,7 @@ pub fn instantiate(st: &mut State, ty_scheme: &TyScheme) -> Ty {
       }
       let new_tv = st.new_ty_var(false);
       subst.insert(tv, Ty::Var(new_tv));
-      st.subst.insert_overloaded(new_tv, overloads.clone());
+      st.subst.insert_overloaded(new_tv, name, overloads.clone());
     }
   }
   let mut ty = ty_scheme.ty.clone();
@@ -51,37 +52,140 @@ pub fn instantiate(st: &mut State, ty_scheme: &TyScheme) -> Ty {
   ty
 }
 
+/// Returns the type of an integer constant, which may be an `int` or an `IntInf.int`, by SML
+/// Definition Appendix E. It is an `int` if nothing says otherwise.
+pub fn int_constant(st: &mut State) -> Ty {
+  let tv = st.new_ty_var(false);
+  let syms = vec![Sym::INT, Sym::INT_INF];
+  st.subst.insert_overloaded(tv, StrRef::INT_CONSTANT, syms);
+  Ty::Var(tv)
+}
+
+/// Returns the type of the word constant `n` at `loc`, which may be a `word` or a `word8`, by SML
+/// Definition Appendix E. It is a `word` if nothing says otherwise.
+pub fn word_constant(st: &mut State, loc: Loc, n: u32) -> Ty {
+  let tv = st.new_ty_var(false);
+  let syms = vec![Sym::WORD, Sym::WORD8];
+  st.subst.insert_overloaded(tv, StrRef::WORD_CONSTANT, syms);
+  if n > u8::MAX.into() {
+    st.word_constants.push((loc.wrap(n), tv));
+  }
+  Ty::Var(tv)
+}
+
+/// Reports the word constants which turned out to be a `word8` but are too big to be one. Requires
+/// that the types of the word constants be known.
+pub fn ck_word_constants(st: &mut State) {
+  for (n, tv) in std::mem::take(&mut st.word_constants) {
+    let mut ty = Ty::Var(tv);
+    ty.apply(&st.subst);
+    if ty == Ty::WORD8 {
+      st.errors.push(n.loc.wrap(Error::Word8ConstantOutOfRange));
+    }
+  }
+}
+
 /// First, this marks all the type variables given by `ty_vars` (and `cx.ty_vars` which maps the AST
 /// ty vars to statics ty vars) as no longer bound in the `Subst` in the `State`.
 ///
-/// Then, this mutates the `TyScheme`, which upon entry, binds no type variables, to bind all free
-/// type variables in the `Ty` in the `TyScheme`, except for those type variables which are:
+/// Then, this mutates every `TyScheme` in the `ValEnv`, each of which upon entry binds no type
+/// variables, to bind all free type variables in the `Ty` in the `TyScheme`, except for those type
+/// variables which are:
 ///
-/// - free in the `TyEnv` in the `Cx`, or
-/// - are overloaded type variables as noted by the `Subst`, or
+/// - free in the `Env` in the `Cx`, or
 /// - are actually bound as noted by the `Subst`.
+///
+/// Overloaded type variables, as noted by the `Subst`, which are not free in the `Env` are solved to
+/// be their default types instead of being bound.
+///
+/// The `TyScheme`s of the names in `expansive`, which were bound to expansive expressions, bind no
+/// type variables at all, by SML Definition section 4.8.
+///
+/// Returns `Ok(())` iff none of the `ty_vars` remain free in the `ValEnv` afterwards, and no record
+/// ty var would be generalized.
 pub fn generalize(
   cx: &Cx,
   st: &mut State,
   ty_vars: &[Located<AstTyVar<StrRef>>],
-  ty_scheme: &mut TyScheme,
-) {
-  assert!(ty_scheme.ty_vars.is_empty());
-  assert!(ty_scheme.overload.is_none());
-  // could just be `ty_scheme.apply` by the above assert.
-  ty_scheme.ty.apply(&st.subst);
+  val_env: &mut ValEnv,
+  expansive: &HashSet<StrRef>,
+) -> Result<()> {
   for tv in ty_vars {
     let tv = cx.ty_vars.get(&tv.val).unwrap();
     // though the type variable is no longer bound by the `Subst`, it ought to be bound by the
-    // `TyScheme`.
+    // `TyScheme`s.
     st.subst.remove_bound(tv);
   }
-  let ty_env_ty_vars = cx.env.ty_env.free_ty_vars(&st.tys);
-  for tv in ty_scheme.ty.free_ty_vars() {
-    if ty_env_ty_vars.contains(&tv) || st.subst.is_overloaded(&tv) || st.subst.is_bound(&tv) {
+  // the types in the `Env` may not have had the `Subst` applied to them, so apply it to each free
+  // type variable to see what that type variable really is.
+  let mut env_ty_vars = TyVarSet::new();
+  let mut stack: Vec<_> = cx.env.free_ty_vars(&st.tys).into_iter().collect();
+  while let Some(tv) = stack.pop() {
+    let mut ty = Ty::Var(tv);
+    ty.apply(&st.subst);
+    for tv in ty.free_ty_vars() {
+      if !env_ty_vars.insert(tv) {
+        continue;
+      }
+      // the types of the rows of a record ty var are also free in the `Env`.
+      if let Some(rows) = st.subst.get_record(&tv) {
+        stack.extend(rows.val.values().flat_map(Ty::free_ty_vars));
+      }
+    }
+  }
+  for (name, val_info) in val_env.iter_mut() {
+    let ty_scheme = &mut val_info.ty_scheme;
+    assert!(ty_scheme.ty_vars.is_empty());
+    assert!(ty_scheme.overload.is_none());
+    // could just be `ty_scheme.apply` by the above assert.
+    ty_scheme.ty.apply(&st.subst);
+    // the context may yet determine these type variables, including overloaded ones.
+    if expansive.contains(name) {
       continue;
     }
-    ty_scheme.ty_vars.push(tv);
+    // SML Definition Appendix E - overloaded ty vars may not be generalized, so if the context
+    // hasn't determined them yet, it never will. use the defaults.
+    for tv in ty_scheme.ty.free_ty_vars() {
+      if !env_ty_vars.contains(&tv) && st.subst.is_overloaded(&tv) {
+        st.subst.use_overloaded_default(&tv);
+      }
+    }
+    ty_scheme.ty.apply(&st.subst);
+    for tv in ty_scheme.ty.free_ty_vars() {
+      if env_ty_vars.contains(&tv) || st.subst.is_bound(&tv) {
+        continue;
+      }
+      if let Some(rows) = st.subst.get_record(&tv) {
+        let mut rows = rows.clone();
+        for ty in rows.val.values_mut() {
+          ty.apply(&st.subst);
+        }
+        return Err(rows.loc.wrap(Error::UnresolvedRecordTy(rows.val)));
+      }
+      ty_scheme.ty_vars.push(tv);
+    }
+  }
+  // SML Definition (15) requires U ∩ tyvars VE' = ∅.
+  for ast_tv in ty_vars {
+    let tv = cx.ty_vars.get(&ast_tv.val).unwrap();
+    for val_info in val_env.values() {
+      if val_info.ty_scheme.free_ty_vars().contains(tv) {
+        return Err(ast_tv.loc.wrap(Error::TyVarEscape(ast_tv.val.name)));
+      }
+    }
+  }
+  Ok(())
+}
+
+/// Records a warning in the `State` for each variable bound in a pattern in `val_env` which has not
+/// been used.
+pub fn ck_unused(st: &mut State, val_env: &ValEnv) {
+  for (&name, val_info) in val_env.iter() {
+    if let Some(def) = val_info.def {
+      if !st.used.contains(&def.val) {
+        st.errors.push(def.loc.wrap(Error::Unused(name)));
+      }
+    }
   }
 }
 
@@ -97,6 +201,20 @@ pub fn get_env<'env>(mut env: &'env Env, long: &Long<StrRef>) -> Result<&'env En
   Ok(env)
 }
 
+/// Returns `Ok(e)` iff `env` contains the environment `e` of the structure named by `long`, after
+/// traversing the `StrEnv`s of `env` as directed by `long.structures`.
+pub fn get_struct<'env>(env: &'env Env, long: &Long<StrRef>) -> Result<&'env Env> {
+  match get_env(env, long)?.str_env.get(&long.last.val) {
+    None => Err(
+      long
+        .last
+        .loc
+        .wrap(Error::Undefined(Item::Struct, long.last.val)),
+    ),
+    Some(env) => Ok(env),
+  }
+}
+
 /// Returns `Ok(vi)` iff the `ValEnv` of `env` maps `name` to `vi`.
 pub fn get_val_info(env: &Env, name: Located<StrRef>) -> Result<&ValInfo> {
   match env.val_env.get(&name.val) {
@@ -122,7 +240,7 @@ pub fn env_ins<T>(
   item: Item,
 ) -> Result<()> {
   if map.insert(key.val, val).is_some() {
-    Err(key.loc.wrap(Error::Duplicate(item, key.val)))
+    Err(key.loc.wrap(Error::Duplicate(item, key.val, None)))
   } else {
     Ok(())
   }
@@ -149,10 +267,11 @@ pub fn insert_ty_vars(
   st: &mut State,
   ty_vars: &[Located<AstTyVar<StrRef>>],
 ) -> Result<()> {
-  let mut set = HashSet::new();
+  let mut seen = HashMap::new();
   for tv in ty_vars {
-    if !set.insert(tv.val.name) {
-      return Err(tv.loc.wrap(Error::Duplicate(Item::TyVar, tv.val.name)));
+    if let Some(prev) = seen.insert(tv.val.name, tv.loc) {
+      let err = Error::Duplicate(Item::TyVar, tv.val.name, Some(prev));
+      return Err(tv.loc.wrap(err));
     }
     let new_tv = st.new_ty_var(tv.val.equality);
     cx.ty_vars.insert(tv.val, new_tv);
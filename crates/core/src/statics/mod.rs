@@ -10,28 +10,225 @@ mod ty_rzn;
 mod types;
 
 use crate::ast::TopDec;
-use crate::intern::StrRef;
-use crate::loc::Located;
-use crate::statics::types::{Basis, Result, State};
+use crate::codec::{Decoder, Encoder};
+use crate::intern::{StrRef, StrStore};
+use crate::loc::{Loc, Located};
+use crate::statics::types::{Basis, Env, Info, State};
+use std::ops::Range;
+
+pub use crate::statics::std_lib::BasisLevel;
+pub use crate::statics::types::{Error, Fingerprint, Member, MemberKind, Severity, TyLimit};
 
 /// The data computed when running static analysis.
+#[derive(Clone)]
 pub struct Statics {
   bs: Basis,
   st: State,
+  /// The environment of the standard basis, before anything else was checked.
+  std_lib: Env,
+}
+
+/// Where an identifier was bound, as reported by `Statics::definition`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Definition {
+  /// At this location in the program being checked.
+  Loc(Loc),
+  /// In the standard basis, with this name.
+  StdLib(StrRef),
 }
 
 impl Statics {
   #[allow(clippy::new_without_default)]
-  /// Returns the initial information to begin running the statics.
+  /// Returns the initial information to begin running the statics, with the full standard basis.
   pub fn new() -> Self {
-    let (bs, st) = std_lib::get();
-    Self { bs, st }
+    Self::with_basis(BasisLevel::Full)
+  }
+
+  /// Returns the initial information to begin running the statics, with only as much of the
+  /// standard basis as `level` allows.
+  pub fn with_basis(level: BasisLevel) -> Self {
+    let (bs, st) = std_lib::get(level);
+    let std_lib = bs.env.clone();
+    Self { bs, st, std_lib }
+  }
+
+  /// Checks `top_decs`, from some file other than the one to be checked next, as if they were part
+  /// of the standard basis. Errors in them are ignored, and the identifiers they bind have no known
+  /// location.
+  pub fn add_prelude(&mut self, top_decs: &[Located<TopDec<StrRef>>]) {
+    for top_dec in top_decs {
+      self.get(top_dec);
+    }
+    // the locations are into the prelude, not the file checked next.
+    self.forget_locs();
+  }
+
+  /// Returns the statics with which to check the next file of a project, after the files checked so
+  /// far by this. The identifiers those files bind are available, but have no known location.
+  pub fn next_file(&self) -> Self {
+    let mut ret = self.clone();
+    ret.forget_locs();
+    ret
+  }
+
+  /// Returns a fingerprint of what is bound after checking some files with this, which was `before`
+  /// beforehand. Checking the same declarations with `before` again gives an equal fingerprint, so
+  /// the files checked after them needn't be checked again.
+  pub fn fingerprint(&self, before: &Self) -> Fingerprint {
+    self.bs.fingerprint(&self.st, &before.st)
+  }
+
+  /// Encodes what changed from `before` to this. Both should be from `next_file`, since what is
+  /// known about locations in the files checked so far is not encoded.
+  pub fn encode_delta(&self, before: &Self, e: &mut Encoder<'_>) {
+    self.bs.encode_delta(&before.bs, e);
+    self.st.encode_delta(&before.st, e);
+  }
+
+  /// Returns `before` with the changes encoded by `encode_delta` applied, or `None` if the encoding
+  /// is invalid or removes something `before` doesn't have.
+  pub fn decode_delta(before: &Self, d: &mut Decoder<'_>) -> Option<Self> {
+    let mut ret = before.clone();
+    ret.bs.decode_delta(d)?;
+    ret.st.decode_delta(d)?;
+    Some(ret)
+  }
+
+  /// Forgets everything about locations in the files checked so far.
+  fn forget_locs(&mut self) {
+    self.bs.forget_locs();
+    self.st.tys.forget_locs();
+    self.st.info.clear();
+    self.st.decs.clear();
+  }
+
+  /// Performs static analysis on a top-level declaration. Returns the errors and warnings, in the
+  /// order they were encountered. Everything typechecks iff none of them have `Severity::Error`.
+  pub fn get(&mut self, top_dec: &Located<TopDec<StrRef>>) -> Vec<Located<Error>> {
+    if let Err(e) = ck::ck_top_dec(&mut self.bs, &mut self.st, top_dec) {
+      self.st.errors.push(e);
+    }
+    // even if checking stopped early, nothing after this may determine the types any further.
+    self.st.subst.use_overloaded_defaults();
+    let mut errors = std::mem::take(&mut self.st.errors);
+    for e in errors.iter_mut() {
+      e.val.apply(&self.st.subst);
+    }
+    errors
+  }
+
+  /// Returns a description of the innermost expression or pattern checked so far whose location
+  /// contains the byte offset `idx`, along with that location. For identifiers this includes what
+  /// kind of identifier it is, as in `val x : int`. For other expressions and patterns it is just
+  /// the type.
+  pub fn hover(&self, store: &StrStore, idx: usize) -> Option<Located<String>> {
+    let (loc, info) = self.info_at(idx)?;
+    Some(loc.wrap(info.message(store, &self.st.subst)))
+  }
+
+  /// Returns where the identifier at the byte offset `idx` was bound, if known.
+  pub fn definition(&self, idx: usize) -> Option<Definition> {
+    let info = self.info_at(idx)?.1;
+    if let Some(def) = info.def {
+      return Some(Definition::Loc(def));
+    }
+    let (name, _) = info.id?;
+    if self.std_lib.val_env.contains_key(&name) {
+      Some(Definition::StdLib(name))
+    } else {
+      None
+    }
+  }
+
+  /// Returns the location of the declaration binding the identifier bound at `def`, if that
+  /// declaration binds no other identifiers, so that removing it removes only that identifier.
+  pub fn sole_binding(&self, def: Loc) -> Option<Loc> {
+    let dec = *self.st.decs.get(&def)?;
+    let others = self.st.decs.iter().any(|(&k, &v)| k != def && v == dec);
+    if others {
+      None
+    } else {
+      Some(dec)
+    }
+  }
+
+  /// Returns the doc comment of the identifier at the byte offset `idx`, which is the comment
+  /// directly before the innermost declaration binding that identifier, with the comment delimiters
+  /// removed and each line trimmed. `bs` is the source and `comments` are from its `Lexer`.
+  pub fn doc(&self, bs: &[u8], comments: &[Loc], idx: usize) -> Option<String> {
+    let def = self.info_at(idx)?.1.def?;
+    let start = Range::from(*self.st.decs.get(&def)?).start;
+    let comment = comments
+      .iter()
+      .map(|&loc| Range::from(loc))
+      .take_while(|comment| comment.end <= start)
+      .last()?;
+    // a blank line separates a comment from the declaration after it.
+    let between = &bs[comment.end..start];
+    if !between.iter().all(u8::is_ascii_whitespace)
+      || between.iter().filter(|&&b| b == b'\n').count() > 1
+    {
+      return None;
+    }
+    let text = std::str::from_utf8(&bs[comment.start + 2..comment.end - 2]).ok()?;
+    let lines: Vec<_> = text.lines().map(str::trim).collect();
+    let ret = lines.join("\n").trim().to_owned();
+    if ret.is_empty() {
+      None
+    } else {
+      Some(ret)
+    }
+  }
+
+  /// Returns the occurrences of the identifier at the byte offset `idx`, in order, each with
+  /// whether it is the occurrence which binds the identifier. Returns `None` if there is no
+  /// identifier there, or if where it was bound is not known, as for identifiers from the standard
+  /// library.
+  pub fn occurrences(&self, idx: usize) -> Option<Vec<Located<bool>>> {
+    let def = self.info_at(idx)?.1.def?;
+    let mut ret: Vec<_> = self
+      .st
+      .info
+      .iter()
+      .filter(|(_, info)| info.def == Some(def))
+      .map(|(&loc, _)| loc.wrap(loc == def))
+      .collect();
+    ret.sort_by_key(|x| Range::from(x.loc).start);
+    Some(ret)
   }
 
-  /// Performs static analysis on a top-level declaration. Returns `Ok(())` iff everything
-  /// typechecks.
-  pub fn get(&mut self, top_dec: &Located<TopDec<StrRef>>) -> Result<()> {
-    ck::ck_top_dec(&mut self.bs, &mut self.st, top_dec)
+  /// Returns the innermost expression or pattern checked so far whose location contains the byte
+  /// offset `idx`.
+  fn info_at(&self, idx: usize) -> Option<(Loc, &Info)> {
+    let (&loc, info) = self
+      .st
+      .info
+      .iter()
+      .filter(|(&loc, _)| Range::from(loc).contains(&idx))
+      .min_by_key(|(&loc, _)| Range::from(loc).len())?;
+    Some((loc, info))
+  }
+
+  /// Returns the signatures in the top-level basis.
+  pub fn signatures(&self, store: &StrStore) -> Vec<Member> {
+    self.bs.sig_members(store)
+  }
+
+  /// Returns the members of the structure named by `path` in the top-level environment, or the
+  /// members of the top-level environment itself if `path` is empty. Returns `None` if there is no
+  /// such structure.
+  pub fn members(&self, store: &StrStore, path: &[&str]) -> Option<Vec<Member>> {
+    let mut env = &self.bs.env;
+    for &name in path {
+      env = env.str_env.iter().find_map(|(&s, env)| {
+        if store.get(s) == name {
+          Some(env)
+        } else {
+          None
+        }
+      })?;
+    }
+    Some(env.members(store, &self.st.subst, &self.st.tys))
   }
 
   /// Finish running the statics.
@@ -40,3 +237,316 @@ impl Statics {
     assert!(self.bs.free_ty_vars(&self.st.tys).is_empty());
   }
 }
+
+/// Lexes, parses, and checks `src` with `statics`, returning the errors and warnings.
+#[cfg(test)]
+fn check(
+  store: &mut crate::intern::StrStoreMut,
+  statics: &mut Statics,
+  src: &str,
+) -> Vec<Located<Error>> {
+  let lexer = crate::lex::get(store, src.as_bytes()).unwrap();
+  let top_decs = crate::parse::get(lexer).unwrap();
+  top_decs
+    .iter()
+    .flat_map(|top_dec| statics.get(top_dec))
+    .collect()
+}
+
+#[test]
+fn hover() {
+  let src = "datatype d = D of int\nfun f x = D (x + 1)\nval y = f 3\ntype t = d * string\nval z : t list = []\n";
+  let mut store = crate::intern::StrStoreMut::new();
+  let mut statics = Statics::new();
+  assert!(check(&mut store, &mut statics, src).is_empty());
+  let store = store.finish();
+  let get = |needle: &str, offset: usize| {
+    let idx = src.find(needle).unwrap() + offset;
+    statics.hover(&store, idx).unwrap().val
+  };
+  assert_eq!(get("f x", 0), "val f : int -> d");
+  assert_eq!(get("x =", 0), "val x : int");
+  assert_eq!(get("D (", 0), "constructor D : int -> d");
+  assert_eq!(get("+ 1", 0), "val + : int * int -> int");
+  assert_eq!(get("y =", 0), "val y : d");
+  assert_eq!(get(": t", 2), "d * string");
+  assert_eq!(get("list =", 0), "(d * string) list");
+}
+
+#[test]
+fn members() {
+  let src = "structure S = struct\n  datatype 'a t = T of 'a\n  val x = 3\n  exception E\nend\nsignature SIG = sig end\n";
+  let mut store = crate::intern::StrStoreMut::new();
+  let mut statics = Statics::new();
+  assert!(check(&mut store, &mut statics, src).is_empty());
+  let store = store.finish();
+  let got: Vec<_> = statics
+    .members(&store, &["S"])
+    .unwrap()
+    .into_iter()
+    .map(|m| (m.kind, m.detail))
+    .collect();
+  let want = vec![
+    (MemberKind::Exn, "exception E : exn".to_owned()),
+    (MemberKind::Ctor, "constructor T : 'a -> 'a t".to_owned()),
+    (MemberKind::Ty, "type 'a t".to_owned()),
+    (MemberKind::Val, "val x : int".to_owned()),
+  ];
+  assert_eq!(got, want);
+  assert!(statics.members(&store, &["S", "S"]).is_none());
+  let got: Vec<_> = statics
+    .signatures(&store)
+    .into_iter()
+    .map(|m| m.detail)
+    .collect();
+  let want = vec![
+    "signature MONO_ARRAY".to_owned(),
+    "signature MONO_VECTOR".to_owned(),
+    "signature SIG".to_owned(),
+  ];
+  assert_eq!(got, want);
+}
+
+#[test]
+fn occurrences() {
+  let src = "fun f x = x + 1\nval y = f (f 3)\nval z = y\n";
+  let mut store = crate::intern::StrStoreMut::new();
+  let mut statics = Statics::new();
+  assert!(check(&mut store, &mut statics, src).is_empty());
+  let get = |needle: &str| {
+    let idx = src.find(needle).unwrap();
+    statics
+      .occurrences(idx)
+      .unwrap()
+      .into_iter()
+      .map(|x| (Range::from(x.loc).start, x.val))
+      .collect::<Vec<_>>()
+  };
+  assert_eq!(get("f x"), vec![(4, true), (24, false), (27, false)]);
+  assert_eq!(get("x + 1"), vec![(6, true), (10, false)]);
+  assert_eq!(get("y\n"), vec![(20, true), (40, false)]);
+  assert!(statics.occurrences(src.find("+").unwrap()).is_none());
+}
+
+#[test]
+fn doc() {
+  let src =
+    "(* Adds one.\n   Never fails. *)\nfun inc x = x + 1\n\n(* unattached *)\n\nval y = inc 3\n";
+  let mut store = crate::intern::StrStoreMut::new();
+  let mut statics = Statics::new();
+  assert!(check(&mut store, &mut statics, src).is_empty());
+  let comments = crate::lex::get(&mut store, src.as_bytes())
+    .unwrap()
+    .comments();
+  let get = |needle: &str| {
+    let idx = src.find(needle).unwrap();
+    statics.doc(src.as_bytes(), &comments, idx)
+  };
+  let want = Some("Adds one.\nNever fails.".to_owned());
+  assert_eq!(get("inc x"), want);
+  assert_eq!(get("inc 3"), want);
+  assert_eq!(get("x ="), None);
+  assert_eq!(get("y ="), None);
+}
+
+#[test]
+fn definition() {
+  let src = "val x = 3\nval y = x + 1\n";
+  let mut store = crate::intern::StrStoreMut::new();
+  let mut statics = Statics::new();
+  assert!(check(&mut store, &mut statics, src).is_empty());
+  let get = |needle: &str| statics.definition(src.find(needle).unwrap());
+  let x = src.find('x').unwrap();
+  assert_eq!(get("x +"), Some(Definition::Loc(Loc::new(x, x + 1))));
+  assert_eq!(get("+ 1"), Some(Definition::StdLib(StrRef::PLUS)));
+  assert_eq!(get("1\n"), None);
+}
+
+#[test]
+fn sole_binding() {
+  let src = "val _ = let val x = 1 val (y, z) = (2, 3) in z end\n";
+  let mut store = crate::intern::StrStoreMut::new();
+  let mut statics = Statics::new();
+  assert_eq!(check(&mut store, &mut statics, src).len(), 2);
+  let get = |needle: &str| {
+    let start = src.find(needle).unwrap();
+    let def = Loc::new(start, start + 1);
+    statics.sole_binding(def).map(|loc| &src[Range::from(loc)])
+  };
+  assert_eq!(get("x ="), Some("val x = 1"));
+  assert_eq!(get("y,"), None);
+}
+
+#[test]
+fn add_prelude() {
+  let prelude = "datatype t = A | B\nfun inc x = x + 1\nval bad = 1 + true\n";
+  let src = "val x = inc 3\nval y = A\n";
+  let mut store = crate::intern::StrStoreMut::new();
+  let prelude = crate::lex::get(&mut store, prelude.as_bytes()).unwrap();
+  let mut statics = Statics::new();
+  statics.add_prelude(&crate::parse::get(prelude).unwrap());
+  assert!(check(&mut store, &mut statics, src).is_empty());
+  assert_eq!(statics.definition(src.find("inc").unwrap()), None);
+  assert_eq!(statics.definition(src.find('A').unwrap()), None);
+  let x = src.find('x').unwrap();
+  assert_eq!(
+    statics.definition(x),
+    Some(Definition::Loc(Loc::new(x, x + 1)))
+  );
+}
+
+#[test]
+fn next_file() {
+  let fst = "fun inc x = x + 1\n";
+  let snd = "val y = inc 3\n";
+  let mut store = crate::intern::StrStoreMut::new();
+  let mut statics = Statics::new();
+  assert!(check(&mut store, &mut statics, fst).is_empty());
+  let mut statics = statics.next_file();
+  assert!(check(&mut store, &mut statics, snd).is_empty());
+  assert_eq!(statics.definition(snd.find("inc").unwrap()), None);
+  let y = snd.find('y').unwrap();
+  assert_eq!(
+    statics.definition(y),
+    Some(Definition::Loc(Loc::new(y, y + 1)))
+  );
+}
+
+#[test]
+fn fingerprint() {
+  let srcs = [
+    "datatype t = A of int\nfun f x = A (x + 1)\nsignature S = sig type u val g : u -> t end\n",
+    "\n\ndatatype t = A of int\n(* hi *)\nfun f y = let val z = y in A z end\nsignature S = sig type u val g : u -> t end\n",
+    "datatype t = A of int\nfun f x = A (x + 1)\nsignature S = sig type u val g : t -> u end\n",
+    "datatype t = A of real\nfun f x = A (x + 1.0)\nsignature S = sig type u val g : u -> t end\n",
+  ];
+  let mut store = crate::intern::StrStoreMut::new();
+  let before = Statics::new();
+  let fingerprints: Vec<_> = srcs
+    .iter()
+    .map(|src| {
+      let mut statics = before.clone();
+      assert!(check(&mut store, &mut statics, src).is_empty());
+      statics.fingerprint(&before)
+    })
+    .collect();
+  assert_eq!(fingerprints[0], fingerprints[1]);
+  assert_ne!(fingerprints[0], fingerprints[2]);
+  assert_ne!(fingerprints[0], fingerprints[3]);
+}
+
+#[test]
+fn delta() {
+  let fst =
+    "datatype t = A of int | B\nfun f x = A (x + 1)\nsignature S = sig type u val g : u -> t end\n";
+  let snd = "structure T = struct fun h (A x) = x | h B = 0 end\nval y = f 3\nval z = T.h y\n";
+  let mut store = crate::intern::StrStoreMut::new();
+  let before = Statics::new();
+  let mut after = before.clone();
+  assert!(check(&mut store, &mut after, fst).is_empty());
+  let after = after.next_file();
+  let bs = {
+    let store = store.clone().finish();
+    let mut e = Encoder::new(&store);
+    after.encode_delta(&before, &mut e);
+    e.finish()
+  };
+  let mut d = Decoder::new(&bs, &mut store).unwrap();
+  let mut decoded = Statics::decode_delta(&before, &mut d).unwrap();
+  assert!(d.is_done());
+  assert_eq!(decoded.fingerprint(&before), after.fingerprint(&before));
+  assert!(check(&mut store, &mut decoded, snd).is_empty());
+  let store = store.finish();
+  let hover = decoded.hover(&store, snd.find("h y").unwrap()).unwrap();
+  assert_eq!(hover.val, "val h : t -> int");
+}
+
+#[test]
+fn max_depth() {
+  // each is a prefix, something to nest, the innermost thing, something to close it, and a suffix.
+  let shapes = [
+    ("val x = ", "1 + ", "1", "", ""),
+    ("val x = ", "1 :: ", "[]", "", ""),
+    ("val x = ", "(", "1", ")", ""),
+    ("val x = ", "(1, ", "1", ")", ""),
+    ("val x = ", "[", "1", "]", ""),
+    ("val x = ", "{a = ", "1", "}", ""),
+    ("val x = ", "SOME (", "1", ")", ""),
+    ("val x = ", "let in ", "1", " end", ""),
+    ("val x = ", "let val x = ", "1", " in x end", ""),
+    // applied, since checking a `fn` whose type is as deep as the nesting is slow.
+    ("val x = ", "(fn _ => ", "1", ") ()", ""),
+    ("val x = ", "case 1 of _ => ", "1", "", ""),
+    ("val x = ", "if true then 1 else ", "1", "", ""),
+    ("val ", "(", "x", ")", " = 1"),
+    ("val ", "(_, ", "_", ")", " = ()"),
+    ("val x : ", "unit -> ", "int", "", " = 1"),
+    ("val x : ", "", "int", " list", " = []"),
+    ("", "local in ", "val x = 1", " end", ""),
+    (
+      "structure S = ",
+      "struct structure S = ",
+      "struct end",
+      " end",
+      "",
+    ),
+    ("structure S = ", "let in ", "struct end", " end", ""),
+    (
+      "structure S = struct ",
+      "local in ",
+      "val x = 1",
+      " end",
+      " end",
+    ),
+    (
+      "signature S = ",
+      "sig structure S : ",
+      "sig end",
+      " end",
+      "",
+    ),
+  ];
+  let parse = |(pre, open, mid, close, post): (&str, &str, &str, &str, &str), n: usize| {
+    let src = format!(
+      "{}{}{}{}{}",
+      pre,
+      open.repeat(n),
+      mid,
+      close.repeat(n),
+      post
+    );
+    let mut store = crate::intern::StrStoreMut::new();
+    let lexer = crate::lex::get(&mut store, src.as_bytes()).unwrap();
+    crate::parse::get(lexer).ok()
+  };
+  std::thread::Builder::new()
+    .stack_size(crate::parse::STACK_SIZE)
+    .spawn(move || {
+      for shape in shapes {
+        // find the deepest nesting of this shape which parses. then check it and drop it.
+        let (mut lo, mut hi) = (0, crate::parse::MAX_DEPTH + 1);
+        while hi - lo > 1 {
+          let mid = (lo + hi) / 2;
+          if parse(shape, mid).is_some() {
+            lo = mid;
+          } else {
+            hi = mid;
+          }
+        }
+        assert!(
+          lo > crate::parse::MAX_DEPTH / 3,
+          "{:?} only parses {} deep",
+          shape,
+          lo
+        );
+        let top_decs = parse(shape, lo).unwrap();
+        let mut statics = Statics::new();
+        for top_dec in top_decs.iter() {
+          statics.get(top_dec);
+        }
+      }
+    })
+    .unwrap()
+    .join()
+    .unwrap();
+}
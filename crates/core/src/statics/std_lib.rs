@@ -1,10 +1,47 @@
 //! The static standard library.
+//!
+//! Only the initial basis of the SML Definition is built here. The rest is specified in
+//! std_lib.sml, which is checked like any other signature.
 
-use crate::intern::StrRef;
+use crate::ast::{Label, TopDec};
+use crate::intern::{StrRef, StrStoreMut};
+use crate::statics::ck::{ck_sig_exp, ck_top_dec};
 use crate::statics::types::{
-  Basis, Env, FunEnv, SigEnv, State, StrEnv, Sym, Ty, TyEnv, TyInfo, TyScheme, ValEnv, ValInfo,
+  Basis, Env, FunEnv, SigEnv, State, Sym, Ty, TyEnv, TyInfo, TyScheme, ValEnv, ValInfo,
 };
+use crate::{lex, parse};
 use maplit::btreemap;
+use std::str::FromStr;
+
+/// How much of the standard basis to make available before checking anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BasisLevel {
+  /// Only the initial basis of the SML Definition (Appendix C and D): the built-in types, `true`,
+  /// `false`, `nil`, `::`, `ref`, `=`, `:=`, the overloaded arithmetic and comparison operators,
+  /// and the exceptions `Match` and `Bind`.
+  None,
+  /// Every top-level type and value, but no structures like `List` or `String`, and no signatures.
+  Minimal,
+  /// Everything.
+  #[default]
+  Full,
+}
+
+impl FromStr for BasisLevel {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "none" => Ok(Self::None),
+      "minimal" => Ok(Self::Minimal),
+      "full" => Ok(Self::Full),
+      _ => Err(format!(
+        "unknown basis level `{}`, expected none, minimal, or full",
+        s
+      )),
+    }
+  }
+}
 
 /// Given `t`, returns `t ref`.
 fn ref_ty(t: Ty) -> Ty {
@@ -47,14 +84,6 @@ fn ref_val_env(st: &mut State) -> ValEnv {
   btreemap![StrRef::REF => ref_]
 }
 
-fn order_val_env() -> ValEnv {
-  btreemap![
-    StrRef::LESS => ValInfo::ctor(TyScheme::mono(Ty::ORDER)),
-    StrRef::EQUAL => ValInfo::ctor(TyScheme::mono(Ty::ORDER)),
-    StrRef::GREATER => ValInfo::ctor(TyScheme::mono(Ty::ORDER)),
-  ]
-}
-
 fn overloaded(st: &mut State, overloads: Vec<Sym>) -> ValInfo {
   let a = st.new_ty_var(false);
   ValInfo::val(TyScheme {
@@ -78,7 +107,25 @@ fn overloaded_cmp(st: &mut State) -> ValInfo {
   ValInfo::val(TyScheme {
     ty_vars: vec![a],
     ty: Ty::Arrow(Ty::pair(Ty::Var(a), Ty::Var(a)).into(), Ty::BOOL.into()),
-    overload: Some(vec![Sym::INT, Sym::WORD, Sym::REAL, Sym::STRING, Sym::CHAR]),
+    overload: Some(vec![
+      Sym::INT,
+      Sym::INT_INF,
+      Sym::WORD,
+      Sym::WORD8,
+      Sym::REAL,
+      Sym::STRING,
+      Sym::CHAR,
+    ]),
+  })
+}
+
+/// Returns the value of `=` or `<>`.
+fn equality(st: &mut State) -> ValInfo {
+  let a = st.new_ty_var(true);
+  ValInfo::val(TyScheme {
+    ty_vars: vec![a],
+    ty: Ty::Arrow(Ty::pair(Ty::Var(a), Ty::Var(a)).into(), Ty::BOOL.into()),
+    overload: None,
   })
 }
 
@@ -90,10 +137,38 @@ fn base_ty(ty: Ty, equality: bool) -> TyInfo {
   }
 }
 
-pub fn get() -> (Basis, State) {
-  let real_int = || vec![Sym::INT, Sym::REAL];
-  let word_int = || vec![Sym::INT, Sym::WORD];
-  let num = || vec![Sym::INT, Sym::WORD, Sym::REAL];
+/// The standard basis beyond the initial basis, as a signature.
+const STD_LIB: &str = include_str!("std_lib.sml");
+
+thread_local! {
+  /// The full basis. Checking `STD_LIB` takes a while, so it's only done once.
+  static FULL: (Basis, State) = {
+    let (mut bs, mut st) = initial();
+    let (sig_env, env) = ck_std_lib(&bs, &mut st);
+    bs.sig_env.extend(sig_env);
+    bs.env.extend(env);
+    (bs, st)
+  };
+}
+
+pub fn get(level: BasisLevel) -> (Basis, State) {
+  match level {
+    BasisLevel::None => initial(),
+    BasisLevel::Minimal => {
+      let (mut bs, st) = FULL.with(Clone::clone);
+      bs.sig_env.clear();
+      bs.env.str_env.clear();
+      (bs, st)
+    }
+    BasisLevel::Full => FULL.with(Clone::clone),
+  }
+}
+
+/// Returns the initial basis of the SML Definition.
+fn initial() -> (Basis, State) {
+  let real_int = || vec![Sym::INT, Sym::INT_INF, Sym::REAL];
+  let word_int = || vec![Sym::INT, Sym::INT_INF, Sym::WORD, Sym::WORD8];
+  let num = || vec![Sym::INT, Sym::INT_INF, Sym::WORD, Sym::WORD8, Sym::REAL];
   let real = || vec![Sym::REAL];
   let mut st = State::default();
   st.tys.insert(
@@ -132,11 +207,29 @@ pub fn get() -> (Basis, State) {
       equality: true,
     },
   );
+  let a = st.new_ty_var(false);
+  st.tys.insert(
+    Sym::VECTOR,
+    TyInfo {
+      ty_fcn: TyScheme {
+        ty_vars: vec![a],
+        ty: Ty::vector(Ty::Var(a)),
+        overload: None,
+      },
+      val_env: ValEnv::new(),
+      equality: true,
+    },
+  );
+  let a = st.new_ty_var(false);
   st.tys.insert(
-    Sym::ORDER,
+    Sym::ARRAY,
     TyInfo {
-      ty_fcn: TyScheme::mono(Ty::ORDER),
-      val_env: order_val_env(),
+      ty_fcn: TyScheme {
+        ty_vars: vec![a],
+        ty: Ty::Ctor(vec![Ty::Var(a)], Sym::ARRAY),
+        overload: None,
+      },
+      val_env: ValEnv::new(),
       equality: true,
     },
   );
@@ -149,25 +242,22 @@ pub fn get() -> (Basis, State) {
     ),
     overload: None,
   });
-  let a = st.new_ty_var(true);
-  let eq = ValInfo::val(TyScheme {
-    ty_vars: vec![a],
-    ty: Ty::Arrow(Ty::pair(Ty::Var(a), Ty::Var(a)).into(), Ty::BOOL.into()),
-    overload: None,
-  });
   st.tys.insert(Sym::INT, base_ty(Ty::INT, true));
   st.tys.insert(Sym::REAL, base_ty(Ty::REAL, false));
   st.tys.insert(Sym::STRING, base_ty(Ty::STRING, true));
   st.tys.insert(Sym::CHAR, base_ty(Ty::CHAR, true));
   st.tys.insert(Sym::WORD, base_ty(Ty::WORD, true));
+  st.tys.insert(Sym::WORD8, base_ty(Ty::WORD8, true));
   st.tys.insert(Sym::EXN, base_ty(Ty::EXN, false));
+  st.tys.insert(Sym::INT_INF, base_ty(Ty::INT_INF, true));
   let unit = Ty::Record(btreemap![]);
-  st.tys.insert(Sym::UNIT, base_ty(unit, false));
+  st.tys.insert(Sym::UNIT, base_ty(unit, true));
+  // SML Definition Appendix C
   let bs = Basis {
     fun_env: FunEnv::new(),
     sig_env: SigEnv::new(),
     env: Env {
-      str_env: StrEnv::new(),
+      str_env: btreemap![],
       ty_env: TyEnv {
         inner: btreemap![
           StrRef::UNIT => Sym::UNIT,
@@ -180,16 +270,14 @@ pub fn get() -> (Basis, State) {
           StrRef::LIST => Sym::LIST,
           StrRef::REF => Sym::REF,
           StrRef::EXN => Sym::EXN,
-          StrRef::ORDER => Sym::ORDER,
         ],
       },
       val_env: bool_val_env()
         .into_iter()
         .chain(list_val_env(&mut st))
         .chain(ref_val_env(&mut st))
-        .chain(order_val_env())
         .chain(btreemap![
-          StrRef::EQ => eq,
+          StrRef::EQ => equality(&mut st),
           StrRef::ASSIGN => assign,
           StrRef::MATCH => ValInfo::exn(),
           StrRef::BIND => ValInfo::exn(),
@@ -217,3 +305,72 @@ pub fn get() -> (Basis, State) {
   }
   (bs, st)
 }
+
+/// Checks `STD_LIB` in `bs`, the initial basis. Returns the signatures other than `BASIS`, and the
+/// environment `BASIS` specifies.
+fn ck_std_lib(bs: &Basis, st: &mut State) -> (SigEnv, Env) {
+  let mut store = StrStoreMut::new();
+  let lexer = lex::get(&mut store, STD_LIB.as_bytes()).unwrap();
+  let top_decs = parse::get(lexer).unwrap();
+  let (basis, sigs) = top_decs.split_last().unwrap();
+  // these types are built in, but not available at the top level under these names.
+  let mut bs = bs.clone();
+  bs.env.ty_env.inner.extend(btreemap![
+    StrRef::VECTOR => Sym::VECTOR,
+    StrRef::ARRAY => Sym::ARRAY,
+    StrRef::WORD8 => Sym::WORD8,
+    StrRef::INT_INF => Sym::INT_INF,
+  ]);
+  for top_dec in sigs {
+    ck_top_dec(&mut bs, st, top_dec).unwrap();
+  }
+  let sig_exp = match &basis.val {
+    TopDec::SigDec(sig_binds) if sig_binds.len() == 1 => &sig_binds[0].exp,
+    _ => unreachable!("std_lib.sml does not end with `BASIS`"),
+  };
+  let env = ck_sig_exp(&bs, st, sig_exp).unwrap().env;
+  assert!(st.errors.is_empty());
+  for (name, sig) in bs.sig_env.iter() {
+    assert!(name.is_special());
+    assert_special(&sig.env);
+  }
+  assert_special(&env);
+  (bs.sig_env, env)
+}
+
+/// Panics unless every name in `env`, including the record labels in the types of its values, is a
+/// special `StrRef`. Any other `StrRef` would not mean the same thing in the program being checked.
+fn assert_special(env: &Env) {
+  fn ty_special(ty: &Ty) {
+    match ty {
+      Ty::Var(_) => {}
+      Ty::Record(rows) => {
+        for (lab, ty) in rows {
+          if let Label::Vid(name) = lab {
+            assert!(name.is_special());
+          }
+          ty_special(ty);
+        }
+      }
+      Ty::Arrow(t, u) => {
+        ty_special(t);
+        ty_special(u);
+      }
+      Ty::Ctor(args, sym) => {
+        assert!(sym.name().is_special());
+        args.iter().for_each(ty_special);
+      }
+    }
+  }
+  for (name, env) in env.str_env.iter() {
+    assert!(name.is_special());
+    assert_special(env);
+  }
+  for name in env.ty_env.inner.keys() {
+    assert!(name.is_special());
+  }
+  for (name, val_info) in env.val_env.iter() {
+    assert!(name.is_special());
+    ty_special(&val_info.ty_scheme.ty);
+  }
+}
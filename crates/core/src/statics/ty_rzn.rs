@@ -32,6 +32,11 @@ impl TyRealization {
     assert!(self.inner.insert(key, Out::Sym(val)).is_none());
   }
 
+  /// Returns whether this maps `key` to anything.
+  pub fn contains_key(&self, key: &Sym) -> bool {
+    self.inner.contains_key(key)
+  }
+
   /// Applies this to an `Env`.
   pub fn get_env(&self, tys: &mut Tys, env: &mut Env) {
     for env in env.str_env.values_mut() {
@@ -42,10 +47,13 @@ impl TyRealization {
         None => continue,
         Some(Out::TyFcn(..)) => unreachable!(),
         Some(&Out::Sym(new)) => {
-          let mut ty_info = tys.get(old).clone();
-          self.get_ty(&mut ty_info.ty_fcn.ty);
-          self.get_val_env(&mut ty_info.val_env);
-          tys.insert(new, ty_info);
+          // the same type name may be bound more than once, e.g. by datatype replication.
+          if !tys.contains_key(&new) {
+            let mut ty_info = tys.get(old).clone();
+            self.get_ty(&mut ty_info.ty_fcn.ty);
+            self.get_val_env(&mut ty_info.val_env);
+            tys.insert(new, ty_info);
+          }
           *old = new;
         }
       }
@@ -8,6 +8,10 @@
 //! the comment there telling you to come here).
 
 use crate::ast::{Label, TyPrec};
+use crate::codec::{
+  decode_map_delta, decode_set_delta, encode_map_delta, encode_set_delta, Decode, Decoder, Encode,
+  Encoder,
+};
 use crate::intern::{StrRef, StrStore};
 use crate::loc::{Loc, Located};
 use crate::token::TyVar as AstTyVar;
@@ -17,15 +21,17 @@ use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt;
 
 /// An error encountered during static analysis.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[allow(missing_docs)]
 pub enum Error {
   Undefined(Item, StrRef),
-  Duplicate(Item, StrRef),
-  DuplicateLabel(Label),
+  Duplicate(Item, StrRef, Option<Loc>),
+  DuplicateLabel(Label, Loc),
   Circularity(TyVar, Ty),
   TyMismatch(Ty, Ty),
-  OverloadTyMismatch(Vec<Sym>, Ty),
+  FieldTyMismatch(Vec<Label>, Ty, Ty),
+  RecordLabelMismatch(Vec<Label>, Vec<Label>),
+  OverloadTyMismatch(StrRef, Vec<Sym>, Ty),
   PatWrongIdStatus,
   ExnWrongIdStatus(IdStatus),
   WrongNumTyArgs(usize, usize),
@@ -35,7 +41,7 @@ pub enum Error {
   NonExhaustiveMatch,
   NonExhaustiveBinding,
   UnreachablePattern,
-  FunDecNameMismatch(StrRef, StrRef),
+  FunDecNameMismatch(StrRef, StrRef, Loc),
   FunDecWrongNumPats(usize, usize),
   PatNotConsTy(Ty),
   PatNotArrowTy(Ty),
@@ -44,32 +50,197 @@ pub enum Error {
   NotArrowTy(Ty),
   IdStatusMismatch(IdStatus, IdStatus),
   ValEnvMismatch(Vec<StrRef>, Vec<StrRef>),
+  TyVarInScope(StrRef),
+  TyVarEscape(StrRef),
+  NotFlexible(StrRef),
+  NotRecordTy(Ty),
+  MissingLabel(Label, Ty),
+  UnresolvedRecordTy(BTreeMap<Label, Ty>),
+  Unused(StrRef),
+  RecValNotFn,
+  UnboundTyVar(StrRef),
+  Word8ConstantOutOfRange,
+  UnresolvedTy(StrRef, Ty),
   Todo(&'static str),
 }
 
 impl Error {
-  /// A human-readable description of the error.
+  /// A stable numeric code identifying the kind of the error. See `doc/errors.md`.
+  pub fn code(&self) -> u16 {
+    match self {
+      Self::Undefined(..) => 2001,
+      Self::Duplicate(..) => 2002,
+      Self::DuplicateLabel(..) => 2003,
+      Self::Circularity(..) => 2004,
+      Self::TyMismatch(..) => 2005,
+      Self::OverloadTyMismatch(..) => 2006,
+      Self::PatWrongIdStatus => 2007,
+      Self::ExnWrongIdStatus(_) => 2008,
+      Self::WrongNumTyArgs(..) => 2009,
+      Self::NonVarInAs(_) => 2010,
+      Self::ForbiddenBinding(_) => 2011,
+      Self::TyNameEscape => 2012,
+      Self::NonExhaustiveMatch => 2013,
+      Self::NonExhaustiveBinding => 2014,
+      Self::UnreachablePattern => 2015,
+      Self::FunDecNameMismatch(..) => 2016,
+      Self::FunDecWrongNumPats(..) => 2017,
+      Self::PatNotConsTy(_) => 2018,
+      Self::PatNotArrowTy(_) => 2019,
+      Self::DatatypeCopyNotDatatype => 2020,
+      Self::NotEquality(_) => 2021,
+      Self::NotArrowTy(_) => 2022,
+      Self::IdStatusMismatch(..) => 2023,
+      Self::ValEnvMismatch(..) => 2024,
+      Self::TyVarInScope(_) => 2025,
+      Self::TyVarEscape(_) => 2026,
+      Self::NotFlexible(_) => 2027,
+      Self::NotRecordTy(_) => 2028,
+      Self::MissingLabel(..) => 2029,
+      Self::UnresolvedRecordTy(_) => 2030,
+      Self::FieldTyMismatch(..) => 2031,
+      Self::RecordLabelMismatch(..) => 2032,
+      Self::Unused(_) => 2033,
+      Self::RecValNotFn => 2034,
+      Self::UnboundTyVar(..) => 2035,
+      Self::Word8ConstantOutOfRange => 2036,
+      Self::UnresolvedTy(..) => 2037,
+      Self::Todo(_) => 2999,
+    }
+  }
+
+  /// Applies a substitution to the types in this, so they show everything that came to be known
+  /// about them after the error was found.
+  pub fn apply(&mut self, subst: &Subst) {
+    match self {
+      Self::TyMismatch(want, got) | Self::FieldTyMismatch(_, want, got) => {
+        want.apply(subst);
+        got.apply(subst);
+      }
+      Self::OverloadTyMismatch(_, _, ty)
+      | Self::PatNotConsTy(ty)
+      | Self::PatNotArrowTy(ty)
+      | Self::NotEquality(ty)
+      | Self::NotArrowTy(ty)
+      | Self::NotRecordTy(ty)
+      | Self::MissingLabel(_, ty)
+      | Self::UnresolvedTy(_, ty) => ty.apply(subst),
+      Self::UnresolvedRecordTy(rows) => {
+        for ty in rows.values_mut() {
+          ty.apply(subst);
+        }
+      }
+      // the ty var would no longer show up in the type if it were solved after the error.
+      Self::Circularity(..)
+      | Self::Undefined(..)
+      | Self::Duplicate(..)
+      | Self::DuplicateLabel(..)
+      | Self::RecordLabelMismatch(..)
+      | Self::PatWrongIdStatus
+      | Self::ExnWrongIdStatus(_)
+      | Self::WrongNumTyArgs(..)
+      | Self::NonVarInAs(_)
+      | Self::ForbiddenBinding(_)
+      | Self::TyNameEscape
+      | Self::NonExhaustiveMatch
+      | Self::NonExhaustiveBinding
+      | Self::UnreachablePattern
+      | Self::FunDecNameMismatch(..)
+      | Self::FunDecWrongNumPats(..)
+      | Self::DatatypeCopyNotDatatype
+      | Self::IdStatusMismatch(..)
+      | Self::ValEnvMismatch(..)
+      | Self::TyVarInScope(_)
+      | Self::TyVarEscape(_)
+      | Self::NotFlexible(_)
+      | Self::Unused(_)
+      | Self::RecValNotFn
+      | Self::UnboundTyVar(_)
+      | Self::Word8ConstantOutOfRange
+      | Self::Todo(_) => {}
+    }
+  }
+
+  /// How severe the error is. Warnings do not cause checking to fail.
+  pub fn severity(&self) -> Severity {
+    match self {
+      // SML Definition section 4.11 - the compiler should give warning, not reject the program.
+      Self::NonExhaustiveMatch | Self::NonExhaustiveBinding | Self::Unused(_) => Severity::Warning,
+      _ => Severity::Error,
+    }
+  }
+
+  /// Other locations related to the error, each with a description of how it is related.
+  pub fn related(&self) -> Vec<Located<&'static str>> {
+    match self {
+      Self::Duplicate(_, _, Some(loc)) | Self::DuplicateLabel(_, loc) => {
+        vec![loc.wrap("first defined here")]
+      }
+      Self::FunDecNameMismatch(_, _, loc) => vec![loc.wrap("name in the first clause")],
+      _ => Vec::new(),
+    }
+  }
+
+  /// A human-readable description of the error. Large types are elided as per the default
+  /// `TyLimit`.
   pub fn message(&self, store: &StrStore) -> String {
+    self.message_with(store, TyLimit::default())
+  }
+
+  /// A human-readable description of the error, showing types within `limit`.
+  pub fn message_with(&self, store: &StrStore, limit: TyLimit) -> String {
+    let mut show = TyShow::new(limit);
     match self {
       Self::Undefined(item, id) => format!("undefined {}: {}", item, store.get(*id)),
-      Self::Duplicate(item, id) => format!("duplicate {}: {}", item, store.get(*id)),
-      Self::DuplicateLabel(lab) => format!("duplicate label: {}", show_lab(store, *lab)),
+      Self::Duplicate(item, id, _) => format!("duplicate {}: {}", item, store.get(*id)),
+      Self::DuplicateLabel(lab, _) => format!("duplicate label: {}", show_lab(store, *lab)),
       Self::Circularity(ty_var, ty) => {
-        format!("circularity: {:?} in {}", ty_var, show_ty(store, &ty))
+        let ty_var = show.ty_var(*ty_var);
+        format!(
+          "circularity: {} in {}",
+          ty_var,
+          show_ty(store, &mut show, ty)
+        )
       }
       Self::TyMismatch(want, got) => format!(
         "mismatched types: expected {}, found {}",
-        show_ty(store, &want),
-        show_ty(store, &got)
+        show_ty(store, &mut show, want),
+        show_ty(store, &mut show, got)
       ),
-      Self::OverloadTyMismatch(want, got) => {
-        let mut ret = "mismatched types: expected one of ".to_owned();
+      Self::FieldTyMismatch(path, want, got) => {
+        let path: Vec<_> = path.iter().map(|&lab| show_lab(store, lab)).collect();
+        format!(
+          "mismatched types in record field {}: expected {}, found {}",
+          path.join("."),
+          show_ty(store, &mut show, want),
+          show_ty(store, &mut show, got)
+        )
+      }
+      Self::RecordLabelMismatch(missing, extra) => {
+        let mut parts = Vec::new();
+        for (what, labs) in [("missing", missing), ("unexpected", extra)].iter() {
+          match labs.len() {
+            0 => {}
+            1 => parts.push(format!("{} label {}", what, show_lab(store, labs[0]))),
+            _ => {
+              let labs: Vec<_> = labs.iter().map(|&lab| show_lab(store, lab)).collect();
+              parts.push(format!("{} labels {}", what, labs.join(", ")));
+            }
+          }
+        }
+        format!("mismatched record types: {}", parts.join("; "))
+      }
+      Self::OverloadTyMismatch(name, want, got) => {
+        let mut ret = format!(
+          "mismatched types for overloaded {}: expected one of ",
+          store.get(*name)
+        );
         for &sym in want {
-          show_ty_impl(&mut ret, store, &Ty::base(sym), TyPrec::Arrow);
+          show_ty_impl(&mut ret, store, &mut show, &Ty::base(sym), TyPrec::Arrow, 0);
           ret.push_str(", ");
         }
         ret.push_str("found ");
-        show_ty_impl(&mut ret, store, got, TyPrec::Arrow);
+        show_ty_impl(&mut ret, store, &mut show, got, TyPrec::Arrow, 0);
         ret
       }
       Self::PatWrongIdStatus => {
@@ -92,7 +263,7 @@ impl Error {
       Self::NonExhaustiveMatch => "non-exhaustive match".to_owned(),
       Self::NonExhaustiveBinding => "non-exhaustive binding".to_owned(),
       Self::UnreachablePattern => "unreachable pattern".to_owned(),
-      Self::FunDecNameMismatch(want, got) => format!(
+      Self::FunDecNameMismatch(want, got, _) => format!(
         "mismatched names: expected {}, found {}",
         store.get(*want),
         store.get(*got)
@@ -103,17 +274,17 @@ impl Error {
       ),
       Self::PatNotConsTy(ty) => format!(
         "mismatched types: expected a constructor type, found {}",
-        show_ty(store, ty)
+        show_ty(store, &mut show, ty)
       ),
       Self::PatNotArrowTy(ty) => format!(
         "mismatched types: expected an arrow type, found {}",
-        show_ty(store, ty)
+        show_ty(store, &mut show, ty)
       ),
       Self::DatatypeCopyNotDatatype => {
         "right-hand side of datatype copy is not a datatype".to_owned()
       }
-      Self::NotEquality(ty) => format!("not an equality type: {}", show_ty(store, ty)),
-      Self::NotArrowTy(ty) => format!("not a function type: {}", show_ty(store, ty)),
+      Self::NotEquality(ty) => format!("not an equality type: {}", show_ty(store, &mut show, ty)),
+      Self::NotArrowTy(ty) => format!("not a function type: {}", show_ty(store, &mut show, ty)),
       Self::IdStatusMismatch(want, got) => format!(
         "mismatched identifier statuses: expected {}, found {}",
         want, got
@@ -127,11 +298,52 @@ impl Error {
           want, got
         )
       }
+      Self::TyVarInScope(id) => format!("type variable already in scope: {}", store.get(*id)),
+      Self::TyVarEscape(id) => format!("type variable escapes its scope: {}", store.get(*id)),
+      Self::NotFlexible(id) => format!("type is not flexible: {}", store.get(*id)),
+      Self::NotRecordTy(ty) => format!(
+        "mismatched types: expected a record type, found {}",
+        show_ty(store, &mut show, ty)
+      ),
+      Self::MissingLabel(lab, ty) => format!(
+        "mismatched types: expected a record type with label {}, found {}",
+        show_lab(store, *lab),
+        show_ty(store, &mut show, ty)
+      ),
+      Self::UnresolvedRecordTy(rows) => {
+        let mut ret = "cannot determine the full record type here: { ".to_owned();
+        for (lab, ty) in sorted_rows(store, rows) {
+          show_row(&mut ret, store, &mut show, lab, ty, 1);
+          ret.push_str(", ");
+        }
+        ret.push_str("... }; add a type annotation");
+        ret
+      }
+      Self::Unused(id) => format!("unused variable: {}", store.get(*id)),
+      Self::RecValNotFn => "the expression in a `val rec` is not a `fn`".to_owned(),
+      Self::UnboundTyVar(tv) => format!("unbound type variable: {}", store.get(*tv)),
+      Self::Word8ConstantOutOfRange => {
+        format!("word8 constant out of range: must be at most {}", u8::MAX)
+      }
+      Self::UnresolvedTy(id, ty) => format!(
+        "cannot determine the full type of {}: {}; add a type annotation",
+        store.get(*id),
+        show_ty(store, &mut show, ty)
+      ),
       Self::Todo(msg) => format!("unsupported language construct: {}", msg),
     }
   }
 }
 
+/// How severe an error is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+  /// The program is invalid.
+  Error,
+  /// The program is valid, but probably not as intended.
+  Warning,
+}
+
 /// Show a label.
 fn show_lab(store: &StrStore, lab: Label) -> String {
   match lab {
@@ -140,51 +352,143 @@ fn show_lab(store: &StrStore, lab: Label) -> String {
   }
 }
 
+/// Returns the rows in the order they should be shown: numeric labels in numeric order, then the
+/// others in alphabetical order. (The order of `Label`s follows the order of their `StrRef`s, which
+/// is not alphabetical.)
+fn sorted_rows<'a, T>(store: &StrStore, rows: &'a BTreeMap<Label, T>) -> Vec<(Label, &'a T)> {
+  let mut ret: Vec<_> = rows.iter().map(|(&lab, x)| (lab, x)).collect();
+  ret.sort_by(|&(a, _), &(b, _)| match (a, b) {
+    (Label::Num(a), Label::Num(b)) => a.cmp(&b),
+    (Label::Num(_), Label::Vid(_)) => std::cmp::Ordering::Less,
+    (Label::Vid(_), Label::Num(_)) => std::cmp::Ordering::Greater,
+    (Label::Vid(a), Label::Vid(b)) => store.get(a).cmp(store.get(b)),
+  });
+  ret
+}
+
+/// Limits on how much of a type to show in an error message. Parts of a type beyond these limits
+/// are elided as `...`.
+#[derive(Debug, Clone, Copy)]
+pub struct TyLimit {
+  /// How deeply nested a part of a type may be and still be shown. The result of an arrow type is
+  /// not considered nested in the arrow type, so long curried function types are shown in full.
+  pub depth: usize,
+  /// How many rows of a single record or tuple type to show.
+  pub width: usize,
+}
+
+impl TyLimit {
+  /// No limit, so types are always shown in full.
+  pub const NONE: Self = Self {
+    depth: usize::MAX,
+    width: usize::MAX,
+  };
+}
+
+impl Default for TyLimit {
+  fn default() -> Self {
+    Self {
+      depth: 8,
+      width: 12,
+    }
+  }
+}
+
+/// Information for showing the types in a single error message.
+struct TyShow {
+  /// Names for type variables, so that users see `'a`, `'b`, etc. in order of first occurrence
+  /// instead of the internal ids of the type variables.
+  names: HashMap<TyVar, usize>,
+  limit: TyLimit,
+}
+
+impl TyShow {
+  fn new(limit: TyLimit) -> Self {
+    Self {
+      names: HashMap::new(),
+      limit,
+    }
+  }
+
+  /// Returns the name for `tv`, giving it the next unused name if it doesn't have one yet.
+  fn ty_var(&mut self, tv: TyVar) -> String {
+    let len = self.names.len();
+    let idx = *self.names.entry(tv).or_insert(len);
+    let mut ret = if tv.equality { "''" } else { "'" }.to_owned();
+    ret.push((b'a' + (idx % 26) as u8) as char);
+    if idx >= 26 {
+      ret.push_str(&(idx / 26).to_string());
+    }
+    ret
+  }
+}
+
 /// Show a type.
-fn show_ty(store: &StrStore, ty: &Ty) -> String {
+fn show_ty(store: &StrStore, show: &mut TyShow, ty: &Ty) -> String {
   let mut buf = String::new();
-  show_ty_impl(&mut buf, store, ty, TyPrec::Arrow);
+  show_ty_impl(&mut buf, store, show, ty, TyPrec::Arrow, 0);
   buf
 }
 
 /// The impl of `show_ty`. This has a `TyPrec` argument to correctly show types with minimal amounts
-/// of parentheses while still being correct. It also mutates the input `buf` instead of returning a
-/// new `String`.
-fn show_ty_impl(buf: &mut String, store: &StrStore, ty: &Ty, prec: TyPrec) {
+/// of parentheses while still being correct, and a `depth` argument to elide parts of the type
+/// nested too deeply. It also mutates the input `buf` instead of returning a new `String`.
+fn show_ty_impl(
+  buf: &mut String,
+  store: &StrStore,
+  show: &mut TyShow,
+  ty: &Ty,
+  prec: TyPrec,
+  depth: usize,
+) {
+  // type variables and base types are short, so there's no point in eliding them.
+  let is_leaf = match ty {
+    Ty::Var(_) => true,
+    Ty::Record(rows) => rows.is_empty(),
+    Ty::Arrow(..) => false,
+    Ty::Ctor(args, _) => args.is_empty(),
+  };
+  if !is_leaf && depth >= show.limit.depth {
+    buf.push_str("...");
+    return;
+  }
   match ty {
-    Ty::Var(tv) => buf.push_str(&format!("{:?}", tv)),
+    Ty::Var(tv) => buf.push_str(&show.ty_var(*tv)),
     Ty::Record(rows) => {
       if rows.is_empty() {
         buf.push_str("unit");
         return;
       }
-      let is_tuple = rows.len() >= 2
-        && rows
-          .keys()
-          .enumerate()
-          .all(|(idx, lab)| Label::tuple(idx) == *lab);
-      if is_tuple {
+      if is_tuple(rows) {
         if prec > TyPrec::Star {
           buf.push_str("(");
         }
         let mut tys = rows.values();
         let ty = tys.next().unwrap();
-        show_ty_impl(buf, store, ty, TyPrec::App);
-        for ty in tys {
+        show_ty_impl(buf, store, show, ty, TyPrec::App, depth + 1);
+        for (idx, ty) in tys.enumerate() {
           buf.push_str(" * ");
-          show_ty_impl(buf, store, ty, TyPrec::App);
+          if idx + 1 >= show.limit.width {
+            buf.push_str("...");
+            break;
+          }
+          show_ty_impl(buf, store, show, ty, TyPrec::App, depth + 1);
         }
         if prec > TyPrec::Star {
           buf.push_str(")");
         }
       } else {
         buf.push_str("{ ");
-        let mut rows = rows.iter();
+        let mut rows = sorted_rows(store, rows).into_iter();
         let (lab, ty) = rows.next().unwrap();
-        show_row(buf, store, *lab, ty);
-        for (lab, ty) in rows {
+        show_row(buf, store, show, lab, ty, depth + 1);
+        for (idx, (lab, ty)) in rows.enumerate() {
           buf.push_str(", ");
-          show_row(buf, store, *lab, ty);
+          if idx + 1 >= show.limit.width {
+            buf.push_str("...");
+            break;
+          }
+          show_row(buf, store, show, lab, ty, depth + 1);
         }
         buf.push_str(" }");
       }
@@ -193,9 +497,9 @@ fn show_ty_impl(buf: &mut String, store: &StrStore, ty: &Ty, prec: TyPrec) {
       if prec > TyPrec::Arrow {
         buf.push_str("(");
       }
-      show_ty_impl(buf, store, lhs, TyPrec::Star);
+      show_ty_impl(buf, store, show, lhs, TyPrec::Star, depth + 1);
       buf.push_str(" -> ");
-      show_ty_impl(buf, store, rhs, TyPrec::Arrow);
+      show_ty_impl(buf, store, show, rhs, TyPrec::Arrow, depth);
       if prec > TyPrec::Arrow {
         buf.push_str(")");
       }
@@ -204,13 +508,13 @@ fn show_ty_impl(buf: &mut String, store: &StrStore, ty: &Ty, prec: TyPrec) {
       let mut args_iter = args.iter();
       if let Some(arg) = args_iter.next() {
         if args.len() == 1 {
-          show_ty_impl(buf, store, arg, TyPrec::App);
+          show_ty_impl(buf, store, show, arg, TyPrec::App, depth + 1);
         } else {
           buf.push_str("(");
-          show_ty_impl(buf, store, arg, TyPrec::Arrow);
+          show_ty_impl(buf, store, show, arg, TyPrec::Arrow, depth + 1);
           for arg in args_iter {
             buf.push_str(", ");
-            show_ty_impl(buf, store, arg, TyPrec::Arrow);
+            show_ty_impl(buf, store, show, arg, TyPrec::Arrow, depth + 1);
           }
           buf.push_str(")");
         }
@@ -221,11 +525,28 @@ fn show_ty_impl(buf: &mut String, store: &StrStore, ty: &Ty, prec: TyPrec) {
   }
 }
 
+/// Returns whether these rows are those of a tuple type, i.e. there are at least 2 of them and they
+/// are labeled 1, 2, 3, ... in order.
+fn is_tuple(rows: &BTreeMap<Label, Ty>) -> bool {
+  rows.len() >= 2
+    && rows
+      .keys()
+      .enumerate()
+      .all(|(idx, lab)| Label::tuple(idx) == *lab)
+}
+
 /// Show a row.
-fn show_row(buf: &mut String, store: &StrStore, lab: Label, ty: &Ty) {
+fn show_row(
+  buf: &mut String,
+  store: &StrStore,
+  show: &mut TyShow,
+  lab: Label,
+  ty: &Ty,
+  depth: usize,
+) {
   buf.push_str(&show_lab(store, lab));
   buf.push_str(" : ");
-  show_ty_impl(buf, store, ty, TyPrec::Arrow);
+  show_ty_impl(buf, store, show, ty, TyPrec::Arrow, depth);
 }
 
 /// A specialized Result type that many functions doing static analysis return.
@@ -280,6 +601,15 @@ impl fmt::Debug for TyVar {
   }
 }
 
+/// An overload constraint on a type variable.
+#[derive(Debug, Clone, PartialEq)]
+struct Overload {
+  /// The overloaded identifier whose use gave rise to the constraint.
+  name: StrRef,
+  /// The symbols of the base types the type variable may be. The first is the default.
+  syms: Vec<Sym>,
+}
+
 /// A substitution, a mapping from type variables to types. The types themselves may be other type
 /// variables, but for all 'output' types in the substitution, there exists no type variable in any
 /// 'output' type which is already mapped to something else in this substitution.
@@ -288,7 +618,10 @@ pub struct Subst {
   /// The conventional substitutions.
   regular: HashMap<TyVar, Ty>,
   /// The overload constraints.
-  overload: HashMap<TyVar, Vec<Sym>>,
+  overload: HashMap<TyVar, Overload>,
+  /// The record constraints. A type variable mapped to some rows must eventually be substituted for
+  /// a record type with at least those rows. The `Loc` is where the constraint arose.
+  record: HashMap<TyVar, Located<BTreeMap<Label, Ty>>>,
   /// Used for user-annotated type variables which may not be substituted for arbitrary types.
   bound: HashSet<TyVar>,
 }
@@ -299,6 +632,7 @@ impl Subst {
   pub fn insert_bound(&mut self, tv: TyVar) {
     assert!(!self.regular.contains_key(&tv));
     assert!(!self.overload.contains_key(&tv));
+    assert!(!self.record.contains_key(&tv));
     assert!(self.bound.insert(tv));
   }
 
@@ -307,6 +641,7 @@ impl Subst {
   pub fn remove_bound(&mut self, tv: &TyVar) {
     assert!(!self.regular.contains_key(tv));
     assert!(!self.overload.contains_key(tv));
+    assert!(!self.record.contains_key(tv));
     assert!(self.bound.remove(tv));
   }
 
@@ -315,26 +650,50 @@ impl Subst {
     self.bound.contains(tv)
   }
 
-  /// Inserts an overloaded ty var. It will only be allowed to be one of the given base types whose
-  /// symbol is given by `Sym`. The first `Sym` in the `Vec` is the symbol of the default type, used
-  /// if the overloaded ty var is never constrained.
-  pub fn insert_overloaded(&mut self, tv: TyVar, syms: Vec<Sym>) {
+  /// Inserts an overloaded ty var, which arose from a use of the overloaded identifier `name`. It
+  /// will only be allowed to be one of the given base types whose symbol is given by `Sym`. The
+  /// first `Sym` in the `Vec` is the symbol of the default type, used if the overloaded ty var is
+  /// never constrained.
+  pub fn insert_overloaded(&mut self, tv: TyVar, name: StrRef, syms: Vec<Sym>) {
     assert!(!syms.is_empty());
     assert!(!self.bound.contains(&tv));
     assert!(!self.regular.contains_key(&tv));
-    assert!(self.overload.insert(tv, syms).is_none());
+    assert!(!self.record.contains_key(&tv));
+    assert!(self.overload.insert(tv, Overload { name, syms }).is_none());
+  }
+
+  /// Inserts a record ty var. It will only be allowed to be a record type with at least the given
+  /// rows. It is an error if the record ty var is never constrained to be a particular record type.
+  pub fn insert_record(&mut self, tv: TyVar, rows: Located<BTreeMap<Label, Ty>>) {
+    assert!(!self.bound.contains(&tv));
+    assert!(!self.regular.contains_key(&tv));
+    assert!(!self.overload.contains_key(&tv));
+    assert!(self.record.insert(tv, rows).is_none());
+  }
+
+  /// Returns the rows this ty var must have, if it is a record ty var.
+  pub fn get_record(&self, tv: &TyVar) -> Option<&Located<BTreeMap<Label, Ty>>> {
+    self.record.get(tv)
   }
 
   /// Solve all overloaded ty vars, which have not already be solved to be some concrete type, to be
   /// their default concrete types.
   pub fn use_overloaded_defaults(&mut self) {
     let overload = std::mem::take(&mut self.overload);
-    for (tv, syms) in overload {
-      let ty = Ty::base(*syms.first().unwrap());
+    for (tv, ov) in overload {
+      let ty = Ty::base(*ov.syms.first().unwrap());
       self.insert(tv, ty);
     }
   }
 
+  /// Solve the overloaded ty var, which has not already been solved to be some concrete type, to be
+  /// its default concrete type.
+  pub fn use_overloaded_default(&mut self, tv: &TyVar) {
+    let ov = self.overload.remove(tv).unwrap();
+    let ty = Ty::base(*ov.syms.first().unwrap());
+    self.insert(*tv, ty);
+  }
+
   /// Returns whether this is an overloaded ty var.
   pub fn is_overloaded(&mut self, tv: &TyVar) -> bool {
     self.overload.contains_key(&tv)
@@ -344,10 +703,12 @@ impl Subst {
   /// the information contained by this new mapping.
   pub fn insert(&mut self, tv: TyVar, ty: Ty) {
     assert!(!self.overload.contains_key(&tv));
+    assert!(!self.record.contains_key(&tv));
     assert!(!self.bound.contains(&tv));
     let subst = Self {
       regular: hashmap![tv => ty.clone()],
       overload: hashmap![],
+      record: hashmap![],
       bound: hashset![],
     };
     for other in self.regular.values_mut() {
@@ -395,14 +756,43 @@ impl Subst {
       }
       (Ty::Record(rows_want), Ty::Record(mut rows_got)) => {
         if !eq_iter(rows_want.keys(), rows_got.keys()) {
-          return Err(loc.wrap(Error::TyMismatch(
-            Ty::Record(rows_want),
-            Ty::Record(rows_got),
+          // for non-tuple records, only report which labels differ, since the whole types may be
+          // large. tuples and unit are better shown whole.
+          if rows_want.is_empty()
+            || rows_got.is_empty()
+            || is_tuple(&rows_want)
+            || is_tuple(&rows_got)
+          {
+            return Err(loc.wrap(Error::TyMismatch(
+              Ty::Record(rows_want),
+              Ty::Record(rows_got),
+            )));
+          }
+          let missing = rows_want.keys().filter(|lab| !rows_got.contains_key(lab));
+          let extra = rows_got.keys().filter(|lab| !rows_want.contains_key(lab));
+          return Err(loc.wrap(Error::RecordLabelMismatch(
+            missing.copied().collect(),
+            extra.copied().collect(),
           )));
         }
+        let tuple = is_tuple(&rows_want);
         for (lab, want) in rows_want {
           let got = rows_got.remove(&lab).unwrap();
-          self.unify(loc, tys, want, got)?;
+          if let Err(e) = self.unify(loc, tys, want, got) {
+            if tuple {
+              return Err(e);
+            }
+            // note which field the mismatch was in, so as to not have to show the whole types.
+            let val = match e.val {
+              Error::TyMismatch(want, got) => Error::FieldTyMismatch(vec![lab], want, got),
+              Error::FieldTyMismatch(mut path, want, got) => {
+                path.insert(0, lab);
+                Error::FieldTyMismatch(path, want, got)
+              }
+              val => val,
+            };
+            return Err(e.loc.wrap(val));
+          }
         }
         Ok(())
       }
@@ -432,35 +822,151 @@ impl Subst {
 
   /// A helper for `unify`, which inserts the tv => ty mapping iff tv != ty and tv not in ty.
   /// Requires that `tv` not be bound.
-  fn bind(&mut self, loc: Loc, tys: &Tys, tv: TyVar, ty: Ty) -> Result<()> {
+  fn bind(&mut self, loc: Loc, tys: &Tys, tv: TyVar, mut ty: Ty) -> Result<()> {
     if ty.free_ty_vars().contains(&tv) {
       return Err(loc.wrap(Error::Circularity(tv, ty)));
     }
     // here's the single solitary reason we have to pass a `Tys` all the way down here.
-    if tv.equality && !ty.is_equality(tys) {
-      return Err(loc.wrap(Error::NotEquality(ty)));
-    }
-    if let Some(syms) = self.overload.remove(&tv) {
-      let syms = match &ty {
-        Ty::Var(other) => {
-          self.overload.insert(*other, syms);
-          None
+    if tv.equality {
+      if !self.make_equality(tys, &ty) {
+        return Err(loc.wrap(Error::NotEquality(ty)));
+      }
+      ty.apply(self);
+    }
+    if let Some(ov) = self.overload.remove(&tv) {
+      let ok = match &ty {
+        // a bound ty var may not be solved to be any of the overloaded types.
+        Ty::Var(other) if self.is_bound(other) => false,
+        Ty::Var(other) => match self.overload.get_mut(other) {
+          None => {
+            self.overload.insert(*other, ov.clone());
+            true
+          }
+          Some(other_ov) => {
+            let both: Vec<_> = other_ov
+              .syms
+              .iter()
+              .copied()
+              .filter(|x| ov.syms.contains(x))
+              .collect();
+            if both.is_empty() {
+              false
+            } else {
+              other_ov.syms = both;
+              true
+            }
+          }
+        },
+        Ty::Record(_) | Ty::Arrow(_, _) => false,
+        Ty::Ctor(args, sym) => args.is_empty() && ov.syms.iter().any(|x| x == sym),
+      };
+      if !ok {
+        // keep the constraint, so the ty var still gets its default if nothing else solves it.
+        let err = Error::OverloadTyMismatch(ov.name, ov.syms.clone(), ty);
+        self.overload.insert(tv, ov);
+        return Err(loc.wrap(err));
+      }
+    }
+    let record = self.record.remove(&tv);
+    self.insert(tv, ty.clone());
+    match record {
+      None => Ok(()),
+      Some(rows) => self.bind_record(loc, tys, rows, ty),
+    }
+  }
+
+  /// A helper for `bind`, which returns whether `ty` admits equality once every type variable in it
+  /// which may be substituted is substituted for an equality type variable, and updates self to do
+  /// those substitutions. Requires that `ty` have had self applied to it.
+  fn make_equality(&mut self, tys: &Tys, ty: &Ty) -> bool {
+    match ty {
+      Ty::Var(tv) => {
+        if let Some(ty) = self.regular.get(tv) {
+          // an earlier part of `ty` already caused this to be substituted.
+          let ty = ty.clone();
+          return self.make_equality(tys, &ty);
         }
-        Ty::Record(_) | Ty::Arrow(_, _) => Some(syms),
-        Ty::Ctor(args, sym) => {
-          if args.is_empty() && syms.iter().any(|x| x == sym) {
-            None
-          } else {
-            Some(syms)
+        if tv.equality {
+          return true;
+        }
+        if self.is_bound(tv) {
+          return false;
+        }
+        // this is the only place we make a ty var without `State#new_ty_var`. it is fresh since
+        // `tv` is not an equality ty var, and we substitute `tv` away right after.
+        let new = TyVar {
+          id: tv.id,
+          equality: true,
+        };
+        if let Some(mut ov) = self.overload.remove(tv) {
+          ov.syms.retain(|x| tys.get(x).equality);
+          if ov.syms.is_empty() {
+            return false;
           }
+          self.overload.insert(new, ov);
         }
-      };
-      if let Some(syms) = syms {
-        return Err(loc.wrap(Error::OverloadTyMismatch(syms, ty)));
+        if let Some(rows) = self.record.remove(tv) {
+          self.record.insert(new, rows);
+        }
+        self.insert(*tv, Ty::Var(new));
+        true
+      }
+      Ty::Record(rows) => rows.values().all(|ty| self.make_equality(tys, ty)),
+      Ty::Arrow(_, _) => false,
+      Ty::Ctor(args, sym) => {
+        sym.is_mutable()
+          || (tys.get(sym).equality && args.iter().all(|ty| self.make_equality(tys, ty)))
       }
     }
-    self.insert(tv, ty);
-    Ok(())
+  }
+
+  /// A helper for `bind`, which checks that `ty` has at least the rows `want`, which were the rows of
+  /// a record ty var just substituted for `ty`.
+  fn bind_record(
+    &mut self,
+    loc: Loc,
+    tys: &Tys,
+    want: Located<BTreeMap<Label, Ty>>,
+    ty: Ty,
+  ) -> Result<()> {
+    match ty {
+      Ty::Var(other) => {
+        if self.is_bound(&other) || self.is_overloaded(&other) {
+          return Err(loc.wrap(Error::NotRecordTy(ty)));
+        }
+        let mut have = match self.record.remove(&other) {
+          None => {
+            self.record.insert(other, want);
+            return Ok(());
+          }
+          Some(x) => x,
+        };
+        let mut to_unify = Vec::new();
+        for (lab, want) in want.val {
+          match have.val.get(&lab) {
+            None => {
+              have.val.insert(lab, want);
+            }
+            Some(got) => to_unify.push((want, got.clone())),
+          }
+        }
+        self.record.insert(other, have);
+        for (want, got) in to_unify {
+          self.unify(loc, tys, want, got)?;
+        }
+        Ok(())
+      }
+      Ty::Record(rows) => {
+        for (lab, want) in want.val {
+          match rows.get(&lab) {
+            None => return Err(loc.wrap(Error::MissingLabel(lab, Ty::Record(rows)))),
+            Some(got) => self.unify(loc, tys, want, got.clone())?,
+          }
+        }
+        Ok(())
+      }
+      Ty::Arrow(..) | Ty::Ctor(..) => Err(loc.wrap(Error::NotRecordTy(ty))),
+    }
   }
 }
 
@@ -485,6 +991,12 @@ impl Sym {
     self.id.is_none()
   }
 
+  /// Returns whether this is the type name of a mutable type. These types admit equality no matter
+  /// their type arguments, since their values are compared by identity.
+  pub fn is_mutable(&self) -> bool {
+    *self == Self::REF || *self == Self::ARRAY
+  }
+
   /// Returns the underlying name of this Sym.
   pub fn name(&self) -> StrRef {
     self.name
@@ -497,9 +1009,12 @@ impl Sym {
   pub const WORD: Self = Self::base(StrRef::WORD);
   pub const INT: Self = Self::base(StrRef::INT);
   pub const REAL: Self = Self::base(StrRef::REAL);
-  pub const ORDER: Self = Self::base(StrRef::ORDER);
   pub const LIST: Self = Self::base(StrRef::LIST);
   pub const REF: Self = Self::base(StrRef::REF);
+  pub const VECTOR: Self = Self::base(StrRef::VECTOR);
+  pub const WORD8: Self = Self::base(StrRef::WORD8);
+  pub const ARRAY: Self = Self::base(StrRef::ARRAY);
+  pub const INT_INF: Self = Self::base(StrRef::INT_INF);
   pub const UNIT: Self = Self::base(StrRef::UNIT);
 }
 
@@ -522,11 +1037,21 @@ impl Ty {
     Self::Ctor(Vec::new(), sym)
   }
 
+  /// Returns `unit`, the empty record type.
+  pub fn unit() -> Self {
+    Self::Record(BTreeMap::new())
+  }
+
   /// Given `t`, returns `t list`.
   pub fn list(elem: Self) -> Self {
     Self::Ctor(vec![elem], Sym::LIST)
   }
 
+  /// Given `t`, returns `t vector`.
+  pub fn vector(elem: Self) -> Self {
+    Self::Ctor(vec![elem], Sym::VECTOR)
+  }
+
   /// Given `t` and `u`, returns `t * u`.
   pub fn pair(lhs: Self, rhs: Self) -> Self {
     Self::Record(btreemap![Label::Num(1) => lhs, Label::Num(2) => rhs])
@@ -584,12 +1109,20 @@ impl Ty {
 
   /// Returns whether this is an equality type.
   pub fn is_equality(&self, tys: &Tys) -> bool {
+    self.is_equality_with(tys, &[])
+  }
+
+  /// Returns whether this is an equality type, assuming that the type variables in `ty_vars` are
+  /// equality type variables. This is useful when determining whether a type function respects
+  /// equality, since e.g. `'a list` admits equality whenever `'a` does.
+  pub fn is_equality_with(&self, tys: &Tys, ty_vars: &[TyVar]) -> bool {
     match self {
-      Self::Var(tv) => tv.equality,
-      Self::Record(rows) => rows.values().all(|ty| ty.is_equality(tys)),
+      Self::Var(tv) => tv.equality || ty_vars.contains(tv),
+      Self::Record(rows) => rows.values().all(|ty| ty.is_equality_with(tys, ty_vars)),
       Self::Arrow(_, _) => false,
       Self::Ctor(args, sym) => {
-        *sym == Sym::REF || (tys.get(sym).equality && args.iter().all(|ty| ty.is_equality(tys)))
+        sym.is_mutable()
+          || (tys.get(sym).equality && args.iter().all(|ty| ty.is_equality_with(tys, ty_vars)))
       }
     }
   }
@@ -599,13 +1132,14 @@ impl Ty {
   pub const BOOL: Self = Self::base(Sym::BOOL);
   pub const STRING: Self = Self::base(Sym::STRING);
   pub const WORD: Self = Self::base(Sym::WORD);
+  pub const WORD8: Self = Self::base(Sym::WORD8);
+  pub const INT_INF: Self = Self::base(Sym::INT_INF);
   pub const INT: Self = Self::base(Sym::INT);
   pub const REAL: Self = Self::base(Sym::REAL);
-  pub const ORDER: Self = Self::base(Sym::ORDER);
 }
 
 /// A type scheme, a 'forall' type.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct TyScheme {
   pub ty_vars: Vec<TyVar>,
   pub ty: Ty,
@@ -665,7 +1199,7 @@ impl TyScheme {
 pub type TyFcn = TyScheme;
 
 /// Information about a type. TyStr from the Definition.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct TyInfo {
   pub ty_fcn: TyFcn,
   /// NOTE I think this is empty iff this is a special type (int, word, etc) or a `type t` in a
@@ -679,7 +1213,7 @@ pub struct TyInfo {
 }
 
 /// A collection of symbol types.
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct Tys {
   inner: HashMap<Sym, TyInfo>,
 }
@@ -692,23 +1226,32 @@ impl Tys {
 
   /// Inserts a datatype under construction into this.
   pub fn insert_datatype(&mut self, sym: Sym, ty_fcn: TyFcn) {
-    // we don't yet know whether this new type respects equality so just baldly assert that does
-    // not. also we haven't analyzed the `ConBind`s yet, so the `ValEnv` is empty.
+    // we don't yet know whether this new type respects equality, so optimistically assume that it
+    // does. see `remove_equality`. also we haven't analyzed the `ConBind`s yet, so the `ValEnv` is
+    // empty.
     let ty_info = TyInfo {
       ty_fcn,
       val_env: ValEnv::new(),
-      equality: false,
+      equality: true,
     };
     assert!(self.inner.insert(sym, ty_info).is_none());
   }
 
   /// Finishes a datatype under construction.
-  pub fn finish_datatype(&mut self, sym: &Sym, val_env: ValEnv, equality: bool) {
+  pub fn finish_datatype(&mut self, sym: &Sym, val_env: ValEnv) {
     let info = self.inner.get_mut(&sym).unwrap();
     assert!(info.val_env.is_empty());
-    assert!(!info.equality);
     info.val_env = val_env;
-    info.equality = equality;
+  }
+
+  /// Marks a datatype under construction as not respecting equality. Since the datatypes in a
+  /// single declaration may refer to each other, we first assume they all respect equality and then
+  /// remove equality from those that don't until nothing changes, which gives the maximal
+  /// assignment of equality as required by the Definition.
+  pub fn remove_equality(&mut self, sym: &Sym) {
+    let info = self.inner.get_mut(sym).unwrap();
+    assert!(info.equality);
+    info.equality = false;
   }
 
   /// Returns the `TyInfo` referred to by the `Sym`.
@@ -720,13 +1263,27 @@ impl Tys {
   pub fn contains_key(&self, sym: &Sym) -> bool {
     self.inner.contains_key(sym)
   }
+
+  /// Forgets where the constructors of every datatype in this were bound.
+  pub fn forget_locs(&mut self) {
+    for ty_info in self.inner.values_mut() {
+      forget_locs(&mut ty_info.val_env);
+    }
+  }
+}
+
+/// Forgets where every value in `val_env` was bound.
+fn forget_locs(val_env: &mut ValEnv) {
+  for val_info in val_env.values_mut() {
+    val_info.loc = None;
+  }
 }
 
 /// A structure environment.
 pub type StrEnv = BTreeMap<StrRef, Env>;
 
 /// A type environment.
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct TyEnv {
   pub inner: BTreeMap<StrRef, Sym>,
 }
@@ -785,12 +1342,16 @@ impl fmt::Display for IdStatus {
 }
 
 /// Information about a value.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ValInfo {
   /// Its type scheme.
   pub ty_scheme: TyScheme,
   /// Its identifier status.
   pub id_status: IdStatus,
+  /// If it is a variable bound in a pattern, the ID of that variable and where it was bound.
+  pub def: Option<Located<usize>>,
+  /// Where it was bound, if it was bound in the program being checked.
+  pub loc: Option<Loc>,
 }
 
 impl ValInfo {
@@ -799,6 +1360,8 @@ impl ValInfo {
     Self {
       ty_scheme,
       id_status: IdStatus::Ctor,
+      def: None,
+      loc: None,
     }
   }
 
@@ -807,6 +1370,8 @@ impl ValInfo {
     Self {
       ty_scheme: TyScheme::mono(Ty::EXN),
       id_status: IdStatus::Exn,
+      def: None,
+      loc: None,
     }
   }
 
@@ -817,6 +1382,8 @@ impl ValInfo {
     Self {
       ty_scheme: TyScheme::mono(Ty::Arrow(ty.into(), Ty::EXN.into())),
       id_status: IdStatus::Exn,
+      def: None,
+      loc: None,
     }
   }
 
@@ -825,16 +1392,127 @@ impl ValInfo {
     Self {
       ty_scheme,
       id_status: IdStatus::Val,
+      def: None,
+      loc: None,
     }
   }
+
+  /// Returns a new `ValInfo` for the variable `def` of type `ty` bound in a pattern.
+  pub fn pat_var(ty: Ty, def: Located<usize>) -> Self {
+    Self {
+      ty_scheme: TyScheme::mono(ty),
+      id_status: IdStatus::Val,
+      loc: Some(def.loc),
+      def: Some(def),
+    }
+  }
+
+  /// Returns this, noting that it was bound at `loc`.
+  pub fn bound_at(mut self, loc: Loc) -> Self {
+    self.loc = Some(loc);
+    self
+  }
 }
 
 /// An environment of values.
 pub type ValEnv = BTreeMap<StrRef, ValInfo>;
 
+/// An item in an `Env`, as reported by `Env::members`.
+#[derive(Debug)]
+pub struct Member {
+  /// Its name.
+  pub name: String,
+  /// What kind of item it is.
+  pub kind: MemberKind,
+  /// A human-readable description, like `val length : 'a list -> int`.
+  pub detail: String,
+}
+
+impl Member {
+  fn new(name: String, kind: MemberKind, detail: String) -> Self {
+    Self { name, kind, detail }
+  }
+}
+
+/// A kind of `Member`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemberKind {
+  /// A structure.
+  Struct,
+  /// A type.
+  Ty,
+  /// A value.
+  Val,
+  /// A constructor.
+  Ctor,
+  /// An exception.
+  Exn,
+  /// A signature.
+  Sig,
+}
+
+/// Information about an expression or pattern, recorded for tools like the language server.
+#[derive(Debug, Clone)]
+pub struct Info {
+  /// If it is an identifier, its name and identifier status.
+  pub id: Option<(StrRef, IdStatus)>,
+  /// If it is an identifier, where that identifier was bound, if known.
+  pub def: Option<Loc>,
+  /// Its type, to which the `Subst` may not yet have been applied.
+  pub ty: Ty,
+}
+
+impl Info {
+  /// Returns a new `Info` for a non-identifier of type `ty`.
+  pub fn ty(ty: Ty) -> Self {
+    Self {
+      id: None,
+      def: None,
+      ty,
+    }
+  }
+
+  /// Returns a new `Info` for the identifier `name` with status `id_status` and type `ty`.
+  pub fn id(name: StrRef, id_status: IdStatus, ty: Ty) -> Self {
+    Self {
+      id: Some((name, id_status)),
+      def: None,
+      ty,
+    }
+  }
+
+  /// Returns a new `Info` for the identifier `name` of type `ty`, with the identifier status and
+  /// binding location from `val_info`.
+  pub fn val(name: StrRef, val_info: &ValInfo, ty: Ty) -> Self {
+    Self {
+      id: Some((name, val_info.id_status)),
+      def: val_info.loc,
+      ty,
+    }
+  }
+
+  /// Returns a human-readable description of this, like `val x : int`.
+  pub fn message(&self, store: &StrStore, subst: &Subst) -> String {
+    let mut ty = self.ty.clone();
+    ty.apply(subst);
+    let ty = show_ty(store, &mut TyShow::new(TyLimit::default()), &ty);
+    match self.id {
+      None => ty,
+      Some((name, id_status)) => {
+        let kind = match id_status {
+          IdStatus::Ctor => "constructor",
+          IdStatus::Exn => "exception",
+          IdStatus::Val => "val",
+        };
+        format!("{} {} : {}", kind, store.get(name), ty)
+      }
+    }
+  }
+}
+
 /// An environment. Structures (and therefore the "top-level") and signatures are essentially
 /// represented as this.
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct Env {
   /// The structures defined in this structure.
   pub str_env: StrEnv,
@@ -845,6 +1523,54 @@ pub struct Env {
 }
 
 impl Env {
+  /// Forgets where every value in this, or in a structure in this, was bound.
+  pub fn forget_locs(&mut self) {
+    for env in self.str_env.values_mut() {
+      env.forget_locs();
+    }
+    forget_locs(&mut self.val_env);
+  }
+
+  /// Returns the structures, types, and values in this, each with a human-readable description,
+  /// sorted by name.
+  pub fn members(&self, store: &StrStore, subst: &Subst, tys: &Tys) -> Vec<Member> {
+    let mut ret = Vec::new();
+    for &name in self.str_env.keys() {
+      let name = store.get(name).to_owned();
+      let detail = format!("structure {}", name);
+      ret.push(Member::new(name, MemberKind::Struct, detail));
+    }
+    for (&name, sym) in self.ty_env.inner.iter() {
+      let mut show = TyShow::new(TyLimit::default());
+      let ty_vars: Vec<_> = tys
+        .get(sym)
+        .ty_fcn
+        .ty_vars
+        .iter()
+        .map(|&tv| show.ty_var(tv))
+        .collect();
+      let name = store.get(name).to_owned();
+      let detail = match ty_vars.len() {
+        0 => format!("type {}", name),
+        1 => format!("type {} {}", ty_vars[0], name),
+        _ => format!("type ({}) {}", ty_vars.join(", "), name),
+      };
+      ret.push(Member::new(name, MemberKind::Ty, detail));
+    }
+    for (&name, val_info) in self.val_env.iter() {
+      let info = Info::id(name, val_info.id_status, val_info.ty_scheme.ty.clone());
+      let kind = match val_info.id_status {
+        IdStatus::Ctor => MemberKind::Ctor,
+        IdStatus::Exn => MemberKind::Exn,
+        IdStatus::Val => MemberKind::Val,
+      };
+      let detail = info.message(store, subst);
+      ret.push(Member::new(store.get(name).to_owned(), kind, detail));
+    }
+    ret.sort_by(|a, b| a.name.cmp(&b.name));
+    ret
+  }
+
   /// Extends an environment with another. `other` overwrites `self`.
   pub fn extend(&mut self, other: Self) {
     for (name, env) in other.str_env {
@@ -863,17 +1589,17 @@ impl Env {
   pub fn maybe_extend(&mut self, other: Self, loc: Loc) -> Result<()> {
     for (name, env) in other.str_env {
       if self.str_env.insert(name, env).is_some() {
-        return Err(loc.wrap(Error::Duplicate(Item::Struct, name)));
+        return Err(loc.wrap(Error::Duplicate(Item::Struct, name, None)));
       }
     }
     for (name, sym) in other.ty_env.inner {
       if self.ty_env.inner.insert(name, sym).is_some() {
-        return Err(loc.wrap(Error::Duplicate(Item::Ty, name)));
+        return Err(loc.wrap(Error::Duplicate(Item::Ty, name, None)));
       }
     }
     for (name, val_info) in other.val_env {
       if self.val_env.insert(name, val_info).is_some() {
-        return Err(loc.wrap(Error::Duplicate(Item::Val, name)));
+        return Err(loc.wrap(Error::Duplicate(Item::Val, name, None)));
       }
     }
     Ok(())
@@ -962,7 +1688,7 @@ impl Cx {
 }
 
 /// A signature.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Sig {
   /// The set of type names that this signature binds. The "variables" that are "substituted" when
   /// this signature is matched against an environment (from a structure expression).
@@ -975,7 +1701,7 @@ pub struct Sig {
 /// A functor signature. Note that in the Definition this is TyNameSet x (Env x Sig) but that's
 /// isomorphic. I feel like the latter makes more sense, though there could be something I'm not
 /// understanding.
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct FunSig {
   pub input: Sig,
   pub output: Sig,
@@ -997,6 +1723,34 @@ pub struct Basis {
 }
 
 impl Basis {
+  /// Forgets where every value in this was bound, including the values in signatures and functor
+  /// signatures.
+  pub fn forget_locs(&mut self) {
+    self.env.forget_locs();
+    for sig in self.sig_env.values_mut() {
+      sig.env.forget_locs();
+    }
+    for fun_sig in self.fun_env.values_mut() {
+      fun_sig.input.env.forget_locs();
+      fun_sig.output.env.forget_locs();
+    }
+  }
+
+  /// Returns the signatures in this, in order by name.
+  pub fn sig_members(&self, store: &StrStore) -> Vec<Member> {
+    let mut ret: Vec<_> = self
+      .sig_env
+      .keys()
+      .map(|&name| {
+        let name = store.get(name).to_owned();
+        let detail = format!("signature {}", name);
+        Member::new(name, MemberKind::Sig, detail)
+      })
+      .collect();
+    ret.sort_by(|a, b| a.name.cmp(&b.name));
+    ret
+  }
+
   /// Apply a substitution to this.
   pub fn apply(&mut self, subst: &Subst, tys: &mut Tys) {
     for fun_sig in self.fun_env.values_mut() {
@@ -1041,9 +1795,205 @@ impl Basis {
   }
 }
 
+/// Describes a basis, for telling whether checking some declarations again bound the same things.
+/// The symbols and type variables generated since `before` are numbered in the order they appear,
+/// since checking again generates new ones. The locations of values are left out.
+struct Describe<'a> {
+  st: &'a State,
+  before: &'a State,
+  syms: HashMap<Sym, usize>,
+  ty_vars: HashMap<TyVar, usize>,
+  /// The type variables bound by the innermost type scheme being described.
+  bound: Vec<TyVar>,
+  buf: String,
+}
+
+impl Describe<'_> {
+  fn basis(&mut self, bs: &Basis) {
+    self.env(&bs.env);
+    let mut sigs: Vec<_> = bs.sig_env.iter().collect();
+    sigs.sort_by_key(|&(&name, _)| name);
+    for (name, sig) in sigs {
+      self.buf.push_str(&format!("signature {:?} = ", name));
+      self.sig(sig);
+    }
+    let mut funs: Vec<_> = bs.fun_env.iter().collect();
+    funs.sort_by_key(|&(&name, _)| name);
+    for (name, fun_sig) in funs {
+      self.buf.push_str(&format!("functor {:?} : ", name));
+      self.sig(&fun_sig.input);
+      self.buf.push_str(" -> ");
+      self.sig(&fun_sig.output);
+    }
+  }
+
+  fn sig(&mut self, sig: &Sig) {
+    self.buf.push_str("sig ");
+    self.env(&sig.env);
+    // the bound type names appear in the environment, if they are used at all, so they are
+    // numbered by now. sort so that the order of the set doesn't matter.
+    let mut ty_names: Vec<_> = sig
+      .ty_names
+      .iter()
+      .map(|sym| {
+        let start = self.buf.len();
+        self.sym(sym);
+        self.buf.split_off(start)
+      })
+      .collect();
+    ty_names.sort();
+    self.buf.push_str(&ty_names.join(" "));
+    self.buf.push_str(" end ");
+  }
+
+  fn env(&mut self, env: &Env) {
+    for (name, env) in env.str_env.iter() {
+      self.buf.push_str(&format!("structure {:?} = {{ ", name));
+      self.env(env);
+      self.buf.push_str("} ");
+    }
+    for (name, sym) in env.ty_env.inner.iter() {
+      self.buf.push_str(&format!("type {:?} = ", name));
+      self.sym(sym);
+      self.buf.push(' ');
+    }
+    self.val_env(&env.val_env);
+  }
+
+  fn val_env(&mut self, val_env: &ValEnv) {
+    for (name, val_info) in val_env.iter() {
+      self
+        .buf
+        .push_str(&format!("{} {:?} : ", val_info.id_status, name));
+      self.ty_scheme(&val_info.ty_scheme);
+      self.buf.push(' ');
+    }
+  }
+
+  fn ty_scheme(&mut self, ty_scheme: &TyScheme) {
+    let bound = std::mem::replace(&mut self.bound, ty_scheme.ty_vars.clone());
+    self
+      .buf
+      .push_str(&format!("forall {} . ", ty_scheme.ty_vars.len()));
+    self.ty(&ty_scheme.ty);
+    if let Some(syms) = &ty_scheme.overload {
+      self.buf.push_str(" overload");
+      for sym in syms {
+        self.buf.push(' ');
+        self.sym(sym);
+      }
+    }
+    self.bound = bound;
+  }
+
+  fn ty(&mut self, ty: &Ty) {
+    match ty {
+      Ty::Var(tv) => {
+        if !self.bound.contains(tv) {
+          if let Some(ty) = self.st.subst.regular.get(tv) {
+            self.ty(ty);
+            return;
+          }
+        }
+        let equality = if tv.equality { "''" } else { "'" };
+        let name = match self.bound.iter().position(|x| x == tv) {
+          Some(idx) => format!("b{}", idx),
+          None => {
+            if tv.id < self.before.next_ty_var {
+              format!("{}", tv.id)
+            } else {
+              let n = self.ty_vars.len();
+              format!("n{}", self.ty_vars.entry(*tv).or_insert(n))
+            }
+          }
+        };
+        self.buf.push_str(equality);
+        self.buf.push_str(&name);
+      }
+      Ty::Record(rows) => {
+        self.buf.push('{');
+        for (lab, ty) in rows.iter() {
+          self.buf.push_str(&format!("{:?}: ", lab));
+          self.ty(ty);
+          self.buf.push_str(", ");
+        }
+        self.buf.push('}');
+      }
+      Ty::Arrow(lhs, rhs) => {
+        self.buf.push('(');
+        self.ty(lhs);
+        self.buf.push_str(" -> ");
+        self.ty(rhs);
+        self.buf.push(')');
+      }
+      Ty::Ctor(args, sym) => {
+        self.buf.push('(');
+        for arg in args {
+          self.ty(arg);
+          self.buf.push_str(", ");
+        }
+        self.buf.push_str(") ");
+        self.sym(sym);
+      }
+    }
+  }
+
+  /// Describes `sym`. A symbol generated since `before` gets a number, and the first time, a
+  /// description of the type it refers to as well.
+  fn sym(&mut self, sym: &Sym) {
+    if self.before.generated_syms().contains_sym(sym) {
+      self.buf.push_str(&format!("{:?}", sym));
+      return;
+    }
+    if let Some(n) = self.syms.get(sym) {
+      self.buf.push_str(&format!("#{}", n));
+      return;
+    }
+    let n = self.syms.len();
+    self.syms.insert(*sym, n);
+    self.buf.push_str(&format!("#{} {:?}", n, sym.name));
+    let ty_info = match self.st.tys.inner.get(sym) {
+      Some(x) => x,
+      None => return,
+    };
+    let bound = std::mem::take(&mut self.bound);
+    self.buf.push_str(" [");
+    self.ty_scheme(&ty_info.ty_fcn);
+    if ty_info.equality {
+      self.buf.push_str(" eq");
+    }
+    self.buf.push(' ');
+    self.val_env(&ty_info.val_env);
+    self.buf.push(']');
+    self.bound = bound;
+  }
+}
+
+/// A description of what was bound by some declarations. See `Basis::fingerprint`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Fingerprint(String);
+
+impl Basis {
+  /// Returns a fingerprint of this, after checking some declarations with `st`, which was `before`
+  /// beforehand. Checking the same declarations after `before` again gives an equal fingerprint,
+  /// even if they moved within the file.
+  pub fn fingerprint(&self, st: &State, before: &State) -> Fingerprint {
+    let mut d = Describe {
+      st,
+      before,
+      syms: HashMap::new(),
+      ty_vars: HashMap::new(),
+      bound: Vec::new(),
+      buf: String::new(),
+    };
+    d.basis(self);
+    Fingerprint(d.buf)
+  }
+}
+
 /// The state passed around by many of the statics functions. There's only one of these, and it's
 /// constantly being mutably, additively updated as we go.
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct State {
   /// The next type variable ID to hand out. Invariant: Always increases.
   next_ty_var: usize,
@@ -1054,6 +2004,22 @@ pub struct State {
   /// The types that 'have been generated' and information about them. Invariant: Always grows in
   /// size.
   pub tys: Tys,
+  /// The next pattern variable ID to hand out. Invariant: Always increases.
+  next_pat_var: usize,
+  /// The IDs of the pattern variables which have been used so far.
+  pub used: HashSet<usize>,
+  /// Information about the expressions and patterns checked so far, keyed by their locations.
+  pub info: HashMap<Loc, Info>,
+  /// For each identifier bound by a declaration checked so far, the location of the innermost
+  /// declaration binding it, keyed by where the identifier was bound.
+  pub decs: HashMap<Loc, Loc>,
+  /// The errors encountered so far which did not stop the checking of the rest of the program,
+  /// including all warnings.
+  pub errors: Vec<Located<Error>>,
+  /// The word constants too big to be a `word8`, checked so far in the current top-level
+  /// declaration, with the type variables which are their types. Once those are known, the
+  /// constants are checked to fit in them.
+  pub word_constants: Vec<(Located<u32>, TyVar)>,
 }
 
 impl State {
@@ -1071,6 +2037,19 @@ impl State {
     Sym { id, name: name.val }
   }
 
+  /// Returns a fresh pattern variable ID, for a variable bound at `loc`.
+  pub fn new_pat_var(&mut self, loc: Loc) -> Located<usize> {
+    let id = self.next_pat_var;
+    self.next_pat_var += 1;
+    loc.wrap(id)
+  }
+
+  /// Returns the pattern variable ID that will next be handed out. All IDs handed out after this
+  /// call will be at least this.
+  pub fn next_pat_var(&self) -> usize {
+    self.next_pat_var
+  }
+
   /// Returns an opaque type that contains information about what symbols have been generated.
   pub fn generated_syms(&self) -> GeneratedSyms {
     GeneratedSyms {
@@ -1092,17 +2071,589 @@ pub struct GeneratedSyms {
 impl GeneratedSyms {
   /// Returns whether all symbols in `ty_names` were generated before this `GeneratedSyms` was.
   pub fn contains(&self, ty_names: &TyNameSet) -> bool {
-    for sym in ty_names {
-      match sym.id {
-        None => continue,
-        Some(id) => {
-          if id.val >= self.next_sym {
-            return false;
-          }
-        }
+    ty_names.iter().all(|sym| self.contains_sym(sym))
+  }
+
+  /// Returns whether `sym` was generated before this `GeneratedSyms` was.
+  pub fn contains_sym(&self, sym: &Sym) -> bool {
+    match sym.id {
+      None => true,
+      Some(id) => id.val < self.next_sym,
+    }
+  }
+}
+
+// The encodings of the statics, for keeping them on disk. See `codec`.
+
+impl Encode for TyVar {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.id.encode(e);
+    self.equality.encode(e);
+  }
+}
+
+impl Decode for TyVar {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let id = usize::decode(d)?;
+    let equality = bool::decode(d)?;
+    Some(Self { id, equality })
+  }
+}
+
+impl Encode for Sym {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.name.encode(e);
+    self.id.encode(e);
+  }
+}
+
+impl Decode for Sym {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let name = StrRef::decode(d)?;
+    let id = Option::decode(d)?;
+    Some(Self { name, id })
+  }
+}
+
+impl Encode for Ty {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    match self {
+      Self::Var(tv) => {
+        e.byte(0);
+        tv.encode(e);
+      }
+      Self::Record(rows) => {
+        e.byte(1);
+        rows.encode(e);
+      }
+      Self::Arrow(arg, res) => {
+        e.byte(2);
+        arg.encode(e);
+        res.encode(e);
+      }
+      Self::Ctor(args, sym) => {
+        e.byte(3);
+        args.encode(e);
+        sym.encode(e);
+      }
+    }
+  }
+}
+
+impl Decode for Ty {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let ret = match d.byte()? {
+      0 => Self::Var(TyVar::decode(d)?),
+      1 => Self::Record(BTreeMap::decode(d)?),
+      2 => {
+        let arg = Box::decode(d)?;
+        Self::Arrow(arg, Box::decode(d)?)
+      }
+      3 => {
+        let args = Vec::decode(d)?;
+        Self::Ctor(args, Sym::decode(d)?)
+      }
+      _ => return None,
+    };
+    Some(ret)
+  }
+}
+
+impl Encode for TyScheme {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.ty_vars.encode(e);
+    self.ty.encode(e);
+    self.overload.encode(e);
+  }
+}
+
+impl Decode for TyScheme {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let ty_vars = Vec::decode(d)?;
+    let ty = Ty::decode(d)?;
+    let overload = Option::decode(d)?;
+    Some(Self {
+      ty_vars,
+      ty,
+      overload,
+    })
+  }
+}
+
+impl Encode for TyInfo {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.ty_fcn.encode(e);
+    self.val_env.encode(e);
+    self.equality.encode(e);
+  }
+}
+
+impl Decode for TyInfo {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let ty_fcn = TyScheme::decode(d)?;
+    let val_env = ValEnv::decode(d)?;
+    let equality = bool::decode(d)?;
+    Some(Self {
+      ty_fcn,
+      val_env,
+      equality,
+    })
+  }
+}
+
+impl Encode for IdStatus {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    e.byte(match self {
+      Self::Ctor => 0,
+      Self::Exn => 1,
+      Self::Val => 2,
+    });
+  }
+}
+
+impl Decode for IdStatus {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    match d.byte()? {
+      0 => Some(Self::Ctor),
+      1 => Some(Self::Exn),
+      2 => Some(Self::Val),
+      _ => None,
+    }
+  }
+}
+
+impl Encode for ValInfo {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.ty_scheme.encode(e);
+    self.id_status.encode(e);
+    self.def.encode(e);
+    self.loc.encode(e);
+  }
+}
+
+impl Decode for ValInfo {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let ty_scheme = TyScheme::decode(d)?;
+    let id_status = IdStatus::decode(d)?;
+    let def = Option::decode(d)?;
+    let loc = Option::decode(d)?;
+    Some(Self {
+      ty_scheme,
+      id_status,
+      def,
+      loc,
+    })
+  }
+}
+
+impl Encode for Env {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.str_env.encode(e);
+    self.ty_env.inner.encode(e);
+    self.val_env.encode(e);
+  }
+}
+
+impl Decode for Env {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let str_env = StrEnv::decode(d)?;
+    let ty_env = TyEnv {
+      inner: BTreeMap::decode(d)?,
+    };
+    let val_env = ValEnv::decode(d)?;
+    Some(Self {
+      str_env,
+      ty_env,
+      val_env,
+    })
+  }
+}
+
+impl Encode for Sig {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.ty_names.encode(e);
+    self.env.encode(e);
+  }
+}
+
+impl Decode for Sig {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let ty_names = TyNameSet::decode(d)?;
+    let env = Env::decode(d)?;
+    Some(Self { ty_names, env })
+  }
+}
+
+impl Encode for FunSig {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.input.encode(e);
+    self.output.encode(e);
+  }
+}
+
+impl Decode for FunSig {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let input = Sig::decode(d)?;
+    let output = Sig::decode(d)?;
+    Some(Self { input, output })
+  }
+}
+
+impl Encode for Overload {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.name.encode(e);
+    self.syms.encode(e);
+  }
+}
+
+impl Decode for Overload {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let name = StrRef::decode(d)?;
+    let syms = Vec::decode(d)?;
+    Some(Self { name, syms })
+  }
+}
+
+impl Encode for Item {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    e.byte(match self {
+      Self::Val => 0,
+      Self::Ty => 1,
+      Self::TyVar => 2,
+      Self::Struct => 3,
+      Self::Sig => 4,
+      Self::Functor => 5,
+    });
+  }
+}
+
+impl Decode for Item {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    match d.byte()? {
+      0 => Some(Self::Val),
+      1 => Some(Self::Ty),
+      2 => Some(Self::TyVar),
+      3 => Some(Self::Struct),
+      4 => Some(Self::Sig),
+      5 => Some(Self::Functor),
+      _ => None,
+    }
+  }
+}
+
+impl Encode for Fingerprint {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    self.0.encode(e);
+  }
+}
+
+impl Decode for Fingerprint {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    String::decode(d).map(Self)
+  }
+}
+
+/// The messages of the `Error::Todo`s, which are encoded as their index in this.
+const TODOS: [&str; 3] = ["`functor` specifications", "`sharing`", "bad free ty var"];
+
+impl Encode for Error {
+  fn encode(&self, e: &mut Encoder<'_>) {
+    match self {
+      Self::Undefined(item, name) => {
+        e.byte(0);
+        item.encode(e);
+        name.encode(e);
+      }
+      Self::Duplicate(item, name, loc) => {
+        e.byte(1);
+        item.encode(e);
+        name.encode(e);
+        loc.encode(e);
+      }
+      Self::DuplicateLabel(lab, loc) => {
+        e.byte(2);
+        lab.encode(e);
+        loc.encode(e);
+      }
+      Self::Circularity(tv, ty) => {
+        e.byte(3);
+        tv.encode(e);
+        ty.encode(e);
+      }
+      Self::TyMismatch(want, got) => {
+        e.byte(4);
+        want.encode(e);
+        got.encode(e);
+      }
+      Self::FieldTyMismatch(path, want, got) => {
+        e.byte(5);
+        path.encode(e);
+        want.encode(e);
+        got.encode(e);
+      }
+      Self::RecordLabelMismatch(want, got) => {
+        e.byte(6);
+        want.encode(e);
+        got.encode(e);
+      }
+      Self::OverloadTyMismatch(name, syms, ty) => {
+        e.byte(7);
+        name.encode(e);
+        syms.encode(e);
+        ty.encode(e);
+      }
+      Self::PatWrongIdStatus => e.byte(8),
+      Self::ExnWrongIdStatus(id_status) => {
+        e.byte(9);
+        id_status.encode(e);
+      }
+      Self::WrongNumTyArgs(want, got) => {
+        e.byte(10);
+        want.encode(e);
+        got.encode(e);
+      }
+      Self::NonVarInAs(name) => {
+        e.byte(11);
+        name.encode(e);
+      }
+      Self::ForbiddenBinding(name) => {
+        e.byte(12);
+        name.encode(e);
+      }
+      Self::TyNameEscape => e.byte(13),
+      Self::NonExhaustiveMatch => e.byte(14),
+      Self::NonExhaustiveBinding => e.byte(15),
+      Self::UnreachablePattern => e.byte(16),
+      Self::FunDecNameMismatch(want, got, loc) => {
+        e.byte(17);
+        want.encode(e);
+        got.encode(e);
+        loc.encode(e);
+      }
+      Self::FunDecWrongNumPats(want, got) => {
+        e.byte(18);
+        want.encode(e);
+        got.encode(e);
+      }
+      Self::PatNotConsTy(ty) => {
+        e.byte(19);
+        ty.encode(e);
+      }
+      Self::PatNotArrowTy(ty) => {
+        e.byte(20);
+        ty.encode(e);
+      }
+      Self::DatatypeCopyNotDatatype => e.byte(21),
+      Self::NotEquality(ty) => {
+        e.byte(22);
+        ty.encode(e);
+      }
+      Self::NotArrowTy(ty) => {
+        e.byte(23);
+        ty.encode(e);
+      }
+      Self::IdStatusMismatch(want, got) => {
+        e.byte(24);
+        want.encode(e);
+        got.encode(e);
+      }
+      Self::ValEnvMismatch(want, got) => {
+        e.byte(25);
+        want.encode(e);
+        got.encode(e);
+      }
+      Self::TyVarInScope(name) => {
+        e.byte(26);
+        name.encode(e);
+      }
+      Self::TyVarEscape(name) => {
+        e.byte(27);
+        name.encode(e);
+      }
+      Self::NotFlexible(name) => {
+        e.byte(28);
+        name.encode(e);
+      }
+      Self::NotRecordTy(ty) => {
+        e.byte(29);
+        ty.encode(e);
+      }
+      Self::MissingLabel(lab, ty) => {
+        e.byte(30);
+        lab.encode(e);
+        ty.encode(e);
+      }
+      Self::UnresolvedRecordTy(rows) => {
+        e.byte(31);
+        rows.encode(e);
+      }
+      Self::Unused(name) => {
+        e.byte(32);
+        name.encode(e);
+      }
+      Self::RecValNotFn => e.byte(33),
+      Self::UnboundTyVar(name) => {
+        e.byte(34);
+        name.encode(e);
+      }
+      Self::Word8ConstantOutOfRange => e.byte(36),
+      Self::UnresolvedTy(name, ty) => {
+        e.byte(37);
+        name.encode(e);
+        ty.encode(e);
+      }
+      Self::Todo(msg) => {
+        e.byte(35);
+        // a message not in `TODOS` is encoded as one past the end, which doesn't decode.
+        let idx = TODOS.iter().position(|x| x == msg);
+        e.usize(idx.unwrap_or(TODOS.len()));
       }
     }
-    true
+  }
+}
+
+impl Decode for Error {
+  fn decode(d: &mut Decoder<'_>) -> Option<Self> {
+    let ret = match d.byte()? {
+      0 => {
+        let item = Item::decode(d)?;
+        Self::Undefined(item, StrRef::decode(d)?)
+      }
+      1 => {
+        let item = Item::decode(d)?;
+        let name = StrRef::decode(d)?;
+        Self::Duplicate(item, name, Option::decode(d)?)
+      }
+      2 => {
+        let lab = Label::decode(d)?;
+        Self::DuplicateLabel(lab, Loc::decode(d)?)
+      }
+      3 => {
+        let tv = TyVar::decode(d)?;
+        Self::Circularity(tv, Ty::decode(d)?)
+      }
+      4 => {
+        let want = Ty::decode(d)?;
+        Self::TyMismatch(want, Ty::decode(d)?)
+      }
+      5 => {
+        let path = Vec::decode(d)?;
+        let want = Ty::decode(d)?;
+        Self::FieldTyMismatch(path, want, Ty::decode(d)?)
+      }
+      6 => {
+        let want = Vec::decode(d)?;
+        Self::RecordLabelMismatch(want, Vec::decode(d)?)
+      }
+      7 => {
+        let name = StrRef::decode(d)?;
+        let syms = Vec::decode(d)?;
+        Self::OverloadTyMismatch(name, syms, Ty::decode(d)?)
+      }
+      8 => Self::PatWrongIdStatus,
+      9 => Self::ExnWrongIdStatus(IdStatus::decode(d)?),
+      10 => {
+        let want = usize::decode(d)?;
+        Self::WrongNumTyArgs(want, usize::decode(d)?)
+      }
+      11 => Self::NonVarInAs(StrRef::decode(d)?),
+      12 => Self::ForbiddenBinding(StrRef::decode(d)?),
+      13 => Self::TyNameEscape,
+      14 => Self::NonExhaustiveMatch,
+      15 => Self::NonExhaustiveBinding,
+      16 => Self::UnreachablePattern,
+      17 => {
+        let want = StrRef::decode(d)?;
+        let got = StrRef::decode(d)?;
+        Self::FunDecNameMismatch(want, got, Loc::decode(d)?)
+      }
+      18 => {
+        let want = usize::decode(d)?;
+        Self::FunDecWrongNumPats(want, usize::decode(d)?)
+      }
+      19 => Self::PatNotConsTy(Ty::decode(d)?),
+      20 => Self::PatNotArrowTy(Ty::decode(d)?),
+      21 => Self::DatatypeCopyNotDatatype,
+      22 => Self::NotEquality(Ty::decode(d)?),
+      23 => Self::NotArrowTy(Ty::decode(d)?),
+      24 => {
+        let want = IdStatus::decode(d)?;
+        Self::IdStatusMismatch(want, IdStatus::decode(d)?)
+      }
+      25 => {
+        let want = Vec::decode(d)?;
+        Self::ValEnvMismatch(want, Vec::decode(d)?)
+      }
+      26 => Self::TyVarInScope(StrRef::decode(d)?),
+      27 => Self::TyVarEscape(StrRef::decode(d)?),
+      28 => Self::NotFlexible(StrRef::decode(d)?),
+      29 => Self::NotRecordTy(Ty::decode(d)?),
+      30 => {
+        let lab = Label::decode(d)?;
+        Self::MissingLabel(lab, Ty::decode(d)?)
+      }
+      31 => Self::UnresolvedRecordTy(BTreeMap::decode(d)?),
+      32 => Self::Unused(StrRef::decode(d)?),
+      33 => Self::RecValNotFn,
+      34 => Self::UnboundTyVar(StrRef::decode(d)?),
+      35 => Self::Todo(TODOS.get(d.usize()?)?),
+      36 => Self::Word8ConstantOutOfRange,
+      37 => {
+        let name = StrRef::decode(d)?;
+        Self::UnresolvedTy(name, Ty::decode(d)?)
+      }
+      _ => return None,
+    };
+    Some(ret)
+  }
+}
+
+impl Basis {
+  /// Encodes the changes from `before` to this.
+  pub fn encode_delta(&self, before: &Self, e: &mut Encoder<'_>) {
+    encode_map_delta(e, &before.fun_env, &self.fun_env);
+    encode_map_delta(e, &before.sig_env, &self.sig_env);
+    encode_map_delta(e, &before.env.str_env, &self.env.str_env);
+    encode_map_delta(e, &before.env.ty_env.inner, &self.env.ty_env.inner);
+    encode_map_delta(e, &before.env.val_env, &self.env.val_env);
+  }
+
+  /// Applies the changes encoded by `encode_delta` to this.
+  pub fn decode_delta(&mut self, d: &mut Decoder<'_>) -> Option<()> {
+    decode_map_delta(d, &mut self.fun_env)?;
+    decode_map_delta(d, &mut self.sig_env)?;
+    decode_map_delta(d, &mut self.env.str_env)?;
+    decode_map_delta(d, &mut self.env.ty_env.inner)?;
+    decode_map_delta(d, &mut self.env.val_env)
+  }
+}
+
+impl State {
+  /// Encodes the changes from `before` to this, except for the information about locations and the
+  /// errors.
+  pub fn encode_delta(&self, before: &Self, e: &mut Encoder<'_>) {
+    e.usize(self.next_ty_var);
+    e.usize(self.next_sym);
+    e.usize(self.next_pat_var);
+    encode_map_delta(e, &before.subst.regular, &self.subst.regular);
+    encode_map_delta(e, &before.subst.overload, &self.subst.overload);
+    encode_map_delta(e, &before.subst.record, &self.subst.record);
+    encode_set_delta(e, &before.subst.bound, &self.subst.bound);
+    encode_map_delta(e, &before.tys.inner, &self.tys.inner);
+    encode_set_delta(e, &before.used, &self.used);
+  }
+
+  /// Applies the changes encoded by `encode_delta` to this.
+  pub fn decode_delta(&mut self, d: &mut Decoder<'_>) -> Option<()> {
+    self.next_ty_var = d.usize()?;
+    self.next_sym = d.usize()?;
+    self.next_pat_var = d.usize()?;
+    decode_map_delta(d, &mut self.subst.regular)?;
+    decode_map_delta(d, &mut self.subst.overload)?;
+    decode_map_delta(d, &mut self.subst.record)?;
+    decode_set_delta(d, &mut self.subst.bound)?;
+    decode_map_delta(d, &mut self.tys.inner)?;
+    decode_set_delta(d, &mut self.used)
   }
 }
 
@@ -1113,6 +2664,9 @@ pub enum Pat {
   Anything,
   /// Matches a constructor with the given arguments.
   Con(Con, Vec<Pat>),
+  /// Matches a record with at least the given rows, whose type is the given record ty var. This
+  /// should be replaced by a record `Con` with `apply` before checking for exhaustiveness.
+  FlexRecord(TyVar, BTreeMap<Label, Pat>),
 }
 
 impl Pat {
@@ -1130,6 +2684,42 @@ impl Pat {
       Self::Con(Con::Record(pats.len()), pats)
     }
   }
+
+  /// Replaces every `FlexRecord` in this with a record pattern having all the rows of its record
+  /// type as determined by the `Subst`, where the rows not matched by the `FlexRecord` are
+  /// `Anything`. If the record type is not yet known, use the rows known to be in it so far.
+  pub fn apply(&mut self, subst: &Subst) {
+    match self {
+      Self::Anything => {}
+      Self::Con(_, args) => {
+        for arg in args {
+          arg.apply(subst);
+        }
+      }
+      Self::FlexRecord(tv, rows) => {
+        let mut ty = Ty::Var(*tv);
+        ty.apply(subst);
+        let labels: Vec<_> = match &ty {
+          Ty::Record(ty_rows) => ty_rows.keys().copied().collect(),
+          Ty::Var(tv) => match subst.get_record(tv) {
+            None => rows.keys().copied().collect(),
+            Some(ty_rows) => ty_rows.val.keys().copied().collect(),
+          },
+          Ty::Arrow(..) | Ty::Ctor(..) => unreachable!("FlexRecord with non-record type"),
+        };
+        let mut rows = std::mem::take(rows);
+        let pats = labels
+          .into_iter()
+          .map(|lab| {
+            let mut pat = rows.remove(&lab).unwrap_or(Self::Anything);
+            pat.apply(subst);
+            pat
+          })
+          .collect();
+        *self = Self::record(pats);
+      }
+    }
+  }
 }
 
 /// A constructor for a pattern. It is a bit confusing to have both 'Con' and 'Ctor'. We originally
@@ -1138,13 +2728,18 @@ impl Pat {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Con {
   Int(i32),
-  Word(i32),
+  Word(u32),
   String(StrRef),
   Char(u8),
   /// This should never be used directly, use `Pat::record` instead. The usize is the arity.
   Record(usize),
-  /// A constructor from a `datatype` or an `exception`.
+  /// A vector. The usize is the length.
+  Vector(usize),
+  /// A constructor from a `datatype`.
   Ctor(StrRef, Span),
+  /// An exception. Exceptions with qualified names have the `Loc` of the name, so that they are not
+  /// considered to be the same as any other exception.
+  Exn(StrRef, Option<Loc>),
 }
 
 /// A measure of how many constructors exist for a type.
@@ -1158,7 +2753,9 @@ impl Con {
   /// Returns the span of this.
   pub fn span(&self) -> Span {
     match *self {
-      Self::Int(_) | Self::Word(_) | Self::String(_) => Span::PosInf,
+      Self::Int(_) | Self::Word(_) | Self::String(_) | Self::Vector(_) | Self::Exn(..) => {
+        Span::PosInf
+      }
       Self::Char(_) => Span::Finite(256),
       Self::Record(_) => Span::Finite(1),
       Self::Ctor(_, s) => s,
@@ -75,9 +75,10 @@ pub enum Token {
   // special constants
   DecInt(i32, IsNumLab),
   HexInt(i32),
-  DecWord(i32),
-  HexWord(i32),
-  Real(f64),
+  DecWord(u32),
+  HexWord(u32),
+  /// the value and the literal text, which the value does not exactly represent.
+  Real(f64, StrRef),
   String(StrRef),
   Char(u8),
   // identifiers. we can't know the syntax class of most identifiers (VId, TyCon, Lab, StrId)
@@ -280,3 +281,63 @@ pub const OTHER: [(&[u8], Token); 11] = [
   (b"{", Token::LCurly),
   (b"}", Token::RCurly),
 ];
+
+/// What other languages call some keywords. Sorted first by length, then alphabetically.
+const OTHER_LANGUAGES: [(&[u8], Token); 11] = [
+  // 8
+  (b"function", Token::Fun),
+  // 6
+  (b"elseif", Token::Else),
+  (b"lambda", Token::Fn),
+  (b"switch", Token::Case),
+  // 5
+  (b"catch", Token::Handle),
+  (b"elsif", Token::Else),
+  (b"throw", Token::Raise),
+  // 4
+  (b"elif", Token::Else),
+  (b"func", Token::Fun),
+  // 3
+  (b"def", Token::Fun),
+  (b"var", Token::Val),
+];
+
+/// Returns the keyword the alphanumeric identifier `name` was probably meant to be, if any. This is
+/// either what the keyword is called in other languages, or a keyword of at least 5 letters with
+/// one letter added, removed, or changed, or two adjacent letters swapped.
+pub fn near_miss(name: &[u8]) -> Option<Token> {
+  let other = OTHER_LANGUAGES.iter().find(|&&(s, _)| s == name);
+  let typo = || {
+    ALPHA
+      .iter()
+      .find(|&&(s, _)| s.len() >= 5 && s != name && one_edit(s, name))
+  };
+  other.or_else(typo).map(|&(_, tok)| tok)
+}
+
+/// Returns whether `a` and `b` differ by at most one insertion, deletion, substitution, or
+/// transposition of adjacent bytes.
+fn one_edit(a: &[u8], b: &[u8]) -> bool {
+  let (a, b) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+  let pre = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+  let (a, b) = (&a[pre..], &b[pre..]);
+  match b.len() - a.len() {
+    0 => a.len() <= 1 || a[1..] == b[1..] || (a[0] == b[1] && a[1] == b[0] && a[2..] == b[2..]),
+    1 => a == &b[1..],
+    _ => false,
+  }
+}
+
+#[test]
+fn test_near_miss() {
+  assert_eq!(near_miss(b"function"), Some(Token::Fun));
+  assert_eq!(near_miss(b"elsif"), Some(Token::Else));
+  assert_eq!(near_miss(b"strctre"), None);
+  assert_eq!(near_miss(b"strcture"), Some(Token::Structure));
+  assert_eq!(near_miss(b"sturcture"), Some(Token::Structure));
+  assert_eq!(near_miss(b"datatypes"), Some(Token::Datatype));
+  assert_eq!(near_miss(b"exceptoin"), Some(Token::Exception));
+  assert_eq!(near_miss(b"structure"), None);
+  assert_eq!(near_miss(b"vals"), None);
+  assert_eq!(near_miss(b"x"), None);
+}
@@ -0,0 +1,41 @@
+//! Command-line arguments.
+
+pub fn get() -> Result<Option<Args>, String> {
+  let mut args = pico_args::Arguments::from_env();
+  if args.contains(["-h", "--help"]) {
+    print!("{}", include_str!("help.txt"));
+    return Ok(None);
+  }
+  if args.contains(["-v", "--version"]) {
+    println!("{}", env!("CARGO_PKG_VERSION"));
+    return Ok(None);
+  }
+  let port = args
+    .opt_value_from_str("--port")
+    .map_err(|e| e.to_string())?;
+  let socket = args
+    .opt_value_from_str("--socket")
+    .map_err(|e| e.to_string())?;
+  args.finish().map_err(|e| e.to_string())?;
+  let transport = match (port, socket) {
+    (None, None) => Transport::Stdio,
+    (Some(port), None) => Transport::Listen(port),
+    (None, Some(port)) => Transport::Connect(port),
+    (Some(_), Some(_)) => return Err("cannot use both --port and --socket".to_owned()),
+  };
+  Ok(Some(Args { transport }))
+}
+
+pub struct Args {
+  pub transport: Transport,
+}
+
+/// How to talk to the client.
+pub enum Transport {
+  /// Over stdin and stdout.
+  Stdio,
+  /// Over a TCP connection accepted on this port on localhost.
+  Listen(u16),
+  /// Over a TCP connection made to this port on localhost.
+  Connect(u16),
+}
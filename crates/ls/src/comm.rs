@@ -1,18 +1,190 @@
 //! Types for messages to and from the server.
 
+use crate::pos::Encoding;
 use lsp_types::{
-  DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
-  DidSaveTextDocumentParams, InitializeParams, InitializeResult, NumberOrString,
-  PublishDiagnosticsParams,
+  CodeActionParams, CodeActionResponse, CompletionParams, CompletionResponse, Diagnostic,
+  DidChangeConfigurationParams, DidChangeTextDocumentParams, DidChangeWatchedFilesParams,
+  DidCloseTextDocumentParams, DidOpenTextDocumentParams, DidSaveTextDocumentParams,
+  DocumentHighlight, DocumentHighlightParams, DocumentOnTypeFormattingParams, GotoDefinitionParams,
+  GotoDefinitionResponse, Hover, HoverParams, InitializeParams, InitializeResult, NumberOrString,
+  Position, PublishDiagnosticsParams, SelectionRange, SelectionRangeParams, TextEdit, Url,
 };
 use serde::de::DeserializeOwned;
 use serde_json::{from_slice, from_value, json, to_value, to_vec, Error, Map, Value};
+use std::collections::HashMap;
 
 const JSON_RPC_VERSION: &str = "2.0";
 
 pub enum IncomingRequestParams {
-  Initialize(InitializeParams),
+  Initialize(InitializeParams, NewerCapabilities),
   Shutdown,
+  Hover(HoverParams),
+  Completion(CompletionParams),
+  DocumentHighlight(DocumentHighlightParams),
+  Definition(GotoDefinitionParams),
+  SelectionRange(SelectionRangeParams),
+  OnTypeFormatting(DocumentOnTypeFormattingParams),
+  CodeAction(CodeActionParams),
+  /// Our own request, for the contents of a document with a URI of our own scheme.
+  VirtualDocument(Url),
+  ExecuteCommand(Command),
+  DocumentDiagnostic(DocumentDiagnosticParams),
+  WorkspaceDiagnostic(WorkspaceDiagnosticParams),
+  /// The semantic tokens of the whole document.
+  SemanticTokens(Url),
+}
+
+/// A command the client can ask us to execute.
+pub enum Command {
+  /// Show the AST of the file.
+  ShowAst(Url),
+  /// Show the type of the expression at the position in the file.
+  ShowInferredType(Url, Position),
+  /// Check every open file again from scratch.
+  RestartAnalysis,
+}
+
+impl Command {
+  /// The names of the commands.
+  pub const NAMES: [&'static str; 3] = [
+    "millet.showAST",
+    "millet.showInferredType",
+    "millet.restartAnalysis",
+  ];
+
+  fn from_value(val: &Value) -> Option<Self> {
+    let no_args = Vec::new();
+    let args = val
+      .get("arguments")
+      .and_then(Value::as_array)
+      .unwrap_or(&no_args);
+    let uri = || -> Option<Url> { args.first()?.as_str()?.parse().ok() };
+    let ret = match val.get("command")?.as_str()? {
+      "millet.showAST" => Self::ShowAst(uri()?),
+      "millet.showInferredType" => {
+        let pos = from_value(args.get(1)?.clone()).ok()?;
+        Self::ShowInferredType(uri()?, pos)
+      }
+      "millet.restartAnalysis" => Self::RestartAnalysis,
+      _ => return None,
+    };
+    Some(ret)
+  }
+}
+
+/// Client capabilities from LSP 3.17, which is newer than `lsp_types`.
+pub struct NewerCapabilities {
+  /// Whether the client supports pulling diagnostics.
+  pub pull_diagnostics: bool,
+  /// The position encodings the client supports, most preferred first.
+  pub position_encodings: Vec<String>,
+}
+
+impl NewerCapabilities {
+  fn from_value(val: &Value) -> Self {
+    let pull_diagnostics = val.pointer("/textDocument/diagnostic").is_some();
+    let position_encodings = val
+      .pointer("/general/positionEncodings")
+      .and_then(Value::as_array)
+      .map_or_else(Vec::new, |xs| {
+        xs.iter()
+          .filter_map(Value::as_str)
+          .map(str::to_owned)
+          .collect()
+      });
+    Self {
+      pull_diagnostics,
+      position_encodings,
+    }
+  }
+}
+
+/// The semantic token types we report, in the order of their indices in the legend. Semantic tokens
+/// are from LSP 3.16, which is newer than `lsp_types`, so we handle the JSON for them ourselves.
+pub const SEMANTIC_TOKEN_TYPES: [&str; 8] = [
+  "keyword",
+  "number",
+  "string",
+  "typeParameter",
+  "namespace",
+  "variable",
+  "operator",
+  "comment",
+];
+
+// pull diagnostics are from LSP 3.17, which is newer than `lsp_types`, so we handle the JSON for them
+// ourselves.
+
+pub struct DocumentDiagnosticParams {
+  pub uri: Url,
+  pub previous_result_id: Option<String>,
+}
+
+impl DocumentDiagnosticParams {
+  fn from_value(val: &Value) -> Option<Self> {
+    let uri: Url = val
+      .get("textDocument")?
+      .get("uri")?
+      .as_str()?
+      .parse()
+      .ok()?;
+    let previous_result_id = val
+      .get("previousResultId")
+      .and_then(Value::as_str)
+      .map(str::to_owned);
+    Some(Self {
+      uri,
+      previous_result_id,
+    })
+  }
+}
+
+pub struct WorkspaceDiagnosticParams {
+  pub previous_result_ids: HashMap<Url, String>,
+}
+
+impl WorkspaceDiagnosticParams {
+  fn from_value(val: &Value) -> Option<Self> {
+    let mut previous_result_ids = HashMap::new();
+    for prev in val.get("previousResultIds")?.as_array()? {
+      let uri: Url = prev.get("uri")?.as_str()?.parse().ok()?;
+      let value = prev.get("value")?.as_str()?.to_owned();
+      previous_result_ids.insert(uri, value);
+    }
+    Some(Self {
+      previous_result_ids,
+    })
+  }
+}
+
+pub enum DiagnosticReport {
+  Full {
+    result_id: Option<String>,
+    items: Vec<Diagnostic>,
+  },
+  Unchanged {
+    result_id: String,
+  },
+}
+
+impl DiagnosticReport {
+  fn into_value(self) -> Result<Map<String, Value>, Error> {
+    let mut map = Map::with_capacity(3);
+    match self {
+      Self::Full { result_id, items } => {
+        map.insert("kind".to_owned(), "full".into());
+        if let Some(result_id) = result_id {
+          map.insert("resultId".to_owned(), result_id.into());
+        }
+        map.insert("items".to_owned(), to_value(items)?);
+      }
+      Self::Unchanged { result_id } => {
+        map.insert("kind".to_owned(), "unchanged".into());
+        map.insert("resultId".to_owned(), result_id.into());
+      }
+    }
+    Ok(map)
+  }
 }
 
 pub struct Request<Params> {
@@ -33,62 +205,188 @@ pub enum IncomingNotification {
   TextDocChange(DidChangeTextDocumentParams),
   TextDocSave(DidSaveTextDocumentParams),
   TextDocClose(DidCloseTextDocumentParams),
+  ChangeConfiguration(DidChangeConfigurationParams),
+  ChangeWatchedFiles(DidChangeWatchedFilesParams),
 }
 
 pub enum Incoming {
   Request(Request<IncomingRequestParams>),
   Notification(IncomingNotification),
+  /// A message we could not handle, and the error response to send for it.
+  Invalid(Response),
 }
 
 impl Incoming {
-  fn request(id: NumberOrString, params: IncomingRequestParams) -> Self {
-    Self::Request(Request::new(id, params))
-  }
-
+  /// Parses a message. Returns `None` if the message should be ignored, as is the case for unknown
+  /// notifications.
   pub fn try_parse(bs: &[u8]) -> Option<Self> {
-    let mut val: Value = from_slice(bs).ok()?;
-    if val.get("jsonrpc")?.as_str()? != JSON_RPC_VERSION {
-      return None;
+    let mut val: Value = match from_slice(bs) {
+      Ok(x) => x,
+      Err(e) => return Some(Self::invalid(None, ErrorCode::ParseError, e.to_string())),
+    };
+    let id: Option<NumberOrString> = match val.get_mut("id") {
+      None => None,
+      Some(id) => match from_value(std::mem::take(id)) {
+        Ok(x) => Some(x),
+        Err(e) => {
+          return Some(Self::invalid(
+            None,
+            ErrorCode::InvalidRequest,
+            e.to_string(),
+          ))
+        }
+      },
+    };
+    if val.get("jsonrpc").and_then(Value::as_str) != Some(JSON_RPC_VERSION) {
+      let msg = format!("not JSON-RPC {}", JSON_RPC_VERSION);
+      return Some(Self::invalid(id, ErrorCode::InvalidRequest, msg));
     }
-    let ret = match val.get("method")?.as_str()? {
-      "initialize" => Incoming::request(
-        get_id(&mut val)?,
-        IncomingRequestParams::Initialize(get_params(&mut val)?),
-      ),
-      "initialized" => Incoming::Notification(IncomingNotification::Initialized),
-      "shutdown" => Incoming::request(get_id(&mut val)?, IncomingRequestParams::Shutdown),
-      "exit" => Incoming::Notification(IncomingNotification::Exit),
-      "textDocument/didOpen" => {
-        Incoming::Notification(IncomingNotification::TextDocOpen(get_params(&mut val)?))
-      }
-      "textDocument/didClose" => {
-        Incoming::Notification(IncomingNotification::TextDocClose(get_params(&mut val)?))
-      }
-      "textDocument/didChange" => {
-        Incoming::Notification(IncomingNotification::TextDocChange(get_params(&mut val)?))
+    let method = match val.get("method").and_then(Value::as_str) {
+      None => {
+        return Some(Self::invalid(
+          id,
+          ErrorCode::InvalidRequest,
+          "no method".to_owned(),
+        ))
       }
-      "textDocument/didSave" => {
-        Incoming::Notification(IncomingNotification::TextDocSave(get_params(&mut val)?))
-      }
-      _ => return None,
+      Some(x) => x.to_owned(),
+    };
+    let params = val.get_mut("params").map_or(Value::Null, std::mem::take);
+    let ret = match id {
+      None => Self::Notification(parse_notification(&method, params)?),
+      Some(id) => match parse_request(&method, params) {
+        Ok(params) => Self::Request(Request::new(id, params)),
+        Err(e) => Self::Invalid(Response {
+          id: Some(id),
+          res: Err(e),
+        }),
+      },
     };
     Some(ret)
   }
+
+  fn invalid(id: Option<NumberOrString>, code: ErrorCode, message: String) -> Self {
+    Self::Invalid(Response {
+      id,
+      res: Err(ResponseError { code, message }),
+    })
+  }
+}
+
+fn parse_request(method: &str, params: Value) -> Result<IncomingRequestParams, ResponseError> {
+  let ret = match method {
+    "initialize" => {
+      let newer = NewerCapabilities::from_value(params.get("capabilities").unwrap_or(&Value::Null));
+      IncomingRequestParams::Initialize(from_params(params)?, newer)
+    }
+    "shutdown" => IncomingRequestParams::Shutdown,
+    "textDocument/hover" => IncomingRequestParams::Hover(from_params(params)?),
+    "textDocument/completion" => IncomingRequestParams::Completion(from_params(params)?),
+    "textDocument/documentHighlight" => {
+      IncomingRequestParams::DocumentHighlight(from_params(params)?)
+    }
+    "textDocument/definition" => IncomingRequestParams::Definition(from_params(params)?),
+    "textDocument/selectionRange" => IncomingRequestParams::SelectionRange(from_params(params)?),
+    "textDocument/codeAction" => IncomingRequestParams::CodeAction(from_params(params)?),
+    "textDocument/onTypeFormatting" => {
+      IncomingRequestParams::OnTypeFormatting(from_params(params)?)
+    }
+    "millet/virtualDocument" => {
+      let uri = params
+        .get("uri")
+        .and_then(Value::as_str)
+        .and_then(|x| x.parse().ok());
+      match uri {
+        None => return Err(invalid_params()),
+        Some(x) => IncomingRequestParams::VirtualDocument(x),
+      }
+    }
+    "workspace/executeCommand" => match Command::from_value(&params) {
+      None => return Err(invalid_params()),
+      Some(x) => IncomingRequestParams::ExecuteCommand(x),
+    },
+    "textDocument/diagnostic" => match DocumentDiagnosticParams::from_value(&params) {
+      None => return Err(invalid_params()),
+      Some(x) => IncomingRequestParams::DocumentDiagnostic(x),
+    },
+    "workspace/diagnostic" => match WorkspaceDiagnosticParams::from_value(&params) {
+      None => return Err(invalid_params()),
+      Some(x) => IncomingRequestParams::WorkspaceDiagnostic(x),
+    },
+    "textDocument/semanticTokens/full" => {
+      let uri = params
+        .pointer("/textDocument/uri")
+        .and_then(Value::as_str)
+        .and_then(|x| x.parse().ok());
+      match uri {
+        None => return Err(invalid_params()),
+        Some(x) => IncomingRequestParams::SemanticTokens(x),
+      }
+    }
+    _ => {
+      return Err(ResponseError {
+        code: ErrorCode::MethodNotFound,
+        message: format!("unknown method: {}", method),
+      })
+    }
+  };
+  Ok(ret)
 }
 
-fn get_id(val: &mut Value) -> Option<NumberOrString> {
-  from_value(std::mem::take(val.get_mut("id")?)).ok()
+fn parse_notification(method: &str, params: Value) -> Option<IncomingNotification> {
+  let ret = match method {
+    "initialized" => IncomingNotification::Initialized,
+    "exit" => IncomingNotification::Exit,
+    "textDocument/didOpen" => IncomingNotification::TextDocOpen(from_value(params).ok()?),
+    "textDocument/didClose" => IncomingNotification::TextDocClose(from_value(params).ok()?),
+    "textDocument/didChange" => IncomingNotification::TextDocChange(from_value(params).ok()?),
+    "textDocument/didSave" => IncomingNotification::TextDocSave(from_value(params).ok()?),
+    "workspace/didChangeConfiguration" => {
+      IncomingNotification::ChangeConfiguration(from_value(params).ok()?)
+    }
+    "workspace/didChangeWatchedFiles" => {
+      IncomingNotification::ChangeWatchedFiles(from_value(params).ok()?)
+    }
+    _ => return None,
+  };
+  Some(ret)
 }
 
-fn get_params<T>(val: &mut Value) -> Option<T>
+fn from_params<T>(params: Value) -> Result<T, ResponseError>
 where
   T: DeserializeOwned,
 {
-  from_value(std::mem::take(val.get_mut("params")?)).ok()
+  from_value(params).map_err(|e| ResponseError {
+    code: ErrorCode::InvalidParams,
+    message: e.to_string(),
+  })
+}
+
+fn invalid_params() -> ResponseError {
+  ResponseError {
+    code: ErrorCode::InvalidParams,
+    message: "invalid params".to_owned(),
+  }
 }
 
 pub enum ResponseSuccess {
-  Initialize(InitializeResult),
+  /// The encoding is the position encoding the server chose.
+  Initialize(InitializeResult, Encoding),
+  Hover(Option<Hover>),
+  Completion(Option<CompletionResponse>),
+  DocumentHighlight(Option<Vec<DocumentHighlight>>),
+  Definition(Option<GotoDefinitionResponse>),
+  SelectionRange(Option<Vec<SelectionRange>>),
+  OnTypeFormatting(Option<Vec<TextEdit>>),
+  CodeAction(Option<CodeActionResponse>),
+  VirtualDocument(Option<String>),
+  /// The result of a command, which is Markdown for the commands which show something.
+  ExecuteCommand(Option<String>),
+  DocumentDiagnostic(DiagnosticReport),
+  /// Each open file's URI, version, and report.
+  WorkspaceDiagnostic(Vec<(Url, Option<i64>, DiagnosticReport)>),
+  /// The encoded semantic tokens, five numbers for each.
+  SemanticTokens(Option<Vec<u32>>),
   Null,
 }
 
@@ -126,7 +424,47 @@ impl Response {
       Ok(good) => (
         "result",
         match good {
-          ResponseSuccess::Initialize(x) => to_value(x)?,
+          ResponseSuccess::Initialize(x, enc) => {
+            let mut ret = to_value(x)?;
+            // these capabilities are newer than `lsp_types`.
+            ret["capabilities"]["diagnosticProvider"] = json!({
+              "interFileDependencies": false,
+              "workspaceDiagnostics": true,
+            });
+            ret["capabilities"]["positionEncoding"] = enc.name().into();
+            ret["capabilities"]["semanticTokensProvider"] = json!({
+              "legend": {
+                "tokenTypes": SEMANTIC_TOKEN_TYPES,
+                "tokenModifiers": [],
+              },
+              "full": true,
+            });
+            ret
+          }
+          ResponseSuccess::Hover(x) => to_value(x)?,
+          ResponseSuccess::Completion(x) => to_value(x)?,
+          ResponseSuccess::DocumentHighlight(x) => to_value(x)?,
+          ResponseSuccess::Definition(x) => to_value(x)?,
+          ResponseSuccess::SelectionRange(x) => to_value(x)?,
+          ResponseSuccess::OnTypeFormatting(x) => to_value(x)?,
+          ResponseSuccess::CodeAction(x) => to_value(x)?,
+          ResponseSuccess::VirtualDocument(x) => to_value(x)?,
+          ResponseSuccess::ExecuteCommand(x) => to_value(x)?,
+          ResponseSuccess::DocumentDiagnostic(x) => Value::Object(x.into_value()?),
+          ResponseSuccess::WorkspaceDiagnostic(xs) => {
+            let mut items = Vec::with_capacity(xs.len());
+            for (uri, version, report) in xs {
+              let mut item = report.into_value()?;
+              item.insert("uri".to_owned(), to_value(uri)?);
+              item.insert("version".to_owned(), to_value(version)?);
+              items.push(Value::Object(item));
+            }
+            json!({ "items": items })
+          }
+          ResponseSuccess::SemanticTokens(x) => match x {
+            None => Value::Null,
+            Some(data) => json!({ "data": data }),
+          },
           ResponseSuccess::Null => Value::Null,
         },
       ),
@@ -1,38 +1,112 @@
 //! Parsing for LSP headers.
 
-pub fn content_length(bs: &[u8]) -> Option<usize> {
-  let mut i = bs.iter().position(|&b| b == b':')?;
-  if &bs[0..i] != b"Content-Length" {
-    return None;
-  }
-  i += 1;
-  while *bs.get(i)? == b' ' {
-    i += 1;
-  }
-  let start = i;
-  while bs.get(i)?.is_ascii_digit() {
-    i += 1;
+/// The header part of a message.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Headers {
+  /// The length of the content part, in bytes.
+  pub content_length: usize,
+  /// The mime type of the content part, if given.
+  pub content_type: Option<String>,
+}
+
+impl Headers {
+  /// Returns whether the content part is UTF-8, which is the default and the only charset we
+  /// support. The spec permits `utf8` as a synonym for `utf-8`.
+  pub fn is_utf8(&self) -> bool {
+    let content_type = match &self.content_type {
+      None => return true,
+      Some(x) => x,
+    };
+    content_type.split(';').skip(1).all(|param| {
+      let eq = match param.find('=') {
+        None => return true,
+        Some(x) => x,
+      };
+      if !param[..eq].trim().eq_ignore_ascii_case("charset") {
+        return true;
+      }
+      let charset = param[eq + 1..]
+        .trim()
+        .trim_matches('"')
+        .to_ascii_lowercase();
+      charset == "utf-8" || charset == "utf8"
+    })
   }
-  if start == i || *bs.get(i)? != b'\r' || *bs.get(i + 1)? != b'\n' || i + 2 != bs.len() {
-    return None;
+}
+
+/// Parses the header part of a message, which is every line up to and including the empty line
+/// that ends it. Header names are case-insensitive, whitespace around names and values is ignored,
+/// and unknown headers are ignored. Returns `None` if some line is not a header, or if there is not
+/// exactly one valid `Content-Length`.
+pub fn get(bs: &[u8]) -> Option<Headers> {
+  let s = std::str::from_utf8(bs).ok()?;
+  let mut content_length = None;
+  let mut content_type = None;
+  for line in s.split('\n') {
+    let line = line.trim();
+    if line.is_empty() {
+      continue;
+    }
+    let colon = line.find(':')?;
+    let name = line[..colon].trim();
+    let value = line[colon + 1..].trim();
+    if name.eq_ignore_ascii_case("Content-Length") {
+      if content_length.is_some() || value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit())
+      {
+        return None;
+      }
+      content_length = Some(value.parse().ok()?);
+    } else if name.eq_ignore_ascii_case("Content-Type") {
+      content_type = Some(value.to_owned());
+    }
   }
-  let num = std::str::from_utf8(&bs[start..i]).ok()?;
-  Some(num.parse().unwrap())
+  Some(Headers {
+    content_length: content_length?,
+    content_type,
+  })
+}
+
+#[test]
+fn test_get() {
+  let len = |n| {
+    Some(Headers {
+      content_length: n,
+      content_type: None,
+    })
+  };
+  assert_eq!(get(b""), None);
+  assert_eq!(get(b"\r\n"), None);
+  assert_eq!(get(b"Content-Length: 123\r\n\r\n"), len(123));
+  assert_eq!(get(b"Content-Length:123\r\n\r\n"), len(123));
+  assert_eq!(get(b"Content-Length:    789\r\n\r\n"), len(789));
+  assert_eq!(get(b" content-length : 456 \n\n"), len(456));
+  assert_eq!(get(b"CONTENT-LENGTH: 456\r\n\r\n"), len(456));
+  assert_eq!(get(b"X-Foo: bar\r\nContent-Length: 1\r\n\r\n"), len(1));
+  assert_eq!(
+    get(b"Content-Length: 12\r\nContent-Type: application/vscode-jsonrpc; charset=utf-8\r\n\r\n"),
+    Some(Headers {
+      content_length: 12,
+      content_type: Some("application/vscode-jsonrpc; charset=utf-8".to_owned()),
+    })
+  );
+  assert_eq!(get(b"Content-Length 123\r\n\r\n"), None);
+  assert_eq!(get(b"Content-Length: \r\n\r\n"), None);
+  assert_eq!(get(b"Content-Length: -1\r\n\r\n"), None);
+  assert_eq!(get(b"Content-Length: 1 2\r\n\r\n"), None);
+  assert_eq!(get(b"Content-Length: 1\r\nContent-Length: 1\r\n\r\n"), None);
+  assert_eq!(get(b"Content-Type: 123\r\n\r\n"), None);
 }
 
 #[test]
-fn test_content_length() {
-  assert_eq!(content_length(b""), None);
-  assert_eq!(content_length(b"Content-Length: 123"), None);
-  assert_eq!(content_length(b"Content-Length: \r\n 123\r\n"), None);
-  assert_eq!(content_length(b"Content-Length: 123\r\n\r\n"), None);
-  assert_eq!(content_length(b"Content-Length 123\r\n"), None);
-  assert_eq!(content_length(b"Content-Length: 123\n"), None);
-  assert_eq!(content_length(b"Content-Length: 123\r"), None);
-  assert_eq!(content_length(b"Content-Length:123\r\n"), Some(123));
-  assert_eq!(content_length(b"Content-Length: 456\r\n"), Some(456));
-  assert_eq!(content_length(b" Content-Length: 123\r\n"), None);
-  assert_eq!(content_length(b"Content-Length:    789\r\n"), Some(789));
-  assert_eq!(content_length(b"Content-Type: 123\r\n"), None);
-  assert_eq!(content_length(b"Content-Length: \r\n"), None);
+fn test_is_utf8() {
+  let ct = |x: Option<&str>| Headers {
+    content_length: 0,
+    content_type: x.map(str::to_owned),
+  };
+  assert!(ct(None).is_utf8());
+  assert!(ct(Some("application/vscode-jsonrpc")).is_utf8());
+  assert!(ct(Some("application/vscode-jsonrpc; charset=utf-8")).is_utf8());
+  assert!(ct(Some("application/vscode-jsonrpc; charset=utf8")).is_utf8());
+  assert!(ct(Some("application/vscode-jsonrpc; Charset=\"UTF-8\"")).is_utf8());
+  assert!(!ct(Some("application/vscode-jsonrpc; charset=latin1")).is_utf8());
 }
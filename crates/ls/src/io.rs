@@ -1,33 +1,76 @@
 //! Threads for handling I/O.
 
+use crate::args::Transport;
 use crate::comm::{Incoming, Outgoing};
-use crate::headers::content_length;
+use crate::headers;
 use crossbeam_channel::{Receiver, Sender};
-use std::io::BufRead as _;
-use std::io::Read as _;
-use std::io::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::thread::JoinHandle;
 
-pub fn read_stdin(s: Sender<Incoming>) {
-  let stdin = std::io::stdin();
-  let mut stdin = stdin.lock();
+/// Starts a thread to read incoming messages from the client into `s`, and a thread to write
+/// outgoing messages from `r` to the client, using `transport`. Returns the writing thread.
+///
+/// The reading thread is not returned, since it may be blocked reading from a client that will send
+/// nothing more, and so should not be joined.
+pub fn start(
+  transport: Transport,
+  s: Sender<Incoming>,
+  r: Receiver<Outgoing>,
+) -> std::io::Result<JoinHandle<()>> {
+  let stream = match transport {
+    Transport::Stdio => {
+      spawn("read", move || {
+        let stdin = std::io::stdin();
+        read(stdin.lock(), s)
+      })?;
+      return spawn("write", move || {
+        let stdout = std::io::stdout();
+        write(stdout.lock(), r)
+      });
+    }
+    Transport::Listen(port) => TcpListener::bind((Ipv4Addr::LOCALHOST, port))?.accept()?.0,
+    Transport::Connect(port) => TcpStream::connect((Ipv4Addr::LOCALHOST, port))?,
+  };
+  let reader = BufReader::new(stream.try_clone()?);
+  spawn("read", move || read(reader, s))?;
+  spawn("write", move || write(stream, r))
+}
+
+fn spawn<F>(name: &str, f: F) -> std::io::Result<JoinHandle<()>>
+where
+  F: FnOnce() + Send + 'static,
+{
+  std::thread::Builder::new().name(name.to_owned()).spawn(f)
+}
+
+fn read<R: BufRead>(mut input: R, s: Sender<Incoming>) {
   let mut buf = Vec::new();
-  let mut content_len: Option<usize> = None;
   loop {
     buf.clear();
-    assert_ne!(stdin.read_until(b'\n', &mut buf).unwrap(), 0);
-    if let Some(n) = content_length(&buf) {
-      content_len = Some(n);
-      continue;
+    // the header part ends with an empty line.
+    loop {
+      let start = buf.len();
+      match input.read_until(b'\n', &mut buf) {
+        // the client closed the connection, so it won't send anything more.
+        Ok(0) | Err(_) => return,
+        Ok(_) => {}
+      }
+      if buf[start..].iter().all(u8::is_ascii_whitespace) {
+        break;
+      }
     }
-    if buf != b"\r\n" {
-      continue;
-    }
-    let n = match content_len.take() {
+    let headers = match headers::get(&buf) {
       None => continue,
       Some(x) => x,
     };
-    buf = vec![0; n];
-    stdin.read_exact(&mut buf).unwrap();
+    buf = vec![0; headers.content_length];
+    if input.read_exact(&mut buf).is_err() {
+      return;
+    }
+    if !headers.is_utf8() {
+      continue;
+    }
     let msg = match Incoming::try_parse(&buf) {
       None => continue,
       Some(x) => x,
@@ -38,13 +81,15 @@ pub fn read_stdin(s: Sender<Incoming>) {
   }
 }
 
-pub fn write_stdout(r: Receiver<Outgoing>) {
-  let stdout = std::io::stdout();
-  let mut stdout = stdout.lock();
+fn write<W: Write>(mut output: W, r: Receiver<Outgoing>) {
   for res in r {
     let buf = res.into_vec().unwrap();
-    write!(stdout, "Content-Length: {}\r\n\r\n", buf.len()).unwrap();
-    stdout.write_all(&buf).unwrap();
-    stdout.flush().unwrap();
+    let wrote = write!(output, "Content-Length: {}\r\n\r\n", buf.len())
+      .and_then(|()| output.write_all(&buf))
+      .and_then(|()| output.flush());
+    // the client went away, so it won't read anything more.
+    if wrote.is_err() {
+      break;
+    }
   }
 }
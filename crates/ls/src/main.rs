@@ -1,42 +1,99 @@
 //! A language server for Standard ML.
 
+mod args;
 mod comm;
 mod headers;
 mod io;
+mod pos;
+mod project;
+mod settings;
 mod state;
+mod std_lib;
+
+use crossbeam_channel::RecvTimeoutError;
+use millet_core::parse;
+use std::time::Instant;
 
 fn main() {
+  // parsing and checking are recursive, and happen on the thread which handles messages.
+  let exit_ok = std::thread::Builder::new()
+    .name("run".to_owned())
+    .stack_size(parse::STACK_SIZE)
+    .spawn(run)
+    .unwrap()
+    .join()
+    .unwrap();
+  if !exit_ok {
+    std::process::exit(1);
+  }
+}
+
+/// Serves the client until it exits. Returns whether it exited after a shutdown.
+fn run() -> bool {
+  let args = match args::get() {
+    Ok(Some(x)) => x,
+    Ok(None) => return true,
+    Err(e) => {
+      eprintln!("{}", e);
+      std::process::exit(1);
+    }
+  };
   let (s_inc, r_inc) = crossbeam_channel::unbounded();
   let (s_out, r_out) = crossbeam_channel::unbounded();
-  let read_stdin = std::thread::Builder::new()
-    .name("read_stdin".to_owned())
-    .spawn(move || io::read_stdin(s_inc))
-    .unwrap();
-  let write_stdout = std::thread::Builder::new()
-    .name("write_stdout".to_owned())
-    .spawn(move || io::write_stdout(r_out))
-    .unwrap();
+  let write = match io::start(args.transport, s_inc, r_out) {
+    Ok(x) => x,
+    Err(e) => {
+      eprintln!("couldn't connect to the client: {}", e);
+      std::process::exit(1);
+    }
+  };
   let mut st = state::State::new();
   let exit_ok = loop {
-    match r_inc.recv().unwrap() {
+    // if the client closed the connection without sending exit, exit as if it had.
+    let msg = match st.deadline() {
+      None => match r_inc.recv() {
+        Ok(x) => x,
+        Err(_) => break st.got_shutdown(),
+      },
+      // only check a changed file once no more messages come in before it is due.
+      Some(deadline) => {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match r_inc.recv_timeout(timeout) {
+          Ok(x) => x,
+          Err(RecvTimeoutError::Timeout) => {
+            if let Some(state::Action::Respond(xs)) = st.check_due() {
+              for x in xs {
+                s_out.send(x).unwrap();
+              }
+            }
+            continue;
+          }
+          Err(RecvTimeoutError::Disconnected) => break st.got_shutdown(),
+        }
+      }
+    };
+    match msg {
       comm::Incoming::Request(req) => {
-        let res = st.handle_request(req);
-        s_out.send(comm::Outgoing::Response(res)).unwrap();
+        for x in st.handle_request(req) {
+          s_out.send(x).unwrap();
+        }
       }
+      comm::Incoming::Invalid(res) => s_out.send(comm::Outgoing::Response(res)).unwrap(),
       comm::Incoming::Notification(notif) => match st.handle_notification(notif) {
         None => {}
         Some(action) => match action {
           state::Action::Exit(x) => break x,
-          state::Action::Respond(x) => s_out.send(*x).unwrap(),
+          state::Action::Respond(xs) => {
+            for x in xs {
+              s_out.send(x).unwrap();
+            }
+          }
         },
       },
     }
   };
   drop(r_inc);
   drop(s_out);
-  read_stdin.join().unwrap();
-  write_stdout.join().unwrap();
-  if !exit_ok {
-    std::process::exit(1);
-  }
+  write.join().unwrap();
+  exit_ok
 }
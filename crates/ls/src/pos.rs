@@ -0,0 +1,104 @@
+//! Conversions between byte offsets, which is what a `Loc` uses, and LSP positions.
+
+use lsp_types::{Position, Range};
+use millet_core::line_index::LineIndex;
+use millet_core::loc::Loc;
+use std::convert::TryFrom as _;
+
+/// How the `character` of a `Position` counts the characters in a line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+  /// In UTF-8 code units, i.e. bytes.
+  Utf8,
+  /// In UTF-16 code units. This is the default, and what every client must support.
+  Utf16,
+}
+
+impl Encoding {
+  /// Returns the name of this in the LSP spec.
+  pub fn name(self) -> &'static str {
+    match self {
+      Self::Utf8 => "utf-8",
+      Self::Utf16 => "utf-16",
+    }
+  }
+
+  /// Returns the number of code units the byte `b` adds to the `character` of a `Position`. For a
+  /// byte which begins a character this is the number of units for that whole character, and for
+  /// other bytes it is 0.
+  fn units(self, b: u8) -> u64 {
+    match self {
+      Self::Utf8 => 1,
+      Self::Utf16 => {
+        if b & 0b1100_0000 == 0b1000_0000 {
+          0
+        } else if b & 0b1111_1000 == 0b1111_0000 {
+          // a four-byte character is a surrogate pair in UTF-16.
+          2
+        } else {
+          1
+        }
+      }
+    }
+  }
+}
+
+/// Returns the range of `loc` in `bs`.
+pub fn range(bs: &[u8], enc: Encoding, loc: Loc) -> Range {
+  let range: std::ops::Range<usize> = loc.into();
+  Range {
+    start: position(bs, enc, range.start),
+    end: position(bs, enc, range.end),
+  }
+}
+
+/// Returns the position of the byte offset `byte_idx` in `bs`.
+pub fn position(bs: &[u8], enc: Encoding, byte_idx: usize) -> Position {
+  let byte_idx = byte_idx.min(bs.len());
+  let line_col = LineIndex::new(bs).line_col(byte_idx);
+  let line_start = byte_idx - line_col.col;
+  Position {
+    line: line_col.line as u64,
+    character: bs[line_start..byte_idx].iter().map(|&b| enc.units(b)).sum(),
+  }
+}
+
+/// Returns the number of code units in the bytes `bs`, which must not contain a newline.
+pub fn len(bs: &[u8], enc: Encoding) -> u64 {
+  bs.iter().map(|&b| enc.units(b)).sum()
+}
+
+/// The inverse of `position`. Returns `None` if `pos` is neither in `bs` nor at the end of a line.
+pub fn byte_idx(bs: &[u8], enc: Encoding, pos: Position) -> Option<usize> {
+  let line = LineIndex::new(bs).line_range(usize::try_from(pos.line).ok()?)?;
+  let mut character = 0;
+  for idx in line.clone() {
+    let units = enc.units(bs[idx]);
+    if character == pos.character && units != 0 {
+      return Some(idx);
+    }
+    character += units;
+  }
+  if character == pos.character {
+    Some(line.end)
+  } else {
+    None
+  }
+}
+
+#[test]
+fn test_position() {
+  // 'é' is 2 bytes and 1 UTF-16 unit, '𝄞' is 4 bytes and 2 UTF-16 units.
+  let bs = "a\né𝄞b".as_bytes();
+  let b_idx = bs.len() - 1;
+  let pos = |line, character| Position { line, character };
+  assert_eq!(position(bs, Encoding::Utf8, 1), pos(0, 1));
+  assert_eq!(position(bs, Encoding::Utf8, 2), pos(1, 0));
+  assert_eq!(position(bs, Encoding::Utf8, b_idx), pos(1, 6));
+  assert_eq!(position(bs, Encoding::Utf16, b_idx), pos(1, 3));
+  assert_eq!(byte_idx(bs, Encoding::Utf8, pos(1, 6)), Some(b_idx));
+  assert_eq!(byte_idx(bs, Encoding::Utf16, pos(1, 3)), Some(b_idx));
+  assert_eq!(byte_idx(bs, Encoding::Utf16, pos(1, 4)), Some(bs.len()));
+  assert_eq!(byte_idx(bs, Encoding::Utf16, pos(1, 5)), None);
+  assert_eq!(byte_idx(bs, Encoding::Utf16, pos(2, 0)), None);
+}
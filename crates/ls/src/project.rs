@@ -0,0 +1,36 @@
+//! The project file, which lists the files of the project in the order to check them.
+
+use serde_json::Value;
+
+/// The name of the project file, which goes in the root.
+pub const FILE_NAME: &str = "millet.json";
+
+/// Returns the paths of the files listed in the project file with contents `val`, in order. They
+/// are relative to the root. Entries which are not strings are skipped.
+///
+/// The project file looks like:
+///
+/// ```json
+/// { "files": ["src/util.sml", "src/main.sml"] }
+/// ```
+pub fn files(val: &Value) -> Vec<String> {
+  match val.get("files").and_then(Value::as_array) {
+    None => Vec::new(),
+    Some(files) => files
+      .iter()
+      .filter_map(Value::as_str)
+      .map(str::to_owned)
+      .collect(),
+  }
+}
+
+#[test]
+fn test_files() {
+  let val = serde_json::json!({ "files": ["a.sml", 3, "dir/b.sml"] });
+  assert_eq!(
+    files(&val),
+    vec!["a.sml".to_owned(), "dir/b.sml".to_owned()]
+  );
+  assert!(files(&serde_json::json!({ "files": "a.sml" })).is_empty());
+  assert!(files(&serde_json::json!([])).is_empty());
+}
@@ -0,0 +1,148 @@
+//! Settings for the server, which the client may provide.
+
+use millet_core::parse::LanguageFeatures;
+use millet_core::statics::BasisLevel;
+use serde_json::Value;
+use std::collections::HashSet;
+
+/// Settings for the server.
+#[derive(Debug, Default)]
+pub struct Settings {
+  /// The codes of errors which should not be reported.
+  pub disabled_codes: HashSet<u16>,
+  /// Glob patterns for files which should not be checked.
+  pub ignored: Vec<String>,
+  /// The syntax extensions to allow.
+  pub features: LanguageFeatures,
+  /// How much of the standard basis to preload.
+  pub basis: BasisLevel,
+  /// Paths of files to check before every file, as if they were part of the standard basis.
+  pub prelude: Vec<String>,
+  /// Whether to keep the results of checking in the cache directory in the root.
+  pub cache: bool,
+}
+
+impl Settings {
+  /// Returns the settings in `val`, which may either be the settings themselves or contain them
+  /// under the key `millet`. Settings which are missing or malformed get their default values.
+  ///
+  /// The settings look like:
+  ///
+  /// ```json
+  /// {
+  ///   "diagnostics": { "2033": false },
+  ///   "ignore": ["tests/**"],
+  ///   "successorML": true,
+  ///   "basis": "minimal",
+  ///   "prelude": ["lib/support.sml"],
+  ///   "cache": true
+  /// }
+  /// ```
+  pub fn from_value(val: &Value) -> Self {
+    let val = val.get("millet").unwrap_or(val);
+    let mut ret = Self::default();
+    if let Some(diagnostics) = val.get("diagnostics").and_then(Value::as_object) {
+      for (code, enabled) in diagnostics {
+        if let (Ok(code), Some(false)) = (code.parse(), enabled.as_bool()) {
+          ret.disabled_codes.insert(code);
+        }
+      }
+    }
+    if let Some(true) = val.get("successorML").and_then(Value::as_bool) {
+      ret.features = LanguageFeatures::successor_ml();
+    }
+    if let Some(Ok(basis)) = val.get("basis").and_then(Value::as_str).map(str::parse) {
+      ret.basis = basis;
+    }
+    if let Some(ignored) = val.get("ignore").and_then(Value::as_array) {
+      ret.ignored = ignored
+        .iter()
+        .filter_map(Value::as_str)
+        .map(str::to_owned)
+        .collect();
+    }
+    if let Some(prelude) = val.get("prelude").and_then(Value::as_array) {
+      ret.prelude = prelude
+        .iter()
+        .filter_map(Value::as_str)
+        .map(str::to_owned)
+        .collect();
+    }
+    if let Some(true) = val.get("cache").and_then(Value::as_bool) {
+      ret.cache = true;
+    }
+    ret
+  }
+
+  /// Returns whether errors with this code should be reported.
+  pub fn is_enabled(&self, code: u16) -> bool {
+    !self.disabled_codes.contains(&code)
+  }
+
+  /// Returns whether the file at `path` should not be checked.
+  pub fn is_ignored(&self, path: &str) -> bool {
+    self
+      .ignored
+      .iter()
+      .any(|pat| glob_match(pat.as_bytes(), path.as_bytes()))
+  }
+}
+
+/// Returns whether `s` matches the glob pattern `pat`. In `pat`, `?` matches any byte except `/`,
+/// `*` matches any sequence of bytes not containing `/`, and `**` matches any sequence of bytes.
+fn glob_match(pat: &[u8], s: &[u8]) -> bool {
+  match pat.split_first() {
+    None => s.is_empty(),
+    Some((&b'*', pat)) => match pat.split_first() {
+      Some((&b'*', pat)) => (0..=s.len()).any(|i| glob_match(pat, &s[i..])),
+      _ => (0..=s.len())
+        .take_while(|&i| i == 0 || s[i - 1] != b'/')
+        .any(|i| glob_match(pat, &s[i..])),
+    },
+    Some((&p, pat)) => match s.split_first() {
+      None => false,
+      Some((&b, s)) => (b == p || (p == b'?' && b != b'/')) && glob_match(pat, s),
+    },
+  }
+}
+
+#[test]
+fn test_glob_match() {
+  assert!(glob_match(b"", b""));
+  assert!(glob_match(b"a.sml", b"a.sml"));
+  assert!(!glob_match(b"a.sml", b"b.sml"));
+  assert!(glob_match(b"?.sml", b"b.sml"));
+  assert!(glob_match(b"*.sml", b"foo.sml"));
+  assert!(!glob_match(b"*.sml", b"dir/foo.sml"));
+  assert!(glob_match(b"dir/*.sml", b"dir/foo.sml"));
+  assert!(glob_match(b"**.sml", b"dir/foo.sml"));
+  assert!(glob_match(b"tests/**", b"tests/a/b.sml"));
+  assert!(!glob_match(b"tests/**", b"src/a/b.sml"));
+  assert!(!glob_match(b"?", b"/"));
+}
+
+#[test]
+fn test_from_value() {
+  let val = serde_json::json!({
+    "millet": { "diagnostics": { "2033": false, "2013": true, "bad": false }, "ignore": ["*.sig", 3] }
+  });
+  let settings = Settings::from_value(&val);
+  assert!(!settings.is_enabled(2033));
+  assert!(settings.is_enabled(2013));
+  assert!(settings.is_enabled(1001));
+  assert!(settings.is_ignored("a.sig"));
+  assert!(!settings.is_ignored("a.sml"));
+  assert_eq!(settings.features, LanguageFeatures::default());
+  assert_eq!(settings.basis, BasisLevel::Full);
+  assert!(settings.prelude.is_empty());
+  assert!(!settings.cache);
+  let settings = Settings::from_value(&serde_json::json!({ "successorML": true, "basis": "none" }));
+  assert_eq!(settings.features, LanguageFeatures::successor_ml());
+  assert_eq!(settings.basis, BasisLevel::None);
+  let settings = Settings::from_value(&serde_json::json!({ "prelude": ["a.sml", false, "b.sml"] }));
+  assert_eq!(
+    settings.prelude,
+    vec!["a.sml".to_owned(), "b.sml".to_owned()]
+  );
+  assert!(Settings::from_value(&serde_json::json!({ "cache": true })).cache);
+}
@@ -1,20 +1,97 @@
 //! The core of the server logic.
 
 use crate::comm::{
-  IncomingNotification, IncomingRequestParams, Outgoing, OutgoingNotification, Request, Response,
-  ResponseSuccess,
+  Command, DiagnosticReport, ErrorCode, IncomingNotification, IncomingRequestParams, Outgoing,
+  OutgoingNotification, Request, Response, ResponseError, ResponseSuccess, SEMANTIC_TOKEN_TYPES,
 };
+use crate::pos::{self, Encoding};
+use crate::settings::Settings;
+use crate::std_lib::{self, StdLib};
 use lsp_types::{
-  Diagnostic, InitializeResult, Position, PublishDiagnosticsParams, Range, ServerCapabilities,
-  ServerInfo, TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+  CodeAction, CodeActionKind, CodeActionOrCommand, CodeActionProviderCapability, CompletionItem,
+  CompletionItemKind, CompletionOptions, CompletionResponse, Diagnostic,
+  DiagnosticRelatedInformation, DiagnosticSeverity, DocumentHighlight, DocumentHighlightKind,
+  DocumentOnTypeFormattingOptions, ExecuteCommandOptions, FileChangeType, GotoDefinitionResponse,
+  Hover, HoverContents, InitializeResult, Location, MarkupContent, MarkupKind, NumberOrString,
+  Position, PublishDiagnosticsParams, SelectionRange, SelectionRangeProviderCapability,
+  ServerCapabilities, ServerInfo, TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Url,
+  WorkDoneProgressOptions, WorkspaceEdit,
 };
-use millet_core::intern::StrStoreMut;
-use millet_core::loc::Loc;
-use millet_core::{lex, parse, statics};
+use millet_core::db::{self, Db};
+use millet_core::highlight::{self, Class};
+use millet_core::intern::{StrStore, StrStoreMut};
+use millet_core::line_index::LineIndex;
+use millet_core::loc::{Loc, Located};
+use millet_core::parse::LanguageFeatures;
+use millet_core::statics::{BasisLevel, MemberKind};
+use millet_core::{indent, lex, selection, statics};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// How long to wait after a change to a file before checking it, in case more changes follow.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Where to keep the results of checking the files, relative to the root, if the settings say to.
+const CACHE_DIR: &str = ".millet/cache";
+
+/// How long to wait after checking the files before saving the results to the cache directory, so
+/// that the results of checks in quick succession are saved once.
+const SAVE_DELAY: Duration = Duration::from_secs(10);
 
 pub struct State {
   root_uri: Option<Url>,
+  got_initialize: bool,
   got_shutdown: bool,
+  settings: Settings,
+  /// Whether the client pulls diagnostics, instead of us publishing them.
+  pull_diagnostics: bool,
+  /// How positions count the characters in a line.
+  encoding: Encoding,
+  /// The next result ID to hand out for a report of diagnostics.
+  next_result_id: u64,
+  /// The open files, and the files of the project.
+  files: HashMap<Url, File>,
+  /// The files listed in the project file, in order. It is read when we start, and again when it
+  /// or the files on disk change.
+  project: Vec<Url>,
+  /// The open files which changed since they were last checked, with when to check them.
+  pending: HashMap<Url, Instant>,
+  /// When to save the results of checking to the cache directory, if there are any not yet saved.
+  save_at: Option<Instant>,
+  /// The virtual document for the standard basis.
+  std_lib: StdLib,
+  /// The contents of the prelude files from the settings, in order. Files which couldn't be read
+  /// are left out.
+  prelude: Vec<Vec<u8>>,
+  /// The results of checking the files, kept to check them again faster.
+  db: Db<Url>,
+}
+
+/// An open file, or a file of the project.
+struct File {
+  /// Whether the client has this open. Otherwise, this is a file of the project read from disk.
+  open: bool,
+  /// The version of the contents, if known.
+  version: Option<i64>,
+  /// The contents.
+  bs: Vec<u8>,
+  /// The diagnostics for the contents.
+  diagnostics: Vec<Diagnostic>,
+  /// Identifies `diagnostics`, so that a client pulling diagnostics can be told they're unchanged.
+  result_id: String,
+  /// The result of checking the most recent contents for which lexing and parsing succeeded.
+  checked: Option<Checked>,
+  /// Whether `checked` is the result of checking `bs`, rather than an earlier version of it.
+  current: bool,
+}
+
+struct Checked {
+  /// Shared by every file checked together.
+  store: Rc<StrStore>,
+  statics: Rc<statics::Statics>,
+  /// The results of lexing and parsing, for doc comments and selection ranges.
+  parsed: Rc<db::Parsed>,
 }
 
 impl State {
@@ -22,134 +99,1188 @@ impl State {
   pub fn new() -> Self {
     Self {
       root_uri: None,
+      got_initialize: false,
       got_shutdown: false,
+      settings: Settings::default(),
+      pull_diagnostics: false,
+      encoding: Encoding::Utf16,
+      next_result_id: 0,
+      files: HashMap::new(),
+      project: Vec::new(),
+      pending: HashMap::new(),
+      save_at: None,
+      std_lib: StdLib::new(),
+      prelude: Vec::new(),
+      db: Db::new(
+        BasisLevel::default(),
+        LanguageFeatures::default(),
+        Vec::new(),
+        None,
+      ),
     }
   }
 
-  /// Returns the Response for this Request.
-  pub fn handle_request(&mut self, req: Request<IncomingRequestParams>) -> Response {
+  /// Returns whether we got a shutdown request, in which case exiting is expected.
+  pub fn got_shutdown(&self) -> bool {
+    self.got_shutdown
+  }
+
+  /// Returns the Response for this Request, followed by any other messages to send because of it.
+  pub fn handle_request(&mut self, req: Request<IncomingRequestParams>) -> Vec<Outgoing> {
+    let is_initialize = matches!(req.params, IncomingRequestParams::Initialize(..));
+    let err = if self.got_shutdown {
+      Some((ErrorCode::InvalidRequest, "got shutdown"))
+    } else if is_initialize && self.got_initialize {
+      Some((ErrorCode::InvalidRequest, "already initialized"))
+    } else if !is_initialize && !self.got_initialize {
+      Some((ErrorCode::ServerNotInitialized, "not initialized"))
+    } else {
+      None
+    };
+    if let Some((code, message)) = err {
+      return vec![Outgoing::Response(Response {
+        id: Some(req.id),
+        res: Err(ResponseError {
+          code,
+          message: message.to_owned(),
+        }),
+      })];
+    }
+    // answer with the results of checking the latest contents.
+    let mut after = Vec::new();
+    if !self.pending.is_empty() {
+      let uris = self.check_all();
+      if let Some(Action::Respond(xs)) = self.push_diagnostics(uris) {
+        after = xs;
+      }
+    }
     let res = match req.params {
-      IncomingRequestParams::Initialize(params) => {
+      IncomingRequestParams::Initialize(params, newer) => {
         // TODO do something with params.process_id
+        self.got_initialize = true;
         self.root_uri = params.root_uri;
-        Ok(ResponseSuccess::Initialize(InitializeResult {
+        self.pull_diagnostics = newer.pull_diagnostics;
+        // prefer UTF-8 if the client supports it, since that's what a `Loc` uses.
+        let utf8 = Encoding::Utf8.name();
+        if newer.position_encodings.iter().any(|x| x == utf8) {
+          self.encoding = Encoding::Utf8;
+        }
+        if let Some(val) = &params.initialization_options {
+          self.settings = Settings::from_value(val);
+          self.load_prelude();
+          self.reset_db();
+        }
+        self.load_project();
+        let result = InitializeResult {
           capabilities: ServerCapabilities {
             text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::Full)),
+            hover_provider: Some(true),
+            document_highlight_provider: Some(true),
+            definition_provider: Some(true),
+            selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
+            code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+            // for `end`, `in`, and `|`.
+            document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
+              first_trigger_character: "d".to_owned(),
+              more_trigger_character: Some(vec!["n".to_owned(), "|".to_owned()]),
+            }),
+            execute_command_provider: Some(ExecuteCommandOptions {
+              commands: Command::NAMES.iter().map(|&x| x.to_owned()).collect(),
+              work_done_progress_options: WorkDoneProgressOptions::default(),
+            }),
+            completion_provider: Some(CompletionOptions {
+              trigger_characters: Some(vec![".".to_owned()]),
+              ..CompletionOptions::default()
+            }),
             ..ServerCapabilities::default()
           },
           server_info: Some(ServerInfo {
             name: "millet-ls".to_owned(),
             version: Some(env!("CARGO_PKG_VERSION").to_owned()),
           }),
-        }))
+        };
+        Ok(ResponseSuccess::Initialize(result, self.encoding))
       }
       IncomingRequestParams::Shutdown => {
         self.got_shutdown = true;
+        self.save();
         Ok(ResponseSuccess::Null)
       }
+      IncomingRequestParams::Hover(params) => {
+        let params = params.text_document_position_params;
+        let hover = self
+          .files
+          .get(&params.text_document.uri)
+          .and_then(|file| hover(file, self.encoding, params.position));
+        Ok(ResponseSuccess::Hover(hover))
+      }
+      IncomingRequestParams::Completion(params) => {
+        let params = params.text_document_position;
+        let completion = self
+          .files
+          .get(&params.text_document.uri)
+          .and_then(|file| completion(file, self.encoding, params.position));
+        Ok(ResponseSuccess::Completion(completion))
+      }
+      IncomingRequestParams::DocumentHighlight(params) => {
+        let params = params.text_document_position_params;
+        let highlights = self
+          .files
+          .get(&params.text_document.uri)
+          .and_then(|file| document_highlight(file, self.encoding, params.position));
+        Ok(ResponseSuccess::DocumentHighlight(highlights))
+      }
+      IncomingRequestParams::Definition(params) => {
+        let params = params.text_document_position_params;
+        let uri = params.text_document.uri;
+        let pos = params.position;
+        let location = self
+          .files
+          .get(&uri)
+          .and_then(|file| definition(file, &uri, &self.std_lib, self.encoding, pos));
+        Ok(ResponseSuccess::Definition(
+          location.map(GotoDefinitionResponse::Scalar),
+        ))
+      }
+      IncomingRequestParams::SelectionRange(params) => {
+        let ranges = self
+          .files
+          .get(&params.text_document.uri)
+          .and_then(|file| selection_ranges(file, self.encoding, &params.positions));
+        Ok(ResponseSuccess::SelectionRange(ranges))
+      }
+      IncomingRequestParams::CodeAction(params) => {
+        let uri = params.text_document.uri;
+        let diagnostics = params.context.diagnostics;
+        let actions = self
+          .files
+          .get(&uri)
+          .and_then(|file| code_actions(file, &uri, self.encoding, &diagnostics));
+        Ok(ResponseSuccess::CodeAction(actions))
+      }
+      IncomingRequestParams::OnTypeFormatting(params) => {
+        let params = params.text_document_position;
+        let edits = self
+          .files
+          .get(&params.text_document.uri)
+          .and_then(|file| on_type_formatting(file, self.encoding, params.position));
+        Ok(ResponseSuccess::OnTypeFormatting(edits))
+      }
+      IncomingRequestParams::VirtualDocument(uri) => {
+        let text = if std_lib::is_uri(&uri) {
+          Some(self.std_lib.text().to_owned())
+        } else {
+          None
+        };
+        Ok(ResponseSuccess::VirtualDocument(text))
+      }
+      IncomingRequestParams::ExecuteCommand(command) => {
+        let ret = match command {
+          Command::ShowAst(uri) => self.files.get(&uri).and_then(show_ast),
+          Command::ShowInferredType(uri, pos) => self
+            .files
+            .get(&uri)
+            .and_then(|file| show_inferred_type(file, self.encoding, pos)),
+          Command::RestartAnalysis => {
+            self.reset_db();
+            let uris = self.check_all();
+            if let Some(Action::Respond(xs)) = self.push_diagnostics(uris) {
+              after.extend(xs);
+            }
+            None
+          }
+        };
+        Ok(ResponseSuccess::ExecuteCommand(ret))
+      }
+      IncomingRequestParams::DocumentDiagnostic(params) => {
+        let report = match self.files.get(&params.uri) {
+          // we only know about open files.
+          None => DiagnosticReport::Full {
+            result_id: None,
+            items: Vec::new(),
+          },
+          Some(file) => file.report(params.previous_result_id.as_deref()),
+        };
+        Ok(ResponseSuccess::DocumentDiagnostic(report))
+      }
+      IncomingRequestParams::WorkspaceDiagnostic(params) => {
+        let reports = self
+          .files
+          .iter()
+          .map(|(uri, file)| {
+            let prev = params.previous_result_ids.get(uri).map(String::as_str);
+            (uri.clone(), file.version, file.report(prev))
+          })
+          .collect();
+        Ok(ResponseSuccess::WorkspaceDiagnostic(reports))
+      }
+      IncomingRequestParams::SemanticTokens(uri) => {
+        let data = self
+          .files
+          .get(&uri)
+          .map(|file| semantic_tokens(&file.bs, self.encoding));
+        Ok(ResponseSuccess::SemanticTokens(data))
+      }
     };
-    Response {
+    let mut ret = vec![Outgoing::Response(Response {
       id: Some(req.id),
       res,
-    }
+    })];
+    ret.append(&mut after);
+    ret
   }
 
   /// Handle a notification by possibly taking some action.
   pub fn handle_notification(&mut self, notif: IncomingNotification) -> Option<Action> {
+    // before initialization, every notification but exit is dropped.
+    if !self.got_initialize && !matches!(notif, IncomingNotification::Exit) {
+      return None;
+    }
     match notif {
       IncomingNotification::Initialized => None,
       IncomingNotification::Exit => Some(Action::Exit(self.got_shutdown)),
-      IncomingNotification::TextDocOpen(params) => Some(mk_diagnostic_action(
-        params.text_document.uri,
-        Some(params.text_document.version),
-        params.text_document.text.as_bytes(),
-      )),
+      IncomingNotification::TextDocOpen(params) => {
+        let uri = params.text_document.uri;
+        let version = Some(params.text_document.version);
+        let bs = params.text_document.text.into_bytes();
+        let result_id = self.new_result_id();
+        // a file of the project which was already checked keeps its results until the next check.
+        let file = self
+          .files
+          .entry(uri)
+          .or_insert_with(|| File::new(Vec::new(), result_id));
+        file.open = true;
+        file.version = version;
+        file.bs = bs;
+        file.current = false;
+        let uris = self.check_all();
+        self.push_diagnostics(uris)
+      }
       IncomingNotification::TextDocChange(mut params) => {
         assert_eq!(params.content_changes.len(), 1);
         let change = params.content_changes.pop().unwrap();
         assert!(change.range.is_none());
-        Some(mk_diagnostic_action(
-          params.text_document.uri,
-          params.text_document.version,
-          change.text.as_bytes(),
-        ))
+        let uri = params.text_document.uri;
+        let file = self.files.get_mut(&uri)?;
+        // wait for the changes to stop before checking. until then, a newer version supersedes
+        // this one, and the diagnostics from the last check stay up.
+        file.version = params.text_document.version;
+        file.bs = change.text.into_bytes();
+        file.current = false;
+        self.pending.insert(uri, Instant::now() + DEBOUNCE);
+        None
+      }
+      IncomingNotification::TextDocSave(params) => {
+        if self.project_file().as_ref() != Some(&params.text_document.uri) {
+          return None;
+        }
+        let mut uris = self.load_project();
+        uris.extend(self.check_all());
+        self.push_diagnostics(uris)
+      }
+      IncomingNotification::TextDocClose(params) => {
+        let uri = params.text_document.uri;
+        self.files.remove(&uri);
+        // other files may have depended on this one. if it is in the project, it is read from disk
+        // instead. if not, it is gone, and its diagnostics would otherwise linger.
+        if self.project.contains(&uri) && !self.load_file(&uri) {
+          self.project.retain(|x| *x != uri);
+        }
+        let mut uris = self.check_all();
+        if !self.files.contains_key(&uri) {
+          uris.push(uri);
+        }
+        self.push_diagnostics(uris)
+      }
+      IncomingNotification::ChangeConfiguration(params) => {
+        self.settings = Settings::from_value(&params.settings);
+        self.load_prelude();
+        self.reset_db();
+        // the settings may change what gets reported, so check every open file again.
+        let uris = self.check_all();
+        self.push_diagnostics(uris)
+      }
+      IncomingNotification::ChangeWatchedFiles(params) => {
+        let project_file = self.project_file();
+        let mut reload = false;
+        let mut changed = false;
+        for change in params.changes {
+          // a file created or deleted may be one listed in the project file, which we left out or
+          // which is now gone.
+          if Some(&change.uri) == project_file.as_ref()
+            || !matches!(change.typ, FileChangeType::Changed)
+          {
+            reload = true;
+          } else if self.files.get(&change.uri).is_some_and(|file| !file.open) {
+            // the files which aren't open are the files of the project.
+            changed = true;
+            reload |= !self.load_file(&change.uri);
+          }
+        }
+        let mut uris = if reload {
+          self.load_project()
+        } else if changed {
+          Vec::new()
+        } else {
+          return None;
+        };
+        uris.extend(self.check_all());
+        self.push_diagnostics(uris)
+      }
+    }
+  }
+
+  /// Returns when the next changed file is due to be checked, or the results are due to be saved, if
+  /// either is.
+  pub fn deadline(&self) -> Option<Instant> {
+    self.pending.values().copied().chain(self.save_at).min()
+  }
+
+  /// Checks the files if any changed file is due to be checked, and saves the results if they are
+  /// due to be saved.
+  pub fn check_due(&mut self) -> Option<Action> {
+    let now = Instant::now();
+    if self.save_at.is_some_and(|at| at <= now) {
+      self.save();
+    }
+    if !self.pending.values().any(|&deadline| deadline <= now) {
+      return None;
+    }
+    // every file is checked together, so those changed files not yet due are checked too.
+    let uris = self.check_all();
+    self.push_diagnostics(uris)
+  }
+
+  /// Checks every file again, in order, as one project. Returns the URIs of the files.
+  fn check_all(&mut self) -> Vec<Url> {
+    self.pending.clear();
+    let mut uris = Vec::new();
+    // the files listed in the project file go first, in that order. the rest go after, in an order
+    // based on what they bind and mention.
+    let listed: Vec<_> = self
+      .project
+      .iter()
+      .filter(|uri| !self.is_ignored(uri))
+      .cloned()
+      .collect();
+    let to_check: HashMap<_, _> = self
+      .files
+      .iter()
+      .filter(|&(uri, _)| !self.is_ignored(uri))
+      .collect();
+    self.db.retain(|uri| to_check.contains_key(uri));
+    for (uri, file) in to_check {
+      self.db.set_contents(uri.clone(), &file.bs);
+    }
+    self.db.set_listed(listed);
+    let all: Vec<_> = self.files.keys().cloned().collect();
+    for uri in all {
+      // ignored files have no diagnostics.
+      let (diagnostics, checked) = if self.is_ignored(&uri) {
+        (Vec::new(), None)
+      } else {
+        let file = &self.files[&uri];
+        check(
+          &mut self.db,
+          &uri,
+          &file.bs,
+          file.open,
+          self.encoding,
+          &self.settings,
+        )
+      };
+      let result_id = if self.files[&uri].diagnostics == diagnostics {
+        None
+      } else {
+        Some(self.new_result_id())
+      };
+      let file = self.files.get_mut(&uri).unwrap();
+      file.current = checked.is_some();
+      // keep the last successful check around for completion, since the file probably won't parse
+      // while in the middle of typing e.g. `List.`.
+      if checked.is_some() {
+        file.checked = checked;
+      }
+      file.diagnostics = diagnostics;
+      if let Some(result_id) = result_id {
+        file.result_id = result_id;
+      }
+      uris.push(uri);
+    }
+    if self.settings.cache && self.save_at.is_none() {
+      self.save_at = Some(Instant::now() + SAVE_DELAY);
+    }
+    uris
+  }
+
+  /// Saves the results of checking to the cache directory, if there is one.
+  fn save(&mut self) {
+    self.save_at = None;
+    // the cache is only to make checking faster, so failing to write it is no reason to fail.
+    let _ = self.db.save();
+  }
+
+  /// Returns a new ID for a report of diagnostics.
+  fn new_result_id(&mut self) -> String {
+    let ret = self.next_result_id.to_string();
+    self.next_result_id += 1;
+    ret
+  }
+
+  /// Returns the action to publish the diagnostics for the files `uris`, unless the client will pull
+  /// them instead.
+  fn push_diagnostics(&self, uris: Vec<Url>) -> Option<Action> {
+    if self.pull_diagnostics {
+      return None;
+    }
+    let outgoing = uris
+      .into_iter()
+      .map(|uri| match self.files.get(&uri) {
+        // this file is gone, so clear any diagnostics from before.
+        None => publish_diagnostics(uri, None, Vec::new()),
+        // this is empty if the file is now clean, which also clears them.
+        Some(file) => {
+          let diagnostics = file.diagnostics.clone();
+          publish_diagnostics(uri, file.version, diagnostics)
+        }
+      })
+      .collect();
+    Some(Action::Respond(outgoing))
+  }
+
+  /// Returns the URI of the project file in the root, if there is a root.
+  fn project_file(&self) -> Option<Url> {
+    let root = self.root_uri.as_ref()?.to_file_path().ok()?;
+    Url::from_file_path(root.join(crate::project::FILE_NAME)).ok()
+  }
+
+  /// Reads the project file in the root again, along with the files it lists which are not open.
+  /// Files which couldn't be read are left out of the project. Returns the URIs of the files no
+  /// longer in the project, which are forgotten unless open.
+  fn load_project(&mut self) -> Vec<Url> {
+    let root = self
+      .root_uri
+      .as_ref()
+      .and_then(|root| root.to_file_path().ok());
+    let paths = root
+      .as_ref()
+      .and_then(|root| std::fs::read(root.join(crate::project::FILE_NAME)).ok())
+      .and_then(|bs| serde_json::from_slice(&bs).ok())
+      .map_or_else(Vec::new, |val| crate::project::files(&val));
+    let mut project = Vec::with_capacity(paths.len());
+    let mut in_project = HashSet::with_capacity(paths.len());
+    for path in paths {
+      let path = match &root {
+        Some(root) => root.join(path),
+        None => continue,
+      };
+      let uri = match Url::from_file_path(&path) {
+        Ok(x) => x,
+        Err(()) => continue,
+      };
+      if in_project.contains(&uri) || !self.load_file(&uri) {
+        continue;
+      }
+      in_project.insert(uri.clone());
+      project.push(uri);
+    }
+    let gone: Vec<_> = self
+      .files
+      .iter()
+      .filter(|&(uri, file)| !file.open && !in_project.contains(uri))
+      .map(|(uri, _)| uri.clone())
+      .collect();
+    for uri in gone.iter() {
+      self.files.remove(uri);
+    }
+    self.project = project;
+    gone
+  }
+
+  /// Reads the file of the project at `uri` from disk again, unless it is open. Returns whether it
+  /// is open or could be read.
+  fn load_file(&mut self, uri: &Url) -> bool {
+    if self.files.get(uri).is_some_and(|file| file.open) {
+      return true;
+    }
+    let bs = match uri
+      .to_file_path()
+      .ok()
+      .and_then(|path| std::fs::read(path).ok())
+    {
+      Some(x) => x,
+      None => return false,
+    };
+    let result_id = self.new_result_id();
+    let file = self
+      .files
+      .entry(uri.clone())
+      .or_insert_with(|| File::new(Vec::new(), result_id));
+    if file.bs != bs {
+      file.bs = bs;
+      file.current = false;
+    }
+    true
+  }
+
+  /// Reads the prelude files from the settings again. Relative paths are relative to the root, if
+  /// there is one.
+  fn load_prelude(&mut self) {
+    let root = self
+      .root_uri
+      .as_ref()
+      .and_then(|root| root.to_file_path().ok());
+    self.prelude = self
+      .settings
+      .prelude
+      .iter()
+      .filter_map(|path| {
+        let path = match &root {
+          Some(root) => root.join(path),
+          None => path.into(),
+        };
+        std::fs::read(path).ok()
+      })
+      .collect();
+  }
+
+  /// Forgets the results of checking the files before. They depend on the settings and the prelude,
+  /// so this must be done when they change. If the settings say to, the results are kept in the
+  /// cache directory in the root, if there is one, for the next time the same files are checked.
+  fn reset_db(&mut self) {
+    if self.save_at.is_some() {
+      self.save();
+    }
+    let cache_dir = self
+      .root_uri
+      .as_ref()
+      .filter(|_| self.settings.cache)
+      .and_then(|root| root.to_file_path().ok())
+      .map(|root| root.join(CACHE_DIR));
+    let features = self.settings.features;
+    self.db = Db::new(
+      self.settings.basis,
+      features,
+      self.prelude.clone(),
+      cache_dir,
+    );
+  }
+
+  /// Returns whether the settings say not to check `uri`. The ignore patterns are matched against
+  /// the path relative to the root, if there is one.
+  fn is_ignored(&self, uri: &Url) -> bool {
+    let path = uri.path();
+    let path = self
+      .root_uri
+      .as_ref()
+      .and_then(|root| path.strip_prefix(root.path().trim_end_matches('/')))
+      .and_then(|path| path.strip_prefix('/'))
+      .unwrap_or(path);
+    self.settings.is_ignored(path)
+  }
+}
+
+impl File {
+  /// Returns a file with contents `bs` which is not open and hasn't been checked.
+  fn new(bs: Vec<u8>, result_id: String) -> Self {
+    Self {
+      open: false,
+      version: None,
+      bs,
+      diagnostics: Vec::new(),
+      result_id,
+      checked: None,
+      current: false,
+    }
+  }
+
+  /// Returns the report of the diagnostics for this to a client which last got the report with
+  /// `prev` as its result ID.
+  fn report(&self, prev: Option<&str>) -> DiagnosticReport {
+    let result_id = self.result_id.clone();
+    if prev == Some(result_id.as_str()) {
+      DiagnosticReport::Unchanged { result_id }
+    } else {
+      DiagnosticReport::Full {
+        result_id: Some(result_id),
+        items: self.diagnostics.clone(),
       }
-      IncomingNotification::TextDocSave(_) => None,
-      IncomingNotification::TextDocClose(_) => None,
     }
   }
 }
 
+fn publish_diagnostics(uri: Url, version: Option<i64>, diagnostics: Vec<Diagnostic>) -> Outgoing {
+  Outgoing::Notification(OutgoingNotification::PublishDiagnostics(
+    PublishDiagnosticsParams {
+      uri,
+      version,
+      diagnostics,
+    },
+  ))
+}
+
 /// An action to take in response to a notification.
 pub enum Action {
   /// Exit the server. The bool is whether the process should exit successfully.
   Exit(bool),
-  /// Respond with an outgoing message.
-  Respond(Box<Outgoing>),
-}
-
-fn mk_diagnostic_action(uri: Url, version: Option<i64>, bs: &[u8]) -> Action {
-  let diagnostics: Vec<_> = ck_one_file(bs).into_iter().collect();
-  Action::Respond(
-    Outgoing::Notification(OutgoingNotification::PublishDiagnostics(
-      PublishDiagnosticsParams {
-        uri,
-        version,
-        diagnostics,
+  /// Respond with some outgoing messages.
+  Respond(Vec<Outgoing>),
+}
+
+/// Returns the diagnostics for `uri`, which has contents `bs`, and if it is `open`, the results of
+/// checking it if it lexed and parsed well enough to check. Only open files are asked about, so the
+/// statics of the others, which mightn't have been computed, aren't needed.
+fn check(
+  db: &mut Db<Url>,
+  uri: &Url,
+  bs: &[u8],
+  open: bool,
+  enc: Encoding,
+  settings: &Settings,
+) -> (Vec<Diagnostic>, Option<Checked>) {
+  let parsed = match db.parsed(uri) {
+    Some(x) => x,
+    None => return (Vec::new(), None),
+  };
+  let elaborated = db.elaborated(uri);
+  let statics = if open { db.statics(uri) } else { None };
+  // every file was lexed to get the order, so the store has all the names by now.
+  let store = db.store();
+  let mut ret = Vec::new();
+  if let Some(e) = &parsed.lex_error {
+    if settings.is_enabled(e.val.code()) {
+      ret.push(mk_diagnostic(bs, enc, e.loc, e.val.code(), e.val.message()));
+    }
+  }
+  for e in parsed.parse_errors.iter() {
+    if settings.is_enabled(e.val.code()) {
+      let mut diagnostic = mk_diagnostic(bs, enc, e.loc, e.val.code(), e.val.message(&store));
+      let related = related_information(uri, bs, enc, e.val.related());
+      if !related.is_empty() {
+        diagnostic.related_information = Some(related);
+      }
+      ret.push(diagnostic);
+    }
+  }
+  let elaborated = match elaborated {
+    Some(x) => x,
+    None => return (ret, None),
+  };
+  for e in elaborated.errors.iter() {
+    if !settings.is_enabled(e.val.code()) {
+      continue;
+    }
+    let mut diagnostic = mk_diagnostic(bs, enc, e.loc, e.val.code(), e.val.message(&store));
+    if let statics::Severity::Warning = e.val.severity() {
+      diagnostic.severity = Some(DiagnosticSeverity::Warning);
+    }
+    let related = related_information(uri, bs, enc, e.val.related());
+    if !related.is_empty() {
+      diagnostic.related_information = Some(related);
+    }
+    ret.push(diagnostic);
+  }
+  let checked = statics.map(|statics| Checked {
+    store,
+    statics,
+    parsed,
+  });
+  (ret, checked)
+}
+
+fn related_information(
+  uri: &Url,
+  bs: &[u8],
+  enc: Encoding,
+  related: Vec<Located<&'static str>>,
+) -> Vec<DiagnosticRelatedInformation> {
+  related
+    .into_iter()
+    .map(|related| DiagnosticRelatedInformation {
+      location: Location {
+        uri: uri.clone(),
+        range: pos::range(bs, enc, related.loc),
       },
-    ))
-    .into(),
-  )
+      message: related.val.to_owned(),
+    })
+    .collect()
+}
+
+fn hover(file: &File, enc: Encoding, pos: Position) -> Option<Hover> {
+  if !file.current {
+    return None;
+  }
+  let checked = file.checked.as_ref()?;
+  let idx = pos::byte_idx(&file.bs, enc, pos)?;
+  let got = checked.statics.hover(&checked.store, idx)?;
+  let mut value = format!("```sml\n{}\n```", got.val);
+  if let Some(doc) = checked.statics.doc(&file.bs, &checked.parsed.comments, idx) {
+    value.push_str("\n\n---\n\n");
+    value.push_str(&doc);
+  }
+  Some(Hover {
+    contents: HoverContents::Markup(MarkupContent {
+      kind: MarkupKind::Markdown,
+      value,
+    }),
+    range: Some(pos::range(&file.bs, enc, got.loc)),
+  })
+}
+
+fn document_highlight(file: &File, enc: Encoding, pos: Position) -> Option<Vec<DocumentHighlight>> {
+  if !file.current {
+    return None;
+  }
+  let checked = file.checked.as_ref()?;
+  let occurrences = checked
+    .statics
+    .occurrences(pos::byte_idx(&file.bs, enc, pos)?)?;
+  let ret = occurrences
+    .into_iter()
+    .map(|x| DocumentHighlight {
+      range: pos::range(&file.bs, enc, x.loc),
+      // the occurrence which binds the identifier is the one which writes it.
+      kind: Some(if x.val {
+        DocumentHighlightKind::Write
+      } else {
+        DocumentHighlightKind::Read
+      }),
+    })
+    .collect();
+  Some(ret)
+}
+
+fn definition(
+  file: &File,
+  uri: &Url,
+  std_lib: &StdLib,
+  enc: Encoding,
+  pos: Position,
+) -> Option<Location> {
+  if !file.current {
+    return None;
+  }
+  let checked = file.checked.as_ref()?;
+  let idx = pos::byte_idx(&file.bs, enc, pos)?;
+  match checked.statics.definition(idx)? {
+    statics::Definition::Loc(loc) => Some(Location {
+      uri: uri.clone(),
+      range: pos::range(&file.bs, enc, loc),
+    }),
+    statics::Definition::StdLib(name) => std_lib.location(checked.store.get(name)),
+  }
+}
+
+/// Returns a selection range for each of `positions`, or `None` if any of them is not in the file.
+fn selection_ranges(
+  file: &File,
+  enc: Encoding,
+  positions: &[Position],
+) -> Option<Vec<SelectionRange>> {
+  if !file.current {
+    return None;
+  }
+  let checked = file.checked.as_ref()?;
+  positions
+    .iter()
+    .map(|&pos| {
+      let idx = pos::byte_idx(&file.bs, enc, pos)?;
+      // the locs are innermost first, so build the chain of parents from the outermost in.
+      let mut ret: Option<SelectionRange> = None;
+      for loc in selection::get(&checked.parsed.top_decs, idx)
+        .into_iter()
+        .rev()
+      {
+        ret = Some(SelectionRange {
+          range: pos::range(&file.bs, enc, loc),
+          parent: ret.map(Box::new),
+        });
+      }
+      // a position with nothing around it still gets an empty range at that position.
+      Some(ret.unwrap_or(SelectionRange {
+        range: lsp_types::Range {
+          start: pos,
+          end: pos,
+        },
+        parent: None,
+      }))
+    })
+    .collect()
+}
+
+/// The code of the warning for an unused variable.
+const UNUSED_CODE: u16 = 2033;
+
+/// Returns the quick fixes for the unused variables reported in `diagnostics`: replacing the
+/// variable with `_`, or removing the binding of it, when either can be done without changing the
+/// meaning of the rest of the program.
+fn code_actions(
+  file: &File,
+  uri: &Url,
+  enc: Encoding,
+  diagnostics: &[Diagnostic],
+) -> Option<Vec<CodeActionOrCommand>> {
+  if !file.current {
+    return None;
+  }
+  let checked = file.checked.as_ref()?;
+  let bs = &file.bs;
+  let mut ret = Vec::new();
+  for diagnostic in diagnostics {
+    let is_unused = matches!(
+      &diagnostic.code,
+      Some(NumberOrString::Number(n)) if *n == UNUSED_CODE.into()
+    );
+    if !is_unused {
+      continue;
+    }
+    let start = pos::byte_idx(bs, enc, diagnostic.range.start)?;
+    let end = pos::byte_idx(bs, enc, diagnostic.range.end)?;
+    let name = std::str::from_utf8(&bs[start..end]).ok()?;
+    let mut fix = |title: String, edit_start: usize, edit_end: usize, new_text: &str| {
+      let edit = TextEdit {
+        range: lsp_types::Range {
+          start: pos::position(bs, enc, edit_start),
+          end: pos::position(bs, enc, edit_end),
+        },
+        new_text: new_text.to_owned(),
+      };
+      let mut changes = HashMap::new();
+      changes.insert(uri.clone(), vec![edit]);
+      ret.push(CodeActionOrCommand::CodeAction(CodeAction {
+        title,
+        kind: Some(CodeActionKind::QUICKFIX),
+        diagnostics: Some(vec![diagnostic.clone()]),
+        edit: Some(WorkspaceEdit::new(changes)),
+        ..CodeAction::default()
+      }));
+    };
+    // `x as p` becomes `p`. `_ as p` is not allowed.
+    if let Some(after_as) = after_word(bs, end, b"as") {
+      let title = format!("Remove `{} as`", name);
+      fix(title, start, skip_whitespace(bs, after_as), "");
+      continue;
+    }
+    // `op x` becomes `_`.
+    let op_start = before_word(bs, start, b"op").unwrap_or(start);
+    fix(format!("Replace `{}` with `_`", name), op_start, end, "_");
+    if let Some(dec) = checked.statics.sole_binding(Loc::new(start, end)) {
+      let dec: std::ops::Range<usize> = dec.into();
+      // only a `val` is sure to bind nothing but variables.
+      if after_word(bs, dec.start, b"val").is_some() {
+        let (dec_start, dec_end) = whole_lines(bs, dec.start, dec.end);
+        fix(
+          format!("Remove the binding of `{}`", name),
+          dec_start,
+          dec_end,
+          "",
+        );
+      }
+    }
+  }
+  Some(ret)
+}
+
+fn is_alpha_num(b: u8) -> bool {
+  b.is_ascii_alphanumeric() || b == b'_' || b == b'\''
+}
+
+fn skip_whitespace(bs: &[u8], mut idx: usize) -> usize {
+  while bs.get(idx).is_some_and(u8::is_ascii_whitespace) {
+    idx += 1;
+  }
+  idx
+}
+
+/// If the first word at or after `idx` is `word`, returns the index just after it.
+fn after_word(bs: &[u8], idx: usize, word: &[u8]) -> Option<usize> {
+  let start = skip_whitespace(bs, idx);
+  let end = start + word.len();
+  let is_word = bs[start..].starts_with(word) && !bs.get(end).copied().is_some_and(is_alpha_num);
+  if is_word {
+    Some(end)
+  } else {
+    None
+  }
 }
 
-fn ck_one_file(bs: &[u8]) -> Option<Diagnostic> {
+/// If the last word before `idx` is `word`, returns the index of the start of it.
+fn before_word(bs: &[u8], idx: usize, word: &[u8]) -> Option<usize> {
+  let idx = skip_whitespace_back(bs, idx);
+  let start = idx.checked_sub(word.len())?;
+  let is_word = &bs[start..idx] == word && (start == 0 || !is_alpha_num(bs[start - 1]));
+  if is_word {
+    Some(start)
+  } else {
+    None
+  }
+}
+
+/// Extends `start..end` to cover the lines it is on, including the final newline, if there is only
+/// whitespace on them outside of it.
+fn whole_lines(bs: &[u8], start: usize, end: usize) -> (usize, usize) {
+  let line_start = line_start(bs, start);
+  let line_end = bs[end..]
+    .iter()
+    .position(|&b| b == b'\n')
+    .map_or(bs.len(), |i| end + i + 1);
+  let only_whitespace = |xs: &[u8]| xs.iter().all(u8::is_ascii_whitespace);
+  if only_whitespace(&bs[line_start..start]) && only_whitespace(&bs[end..line_end]) {
+    (line_start, line_end)
+  } else {
+    (start, end)
+  }
+}
+
+/// Returns the edit re-indenting the line if the text on it before `pos` is just `end`, `in`, or `|`,
+/// to line up with the line of the token it matches.
+fn on_type_formatting(file: &File, enc: Encoding, pos: Position) -> Option<Vec<TextEdit>> {
+  let idx = pos::byte_idx(&file.bs, enc, pos)?;
+  let cur_line_start = line_start(&file.bs, idx);
+  let line = &file.bs[cur_line_start..idx];
+  let indent_len = indentation(line).len();
+  if !matches!(&line[indent_len..], b"end" | b"in" | b"|") {
+    return None;
+  }
+  // the file probably won't parse while in the middle of typing, but it may well lex up to here.
   let mut store = StrStoreMut::new();
-  let lexer = match lex::get(&mut store, bs) {
-    Ok(x) => x,
-    Err(e) => return Some(mk_diagnostic(bs, e.loc, e.val.message())),
-  };
-  let store = store.finish();
-  let top_decs = match parse::get(lexer) {
-    Ok(x) => x,
-    Err(e) => return Some(mk_diagnostic(bs, e.loc, e.val.message(&store))),
+  let ts: Vec<_> = lex::tokens(&mut store, &file.bs)
+    .filter_map(Result::ok)
+    .take_while(|tok| std::ops::Range::from(tok.loc).end <= idx)
+    .collect();
+  let opener: std::ops::Range<usize> = indent::get(&ts, idx)?.into();
+  let want = indentation(&file.bs[line_start(&file.bs, opener.start)..]);
+  if want == &line[..indent_len] {
+    return Some(Vec::new());
+  }
+  let edit = TextEdit {
+    range: lsp_types::Range {
+      start: pos::position(&file.bs, enc, cur_line_start),
+      end: pos::position(&file.bs, enc, cur_line_start + indent_len),
+    },
+    new_text: std::str::from_utf8(want).unwrap().to_owned(),
   };
-  let mut s = statics::Statics::new();
-  for top_dec in top_decs {
-    match s.get(&top_dec) {
-      Ok(()) => {}
-      Err(e) => return Some(mk_diagnostic(bs, e.loc, e.val.message(&store))),
+  Some(vec![edit])
+}
+
+/// Returns the byte offset of the start of the line containing `idx`.
+fn line_start(bs: &[u8], idx: usize) -> usize {
+  bs[..idx]
+    .iter()
+    .rposition(|&b| b == b'\n')
+    .map_or(0, |i| i + 1)
+}
+
+/// Returns the leading spaces and tabs of `bs`.
+fn indentation(bs: &[u8]) -> &[u8] {
+  let len = bs.iter().take_while(|&&b| b == b' ' || b == b'\t').count();
+  &bs[..len]
+}
+
+/// Returns the semantic tokens of `bs`, encoded as the LSP spec says: five numbers for each token,
+/// which are its line and start relative to the previous token, its length, its type, and its
+/// modifiers.
+fn semantic_tokens(bs: &[u8], enc: Encoding) -> Vec<u32> {
+  let lines = LineIndex::new(bs);
+  let mut ret = Vec::new();
+  let mut prev_line = 0;
+  let mut prev_col = 0;
+  for x in highlight::get(bs) {
+    let ty = match semantic_token_type(x.val) {
+      Some(ty) => ty,
+      None => continue,
+    };
+    let range = std::ops::Range::from(x.loc);
+    // a token may not span lines, so comments and strings which do are split up by line.
+    let mut start = range.start;
+    while start < range.end {
+      let line = lines.line(start);
+      let line_range = lines.line_range(line).unwrap();
+      let end = range.end.min(line_range.end);
+      if start < end {
+        let col = pos::len(&bs[line_range.start..start], enc) as u32;
+        let delta_line = (line - prev_line) as u32;
+        let delta_col = if delta_line == 0 { col - prev_col } else { col };
+        let len = pos::len(&bs[start..end], enc) as u32;
+        ret.extend_from_slice(&[delta_line, delta_col, len, ty, 0]);
+        prev_line = line;
+        prev_col = col;
+      }
+      // skip the newline.
+      start = end + 1;
     }
   }
-  None
+  ret
 }
 
-fn mk_diagnostic(bs: &[u8], loc: Loc, message: String) -> Diagnostic {
-  let range: std::ops::Range<usize> = loc.into();
-  let range = Range {
-    start: position(bs, range.start),
-    end: position(bs, range.end),
+/// Returns the index in `SEMANTIC_TOKEN_TYPES` of the type for `class`, if it has one.
+fn semantic_token_type(class: Class) -> Option<u32> {
+  let name = match class {
+    Class::Keyword => "keyword",
+    Class::Punctuation => return None,
+    Class::Number => "number",
+    Class::String => "string",
+    Class::TyVar => "typeParameter",
+    Class::Qualifier => "namespace",
+    Class::AlphaNumIdent => "variable",
+    Class::SymbolicIdent => "operator",
+    Class::Comment => "comment",
   };
+  let idx = SEMANTIC_TOKEN_TYPES
+    .iter()
+    .position(|&x| x == name)
+    .unwrap();
+  Some(idx as u32)
+}
+
+fn show_ast(file: &File) -> Option<String> {
+  if !file.current {
+    return None;
+  }
+  let checked = file.checked.as_ref()?;
+  Some(format!(
+    "```\n{}\n```",
+    checked.store.debug(&checked.parsed.top_decs)
+  ))
+}
+
+fn show_inferred_type(file: &File, enc: Encoding, pos: Position) -> Option<String> {
+  if !file.current {
+    return None;
+  }
+  let checked = file.checked.as_ref()?;
+  let idx = pos::byte_idx(&file.bs, enc, pos)?;
+  let got = checked.statics.hover(&checked.store, idx)?;
+  Some(format!("```sml\n{}\n```", got.val))
+}
+
+fn mk_diagnostic(bs: &[u8], enc: Encoding, loc: Loc, code: u16, message: String) -> Diagnostic {
   Diagnostic {
-    range,
+    range: pos::range(bs, enc, loc),
+    severity: Some(DiagnosticSeverity::Error),
+    code: Some(NumberOrString::Number(code.into())),
     message,
     source: Some("millet-ls".to_owned()),
     ..Diagnostic::default()
   }
 }
 
-fn position(bs: &[u8], byte_idx: usize) -> Position {
-  let mut line = 0;
-  let mut character = 0;
-  for (idx, &b) in bs.iter().enumerate() {
-    if idx == byte_idx {
+fn completion(file: &File, enc: Encoding, pos: Position) -> Option<CompletionResponse> {
+  let checked = file.checked.as_ref()?;
+  let idx = pos::byte_idx(&file.bs, enc, pos)?;
+  let members = if expects_sig(&file.bs, idx) {
+    checked.statics.signatures(&checked.store)
+  } else {
+    let path = qualifier(&file.bs, idx);
+    checked.statics.members(&checked.store, &path)?
+  };
+  let items = members
+    .into_iter()
+    .map(|member| CompletionItem {
+      label: member.name,
+      kind: Some(match member.kind {
+        MemberKind::Struct => CompletionItemKind::Module,
+        MemberKind::Ty => CompletionItemKind::Class,
+        MemberKind::Val => CompletionItemKind::Value,
+        MemberKind::Ctor | MemberKind::Exn => CompletionItemKind::Constructor,
+        MemberKind::Sig => CompletionItemKind::Interface,
+      }),
+      detail: Some(member.detail),
+      ..CompletionItem::default()
+    })
+    .collect();
+  Some(CompletionResponse::Array(items))
+}
+
+/// Returns whether the (possibly empty, partially typed) identifier which ends at `idx` is where a
+/// signature name goes, as after `:>`, after `include`, or after the `:` in `structure S :` or
+/// `functor F (S :`.
+fn expects_sig(bs: &[u8], idx: usize) -> bool {
+  let mut idx = skip_word_back(bs, idx);
+  idx = skip_whitespace_back(bs, idx);
+  if bs[..idx].ends_with(b":>") || before_word(bs, idx, b"include").is_some() {
+    return true;
+  }
+  // a `:` which is not part of a longer symbolic identifier, like `::`.
+  let is_colon = idx > 0
+    && bs[idx - 1] == b':'
+    && (idx == 1 || !b"!%&$#+-/:<=>?@\\~`^|*".contains(&bs[idx - 2]));
+  if !is_colon {
+    return false;
+  }
+  // the name of the structure.
+  let name_end = skip_whitespace_back(bs, idx - 1);
+  let name_start = skip_word_back(bs, name_end);
+  if name_start == name_end {
+    return false;
+  }
+  if before_word(bs, name_start, b"structure").is_some()
+    || before_word(bs, name_start, b"and").is_some()
+  {
+    return true;
+  }
+  // the argument of a functor.
+  let paren = skip_whitespace_back(bs, name_start);
+  if paren == 0 || bs[paren - 1] != b'(' {
+    return false;
+  }
+  let name_end = skip_whitespace_back(bs, paren - 1);
+  let name_start = skip_word_back(bs, name_end);
+  name_start != name_end && before_word(bs, name_start, b"functor").is_some()
+}
+
+/// Returns the index of the start of the alphanumeric word ending at `idx`, which is `idx` if there
+/// is none.
+fn skip_word_back(bs: &[u8], mut idx: usize) -> usize {
+  while idx > 0 && is_alpha_num(bs[idx - 1]) {
+    idx -= 1;
+  }
+  idx
+}
+
+fn skip_whitespace_back(bs: &[u8], mut idx: usize) -> usize {
+  while idx > 0 && bs[idx - 1].is_ascii_whitespace() {
+    idx -= 1;
+  }
+  idx
+}
+
+/// Returns the structure names qualifying the (possibly empty, partially typed) identifier which
+/// ends at `idx`. For instance, if the text before `idx` is `val _ = Foo.Bar.ba`, returns `["Foo",
+/// "Bar"]`.
+fn qualifier(bs: &[u8], idx: usize) -> Vec<&str> {
+  let mut idx = skip_word_back(bs, idx);
+  let mut ret = Vec::new();
+  while idx > 0 && bs[idx - 1] == b'.' {
+    let end = idx - 1;
+    idx = skip_word_back(bs, end);
+    if idx == end {
       break;
     }
-    if b == b'\n' {
-      line += 1;
-      character = 0;
-    } else {
-      character += 1;
-    }
+    ret.push(std::str::from_utf8(&bs[idx..end]).unwrap());
   }
-  Position { line, character }
+  ret.reverse();
+  ret
+}
+
+#[test]
+fn test_on_type_formatting() {
+  let file = File::new(
+    b"val x =\n  let\n    val y = 3\n      in".to_vec(),
+    String::new(),
+  );
+  let pos = |line, character| Position { line, character };
+  let edits = on_type_formatting(&file, Encoding::Utf16, pos(3, 8)).unwrap();
+  assert_eq!(edits.len(), 1);
+  assert_eq!(edits[0].range, lsp_types::Range::new(pos(3, 0), pos(3, 6)));
+  assert_eq!(edits[0].new_text, "  ");
+  let file = File::new(b"val x =\n  let\n  in".to_vec(), String::new());
+  assert_eq!(
+    on_type_formatting(&file, Encoding::Utf16, pos(2, 4)),
+    Some(Vec::new())
+  );
+  let file = File::new(b"val x = y\n  end".to_vec(), String::new());
+  assert_eq!(on_type_formatting(&file, Encoding::Utf16, pos(1, 5)), None);
+  let file = File::new(b"val end = 3".to_vec(), String::new());
+  assert_eq!(on_type_formatting(&file, Encoding::Utf16, pos(0, 7)), None);
 }
@@ -0,0 +1,67 @@
+//! A read-only virtual document describing the standard basis, so that going to the definition of
+//! something from the standard basis doesn't dead-end.
+
+use lsp_types::{Location, Position, Range, Url};
+use millet_core::intern::StrStoreMut;
+use millet_core::statics::{MemberKind, Statics};
+use std::collections::HashMap;
+
+/// The URI of the document. Clients must ask for its contents with a `millet/virtualDocument`
+/// request, since the scheme is our own.
+const URI: &str = "millet-basis:/std_lib.sml";
+
+/// Returns whether `uri` is the URI of the document. Clients may serialize the URI differently, so
+/// only the scheme and path are compared.
+pub fn is_uri(uri: &Url) -> bool {
+  uri.scheme() == "millet-basis" && uri.path() == "/std_lib.sml"
+}
+
+pub struct StdLib {
+  /// The contents of the document.
+  text: String,
+  /// The line in `text` of each value, constructor, and exception.
+  lines: HashMap<String, Range>,
+}
+
+impl StdLib {
+  /// Returns a new `StdLib`.
+  pub fn new() -> Self {
+    let store = StrStoreMut::new().finish();
+    let members = Statics::new().members(&store, &[]).unwrap();
+    let mut text = "(* the standard basis, as known to millet. this is read-only. *)\n".to_owned();
+    let mut lines = HashMap::new();
+    for (idx, member) in members.into_iter().enumerate() {
+      match member.kind {
+        MemberKind::Struct | MemberKind::Ty | MemberKind::Sig => {}
+        MemberKind::Val | MemberKind::Ctor | MemberKind::Exn => {
+          // the first line is the comment.
+          let line = idx as u64 + 1;
+          let range = Range {
+            start: Position { line, character: 0 },
+            end: Position {
+              line,
+              character: member.detail.len() as u64,
+            },
+          };
+          lines.insert(member.name, range);
+        }
+      }
+      text.push_str(&member.detail);
+      text.push('\n');
+    }
+    Self { text, lines }
+  }
+
+  /// Returns the contents of the document.
+  pub fn text(&self) -> &str {
+    &self.text
+  }
+
+  /// Returns the location in the document of the value, constructor, or exception `name`.
+  pub fn location(&self, name: &str) -> Option<Location> {
+    Some(Location {
+      uri: Url::parse(URI).unwrap(),
+      range: *self.lines.get(name)?,
+    })
+  }
+}
@@ -14,9 +14,58 @@ export async function activate(cx: vscode.ExtensionContext) {
   };
   const clientOpts = {
     documentSelector: [{ scheme: "file", language: "sml" }],
+    initializationOptions: config,
+    synchronize: {
+      configurationSection: "millet",
+      // the server reads the project file, and the files it lists which aren't open, from disk.
+      fileEvents: vscode.workspace.createFileSystemWatcher("**/{millet.json,*.sml,*.sig,*.fun}"),
+    },
+    middleware: { executeCommand },
   };
   client = new LanguageClient("millet-ls", serverOpts, clientOpts, true);
   cx.subscriptions.push(client.start());
+  // the server serves read-only documents, like one for the standard basis, with this scheme.
+  const provider = {
+    async provideTextDocumentContent(uri: vscode.Uri): Promise<string> {
+      if (client === null) {
+        return "";
+      }
+      await client.onReady();
+      const text = await client.sendRequest<string | null>(
+        "millet/virtualDocument",
+        { uri: uri.toString() },
+      );
+      return text === null ? "" : text;
+    },
+  };
+  cx.subscriptions.push(
+    vscode.workspace.registerTextDocumentContentProvider("millet-basis", provider),
+  );
+}
+
+// the server's commands need the file and position, which the command palette doesn't give them. and
+// the ones which show something return Markdown, which we open in a new editor.
+async function executeCommand(
+  command: string,
+  args: any[],
+  next: (command: string, args: any[]) => vscode.ProviderResult<any>,
+): Promise<any> {
+  const editor = vscode.window.activeTextEditor;
+  if (args.length === 0 && editor !== undefined) {
+    const uri = editor.document.uri.toString();
+    const pos = editor.selection.active;
+    args = [uri, { line: pos.line, character: pos.character }];
+  }
+  const res = await next(command, args);
+  if (typeof res !== "string") {
+    return res;
+  }
+  const doc = await vscode.workspace.openTextDocument({
+    language: "markdown",
+    content: res,
+  });
+  await vscode.window.showTextDocument(doc, { preview: true });
+  return res;
 }
 
 export async function deactivate() {
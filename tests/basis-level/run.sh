@@ -0,0 +1,6 @@
+"$MILLET" --quiet top.sml str.sml init.sml
+"$MILLET" --quiet --basis minimal top.sml init.sml
+if "$MILLET" --quiet --basis minimal str.sml >/dev/null; then exit 1; fi
+"$MILLET" --quiet --basis none init.sml
+if "$MILLET" --quiet --basis none top.sml >/dev/null; then exit 1; fi
+if "$MILLET" --quiet --basis bogus init.sml >/dev/null 2>&1; then exit 1; fi
@@ -0,0 +1,4 @@
+"$MILLET" --quiet --successor-ml a.sml >out.tmp
+if [ -s out.tmp ]; then exit 1; fi
+if "$MILLET" --quiet a.sml >out.tmp; then exit 1; fi
+rm out.tmp
@@ -0,0 +1,4 @@
+if "$MILLET" --full-types big.sml >out.tmp; then exit 1; fi
+if grep -q '\.\.\.' out.tmp; then exit 1; fi
+grep -q 'int list list list list list list list list list list' out.tmp
+rm out.tmp